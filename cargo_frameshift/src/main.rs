@@ -0,0 +1,107 @@
+//! `cargo frameshift`: cargo-fuzz with the FrameShift driver in place of libFuzzer.
+//!
+//! Builds `frameshift_afl_lib`, localizes it (see `localize.sh`) and runs `cargo fuzz` with `CUSTOM_LIBFUZZER_PATH`
+//! pointing to it, so that `fuzz_target!` crates link the FrameShift driver without changes. The driver maps the
+//! cargo-fuzz layout onto FrameShift: `fuzz/corpus/<target>` is the input, new entries and crashes go to
+//! `fuzz/artifacts/<target>/` (`queue` and `crashes`), and `cargo fuzz cmin` (`-merge=1`) runs `--cmin`.
+use std::{fs, path::{Path, PathBuf}, process::{self, Command}};
+
+use clap::{Args, Parser, Subcommand};
+
+/// Sources of the driver, next to this crate.
+const DRIVER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../frameshift_afl_lib");
+
+#[derive(Parser)]
+#[command(bin_name = "cargo")]
+enum CargoCli {
+    /// Run cargo-fuzz with the FrameShift driver instead of libFuzzer.
+    Frameshift(FrameshiftArgs),
+}
+
+#[derive(Args)]
+struct FrameshiftArgs {
+    /// Static library of frameshift_afl_lib to link instead of building it (localized with `localize.sh`).
+    #[arg(long)]
+    lib: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: FuzzCommand,
+}
+
+#[derive(Subcommand)]
+enum FuzzCommand {
+    /// Build fuzz targets (`cargo fuzz build [args]`).
+    Build(CargoFuzzArgs),
+
+    /// Fuzz a target (`cargo fuzz run <target> [args] [-- <libFuzzer flags and FrameShift options>]`).
+    Run(CargoFuzzArgs),
+
+    /// Minimize the corpus of a target, keeping the `.annotated` sidecars (`cargo fuzz cmin <target> [args]`).
+    Cmin(CargoFuzzArgs),
+}
+
+#[derive(Args)]
+struct CargoFuzzArgs {
+    /// Arguments of the cargo-fuzz command.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+fn main() {
+    let CargoCli::Frameshift(cli) = CargoCli::parse();
+
+    let lib = cli.lib.unwrap_or_else(build_driver);
+    if !lib.is_file() {
+        println!("Driver library {:?} does not exist", lib);
+        process::exit(1);
+    }
+
+    let (name, args) = match cli.command {
+        FuzzCommand::Build(args) => ("build", args.args),
+        FuzzCommand::Run(args) => ("run", args.args),
+        FuzzCommand::Cmin(args) => ("cmin", args.args),
+    };
+
+    // libfuzzer-sys links the library from its path instead of building libFuzzer, and is rebuilt when it changes.
+    let status = Command::new("cargo")
+        .arg("fuzz")
+        .arg(name)
+        .args(&args)
+        .env("CUSTOM_LIBFUZZER_PATH", fs::canonicalize(&lib).unwrap())
+        .status()
+        .expect("Could not run cargo fuzz (install it with `cargo install cargo-fuzz`)");
+    process::exit(status.code().unwrap_or(1));
+}
+
+/// Builds the static library of the driver and hides its Rust runtime, returning the path of the localized library.
+fn build_driver() -> PathBuf {
+    let dir = Path::new(DRIVER_DIR);
+    let target_dir = dir.join("target");
+
+    println!("Building the FrameShift driver in {:?}", dir);
+    run(Command::new("cargo")
+        .args(["build", "--release", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir));
+
+    // libfuzzer-sys links `lib<name>.a` from the directory of CUSTOM_LIBFUZZER_PATH: keep the name of the archive.
+    let localized = target_dir.join("release").join("localized");
+    fs::create_dir_all(&localized).expect("Could not create the directory of the localized driver");
+    let lib = localized.join("libframeshift_afl_lib.a");
+    run(Command::new("sh")
+        .arg(dir.join("localize.sh"))
+        .arg(target_dir.join("release").join("libframeshift_afl_lib.a"))
+        .arg(&lib));
+
+    lib
+}
+
+/// Runs `cmd`, exiting if it fails.
+fn run(cmd: &mut Command) {
+    let status = cmd.status().expect("Could not run command");
+    if !status.success() {
+        println!("Command failed ({}): {:?}", status, cmd);
+        process::exit(status.code().unwrap_or(1));
+    }
+}
@@ -6,7 +6,6 @@ export CUSTOM_LIBFUZZER_PATH=/libframeshift_afl_lib.a
         -Cllvm-args=-sanitizer-coverage-level=4 \
         -Cllvm-args=-sanitizer-coverage-inline-8bit-counters \
         -Cllvm-args=-sanitizer-coverage-pc-table \
-        -Zsanitizer=address \
-        -Clink-arg=-Wl,--allow-multiple-definition)
+        -Zsanitizer=address)
 
 cp ./image-png/fuzz/target/debug/decode /fuzz/decode
@@ -0,0 +1,107 @@
+//! Benchmarks of the relation-aware input operations and of the structure search, on synthesized inputs made of
+//! length-prefixed chunks (`cargo bench` in `frameshift_afl`).
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use frameshift_afl::frameshift_core::{search, CoverageSnapshot, Relation, SearchOptions, Structured};
+
+/// Payload bytes of each chunk.
+const CHUNK_PAYLOAD: usize = 16;
+
+/// Number of chunks of the synthesized inputs (one relation each).
+const CHUNK_COUNTS: [usize; 3] = [4, 32, 256];
+
+/// Builds an input of `chunks` chunks (a 4-byte little-endian length followed by its payload), annotated with the
+/// relation of each length.
+fn chunked_input(chunks: usize) -> Structured {
+    let mut raw = vec![];
+    let mut relations = vec![];
+    for k in 0..chunks {
+        let pos = raw.len();
+        raw.extend((CHUNK_PAYLOAD as u32).to_le_bytes());
+        raw.extend((0..CHUNK_PAYLOAD).map(|i| (k * 7 + i) as u8));
+        relations.push(Relation::new(pos, CHUNK_PAYLOAD as u64, 4, true, pos + 4, pos + 4 + CHUNK_PAYLOAD));
+    }
+
+    let mut input = Structured::raw(raw);
+    for rel in relations {
+        input.add_relation(rel);
+    }
+    input
+}
+
+/// A toy parser of `chunked_input`: one edge per chunk whose length fits, and one per distinct first payload byte.
+fn chunked_target(data: &[u8]) -> CoverageSnapshot {
+    let mut cov = vec![0; 512];
+    cov[0] = 1;
+
+    let mut pos = 0;
+    let mut k = 0;
+    while pos + 4 <= data.len() {
+        let len = u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]) as usize;
+        if pos + 4 + len > data.len() {
+            break;
+        }
+        cov[1 + k % 255] = 1;
+        if len > 0 {
+            cov[256 + data[pos + 4] as usize] = 1;
+        }
+        pos += 4 + len;
+        k += 1;
+    }
+    CoverageSnapshot::from_map(&cov)
+}
+
+fn bench_sanitize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sanitize");
+    for chunks in CHUNK_COUNTS {
+        let input = chunked_input(chunks);
+        group.bench_with_input(BenchmarkId::from_parameter(chunks), &input, |b, input| {
+            b.iter_batched_ref(|| input.clone(), |input| input.sanitize(), BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn bench_insert_disabling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_disabling");
+    for chunks in CHUNK_COUNTS {
+        let input = chunked_input(chunks);
+        // In the middle of the payload of the middle chunk, so that half of the relations move.
+        let idx = (chunks / 2) * (4 + CHUNK_PAYLOAD) + 4 + CHUNK_PAYLOAD / 2;
+        group.bench_with_input(BenchmarkId::from_parameter(chunks), &input, |b, input| {
+            b.iter_batched_ref(|| input.clone(), |input| input.insert_disabling(idx, black_box(&[0x41u8; 8][..])), BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn bench_apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply");
+    for size in [1, 2, 3, 4, 8] {
+        let rel = Relation::new(0, 0x1234 & ((1u64 << (size * 8).min(63)) - 1), size, size % 2 == 0, size, size + 0x10);
+        let mut buffer = vec![0; 0x20];
+        group.bench_with_input(BenchmarkId::from_parameter(size), &rel, |b, rel| {
+            b.iter(|| rel.apply(black_box(&mut buffer)));
+        });
+    }
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for chunks in CHUNK_COUNTS {
+        let input = Structured::raw(chunked_input(chunks).get_raw().to_vec());
+        group.bench_with_input(BenchmarkId::from_parameter(chunks), &input, |b, input| {
+            b.iter(|| {
+                let mut oracle = chunked_target;
+                let options = SearchOptions { max_iters: 1, ..Default::default() };
+                search(input, &mut oracle, options)
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sanitize, bench_insert_disabling, bench_apply, bench_search);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+#!/bin/sh
+# Reference coverage worker for `--coverage-worker "sh coverage_worker.sh"` (see the README): it implements the
+# protocol of `modes::worker::CoverageWorker` and covers the map entry given by the first byte of each input. A real
+# worker replaces `run` by an execution of the target recording its coverage in the map.
+
+input=$(mktemp)
+trap 'rm -f "$input"' EXIT
+
+# Marks entry $1 of the coverage map as covered.
+cover() {
+    printf '\001' | dd of="$FRAMESHIFT_MAP_PATH" bs=1 seek="$1" conv=notrunc 2>/dev/null
+}
+
+# Runs the input in file $1.
+run() {
+    first=$(dd if="$1" bs=1 count=1 2>/dev/null | od -An -tu1 | tr -d ' ')
+    if [ -n "$first" ]; then
+        cover "$first"
+    fi
+}
+
+while :; do
+    # A frame is a u32 little-endian length, then the input. The bytes are read one at a time, so that nothing past
+    # the frame is consumed.
+    len=$(dd bs=1 count=4 2>/dev/null | od -An -tu1 | awk 'NF == 4 { print $1 + 256 * ($2 + 256 * ($3 + 256 * $4)) }')
+    if [ -z "$len" ]; then
+        exit 0
+    fi
+    dd bs=1 count="$len" of="$input" 2>/dev/null
+    run "$input"
+    printf '\000\000\000\000'
+done
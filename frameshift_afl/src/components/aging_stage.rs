@@ -0,0 +1,162 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use libafl::{corpus::{Corpus, CorpusId}, inputs::UsesInput, stages::Stage, state::{HasCorpus, State, UsesState}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, ErrorBacktrace, Named};
+use serde::{Deserialize, Serialize};
+
+use super::structured_input::{annotation_path, read_annotation, write_annotation, StructuredInput};
+
+
+/// Number of times a corpus entry was selected in a row without producing a new corpus entry.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AgingMetadata {
+    pub idle: usize,
+}
+
+impl_serdeany!(AgingMetadata);
+
+/// Marks a corpus entry whose structure was moved to its `.annotated` sidecar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgedMetadata;
+
+impl_serdeany!(AgedMetadata);
+
+/// Detaches the structure of corpus entries which stopped producing new entries, so stale entries don't keep paying
+/// for their relations (memory, sanitization, being picked for crossover).
+///
+/// Runs first on every selected entry: the previous entry is credited if the corpus grew since it was selected,
+/// otherwise it ages by one cycle. Once an entry was idle for `max_idle` cycles, its structure is written to its
+/// `.annotated` sidecar and its relations are dropped from memory. The structure is restored from the sidecar when the
+/// entry is selected again.
+pub struct AgingStage<S> {
+    pub max_idle: Option<usize>,
+    /// Previously selected entry and the corpus size at the time.
+    last: Option<(CorpusId, usize)>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> AgingStage<S> {
+    pub fn new(max_idle: Option<usize>) -> Self {
+        Self {
+            max_idle,
+            last: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> Named for AgingStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("AgingStage")
+    }
+}
+
+impl<S> UsesState for AgingStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S> AgingStage<S>
+where
+    S: State + HasCorpus + UsesInput<Input = StructuredInput>,
+{
+    /// Ages `id` by one cycle (or resets its age), and demotes its structure once it is too old.
+    fn age(&self, state: &mut S, id: CorpusId, productive: bool, max_idle: usize) -> Result<(), Error> {
+        let Ok(cell) = state.corpus().get(id) else {
+            // Removed in the meantime (e.g. culled).
+            return Ok(());
+        };
+        let mut entry = cell.borrow_mut();
+
+        if !entry.has_metadata::<AgingMetadata>() {
+            entry.add_metadata(AgingMetadata::default());
+        }
+        let meta = entry.metadata_mut::<AgingMetadata>()?;
+        meta.idle = if productive { 0 } else { meta.idle + 1 };
+        let idle = meta.idle;
+        if idle < max_idle || entry.has_metadata::<AgedMetadata>() {
+            return Ok(());
+        }
+
+        let Some(path) = entry.file_path().clone() else {
+            return Ok(());
+        };
+        let Some(inner) = entry.input_mut().as_mut() else {
+            return Ok(());
+        };
+        if inner.input.relations.is_empty() {
+            return Ok(());
+        }
+
+        write_annotation(&annotation_path(&path), &inner.input)?;
+
+        // Modified in place: replacing the testcase would also overwrite the sidecar.
+        inner.input.relations.clear();
+        inner.input.relations.shrink_to_fit();
+        entry.add_metadata(AgedMetadata);
+
+        Ok(())
+    }
+
+    /// Restores the structure of a demoted entry from its sidecar.
+    fn restore(&self, state: &mut S, id: CorpusId) -> Result<(), Error> {
+        let mut entry = state.corpus().get(id)?.borrow_mut();
+        if entry.metadata_map_mut().remove::<AgedMetadata>().is_none() {
+            return Ok(());
+        }
+
+        let Some(path) = entry.file_path().clone() else {
+            return Ok(());
+        };
+        // Without a usable sidecar, the entry just stays without structure.
+        let structure = read_annotation(&annotation_path(&path)).ok();
+
+        let inner = entry.input_mut().as_mut().unwrap();
+        if let Some(structure) = structure.filter(|s| s.get_raw() == inner.input.get_raw()) {
+            inner.input = structure;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for AgingStage<S>
+where
+    S: State + HasCorpus + UsesInput<Input = StructuredInput>,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: UsesState<State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        let Some(max_idle) = self.max_idle else {
+            return Ok(());
+        };
+        let corpus_idx = state.corpus().current().ok_or(Error::Empty("missing current".to_string(), ErrorBacktrace {}))?;
+
+        if let Some((last, count)) = self.last.take() {
+            let productive = state.corpus().count() > count;
+            self.age(state, last, productive, max_idle)?;
+        }
+
+        self.restore(state, corpus_idx)?;
+        self.last = Some((corpus_idx, state.corpus().count()));
+
+        Ok(())
+    }
+}
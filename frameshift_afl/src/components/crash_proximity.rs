@@ -0,0 +1,146 @@
+use std::{borrow::Cow, collections::HashSet, marker::PhantomData};
+
+use libafl::{events::EventFirer, executors::ExitKind, feedbacks::{Feedback, MapFeedbackMetadata}, prelude::{MapObserver, ObserversTuple}, state::State, Error, HasMetadata, HasNamedMetadata};
+use libafl_bolts::{impl_serdeany, tuples::{Handle, Handled, MatchNameRef}, AsSlice, Named};
+use libafl_targets::sancov_pcguard::sanitizer_cov_pc_table;
+use serde::{Deserialize, Serialize};
+
+
+/// Returns the PC of every edge, indexed like the edges map (from the pc-tables registered with libafl_targets by
+/// `-fsanitize-coverage=pc-table`).
+///
+/// The pc-table of each module is registered right after its guards, so concatenating the tables in registration
+/// order matches the guard numbering.
+pub fn edge_pcs() -> Vec<usize> {
+    sanitizer_cov_pc_table().flat_map(|table| table.iter().map(|entry| entry.addr())).collect()
+}
+
+/// Smallest layout distance (in bytes between pc-table addresses) between any of `edges` and a crash edge.
+///
+/// This is a heuristic, not a control-flow distance: code laid out close to the crash is usually in the same or a
+/// neighbouring function, which is only a hint that it leads there.
+pub fn crash_distance(pcs: &[usize], crash_edges: &HashSet<usize>, edges: &[usize]) -> Option<usize> {
+    let mut crash_pcs = crash_edges.iter().filter_map(|e| pcs.get(*e).cloned()).collect::<Vec<_>>();
+    crash_pcs.sort_unstable();
+
+    edges.iter()
+        .filter_map(|e| pcs.get(*e))
+        .filter_map(|pc| {
+            let i = crash_pcs.partition_point(|c| c < pc);
+            [i.checked_sub(1), Some(i)].into_iter()
+                .flatten()
+                .filter_map(|j| crash_pcs.get(j))
+                .map(|c| c.abs_diff(*pc))
+                .min()
+        })
+        .min()
+}
+
+
+/// Edges covered by crashing inputs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CrashEdgesMetadata {
+    pub edges: HashSet<usize>,
+}
+
+impl_serdeany!(CrashEdgesMetadata);
+
+/// Records the edges reached by crashing executions in `CrashEdgesMetadata`.
+///
+/// Only edges which were never reached by a non-crashing execution are recorded (these locate the crash), unless
+/// the crash happened entirely in known code. This feedback never marks an input as interesting on its own.
+pub struct CrashEdgesFeedback<C,O> {
+    pub map_handle: Handle<C>,
+    _phantom: PhantomData<O>,
+}
+
+impl<C,O> CrashEdgesFeedback<C,O>
+where
+    C: Named
+{
+    pub fn new(observer: &C) -> Self {
+        Self {
+            map_handle: observer.handle(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<C,O> Named for CrashEdgesFeedback<C,O> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("CrashEdgesFeedback")
+    }
+}
+
+impl<S,C,O> Feedback<S> for CrashEdgesFeedback<C,O>
+where
+    S: State + HasMetadata + HasNamedMetadata,
+    C: Named + AsRef<O>,
+    O: MapObserver + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind != ExitKind::Crash {
+            return Ok(false);
+        }
+
+        let obs = observers.get(&self.map_handle).ok_or_else(|| Error::key_not_found("edges observer not found"))?.as_ref();
+        let hit = obs.as_slice().iter().enumerate().filter(|(_, v)| **v != 0).map(|(i, _)| i).collect::<Vec<_>>();
+
+        // Edges which the corpus feedback has seen before.
+        let history = state.named_metadata::<MapFeedbackMetadata<u8>>(self.map_handle.name()).ok();
+        let unseen = hit.iter()
+            .filter(|i| history.map_or(true, |h| h.history_map.get(**i).map_or(true, |v| *v == 0)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let edges = if unseen.is_empty() { hit } else { unseen };
+
+        if !state.has_metadata::<CrashEdgesMetadata>() {
+            state.add_metadata(CrashEdgesMetadata::default());
+        }
+        state.metadata_mut::<CrashEdgesMetadata>().unwrap().edges.extend(edges);
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_distance() {
+        let pcs = [0x1000, 0x1040, 0x1100, 0x2000, 0x2010];
+        let crash_edges = HashSet::from([2]);
+
+        // The closest of the edges counts, on either side of the crash edge.
+        assert_eq!(crash_distance(&pcs, &crash_edges, &[0, 3]), Some(0x100));
+        assert_eq!(crash_distance(&pcs, &crash_edges, &[1, 4]), Some(0xc0));
+        assert_eq!(crash_distance(&pcs, &crash_edges, &[2]), Some(0));
+
+        // The closest of several crash edges counts too.
+        assert_eq!(crash_distance(&pcs, &HashSet::from([0, 4]), &[3]), Some(0x10));
+    }
+
+    #[test]
+    fn test_crash_distance_unknown_edges() {
+        let pcs = [0x1000, 0x1040];
+
+        // Edges past the pc-table are ignored.
+        assert_eq!(crash_distance(&pcs, &HashSet::from([0]), &[7]), None);
+        assert_eq!(crash_distance(&pcs, &HashSet::from([7]), &[1]), None);
+        assert_eq!(crash_distance(&pcs, &HashSet::new(), &[0, 1]), None);
+        assert_eq!(crash_distance(&pcs, &HashSet::from([0]), &[]), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use libafl::{corpus::Corpus, inputs::UsesInput, prelude::{MutationResult, Mutator}, random_corpus_id, state::{HasCorpus, HasRand}, Error};
+use libafl_bolts::{rands::Rand, Named};
+
+use super::structured_input::StructuredInput;
+
+
+/// Splices a whole relation-governed region (size field and content) of another corpus entry into the input, at
+/// one of its insertion points.
+///
+/// All relations of the input are updated for the insertion and the relations inside the spliced region are carried
+/// over, so the result stays consistent (unlike byte-level splicing).
+#[derive(Default)]
+pub struct StructuredCrossoverMutator;
+
+impl StructuredCrossoverMutator {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for StructuredCrossoverMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StructuredCrossoverMutator")
+    }
+}
+
+impl<S> Mutator<StructuredInput, S> for StructuredCrossoverMutator
+where
+    S: HasRand + HasCorpus + UsesInput<Input = StructuredInput>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        if input.input.fixed_size {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let id = random_corpus_id!(state.corpus(), state.rand_mut());
+        if state.corpus().current().is_some_and(|current| current == id) {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let donor = {
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            testcase.load_input(state.corpus())?.input.clone()
+        };
+
+        let regions = (0..donor.relations.len())
+            .filter(|i| donor.region_of(*i).is_some())
+            .collect::<Vec<_>>();
+        if regions.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let points = input.input.insertion_points();
+
+        let rand = state.rand_mut();
+        let rel_idx = regions[(rand.next() % regions.len() as u64) as usize];
+        let idx = points[(rand.next() % points.len() as u64) as usize];
+
+        match input.input.splice_region(idx, &donor, rel_idx) {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
+
+/// Splices a range of bytes of another corpus entry into the input, at one of its insertion points, inheriting the
+/// donor relations which lie in the range.
+///
+/// The range starts and ends at boundaries of the donor chunks (fields, anchors and insertion points), so it tends
+/// to hold whole fields and regions. Unlike the byte-level splicing of havoc, which only sees the donor bytes, the
+/// relations of both entries survive (see `Structured::splice_from`).
+#[derive(Default)]
+pub struct StructuredSpliceMutator;
+
+impl StructuredSpliceMutator {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for StructuredSpliceMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StructuredSpliceMutator")
+    }
+}
+
+impl<S> Mutator<StructuredInput, S> for StructuredSpliceMutator
+where
+    S: HasRand + HasCorpus + UsesInput<Input = StructuredInput>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        if input.input.fixed_size {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let id = random_corpus_id!(state.corpus(), state.rand_mut());
+        if state.corpus().current().is_some_and(|current| current == id) {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let donor = {
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            testcase.load_input(state.corpus())?.input.clone()
+        };
+
+        let mut bounds = donor.chunks().iter().map(|chunk| chunk.start).collect::<Vec<_>>();
+        bounds.push(donor.get_raw().len());
+        if bounds.len() < 2 {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let points = input.input.insertion_points();
+
+        let rand = state.rand_mut();
+        let start = (rand.next() % (bounds.len() - 1) as u64) as usize;
+        let end = start + 1 + (rand.next() % (bounds.len() - start - 1) as u64) as usize;
+        let idx = points[(rand.next() % points.len() as u64) as usize];
+
+        match input.input.splice_from(idx, &donor, bounds[start]..bounds[end]) {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
+
+/// The havoc splice: replaces the input from a point between its first and last difference with another corpus entry
+/// by the bytes of that entry, like LibAFL's `SpliceMutator`, but inheriting the donor relations which lie in them.
+///
+/// `HasMutatorBytes::splice` only gets the donor bytes, so LibAFL's mutator would drop the donor structure.
+#[derive(Default)]
+pub struct HavocSpliceMutator;
+
+impl HavocSpliceMutator {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for HavocSpliceMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("HavocSpliceMutator")
+    }
+}
+
+/// First and last positions where `this` and `other` differ.
+fn locate_diffs(this: &[u8], other: &[u8]) -> Option<(usize, usize)> {
+    let mut diffs = this.iter().zip(other.iter()).enumerate().filter(|(_, (a, b))| a != b).map(|(i, _)| i);
+    let first = diffs.next()?;
+    Some((first, diffs.last().unwrap_or(first)))
+}
+
+impl<S> Mutator<StructuredInput, S> for HavocSpliceMutator
+where
+    S: HasRand + HasCorpus + UsesInput<Input = StructuredInput>,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        if input.input.fixed_size {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let id = random_corpus_id!(state.corpus(), state.rand_mut());
+        if state.corpus().current().is_some_and(|current| current == id) {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let donor = {
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            testcase.load_input(state.corpus())?.input.clone()
+        };
+
+        let (first, last) = match locate_diffs(input.input.get_raw(), donor.get_raw()) {
+            Some((first, last)) if first != last && last >= 2 => (first, last),
+            _ => return Ok(MutationResult::Skipped),
+        };
+        let split_at = first + (state.rand_mut().next() % (last - first) as u64) as usize;
+
+        match input.input.splice_tail(split_at, &donor) {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use libafl::{corpus::{Corpus, CorpusId}, fuzzer::HasScheduler, inputs::UsesInput, schedulers::{minimizer::IsFavoredMetadata, RemovableScheduler}, stages::Stage, state::{HasCorpus, State, UsesState}, HasMetadata};
+use libafl_bolts::Named;
+
+use super::{monitor::stage_line, structured_input::{annotation_path, InputStatus, StructuredInput}};
+
+
+/// Keeps the corpus below a hard upper bound on the number of entries.
+///
+/// When the bound is exceeded, entries are removed until the corpus is back to 90% of the bound (so that we don't
+/// cull on every new entry). Entries which are not favored by the minimizer (i.e. whose coverage is also reached by
+/// other entries) are removed first, and among those, entries with less structure are removed first.
+pub struct CorpusCullStage<S> {
+    pub max_entries: Option<usize>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> CorpusCullStage<S> {
+    pub fn new(max_entries: Option<usize>) -> Self {
+        Self {
+            max_entries,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// How valuable the structure of a corpus entry is (higher is better).
+fn structure_score(input: &StructuredInput) -> usize {
+    let searched = match input.status {
+        InputStatus::Searched(_) | InputStatus::Frozen(_) => 1,
+        _ => 0,
+    };
+    input.input.relations.iter().filter(|rel| rel.enabled).count() * 2 + searched
+}
+
+impl<S> Named for CorpusCullStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("CorpusCullStage")
+    }
+}
+
+impl<S> UsesState for CorpusCullStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for CorpusCullStage<S>
+where
+    S: State + HasCorpus + UsesInput<Input = StructuredInput>,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: UsesState<State = S> + HasScheduler,
+    Z::Scheduler: RemovableScheduler,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        let max_entries = match self.max_entries {
+            Some(max) if state.corpus().count() > max => max,
+            _ => return Ok(()),
+        };
+
+        let target = (max_entries * 9 / 10).max(1);
+        let current = *state.corpus().current();
+
+        // Rank the removal candidates: (favored, structure score, id).
+        let mut candidates: Vec<(bool, usize, CorpusId)> = vec![];
+        for id in state.corpus().ids() {
+            if Some(id) == current {
+                continue;
+            }
+
+            let testcase = state.corpus().get(id)?.borrow();
+            let favored = testcase.has_metadata::<IsFavoredMetadata>();
+            let score = testcase.input().as_ref().map(structure_score).unwrap_or(0);
+            candidates.push((favored, score, id));
+        }
+        candidates.sort();
+
+        let to_remove = state.corpus().count() - target;
+        for (_, _, id) in candidates.into_iter().take(to_remove) {
+            let testcase = state.corpus_mut().remove(id)?;
+
+            // The corpus removes the raw file, but doesn't know about our sidecar.
+            if let Some(path) = testcase.file_path() {
+                let _ = std::fs::remove_file(annotation_path(path));
+            }
+
+            fuzzer.scheduler_mut().on_remove(state, id, &Some(testcase))?;
+        }
+
+        stage_line(&format!("  [culled corpus to {} entries]", state.corpus().count()));
+
+        Ok(())
+    }
+}
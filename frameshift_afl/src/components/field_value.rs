@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+
+use libafl::{prelude::{MutationResult, Mutator}, state::HasRand, Error, HasMetadata};
+use libafl_bolts::{rands::Rand, Named};
+
+use super::{search_metadata::FieldValueMetadata, structured_input::StructuredInput};
+
+
+/// Filler of the bytes added when growing a region.
+const GROW_FILL: u8 = 0x41;
+
+/// Maximum number of bytes added by a single mutation.
+const MAX_GROWTH: u64 = 0x1000;
+
+/// Sets a length field to a value drawn from the values it holds across the corpus.
+///
+/// In-distribution values are made consistent by growing or shrinking the region of the field. Out-of-distribution
+/// values (with probability `out_of_distribution`) are written as is and the relation is disabled, so the input lies
+/// about its length with a value the target has likely never seen.
+pub struct FieldValueMutator {
+    out_of_distribution: f64,
+}
+
+impl FieldValueMutator {
+    pub fn new(out_of_distribution: f64) -> Self {
+        Self { out_of_distribution }
+    }
+}
+
+impl Named for FieldValueMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("FieldValueMutator")
+    }
+}
+
+impl<S> Mutator<StructuredInput, S> for FieldValueMutator
+where
+    S: HasRand + HasMetadata
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        let Ok(meta) = state.metadata::<FieldValueMetadata>() else {
+            return Ok(MutationResult::Skipped);
+        };
+
+        let raw = input.input.get_raw();
+        let fields = input.input.relations.iter()
+            .enumerate()
+            .filter(|(_, rel)| rel.enabled)
+            .filter_map(|(i, rel)| meta.model.field(raw, rel.pos).filter(|v| v.size == rel.size).map(|v| (i, v.clone())))
+            .collect::<Vec<_>>();
+
+        if fields.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let rand = state.rand_mut();
+        let (idx, values) = &fields[(rand.next() % fields.len() as u64) as usize];
+        let out = (rand.next() as f64 / u64::MAX as f64) < self.out_of_distribution;
+        let r = rand.next();
+
+        let rel = &input.input.relations[*idx];
+        if out || input.input.fixed_size {
+            let Some(value) = values.out_of_distribution(r) else {
+                return Ok(MutationResult::Skipped);
+            };
+
+            let mut lie = rel.clone();
+            lie.value = value;
+            lie.apply(input.input.get_raw_mut());
+            input.input.set_relation_enabled(*idx, false);
+            return Ok(MutationResult::Mutated);
+        }
+
+        let value = values.in_distribution(r);
+        if value == rel.value || value > rel.value.saturating_add(MAX_GROWTH) {
+            return Ok(MutationResult::Skipped);
+        }
+
+        match input.input.resize_region(*idx, value, GROW_FILL) {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use libafl::{corpus::Corpus, fuzzer::{Evaluator, ExecuteInputResult}, inputs::UsesInput, stages::Stage, state::{HasCorpus, State, UsesState}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, ErrorBacktrace, Named};
+use serde::{Deserialize, Serialize};
+
+use super::{monitor::stage_line, structured_input::{InputStatus, StructuredInput}};
+
+
+/// Filler of the bytes added when growing a region.
+const GROW_FILL: u8 = 0x41;
+
+/// Marks a corpus entry whose relations were already tried with boundary values.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoundaryValuesMetadata;
+
+impl_serdeany!(BoundaryValuesMetadata);
+
+/// Deterministically tries boundary values (0, 1, the maximum, value±1, value*2) on every size field of searched
+/// corpus entries, once per entry.
+///
+/// Each value is tried both as a lie about the governed region and consistently (by resizing the region), while the
+/// other relations are kept consistent, since these are exactly the values likely to trigger length-handling bugs.
+pub struct FieldValueMutationalStage<S> {
+    /// Maximum number of relations tried per corpus entry.
+    pub max_relations: usize,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> FieldValueMutationalStage<S> {
+    pub fn new(max_relations: usize) -> Self {
+        Self {
+            max_relations,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> Named for FieldValueMutationalStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("FieldValueMutationalStage")
+    }
+}
+
+impl<S> UsesState for FieldValueMutationalStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for FieldValueMutationalStage<S>
+where
+    S: State + HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: Evaluator<E,EM,State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        let corpus_idx = state.corpus().current().ok_or(Error::Empty("missing current".to_string(), ErrorBacktrace {}))?;
+
+        // Only run on searched (or frozen) entries with structure, once.
+        let testcase = {
+            let entry = state.corpus().get(corpus_idx)?.borrow();
+            if entry.has_metadata::<BoundaryValuesMetadata>() {
+                return Ok(());
+            }
+
+            let inner = entry.input().as_ref().unwrap();
+            let searched = matches!(inner.status, InputStatus::Searched(id) | InputStatus::Frozen(Some(id)) if id == corpus_idx);
+            if !searched || inner.input.relations.is_empty() {
+                return Ok(());
+            }
+            inner.input.clone()
+        };
+
+        state.corpus().get(corpus_idx)?.borrow_mut().add_metadata(BoundaryValuesMetadata);
+
+        let mut execs = 0;
+        let mut interesting = 0;
+        let relations = (0..testcase.relations.len()).filter(|i| testcase.relations[*i].enabled).take(self.max_relations);
+        for idx in relations {
+            for variant in testcase.boundary_variants(idx, GROW_FILL) {
+                let mut input = StructuredInput::new_structured(variant);
+                input.status = InputStatus::Mutated;
+
+                let (res, _) = fuzzer.evaluate_input(state, executor, manager, input)?;
+                execs += 1;
+                if res != ExecuteInputResult::None {
+                    interesting += 1;
+                }
+            }
+        }
+
+        if interesting > 0 {
+            stage_line(&format!("  ({}) [boundary values: {} interesting of {}]", corpus_idx, interesting, execs));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+use std::fs;
+
+use super::structured_input::StructuredInput;
+use crate::core::parts::{part_names, part_path};
+
+/// Writes the registered parts of `input` to their files before it runs (empty if the input lacks a part).
+pub fn write_parts(input: &StructuredInput) {
+    for name in part_names() {
+        fs::write(part_path(&name), input.part(&name).unwrap_or_default()).expect("Could not write input part");
+    }
+}
@@ -1,21 +1,211 @@
-use libafl::{inputs::BytesInput, prelude::Generator};
+use std::{borrow::Cow, marker::PhantomData};
 
-use super::structured_input::StructuredInput;
+use libafl::{corpus::Corpus, fuzzer::{Evaluator, ExecuteInputResult}, inputs::{BytesInput, UsesInput}, mutators::Tokens, prelude::Generator, random_corpus_id, stages::Stage, state::{HasCorpus, HasRand, State, UsesState}, HasMetadata};
+use libafl_bolts::{rands::Rand, Named};
 
+use crate::core::structured::Structured;
 
-pub struct GrammarGenerator;
+use super::{monitor::stage_line, structured_input::{InputStatus, StructuredInput}};
 
-impl<S> Generator<StructuredInput,S> for GrammarGenerator {
-    fn generate(&mut self, _state: &mut S) -> Result<StructuredInput, libafl::Error> {
-        Ok(StructuredInput::new_raw(b"aaaaaaaa"))
+
+/// Maximum number of tokens concatenated into a generated input.
+const MAX_CONCAT: u64 = 8;
+
+/// Longest token considered as a magic header.
+const MAX_MAGIC_LEN: usize = 16;
+
+/// Maximum number of magic headers emitted alone before switching to concatenations.
+const MAX_MAGIC_SEEDS: usize = 32;
+
+/// Random corpus entries considered when looking for a template with relations.
+const TEMPLATE_TRIES: usize = 8;
+
+/// Inputs generated from the learned structures per run of `StructureGenerationStage`.
+const GENERATED_PER_STAGE: usize = 4;
+
+/// Generates new inputs, from the learned structures or from tokens.
+///
+/// Once corpus entries have relations, a structured input is generated from one of them picked as a template (see
+/// `Structured::generate`): its chunks are duplicated, dropped or resized, their contents are randomized, and the
+/// size fields are recomputed. Otherwise (e.g. the initial inputs when the corpus is empty), with a `Tokens` dictionary (from `-x` or the target's autotokens), the likely magic headers are emitted alone first
+/// (binary signatures, then longer tokens), followed by random concatenations of tokens of varying length. Without
+/// tokens, a placeholder input is emitted.
+#[derive(Default)]
+pub struct GrammarGenerator {
+    /// Number of inputs generated so far.
+    generated: usize,
+}
+
+impl GrammarGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Tokens which may be magic headers, most likely first.
+fn magic_headers(tokens: &[Vec<u8>]) -> Vec<&Vec<u8>> {
+    let mut magics = tokens.iter().filter(|t| t.len() >= 2 && t.len() <= MAX_MAGIC_LEN).collect::<Vec<_>>();
+    magics.sort_by_key(|t| (!t.iter().any(|b| !b.is_ascii_graphic()), std::cmp::Reverse(t.len())));
+    magics
+}
+
+impl GrammarGenerator {
+    fn generate_raw<S>(&mut self, state: &mut S) -> Vec<u8>
+    where
+        S: HasRand + HasMetadata
+    {
+        let tokens = state.metadata::<Tokens>().map(|t| t.tokens().to_vec()).unwrap_or_default();
+        if tokens.is_empty() {
+            return b"aaaaaaaa".to_vec();
+        }
+
+        let n = self.generated;
+        self.generated += 1;
+
+        let magics = magic_headers(&tokens);
+        if n < magics.len().min(MAX_MAGIC_SEEDS) {
+            return magics[n].clone();
+        }
+
+        // A magic header (if any) followed by random tokens.
+        let rand = state.rand_mut();
+        let mut raw = if magics.is_empty() {
+            vec![]
+        } else {
+            magics[(rand.next() % magics.len() as u64) as usize].clone()
+        };
+        for _ in 0..1 + rand.next() % MAX_CONCAT {
+            raw.extend_from_slice(&tokens[(rand.next() % tokens.len() as u64) as usize]);
+        }
+        raw
     }
 }
 
+impl GrammarGenerator {
+    /// Generates an input from a random corpus entry with relations, if one is found.
+    fn generate_structured<S>(&mut self, state: &mut S) -> Result<Option<Structured>, libafl::Error>
+    where
+        S: HasRand + HasCorpus + UsesInput<Input = StructuredInput>
+    {
+        if state.corpus().count() == 0 {
+            return Ok(None);
+        }
+
+        for _ in 0..TEMPLATE_TRIES {
+            let id = random_corpus_id!(state.corpus(), state.rand_mut());
+            let template = {
+                let mut testcase = state.corpus().get(id)?.borrow_mut();
+                testcase.load_input(state.corpus())?.input.clone()
+            };
+            if !template.relations.iter().any(|rel| rel.enabled) {
+                continue;
+            }
+
+            let rand = state.rand_mut();
+            return Ok(Some(template.generate(&mut |n| (rand.next() % n as u64) as usize)));
+        }
+        Ok(None)
+    }
+}
+
+impl<S> Generator<StructuredInput,S> for GrammarGenerator
+where
+    S: HasRand + HasMetadata + HasCorpus + UsesInput<Input = StructuredInput>
+{
+    fn generate(&mut self, state: &mut S) -> Result<StructuredInput, libafl::Error> {
+        match self.generate_structured(state)? {
+            Some(structured) => Ok(StructuredInput::new_structured(structured)),
+            None => Ok(StructuredInput::new_raw(&self.generate_raw(state))),
+        }
+    }
+}
+
+/// The same seeds as plain bytes, for the baseline fuzzer.
+impl<S> Generator<BytesInput,S> for GrammarGenerator
+where
+    S: HasRand + HasMetadata
+{
+    fn generate(&mut self, state: &mut S) -> Result<BytesInput, libafl::Error> {
+        Ok(BytesInput::new(self.generate_raw(state)))
+    }
+}
+
+/// Feeds inputs generated from the learned structures (see `GrammarGenerator`) to the fuzzer while the corpus is
+/// small, so that targets with few seeds get structurally valid variations of the formats found so far.
+pub struct StructureGenerationStage<S> {
+    /// Inputs are only generated while the corpus has fewer entries than this (0 disables the stage).
+    pub below_entries: usize,
+    generator: GrammarGenerator,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> StructureGenerationStage<S> {
+    pub fn new(below_entries: usize) -> Self {
+        Self {
+            below_entries,
+            generator: GrammarGenerator::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> Named for StructureGenerationStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StructureGenerationStage")
+    }
+}
+
+impl<S> UsesState for StructureGenerationStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for StructureGenerationStage<S>
+where
+    S: State + HasRand + HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: Evaluator<E,EM,State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        if state.corpus().count() >= self.below_entries {
+            return Ok(());
+        }
+
+        let mut interesting = 0;
+        for _ in 0..GENERATED_PER_STAGE {
+            let Some(structured) = self.generator.generate_structured(state)? else {
+                return Ok(());
+            };
+            let mut input = StructuredInput::new_structured(structured);
+            input.status = InputStatus::Mutated;
+
+            let (res, _) = fuzzer.evaluate_input(state, executor, manager, input)?;
+            if res != ExecuteInputResult::None {
+                interesting += 1;
+            }
+        }
 
-pub struct BytesGenerator;
+        if interesting > 0 {
+            stage_line(&format!("  [generated: {} interesting of {}]", interesting, GENERATED_PER_STAGE));
+        }
 
-impl<S> Generator<BytesInput,S> for BytesGenerator {
-    fn generate(&mut self, _state: &mut S) -> Result<BytesInput, libafl::Error> {
-        Ok(BytesInput::new(b"aaaaaaaa".to_vec()))
+        Ok(())
     }
 }
@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+
+use libafl::{prelude::{MutationResult, Mutator}, state::HasRand, Error};
+use libafl_bolts::{rands::Rand, Named};
+
+use super::structured_input::StructuredInput;
+
+
+/// Maximum number of bytes appended by a single mutation.
+const MAX_EXTENSION: u64 = 32;
+
+/// Appends data after a nested region and extends only the enclosing length fields.
+///
+/// The inner field keeps its value, so the extra bytes are only reachable by a parser that trusts the outer length
+/// over the inner one (e.g. reading past a checked region into trailing data). Generic mutations almost never keep
+/// all outer lengths consistent while doing so.
+#[derive(Default)]
+pub struct LengthExtensionMutator;
+
+impl LengthExtensionMutator {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for LengthExtensionMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("LengthExtensionMutator")
+    }
+}
+
+impl<S> Mutator<StructuredInput, S> for LengthExtensionMutator
+where
+    S: HasRand
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        if input.input.fixed_size {
+            return Ok(MutationResult::Skipped);
+        }
+
+        // Relations which are enclosed by another one.
+        let nested = (0..input.input.relations.len())
+            .filter(|i| input.input.relations[*i].enabled && input.input.parent_of(*i).is_some())
+            .collect::<Vec<_>>();
+
+        if nested.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let rand = state.rand_mut();
+        let idx = nested[(rand.next() % nested.len() as u64) as usize];
+        let len = 1 + rand.next() % MAX_EXTENSION;
+        let data = (0..len).map(|_| rand.next() as u8).collect::<Vec<_>>();
+
+        match input.input.extend_after(idx, &data) {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
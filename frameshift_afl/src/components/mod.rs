@@ -1,5 +1,25 @@
+pub mod aging_stage;
+pub mod crash_proximity;
+pub mod crossover;
+pub mod field_value;
+pub mod field_value_stage;
+pub mod file_set;
 pub mod gen;
+pub mod length_extension;
+pub mod monitor;
+pub mod overread;
+pub mod repro_bundle;
 pub mod structured_input;
 pub mod search_metadata;
+pub mod search_executor;
 pub mod search_stage;
+pub mod stage_weights;
+pub mod stats_stage;
+pub mod string_mutator;
+pub mod structure_energy;
+pub mod sync_stage;
+pub mod target_distance;
 pub mod wrapped_mutator;
+pub mod cull_stage;
+pub mod trim_stage;
+pub mod token_stage;
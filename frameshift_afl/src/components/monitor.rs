@@ -0,0 +1,114 @@
+use std::{fmt::{self, Debug, Formatter}, fs::{File, OpenOptions}, io::{self, Write}, path::Path, sync::Mutex, time::Duration};
+
+use libafl::monitors::{tui::TuiMonitor, ClientStats, Monitor, SimpleMonitor};
+use libafl_bolts::{current_time, format_duration_hms, ClientId};
+
+/// The log taking the status lines of the stages while the TUI owns the terminal.
+static STAGE_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Sends the status lines of the stages to `logfile` instead of stdout (with `--tui`).
+pub fn log_stage_lines(logfile: &Path) -> io::Result<()> {
+    let log = OpenOptions::new().append(true).create(true).open(logfile)?;
+    *STAGE_LOG.lock().unwrap() = Some(log);
+    Ok(())
+}
+
+/// Prints a status line of a stage (e.g. `[searched]`), or logs it with `--tui`.
+pub fn stage_line(line: &str) {
+    match STAGE_LOG.lock().unwrap().as_mut() {
+        Some(log) => {
+            let _ = writeln!(log, "{:?} {line}", current_time());
+        }
+        None => println!("{line}"),
+    }
+}
+
+/// The monitor of the fuzzer: flat status lines (the default), or libafl's terminal UI with `--tui`.
+///
+/// The search stage reports the structure of the corpus through user stats (`annotated`, `relations`,
+/// `search_overhead` and `last_search`), which the TUI lists in the panel of the fuzzing client. As the TUI owns the
+/// terminal, its status lines only go to `log`.
+pub enum FrameShiftMonitor<F>
+where
+    F: FnMut(&str),
+{
+    Simple(SimpleMonitor<F>),
+    Tui { tui: TuiMonitor, log: F },
+}
+
+impl<F> FrameShiftMonitor<F>
+where
+    F: FnMut(&str),
+{
+    /// Prints the status lines through `print_fn`, or shows the TUI (logging the status lines through `print_fn`).
+    pub fn new(tui: bool, print_fn: F) -> Self {
+        if tui {
+            Self::Tui {
+                tui: TuiMonitor::builder().title("FrameShift").enhanced_graphics(true).build(),
+                log: print_fn,
+            }
+        } else {
+            Self::Simple(SimpleMonitor::with_user_monitor(print_fn))
+        }
+    }
+}
+
+impl<F> Debug for FrameShiftMonitor<F>
+where
+    F: FnMut(&str),
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Simple(_) => f.write_str("FrameShiftMonitor::Simple"),
+            Self::Tui { .. } => f.write_str("FrameShiftMonitor::Tui"),
+        }
+    }
+}
+
+impl<F> Monitor for FrameShiftMonitor<F>
+where
+    F: FnMut(&str),
+{
+    fn client_stats_mut(&mut self) -> &mut Vec<ClientStats> {
+        match self {
+            Self::Simple(monitor) => monitor.client_stats_mut(),
+            Self::Tui { tui, .. } => tui.client_stats_mut(),
+        }
+    }
+
+    fn client_stats(&self) -> &[ClientStats] {
+        match self {
+            Self::Simple(monitor) => monitor.client_stats(),
+            Self::Tui { tui, .. } => tui.client_stats(),
+        }
+    }
+
+    fn start_time(&self) -> Duration {
+        match self {
+            Self::Simple(monitor) => monitor.start_time(),
+            Self::Tui { tui, .. } => tui.start_time(),
+        }
+    }
+
+    fn set_start_time(&mut self, time: Duration) {
+        match self {
+            Self::Simple(monitor) => monitor.set_start_time(time),
+            Self::Tui { tui, .. } => tui.set_start_time(time),
+        }
+    }
+
+    fn display(&mut self, event_msg: &str, sender_id: ClientId) {
+        if let Self::Simple(monitor) = self {
+            monitor.display(event_msg, sender_id);
+            return;
+        }
+
+        let line = format!("[{} #{}] run time: {}, corpus: {}, objectives: {}, executions: {}, exec/sec: {}",
+            event_msg, sender_id.0, format_duration_hms(&(current_time() - self.start_time())),
+            self.corpus_size(), self.objective_size(), self.total_execs(), self.execs_per_sec_pretty());
+        if let Self::Tui { tui, log } = self {
+            tui.display(event_msg, sender_id);
+            log(&line);
+        }
+    }
+}
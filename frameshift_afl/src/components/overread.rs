@@ -0,0 +1,206 @@
+use std::{borrow::Cow, cell::RefCell, ffi::{c_char, c_void}, sync::{atomic::{AtomicUsize, Ordering}, OnceLock}};
+
+use libafl::{corpus::Testcase, events::EventFirer, executors::ExitKind, feedbacks::Feedback, prelude::ObserversTuple, state::State, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, Named};
+use nix::libc::{dlsym, RTLD_DEFAULT};
+use serde::{Deserialize, Serialize};
+
+use crate::core::structured::Structured;
+
+use super::monitor::stage_line;
+
+
+/// Poisoned bytes placed after the input (or region), enough for the reads of a length a few bytes too large.
+const REDZONE: usize = 64;
+
+type PoisonFn = unsafe extern "C" fn(*const c_void, usize);
+type ReportCallbackFn = unsafe extern "C" fn(*const c_char);
+type SetReportCallbackFn = unsafe extern "C" fn(Option<ReportCallbackFn>);
+type ReportAddressFn = unsafe extern "C" fn() -> *const c_void;
+
+/// The ASAN interface, looked up at runtime since the target is not always built with ASAN.
+struct Asan {
+    poison: PoisonFn,
+    unpoison: PoisonFn,
+    report_address: ReportAddressFn,
+}
+
+static ASAN: OnceLock<Asan> = OnceLock::new();
+
+/// Redzone of the current execution, as [start, end) addresses.
+static REDZONE_START: AtomicUsize = AtomicUsize::new(0);
+static REDZONE_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Distance past the end of the input of the over-read reported by ASAN in the current execution (1 for the byte right
+/// after the input, 0 if none).
+static OVERREAD: AtomicUsize = AtomicUsize::new(0);
+
+/// Relation whose region is followed by the redzone in the current execution (see `guard_regions`), plus one (0 when
+/// the redzone follows the whole input), and the one of the reported over-read.
+static GUARDED_RELATION: AtomicUsize = AtomicUsize::new(0);
+static OVERREAD_RELATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Buffer holding the input and its redzone, reused across executions.
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+unsafe fn lookup<T>(name: &[u8]) -> Option<T> {
+    let sym = dlsym(RTLD_DEFAULT, name.as_ptr() as *const c_char);
+    (!sym.is_null()).then(|| std::mem::transmute_copy::<*mut c_void, T>(&sym))
+}
+
+/// Called by ASAN before it aborts on an error.
+unsafe extern "C" fn on_report(_report: *const c_char) {
+    let Some(asan) = ASAN.get() else {
+        return;
+    };
+    let addr = (asan.report_address)() as usize;
+    let (start, end) = (REDZONE_START.load(Ordering::Relaxed), REDZONE_END.load(Ordering::Relaxed));
+    if (start..end).contains(&addr) {
+        OVERREAD.store(addr - start + 1, Ordering::Relaxed);
+        OVERREAD_RELATION.store(GUARDED_RELATION.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// Enables the redzone after the inputs (see `guarded`). Returns false if the target is not built with ASAN.
+pub fn enable() -> bool {
+    if ASAN.get().is_some() {
+        return true;
+    }
+    unsafe {
+        let (Some(poison), Some(unpoison), Some(report_address), Some(set_callback)) = (
+            lookup::<PoisonFn>(b"__asan_poison_memory_region\0"),
+            lookup::<PoisonFn>(b"__asan_unpoison_memory_region\0"),
+            lookup::<ReportAddressFn>(b"__asan_get_report_address\0"),
+            lookup::<SetReportCallbackFn>(b"__asan_set_error_report_callback\0"),
+        ) else {
+            return false;
+        };
+
+        let _ = ASAN.set(Asan { poison, unpoison, report_address });
+        set_callback(Some(on_report));
+    }
+    true
+}
+
+/// Runs `f` on a copy of `data` followed by poisoned bytes (when enabled), so that reads past the end of the input
+/// are reported by ASAN even when they would stay within the allocation holding the input (e.g. the spare capacity
+/// of its vector). Otherwise, runs `f` on `data`.
+pub fn guarded<R>(data: &[u8], f: impl FnOnce(&[u8]) -> R) -> R {
+    let Some(asan) = ASAN.get() else {
+        return f(data);
+    };
+
+    BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        unsafe { (asan.unpoison)(buffer.as_ptr() as *const c_void, buffer.len()) };
+        buffer.clear();
+        buffer.extend_from_slice(data);
+        buffer.resize(data.len() + REDZONE, 0);
+
+        let redzone = buffer[data.len()..].as_ptr() as usize;
+        REDZONE_START.store(redzone, Ordering::Relaxed);
+        REDZONE_END.store(redzone + REDZONE, Ordering::Relaxed);
+        unsafe { (asan.poison)(redzone as *const c_void, REDZONE) };
+
+        f(&buffer[..data.len()])
+    })
+}
+
+/// Runs `f` on the input cut at the end of the region of each relation whose field claims more bytes than the region
+/// holds (see `Structured::overrun`), followed by poisoned bytes (when enabled). A target trusting the field reads past
+/// the region into them, which ASAN reports even though the read would otherwise stay within the input (taking the
+/// next fields for the data of the region) and not crash. The fields lied about by the mutations are often those of
+/// disabled relations, which are checked too. Returns whether `f` ran.
+pub fn guard_regions(input: &Structured, mut f: impl FnMut(&[u8])) -> bool {
+    if ASAN.get().is_none() {
+        return false;
+    }
+    let raw = input.get_raw();
+    let mut ran = false;
+    for (idx, rel) in input.relations.iter().enumerate() {
+        if rel.whole_file || rel.insert >= raw.len() || input.overrun(idx).is_none() {
+            continue;
+        }
+        GUARDED_RELATION.store(idx + 1, Ordering::Relaxed);
+        guarded(&raw[..rel.insert], &mut f);
+        ran = true;
+    }
+    GUARDED_RELATION.store(0, Ordering::Relaxed);
+    ran
+}
+
+/// How far past the end of an input, or of the region of one of its relations, the target read (1 for the byte right
+/// after it).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OverreadMetadata {
+    pub past_end: usize,
+    /// The relation whose region was read past (None for the end of the input).
+    #[serde(default)]
+    pub relation: Option<usize>,
+}
+
+impl_serdeany!(OverreadMetadata);
+
+/// Objective for the inputs which made the target read past their end or the region of a lying field (in the redzone
+/// placed by `guarded` or `guard_regions`): a length larger than the data it governs was trusted.
+///
+/// These are kept even when the crash deduplication would drop them, and whatever the exit kind: with ASAN built to
+/// recover (`-fsanitize-recover=address` and `halt_on_error=0`), the execution goes on after the report. How far past
+/// the end the read went is recorded in `OverreadMetadata`.
+#[derive(Default)]
+pub struct OverreadFeedback {
+    last: Option<OverreadMetadata>,
+}
+
+impl Named for OverreadFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("OverreadFeedback")
+    }
+}
+
+impl<S> Feedback<S> for OverreadFeedback
+where
+    S: State,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let past_end = OVERREAD.swap(0, Ordering::Relaxed);
+        let relation = OVERREAD_RELATION.swap(0, Ordering::Relaxed).checked_sub(1);
+        self.last = (past_end > 0).then_some(OverreadMetadata { past_end, relation });
+        match relation {
+            Some(idx) if past_end > 0 => stage_line(&format!("Over-read {} bytes past the region of relation #{}", past_end, idx)),
+            None if past_end > 0 => stage_line(&format!("Over-read {} bytes past the end of the input", past_end)),
+            _ => {}
+        }
+        Ok(self.last.is_some())
+    }
+
+    fn append_metadata<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+        EM: EventFirer<State = S>,
+    {
+        if let Some(metadata) = self.last.take() {
+            testcase.add_metadata(metadata);
+        }
+        Ok(())
+    }
+}
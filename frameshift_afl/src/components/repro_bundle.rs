@@ -0,0 +1,126 @@
+use std::{borrow::Cow, env, fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use libafl::{corpus::{Corpus, Testcase}, events::EventFirer, executors::ExitKind, feedbacks::Feedback, inputs::Input, prelude::ObserversTuple, state::{HasCorpus, State}, Error};
+use libafl_bolts::Named;
+
+use crate::split_args;
+
+use super::monitor::stage_line;
+
+const BUILD_HASH: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_ECMA_182);
+
+/// Quotes `arg` for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Writes a reproduction bundle next to every saved crash, so crashes found elsewhere (e.g. on a cluster) can be
+/// replayed locally without guessing the options.
+///
+/// Placed last in the objective (behind the crash and dedup feedbacks), it accepts every input it sees and writes
+/// `<crashes>/bundle-<name>/` with the crashing input (and its `.annotated` structure), the corpus entry it was mutated
+/// from (`parent`, to compare with `--diff parent crash`), the hash of the fuzzer binary (`build_hash`), the exact
+/// command line (`command`) and a `replay.sh` running the crash through `triage`.
+pub struct ReproBundleFeedback {
+    dir: PathBuf,
+    command: Vec<String>,
+    build_hash: Option<u64>,
+}
+
+impl ReproBundleFeedback {
+    pub fn new(crashes_dir: PathBuf) -> Self {
+        let build_hash = env::current_exe().ok()
+            .and_then(|exe| fs::read(exe).ok())
+            .map(|bin| BUILD_HASH.checksum(&bin));
+
+        Self {
+            dir: crashes_dir,
+            command: env::args().collect(),
+            build_hash,
+        }
+    }
+
+    fn write_bundle<I: Input>(&self, input: &I, parent: Option<I>) -> Result<(), Error> {
+        let bundle = self.dir.join(format!("bundle-{}", input.generate_name(0)));
+        fs::create_dir_all(&bundle)?;
+
+        input.to_file(bundle.join("crash"))?;
+        if let Some(parent) = parent {
+            parent.to_file(bundle.join("parent"))?;
+        }
+
+        let build_hash = self.build_hash.map_or("unknown".to_string(), |h| format!("{:016x}", h));
+        fs::write(bundle.join("build_hash"), format!("{}\n", build_hash))?;
+
+        let command = self.command.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+        fs::write(bundle.join("command"), format!("{}\n", command))?;
+
+        // The target arguments (after `--`) are needed to initialize the target the same way.
+        let exe = env::current_exe().map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|_| self.command[0].clone());
+        let (_, target_args) = split_args(&self.command);
+        let forwarded = match self.command.iter().any(|arg| arg == "--") {
+            true => format!(" -- {}", target_args[1..].iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ")),
+            false => String::new(),
+        };
+
+        let script = bundle.join("replay.sh");
+        fs::write(&script, format!(
+            "#!/bin/sh\n\
+            # Replays the crash of this bundle (FUZZER defaults to the binary which found it, build {}).\n\
+            cd \"$(dirname \"$0\")\"\n\
+            exec \"${{FUZZER:-{}}}\" triage crash{}\n",
+            build_hash, exe, forwarded))?;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+
+        Ok(())
+    }
+}
+
+impl Named for ReproBundleFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("ReproBundleFeedback")
+    }
+}
+
+impl<S> Feedback<S> for ReproBundleFeedback
+where
+    S: State + HasCorpus,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(true)
+    }
+
+    fn append_metadata<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+        EM: EventFirer<State = S>,
+    {
+        if let Some(input) = testcase.input() {
+            // Crashes found while mutating an entry have it as their parent (not those of the seeds and generators).
+            let parent = testcase.parent_id().and_then(|id| state.corpus().cloned_input_for_id(id).ok());
+
+            // A failed bundle must not stop the fuzzer.
+            if let Err(err) = self.write_bundle(input, parent) {
+                stage_line(&format!("Could not write the reproduction bundle: {}", err));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use libafl::{executors::{Executor, ExitKind, HasObservers}, observers::UsesObservers, state::UsesState, Error};
+use libafl_bolts::tuples::RefIndexable;
+
+
+/// Executor of the search probes: either in-process, or forking before each probe (`--fork-mode`, or a timeout budget
+/// with `--search-max-timeouts`) so that a crashing or hanging probe only kills the child instead of the fuzzer (and
+/// the search in progress).
+pub enum SearchExecutor<A,B> {
+    InProcess(A),
+    Fork(B),
+}
+
+impl<A,B> UsesState for SearchExecutor<A,B>
+where
+    A: UsesState,
+    B: UsesState<State = A::State>,
+{
+    type State = A::State;
+}
+
+impl<A,B> UsesObservers for SearchExecutor<A,B>
+where
+    A: UsesObservers,
+    B: UsesObservers<State = A::State, Observers = A::Observers>,
+{
+    type Observers = A::Observers;
+}
+
+impl<A,B> HasObservers for SearchExecutor<A,B>
+where
+    A: HasObservers,
+    B: HasObservers<State = A::State, Observers = A::Observers>,
+{
+    fn observers(&self) -> RefIndexable<&Self::Observers, Self::Observers> {
+        match self {
+            SearchExecutor::InProcess(executor) => executor.observers(),
+            SearchExecutor::Fork(executor) => executor.observers(),
+        }
+    }
+
+    fn observers_mut(&mut self) -> RefIndexable<&mut Self::Observers, Self::Observers> {
+        match self {
+            SearchExecutor::InProcess(executor) => executor.observers_mut(),
+            SearchExecutor::Fork(executor) => executor.observers_mut(),
+        }
+    }
+}
+
+impl<A,B,EM,Z> Executor<EM,Z> for SearchExecutor<A,B>
+where
+    A: Executor<EM,Z>,
+    B: Executor<EM,Z> + UsesState<State = A::State>,
+    EM: UsesState<State = A::State>,
+    Z: UsesState<State = A::State>,
+{
+    fn run_target(&mut self, fuzzer: &mut Z, state: &mut Self::State, mgr: &mut EM, input: &Self::Input) -> Result<ExitKind, Error> {
+        match self {
+            SearchExecutor::InProcess(executor) => executor.run_target(fuzzer, state, mgr, input),
+            SearchExecutor::Fork(executor) => executor.run_target(fuzzer, state, mgr, input),
+        }
+    }
+}
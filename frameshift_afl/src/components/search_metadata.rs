@@ -1,6 +1,10 @@
+use std::{collections::BTreeMap, time::Duration};
+
 use libafl_bolts::impl_serdeany;
 use serde::{Deserialize, Serialize};
 
+use crate::core::{filler::FillerBandit, search::HeuristicStats, structured::Heuristic, value_model::FieldValueModel};
+
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SearchMetadata {
@@ -10,6 +14,10 @@ pub struct SearchMetadata {
     pub search_tests: usize,
     pub target_time_ms: u64,
     pub total_time_ms: u64,
+
+    /// Probe budget and relations of each search heuristic, across all searches.
+    #[serde(default)]
+    pub heuristics: BTreeMap<Heuristic, HeuristicStats>,
 }
 
 impl SearchMetadata {
@@ -20,8 +28,44 @@ impl SearchMetadata {
             search_tests: 0,
             target_time_ms: 0,
             total_time_ms: 0,
+            heuristics: BTreeMap::new(),
         }
     }
 }
 
 impl_serdeany!(SearchMetadata);
+
+/// Filler statistics shared by all searches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FillerMetadata {
+    pub bandit: FillerBandit,
+}
+
+impl_serdeany!(FillerMetadata);
+
+/// Values of the fields found by the searches, across the corpus.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FieldValueMetadata {
+    pub model: FieldValueModel,
+}
+
+impl_serdeany!(FieldValueMetadata);
+
+/// Progress of the warm-up before the first searches (see `Warmup`), kept in the state so that it survives restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WarmupMetadata {
+    pub done: bool,
+    /// Corpus size at its last growth, and the time of that growth since the start of the campaign.
+    pub corpus_count: usize,
+    pub last_growth: Duration,
+}
+
+impl_serdeany!(WarmupMetadata);
+
+/// Number of new corpus entries found while fuzzing a corpus entry.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProductivityMetadata {
+    pub finds: usize,
+}
+
+impl_serdeany!(ProductivityMetadata);
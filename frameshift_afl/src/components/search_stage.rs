@@ -1,39 +1,250 @@
-use std::{borrow::Cow, collections::HashSet, marker::PhantomData};
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, marker::PhantomData, time::{Duration, Instant}};
 
-use libafl::{corpus::Corpus, events::{Event, EventFirer}, inputs::UsesInput, prelude::{AggregatorOps, Executor, HasObservers, MapObserver, ObserversTuple, UserStats, UserStatsValue}, stages::Stage, state::{HasCorpus, State, UsesState}, Error, HasMetadata};
-use libafl_bolts::{prelude::OwnedSlice, tuples::{Handle, Handled}, AsIter, AsSlice, ErrorBacktrace, Named};
+use libafl::{corpus::{Corpus, CorpusId}, events::{Event, EventFirer}, executors::ExitKind, feedbacks::map::{MapIndexesMetadata, MapNoveltiesMetadata}, inputs::UsesInput, observers::{CmpValues, CmpValuesMetadata}, prelude::{AggregatorOps, Executor, HasObservers, MapObserver, ObserversTuple, UserStats, UserStatsValue}, stages::Stage, state::{HasCorpus, HasStartTime, State, UsesState}, Error, HasMetadata};
+use libafl_bolts::{current_time, prelude::OwnedSlice, tuples::{Handle, Handled}, AsIter, AsSlice, ErrorBacktrace, Named};
 
-use crate::core::search::{SearchContext, SearchOptions};
+use crate::core::search::{merge_heuristic_stats, CoverageSnapshot, RawDigest, SearchCheckpoint, SearchContext, SearchGuidance, SearchOptions, SearchProgress, SearchStatus, SearchStop};
 
-use super::{search_metadata::SearchMetadata, structured_input::{InputStatus, StructuredInput}};
+use super::{crash_proximity::{crash_distance, edge_pcs, CrashEdgesMetadata}, monitor::stage_line, search_metadata::{FieldValueMetadata, FillerMetadata, ProductivityMetadata, SearchMetadata, WarmupMetadata}, stats_stage::RelationCount, structured_input::{InputStatus, StructuredInput}};
 
 
+/// Bytes around the changed window of a mutated input which are re-scanned along with it.
+const CHANGE_NEIGHBORHOOD: usize = 16;
+
+/// When prioritizing inputs near crashes, other inputs are only searched once every this many times they come up.
+const CRASH_DEFER_RATIO: usize = 4;
+
+/// Probes granted to a search with a per-byte budget, however small the input.
+const MIN_SEARCH_PROBES: usize = 256;
+
+/// After a warm-up, unproductive inputs are only searched once every this many times they come up while productive
+/// inputs wait for their search.
+const PRODUCTIVITY_DEFER_RATIO: usize = 4;
+
+/// Limits on the fuzzing budget spent searching.
+#[derive(Clone, Debug, Default)]
+pub struct SearchBudget {
+    /// Maximum fraction of the wall time spent searching: while it is exceeded, searches are deferred until their
+    /// entry comes up again.
+    pub max_time_fraction: Option<f64>,
+
+    /// Maximum probes of a search per byte of the input (at least `MIN_SEARCH_PROBES`). Longer searches are truncated
+    /// and resumed where they stopped when their entry comes up again.
+    pub max_probes_per_byte: Option<usize>,
+}
+
+/// Delays the first searches of a campaign, while plain mutations still find new coverage cheaply. Afterwards, the
+/// inputs which were productive during the warm-up are searched first.
+#[derive(Clone, Debug, Default)]
+pub struct Warmup {
+    /// Do not search during this time since the start of the campaign.
+    pub min_time: Option<Duration>,
+
+    /// Do not search until no new corpus entry was found for this long.
+    pub plateau: Option<Duration>,
+}
+
+impl Warmup {
+    pub fn is_enabled(&self) -> bool {
+        self.min_time.is_some() || self.plateau.is_some()
+    }
+}
+
+/// Probe timeouts tolerated by a search (0 for no limit): once they are used up, the search is stopped (see
+/// `SearchGuidance::stop`) and finishes with the relations found so far.
+#[derive(Clone, Debug)]
+struct TimeoutBudget {
+    max: usize,
+    timeouts: usize,
+    stop: SearchStop,
+}
+
+impl TimeoutBudget {
+    fn new(max: usize, stop: SearchStop) -> Self {
+        Self { max, timeouts: 0, stop }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.max > 0 && self.timeouts >= self.max
+    }
+
+    /// Runs `probe` (which returns `None` if the probe timed out) unless the budget is exhausted. A timed out probe
+    /// reaches no coverage.
+    fn probe<F: FnOnce() -> Option<CoverageSnapshot>>(&mut self, probe: F) -> CoverageSnapshot {
+        if !self.exhausted() {
+            if let Some(cov) = probe() {
+                return cov;
+            }
+            self.timeouts += 1;
+            self.stop.set(self.exhausted());
+        }
+        CoverageSnapshot::default()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SearchStageArgs {
-    pub options: SearchOptions
+    pub options: SearchOptions,
+
+    /// If set, prioritize inputs whose new coverage is within this many bytes of code of a crashing location.
+    pub crash_distance: Option<usize>,
+
+    /// Probe timeouts tolerated per search (0 for no limit); afterwards the search finishes with the relations found
+    /// so far.
+    pub max_timeouts: usize,
+
+    /// Run each probe in a forked child, so that crashing probes do not kill the fuzzer. The in-process executor exits
+    /// on a timeout instead of reporting it, so the probes must fork for `max_timeouts` to take effect.
+    pub fork: bool,
+
+    pub budget: SearchBudget,
+
+    pub warmup: Warmup,
 }
 
-pub struct SearchStage<S,C,O> {
+/// Searches the current testcase for relations, running the probes on its own executor (so that probes can use a
+/// longer timeout than the mutational stages).
+pub struct SearchStage<S,C,O,SE> {
     pub map_handle: Handle<C>,
+    /// Value profile observed by the search executor, if the search measures it (see
+    /// `SearchOptions::use_value_profile`).
+    pub value_profile_handle: Option<Handle<C>>,
     pub args: SearchStageArgs,
+    /// Executor used for the search probes, observing the same coverage map through `map_handle`.
+    executor: SE,
+    /// PC of each edge (only loaded when prioritizing inputs near crashes).
+    edge_pcs: Vec<usize>,
+    /// Number of searches deferred because the input was not near a crash, or was unproductive after a warm-up.
+    deferred: usize,
+    /// Start of the fuzzing and time spent searching since then (for `SearchBudget::max_time_fraction`).
+    started: Instant,
+    search_time: Duration,
+    /// Previously selected entry and the corpus size at the time, to credit it with the entries found since.
+    last: Option<(CorpusId, usize)>,
     _phantom: PhantomData<(S,O)>,
 }
 
-impl<S,C,O> SearchStage<S,C,O>
+impl<S,C,O,SE> SearchStage<S,C,O,SE>
 where
     S: State + UsesInput<Input = StructuredInput>,
     O: MapObserver + for<'it> AsIter<'it, Item = u8> + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
     C: Named + AsMut<O> + AsRef<O>
 {
-    pub fn new(observer: &C, args: SearchStageArgs) -> Self {
+    pub fn new(observer: &C, executor: SE, args: SearchStageArgs) -> Self {
+        let edge_pcs = match args.crash_distance {
+            Some(_) => edge_pcs(),
+            None => vec![],
+        };
+
         Self {
             map_handle: observer.handle(),
+            value_profile_handle: None,
             args,
+            executor,
+            edge_pcs,
+            deferred: 0,
+            started: Instant::now(),
+            search_time: Duration::ZERO,
+            last: None,
             _phantom: PhantomData,
         }
     }
 
-    pub fn get_coverage_slice<'a, E,EM,Z,OT>(&self, fuzzer: &mut Z, executor: &mut E, state: &mut S, mgr: &mut EM, input: &[u8]) -> &'a [u8]
+    /// Also measures the value profile observed by `observer` in the search probes.
+    pub fn with_value_profile(mut self, observer: &C) -> Self {
+        self.value_profile_handle = Some(observer.handle());
+        self
+    }
+
+    /// Checks whether the new coverage of a corpus entry is within `max_distance` of a crashing location.
+    ///
+    /// Returns true if no crash has been observed yet.
+    fn near_crash(&self, state: &S, corpus_idx: CorpusId, max_distance: usize) -> Result<bool, Error>
+    where
+        S: HasCorpus + HasMetadata
+    {
+        let crash_edges = match state.metadata::<CrashEdgesMetadata>() {
+            Ok(meta) if !meta.edges.is_empty() => &meta.edges,
+            _ => return Ok(true),
+        };
+
+        let testcase = state.corpus().get(corpus_idx)?.borrow();
+
+        // Prefer the edges first discovered by this entry, otherwise use all of its edges.
+        let edges = match testcase.metadata::<MapNoveltiesMetadata>() {
+            Ok(meta) => meta.list.clone(),
+            Err(_) => testcase.metadata::<MapIndexesMetadata>().map(|meta| meta.list.clone()).unwrap_or_default(),
+        };
+
+        Ok(crash_distance(&self.edge_pcs, crash_edges, &edges).is_some_and(|d| d <= max_distance))
+    }
+
+    /// Credits the previously selected entry with the corpus entries found since it was selected.
+    fn credit(&mut self, state: &S, corpus_idx: CorpusId) -> Result<(), Error>
+    where
+        S: HasCorpus
+    {
+        let count = state.corpus().count();
+        if let Some((last, last_count)) = self.last.replace((corpus_idx, count)) {
+            let finds = count.saturating_sub(last_count);
+            if let (true, Ok(cell)) = (finds > 0, state.corpus().get(last)) {
+                let mut entry = cell.borrow_mut();
+                if !entry.has_metadata::<ProductivityMetadata>() {
+                    entry.add_metadata(ProductivityMetadata::default());
+                }
+                entry.metadata_mut::<ProductivityMetadata>()?.finds += finds;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the warm-up is over (for good, once it is).
+    fn warmed_up(&self, state: &mut S) -> bool
+    where
+        S: HasCorpus + HasMetadata + HasStartTime
+    {
+        let elapsed = current_time().saturating_sub(*state.start_time());
+        let count = state.corpus().count();
+        if !state.has_metadata::<WarmupMetadata>() {
+            state.add_metadata(WarmupMetadata::default());
+        }
+
+        let meta = state.metadata_mut::<WarmupMetadata>().unwrap();
+        if meta.done {
+            return true;
+        }
+        if count != meta.corpus_count {
+            meta.corpus_count = count;
+            meta.last_growth = elapsed;
+        }
+
+        let warmup = &self.args.warmup;
+        meta.done = warmup.min_time.map_or(true, |t| elapsed >= t)
+            && warmup.plateau.map_or(true, |p| elapsed.saturating_sub(meta.last_growth) >= p);
+        if meta.done && warmup.is_enabled() {
+            stage_line(&format!("[warm-up over after {}s, searching]", elapsed.as_secs()));
+        }
+        meta.done
+    }
+
+    /// Checks whether an entry which was productive while fuzzing is waiting for its search.
+    fn productive_pending(&self, state: &S) -> Result<bool, Error>
+    where
+        S: HasCorpus
+    {
+        for id in state.corpus().ids() {
+            let entry = state.corpus().get(id)?.borrow();
+            let productive = entry.metadata::<ProductivityMetadata>().is_ok_and(|meta| meta.finds > 0);
+            if productive && entry.input().as_ref().is_some_and(|input| awaits_search(input, id)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Runs a probe and returns its coverage, along with its value profile if observed, or `None` if the probe timed
+    /// out.
+    pub fn get_coverage<E,EM,Z,OT>(map_handle: &Handle<C>, value_profile_handle: Option<&Handle<C>>, fuzzer: &mut Z, executor: &mut E, state: &mut S, mgr: &mut EM, input: &StructuredInput) -> Option<CoverageSnapshot>
     where
         E: Executor<EM,Z,State = S> + HasObservers<Observers = OT>,
         Z: UsesState<State = E::State>,
@@ -42,38 +253,80 @@ where
     {
         {
             let mut ot = executor.observers_mut();
-            let obs = ot[&self.map_handle].as_mut();
-            obs.reset_map().unwrap();
+            ot[map_handle].as_mut().reset_map().unwrap();
+            if let Some(handle) = value_profile_handle {
+                ot[handle].as_mut().reset_map().unwrap();
+            }
         }
-        let _exit_kind = executor.run_target(fuzzer, state, mgr, &StructuredInput::new_raw(input));
+        let exit_kind = executor.run_target(fuzzer, state, mgr, input);
+        if matches!(exit_kind, Ok(ExitKind::Timeout) | Err(_)) {
+            return None;
+        }
+
         let ot = executor.observers();
-        let obs = ot[&self.map_handle].as_ref();
+        let cov = CoverageSnapshot::from_map(ot[map_handle].as_ref().as_slice());
+        Some(match value_profile_handle {
+            Some(handle) => cov.with_value_profile(ot[handle].as_ref().as_slice()),
+            None => cov,
+        })
+    }
+}
 
-        // Convert to static lifetime - this is unsafe but needed for the oracle
-        let slice = obs.as_slice();
-        unsafe { std::mem::transmute::<&[u8], &'a [u8]>(slice) }
+/// Whether a corpus entry was never searched, or was mutated since its last search.
+fn awaits_search(input: &StructuredInput, id: CorpusId) -> bool {
+    match input.status {
+        InputStatus::New | InputStatus::Mutated => true,
+        InputStatus::Searched(searched) => searched != id,
+        InputStatus::Frozen(frozen) => frozen.is_some_and(|frozen| frozen != id),
+        InputStatus::InProgress => false,
     }
 }
 
-impl<S,C,O> Named for SearchStage<S,C,O> {
+/// Collects the numeric comparison operands which could be a length in an input of size `max_len`.
+fn cmp_operands<S: HasMetadata>(state: &S, max_len: usize) -> HashSet<u64> {
+    let mut values = HashSet::new();
+
+    if let Ok(meta) = state.metadata::<CmpValuesMetadata>() {
+        for cmp in meta.list.iter() {
+            let (a, b) = match cmp {
+                CmpValues::U8((a, b, ..)) => (*a as u64, *b as u64),
+                CmpValues::U16((a, b, ..)) => (*a as u64, *b as u64),
+                CmpValues::U32((a, b, ..)) => (*a as u64, *b as u64),
+                CmpValues::U64((a, b, ..)) => (*a, *b),
+                _ => continue,
+            };
+
+            for v in [a, b] {
+                if v != 0 && v <= max_len as u64 {
+                    values.insert(v);
+                }
+            }
+        }
+    }
+
+    values
+}
+
+impl<S,C,O,SE> Named for SearchStage<S,C,O,SE> {
     fn name(&self) -> &Cow<'static, str> {
         &Cow::Borrowed("SearchStage")
     }
 }
 
-impl<S,C,O> UsesState for SearchStage<S,C,O>
+impl<S,C,O,SE> UsesState for SearchStage<S,C,O,SE>
 where
     S: State
 {
     type State = S;
 }
 
-impl<S,C,O,E,EM,Z> Stage<E,EM,Z> for SearchStage<S,C,O> 
+impl<S,C,O,SE,E,EM,Z> Stage<E,EM,Z> for SearchStage<S,C,O,SE> 
 where
-    S: State + HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+    S: State + HasCorpus + HasMetadata + HasStartTime + UsesInput<Input = StructuredInput>,
     C: Named + AsMut<O> + AsRef<O>,
     O: MapObserver + for<'it> AsIter<'it, Item = u8> + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
-    E: Executor<EM,Z> + UsesState<State = S> + HasObservers,
+    SE: Executor<EM,Z> + UsesState<State = S> + HasObservers,
+    E: UsesState<State = S>,
     Z: UsesState<State = S>,
     EM: UsesState<State = S> + EventFirer
 {
@@ -88,66 +341,234 @@ where
     fn perform(
         &mut self,
         fuzzer: &mut Z,
-        executor: &mut E,
+        _executor: &mut E,
         state: &mut Self::State,
         manager: &mut EM,
     ) -> Result<(), libafl::Error> {
         let corpus_idx = state.corpus().current().ok_or(Error::Empty("missing current".to_string(), ErrorBacktrace {}))?;
 
-        // Fetch the testcase
-        let input = state.corpus().get(corpus_idx).unwrap().borrow();
-        let inner = input.input().as_ref().unwrap();
+        // A loaded frozen entry is bound to its corpus id the first time it comes up, before the later stages mutate
+        // it, so that only the copies are searched.
+        if state.corpus().get(corpus_idx)?.borrow().input().as_ref().is_some_and(|inner| inner.status == InputStatus::Frozen(None)) {
+            let mut other = state.corpus().get(corpus_idx).unwrap().borrow().clone();
+            other.input_mut().as_mut().unwrap().status = InputStatus::Frozen(Some(corpus_idx));
+            state.corpus_mut().replace(corpus_idx, other)?;
+        }
 
-        let will_search = match inner.status {
+        self.credit(state, corpus_idx)?;
+        if !self.warmed_up(state) {
+            return Ok(());
+        }
+
+        // Fetch the testcase status
+        let (status, search_progress) = {
+            let entry = state.corpus().get(corpus_idx).unwrap().borrow();
+            let inner = entry.input().as_ref().unwrap();
+            (inner.status.clone(), inner.search_progress.clone())
+        };
+
+        let will_search = match status {
             // If the input is marked as searched, we only need to search if it has been mutated since the last search.
             InputStatus::Searched(id) => id != corpus_idx,
 
             // If the input is new or mutated, we always search it.
             InputStatus::New | InputStatus::Mutated => true,
 
-            // If the input is in progress, it crashed during the last search, so we skip it.
-            InputStatus::InProgress => false,
+            // If the input is in progress, the last search was killed (e.g. by a hanging probe). With a recorded
+            // progress, resume past the candidate which killed it.
+            InputStatus::InProgress if search_progress.is_some() => {
+                stage_line(&format!("  ({}) [search killed, resuming]", corpus_idx));
+                true
+            }
+
+            // Otherwise it was killed outside of the candidates: keep the structure it had so far instead of
+            // searching it again.
+            InputStatus::InProgress => {
+                let mut other = state.corpus().get(corpus_idx).unwrap().borrow().clone();
+                other.input_mut().as_mut().unwrap().status = InputStatus::Searched(corpus_idx);
+                state.corpus_mut().replace(corpus_idx, other)?;
+
+                stage_line(&format!("  ({}) [search aborted]", corpus_idx));
+                return Ok(());
+            }
+
+            // Frozen structures were verified manually: the entry they were loaded into is never searched, but its
+            // mutated copies are.
+            InputStatus::Frozen(frozen) => frozen.is_some_and(|id| id != corpus_idx),
         };
 
         if !will_search {
             return Ok(());
         }
 
-        drop(input);
+        // Defer inputs far from previous crashes so that the search concentrates around them.
+        if let Some(max_distance) = self.args.crash_distance {
+            if !self.near_crash(state, corpus_idx, max_distance)? {
+                self.deferred += 1;
+                if self.deferred % CRASH_DEFER_RATIO != 0 {
+                    return Ok(());
+                }
+            }
+        }
+
+        // After a warm-up, search the entries which were productive while fuzzing first.
+        if self.args.warmup.is_enabled() {
+            let productive = state.corpus().get(corpus_idx)?.borrow().metadata::<ProductivityMetadata>().is_ok_and(|meta| meta.finds > 0);
+            if !productive && self.productive_pending(state)? {
+                self.deferred += 1;
+                if self.deferred % PRODUCTIVITY_DEFER_RATIO != 0 {
+                    return Ok(());
+                }
+            }
+        }
 
-        // Otherwise, we need to search this input. Mark as in progress and perform the search.
+        // Defer the search while too much of the time went to searching.
+        if let Some(fraction) = self.args.budget.max_time_fraction {
+            if self.search_time.as_secs_f64() > fraction * self.started.elapsed().as_secs_f64() {
+                return Ok(());
+            }
+        }
+
+        // Otherwise, we need to search this input. Mark as in progress and perform the search. The progress is
+        // cleared until the first candidate is reached, so that a search killed again before it is not resumed.
         let mut input = state.corpus().get(corpus_idx).unwrap().borrow().clone();
         input.input_mut().as_mut().unwrap().status = InputStatus::InProgress;
+        input.input_mut().as_mut().unwrap().search_progress = None;
+        // A mutated copy of a frozen entry is searched and stored like any other.
+        input.input_mut().as_mut().unwrap().input.frozen = false;
+
+        let entry = input.input().as_ref().unwrap().clone();
+        let testcase = entry.input.clone();
+
+        // Inputs mutated from a searched input only need the changed bytes to be re-scanned. The neighborhoods of the
+        // existing relations are not re-scanned: with `prune_threshold`, the search re-validates every relation.
+        let positions = input.input().as_ref().unwrap().searched_digest.as_ref()
+            .map(|digest| digest.changed_window(testcase.get_raw(), CHANGE_NEIGHBORHOOD));
 
-        let testcase = input.input().as_ref().unwrap().input.clone();
         state.corpus_mut().replace(corpus_idx, input)?;
 
-        // Set up the oracle
-        let mut oracle = |input: &[u8]| {
-            self.get_coverage_slice(fuzzer, executor, state, manager, input)
+        // Comparison operands collected by the tracing stage for this testcase.
+        let guidance = SearchGuidance {
+            cmp_values: cmp_operands(state, testcase.get_raw().len()),
+            // The filler which worked best so far on inputs of the same format.
+            filler: state.metadata::<FillerMetadata>().map(|meta| meta.bandit.choose(testcase.get_raw())).unwrap_or_default(),
+            positions,
+            resume: search_progress,
+            checkpoint: Some(SearchCheckpoint::default()),
+            stop: (self.args.max_timeouts > 0).then(SearchStop::default),
+        };
+
+        // Set up the oracle: timed out probes count as no coverage, and once the budget is exhausted, the search stops.
+        let (map_handle, value_profile_handle, executor) = (&self.map_handle, self.value_profile_handle.as_ref(), &mut self.executor);
+        let mut budget = TimeoutBudget::new(self.args.max_timeouts, guidance.stop.clone().unwrap_or_default());
+        let checkpoint = guidance.checkpoint.clone().unwrap();
+        let mut synced: Option<SearchProgress> = None;
+
+        // The probes and the status reports of the search both need the state and the manager.
+        let shared = RefCell::new((&mut *state, &mut *manager));
+        let mut oracle = |input: &[u8]| -> CoverageSnapshot {
+            let mut shared = shared.borrow_mut();
+            let (state, manager) = &mut *shared;
+
+            // Before probing a new candidate, store the progress in the entry (in memory, the restarting manager
+            // saves it along with the state), so that a probe killing the fuzzer resumes the search after it.
+            if let Some((progress, relations)) = checkpoint.borrow().as_ref() {
+                if synced.as_ref() != Some(progress) {
+                    if let Some(inner) = state.corpus().get(corpus_idx).unwrap().borrow_mut().input_mut().as_mut() {
+                        inner.input.relations = relations.clone();
+                        inner.search_progress = Some(progress.clone());
+                    }
+                    synced = Some(progress.clone());
+                }
+            }
+
+            budget.probe(|| Self::get_coverage(map_handle, value_profile_handle, fuzzer, executor, &mut **state, &mut **manager, &entry.probe(0, input)))
+        };
+
+        // Long searches show their progress in the monitor.
+        let len = testcase.get_raw().len();
+        let mut on_status = |status: &SearchStatus| {
+            let mut shared = shared.borrow_mut();
+            let (state, manager) = &mut *shared;
+            let progress = format!("#{}: pass {}, {:.0}% of {} bytes, {} relations, {} probes",
+                corpus_idx, status.iteration, status.scanned() * 100.0, len, status.relations, status.probes);
+
+            // The status is informative only, a failure to send it must not abort the search.
+            let _ = manager.fire(&mut **state, Event::UpdateUserStats {
+                name: Cow::Borrowed("search_progress"),
+                value: UserStats::new(UserStatsValue::String(Cow::Owned(progress)), AggregatorOps::None),
+                phantom: PhantomData,
+            });
         };
 
-        let res = SearchContext::search(&testcase, &mut oracle, self.args.options.clone());
+        let mut options = self.args.options.clone();
+        if let Some(per_byte) = self.args.budget.max_probes_per_byte {
+            options.max_tests = Some((per_byte * testcase.get_raw().len()).max(MIN_SEARCH_PROBES));
+        }
+
+        let search_start = Instant::now();
+        let res = SearchContext::search_reporting(&testcase, &mut oracle, options, &guidance, Some(&mut on_status));
+
+        // The other files of a multi-file input are then searched in turn (in full, next to the other files).
+        let mut parts = vec![];
+        if !res.truncated && !budget.exhausted() {
+            for k in 1..entry.part_count() {
+                let mut oracle = |input: &[u8]| -> CoverageSnapshot {
+                    budget.probe(|| Self::get_coverage(map_handle, value_profile_handle, fuzzer, executor, state, manager, &entry.probe(k, input)))
+                };
+                parts.push(SearchContext::search(&entry.parts[k - 1].input, &mut oracle, self.args.options.clone()).input);
+            }
+        }
+        self.search_time += search_start.elapsed();
+
+        if budget.timeouts > 0 {
+            stage_line(&format!("  ({}) [{} probe timeouts]", corpus_idx, budget.timeouts));
+        }
 
         if self.args.options.verbose {
-            println!("{:?}", res.input);
+            stage_line(&format!("{:?}", res.input));
+        }
+
+        // A truncated search is resumed where it stopped the next time the entry comes up (over the same window,
+        // as `searched_digest` is kept).
+        let resume = res.truncated;
+        if resume {
+            stage_line(&format!("  ({}) [search truncated, will resume]", corpus_idx));
         }
 
         // Update the testcase with the new grammar
         {
             let mut other = state.corpus().get(corpus_idx).unwrap().borrow().clone();
             other.input_mut().as_mut().unwrap().input = res.input.clone();
-            other.input_mut().as_mut().unwrap().status = InputStatus::Searched(corpus_idx);
+            other.input_mut().as_mut().unwrap().status = if resume { InputStatus::New } else { InputStatus::Searched(corpus_idx) };
+            if !resume {
+                other.input_mut().as_mut().unwrap().searched_digest = Some(RawDigest::new(res.input.get_raw()));
+            }
+            other.input_mut().as_mut().unwrap().search_progress = res.progress.clone();
+            for (part, searched) in other.input_mut().as_mut().unwrap().parts.iter_mut().zip(parts) {
+                part.input = searched;
+            }
             
             state.corpus_mut().replace(corpus_idx, other)?;
 
-            println!("  ({}) [searched]", corpus_idx);
+            stage_line(&format!("  ({}) [searched]", corpus_idx));
         }
 
         // Ensure we have the state metadata
         if !state.has_metadata::<SearchMetadata>() {
             state.add_metadata(SearchMetadata::new());
         }
+        if !state.has_metadata::<FillerMetadata>() {
+            state.add_metadata(FillerMetadata::default());
+        }
+
+        state.metadata_mut::<FillerMetadata>().unwrap().bandit.record(
+            testcase.get_raw(), guidance.filler, res.filler_trials as u64, res.filler_successes as u64);
+
+        if !state.has_metadata::<FieldValueMetadata>() {
+            state.add_metadata(FieldValueMetadata::default());
+        }
+        state.metadata_mut::<FieldValueMetadata>().unwrap().model.observe(&res.input);
 
         // Update metadata
         let (num_searched, num_found, search_tests, target_time_ms, total_time_ms) = {
@@ -157,6 +578,7 @@ where
             metadata.search_tests += res.test_count;
             metadata.target_time_ms += res.target_test_ms;
             metadata.total_time_ms += res.total_test_ms;
+            merge_heuristic_stats(&mut metadata.heuristics, &res.heuristics);
             (metadata.num_searched, metadata.num_found, metadata.search_tests, metadata.target_time_ms, metadata.total_time_ms)
         };
 
@@ -191,6 +613,108 @@ where
             phantom: PhantomData,
         })?;
 
+        // Structure of the corpus, shown in the panel of the client by the TUI.
+        let mut relation_counts = vec![];
+        for id in state.corpus().ids() {
+            let entry = state.corpus().get(id)?.borrow();
+            relation_counts.push(entry.input().as_ref().map_or(0, |inner| inner.relation_count()));
+        }
+
+        manager.fire(state, Event::UpdateUserStats {
+            name: Cow::Borrowed("annotated"),
+            value: UserStats::new(UserStatsValue::Ratio(relation_counts.iter().filter(|n| **n > 0).count() as u64, relation_counts.len() as u64), AggregatorOps::None),
+            phantom: PhantomData,
+        })?;
+
+        manager.fire(state, Event::UpdateUserStats {
+            name: Cow::Borrowed("relations"),
+            value: UserStats::new(UserStatsValue::String(Cow::Owned(relation_histogram(&relation_counts))), AggregatorOps::None),
+            phantom: PhantomData,
+        })?;
+
+        let elapsed = current_time().saturating_sub(*state.start_time());
+        manager.fire(state, Event::UpdateUserStats {
+            name: Cow::Borrowed("search_overhead"),
+            value: UserStats::new(UserStatsValue::Percent(total_time_ms as f64 / elapsed.as_millis().max(1) as f64), AggregatorOps::None),
+            phantom: PhantomData,
+        })?;
+
+        let relations = res.input.relations.iter().filter(|rel| rel.enabled).count();
+        manager.fire(state, Event::UpdateUserStats {
+            name: Cow::Borrowed("last_search"),
+            value: UserStats::new(UserStatsValue::String(Cow::Owned(format!("#{}: {} relations in {} probes ({} ms)",
+                corpus_idx, relations, res.test_count, res.total_test_ms))), AggregatorOps::None),
+            phantom: PhantomData,
+        })?;
+
         Ok(())
     }
 }
+
+/// Upper bounds (exclusive) of the buckets of the relations per entry histogram, the last bucket being unbounded.
+const RELATION_BUCKETS: [usize; 4] = [1, 2, 4, 8];
+
+/// Histogram of the relations per corpus entry, as `<bucket>:<entries>` pairs (e.g. `0:12 1:3 2-3:4 4-7:1 8+:0`).
+fn relation_histogram(counts: &[usize]) -> String {
+    let mut buckets = vec![];
+    let mut low = 0;
+    for high in RELATION_BUCKETS.into_iter().map(Some).chain([None]) {
+        let entries = counts.iter().filter(|n| **n >= low && high.map_or(true, |high| **n < high)).count();
+        let label = match high {
+            Some(high) if high == low + 1 => format!("{}", low),
+            Some(high) => format!("{}-{}", low, high - 1),
+            None => format!("{}+", low),
+        };
+        buckets.push(format!("{}:{}", label, entries));
+        low = high.unwrap_or(low);
+    }
+    buckets.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::structured::Structured;
+
+    /// A 1-byte length prefixed payload followed by a trailer, the trailer bytes being the edges. A length past the
+    /// end makes the parser hang, which the fork executor reports as a timeout.
+    fn hanging_target(data: &[u8]) -> Option<CoverageSnapshot> {
+        if data.is_empty() || data[0] as usize >= data.len() {
+            return None;
+        }
+        let mut cov = vec![0u8; 64];
+        cov[0] = 1;
+        for b in data[1 + data[0] as usize..].iter() {
+            cov[1 + (*b as usize % 63)] = 1;
+        }
+        Some(CoverageSnapshot::from(cov))
+    }
+
+    #[test]
+    fn test_timeout_budget() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+
+        // Each probe records whether it hung. Without a limit, every probe runs.
+        let search = |max: usize| {
+            let guidance = SearchGuidance { stop: Some(SearchStop::default()), ..Default::default() };
+            let mut budget = TimeoutBudget::new(max, guidance.stop.clone().unwrap());
+            let mut probes = vec![];
+            let mut oracle = |input: &[u8]| budget.probe(|| {
+                let cov = hanging_target(input);
+                probes.push(cov.is_none());
+                cov
+            });
+            let res = SearchContext::search_guided(&seed, &mut oracle, SearchOptions::default(), &guidance);
+            (probes, res.truncated)
+        };
+        let (all, _) = search(0);
+        assert_eq!(all.iter().filter(|hang| **hang).count(), 2);
+
+        // The search stops at the first timeout: no probe runs afterwards, and it is not resumed.
+        let (probes, truncated) = search(1);
+        assert_eq!(probes.iter().filter(|hang| **hang).count(), 1);
+        assert_eq!(probes.last(), Some(&true));
+        assert!(probes.len() < all.len());
+        assert!(!truncated);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{borrow::Cow, collections::BTreeMap, str::FromStr};
+
+use libafl::{corpus::Corpus, stages::Stage, state::{HasCorpus, UsesState}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, Named};
+use serde::{Deserialize, Serialize};
+
+
+/// Stages whose runs are weighted, by the names used in `--stage-weights`.
+pub const WEIGHTED_STAGES: [&str; 3] = ["i2s", "structural", "havoc"];
+
+/// Runs per corpus entry of a stage with an average yield.
+const BASE_RUNS: f64 = 2.0;
+
+/// Most runs per corpus entry of a stage.
+const MAX_RUNS: usize = 8;
+
+/// Weight of the latest run in the moving average of the yield of a stage.
+const YIELD_DECAY: f64 = 0.05;
+
+/// Fixed runs per corpus entry of some of the weighted stages, e.g. `i2s=1,structural=4,havoc=2` (0 disables a
+/// stage). The stages left out are weighted by their yield.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageWeights {
+    pub runs: BTreeMap<String, usize>,
+}
+
+impl FromStr for StageWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut runs = BTreeMap::new();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let Some((name, value)) = item.split_once('=') else {
+                return Err(format!("invalid stage weight: {} (expected <stage>=<runs>)", item));
+            };
+            if !WEIGHTED_STAGES.contains(&name) {
+                return Err(format!("unknown stage: {} (expected one of {})", name, WEIGHTED_STAGES.join(", ")));
+            }
+            let value = value.parse::<usize>().map_err(|e| format!("invalid runs for stage {}: {}", name, e))?;
+            runs.insert(name.to_string(), value.min(MAX_RUNS));
+        }
+        Ok(Self { runs })
+    }
+}
+
+/// Corpus entries found by a weighted stage.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StageYield {
+    pub runs: u64,
+    pub finds: u64,
+    /// Moving average of the entries found per run, favoring the recent runs.
+    pub recent: f64,
+}
+
+/// Yield of each weighted stage, by name, kept in the state so that it survives restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StageYieldMetadata {
+    pub stages: BTreeMap<String, StageYield>,
+}
+
+impl_serdeany!(StageYieldMetadata);
+
+impl StageYieldMetadata {
+    /// Runs per corpus entry of stage `name`, in proportion to its recent yield relative to the other weighted stages
+    /// (each stage runs at least once, so that a stage which stopped finding entries can catch up).
+    pub fn runs(&self, name: &str) -> usize {
+        let recent = |name: &str| self.stages.get(name).map_or(0.0, |stage| stage.recent);
+        let mean = WEIGHTED_STAGES.iter().map(|name| recent(name)).sum::<f64>() / WEIGHTED_STAGES.len() as f64;
+        if mean <= 0.0 {
+            return 1;
+        }
+        ((recent(name) / mean * BASE_RUNS).round() as usize).clamp(1, MAX_RUNS)
+    }
+
+    /// Records a run of stage `name` which added `finds` entries to the corpus.
+    pub fn record(&mut self, name: &str, finds: usize) {
+        let stage = self.stages.entry(name.to_string()).or_default();
+        stage.runs += 1;
+        stage.finds += finds as u64;
+        stage.recent = stage.recent * (1.0 - YIELD_DECAY) + finds as f64 * YIELD_DECAY;
+    }
+}
+
+/// Runs a stage a number of times on each corpus entry: fixed with `--stage-weights`, otherwise adjusted to the
+/// corpus entries the stage added recently compared to the other weighted stages.
+///
+/// The corpus growth during each run is credited to the stage in `StageYieldMetadata`, also when its runs are fixed.
+pub struct WeightedStage<ST> {
+    name: Cow<'static, str>,
+    stage_name: &'static str,
+    stage: ST,
+    /// Fixed runs per corpus entry.
+    runs: Option<usize>,
+}
+
+impl<ST> WeightedStage<ST> {
+    /// Weights `stage`, one of `WEIGHTED_STAGES`.
+    pub fn new(stage_name: &'static str, stage: ST, weights: &StageWeights) -> Self {
+        Self {
+            name: Cow::from(format!("weighted<{}>", stage_name)),
+            stage_name,
+            stage,
+            runs: weights.runs.get(stage_name).copied(),
+        }
+    }
+}
+
+impl<ST> Named for WeightedStage<ST> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<ST> UsesState for WeightedStage<ST>
+where
+    ST: UsesState,
+{
+    type State = ST::State;
+}
+
+impl<ST,E,EM,Z> Stage<E,EM,Z> for WeightedStage<ST>
+where
+    ST: Stage<E,EM,Z>,
+    ST::State: HasCorpus + HasMetadata,
+    E: UsesState<State = ST::State>,
+    EM: UsesState<State = ST::State>,
+    Z: UsesState<State = ST::State>,
+{
+    fn restart_progress_should_run(&mut self, state: &mut Self::State) -> Result<bool, Error> {
+        self.stage.restart_progress_should_run(state)
+    }
+
+    fn clear_restart_progress(&mut self, state: &mut Self::State) -> Result<(), Error> {
+        self.stage.clear_restart_progress(state)
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        if !state.has_metadata::<StageYieldMetadata>() {
+            state.add_metadata(StageYieldMetadata::default());
+        }
+        let runs = match self.runs {
+            Some(runs) => runs,
+            None => state.metadata::<StageYieldMetadata>()?.runs(self.stage_name),
+        };
+
+        for _ in 0..runs {
+            let count = state.corpus().count();
+            self.stage.perform(fuzzer, executor, state, manager)?;
+            let finds = state.corpus().count().saturating_sub(count);
+            state.metadata_mut::<StageYieldMetadata>()?.record(self.stage_name, finds);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_weights_from_str() {
+        let weights = "i2s=1,structural=4,havoc=0".parse::<StageWeights>().unwrap();
+        assert_eq!(weights.runs, BTreeMap::from([("i2s".to_string(), 1), ("structural".to_string(), 4), ("havoc".to_string(), 0)]));
+        assert_eq!("".parse::<StageWeights>().unwrap(), StageWeights::default());
+        assert_eq!("havoc=100,".parse::<StageWeights>().unwrap().runs["havoc"], MAX_RUNS);
+
+        assert!("splice=1".parse::<StageWeights>().is_err());
+        assert!("havoc".parse::<StageWeights>().is_err());
+        assert!("havoc=-1".parse::<StageWeights>().is_err());
+    }
+
+    #[test]
+    fn test_stage_yield() {
+        // Without any find, every stage runs once.
+        let mut metadata = StageYieldMetadata::default();
+        assert!(WEIGHTED_STAGES.iter().all(|name| metadata.runs(name) == 1));
+
+        metadata.record("havoc", 2);
+        let havoc = &metadata.stages["havoc"];
+        assert_eq!((havoc.runs, havoc.finds), (1, 2));
+        assert!((havoc.recent - 2.0 * YIELD_DECAY).abs() < 1e-9);
+
+        // The only productive stage runs 3 times the base runs, the others still once.
+        assert_eq!(metadata.runs("havoc"), 6);
+        assert_eq!(metadata.runs("i2s"), 1);
+
+        // Stages with the same yield run the base runs.
+        for name in WEIGHTED_STAGES {
+            metadata.stages.remove(name);
+            metadata.record(name, 1);
+        }
+        assert!(WEIGHTED_STAGES.iter().all(|name| metadata.runs(name) == BASE_RUNS as usize));
+    }
+}
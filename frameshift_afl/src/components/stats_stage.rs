@@ -0,0 +1,206 @@
+use std::{borrow::Cow, fs::OpenOptions, io::Write, marker::PhantomData, path::{Path, PathBuf}, time::Duration};
+
+use libafl::{
+    corpus::Corpus, feedbacks::MapFeedbackMetadata, inputs::BytesInput, schedulers::{minimizer::IsFavoredMetadata, powersched::SchedulerMetadata},
+    stages::Stage, state::{HasCorpus, HasExecutions, HasSolutions, HasStartTime, State, UsesState}, HasMetadata, HasNamedMetadata
+};
+use libafl_bolts::{current_time, Named};
+
+use super::{search_metadata::SearchMetadata, structured_input::StructuredInput};
+
+
+/// Header of `plot_data`, exactly as written by AFL++ so that afl-plot reads the file.
+const PLOT_HEADER: &str = "# relative_time, cycles_done, cur_item, corpus_count, pending_total, pending_favs, map_size, saved_crashes, saved_hangs, max_depth, execs_per_sec, total_execs, edges_found";
+
+/// Header of `frameshift_plot_data`, with the search statistics which have no column in AFL++'s file.
+const SEARCH_HEADER: &str = "# relative_time, searched, found, relations, search_overhead";
+
+/// Name of the edges observer, whose feedback metadata holds the coverage.
+const EDGES_MAP: &str = "edges";
+
+/// A line of `plot_data`. Hangs are not kept and the depth of the entries is not tracked, so these columns are 0.
+#[derive(Debug, Default)]
+struct PlotLine {
+    relative_time: u64,
+    cycles_done: u64,
+    cur_item: usize,
+    corpus_count: usize,
+    pending_total: usize,
+    pending_favs: usize,
+    /// Share of the map covered (in %).
+    map_size: f64,
+    saved_crashes: usize,
+    execs_per_sec: f64,
+    total_execs: u64,
+    edges_found: usize,
+}
+
+impl PlotLine {
+    /// Formats the line like AFL++.
+    fn format(&self) -> String {
+        format!("{}, {}, {}, {}, {}, {}, {:.2}%, {}, {}, {}, {:.2}, {}, {}",
+            self.relative_time, self.cycles_done, self.cur_item, self.corpus_count, self.pending_total, self.pending_favs,
+            self.map_size, self.saved_crashes, 0, 0, self.execs_per_sec, self.total_execs, self.edges_found)
+    }
+}
+
+/// Appends `line` to the file at `path`, starting the file with `header`.
+fn append_line(path: &Path, header: &str, line: &str) -> Result<(), libafl::Error> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", header)?;
+    }
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+/// Inputs whose relations are counted in the stats.
+pub trait RelationCount {
+    /// Number of enabled relations.
+    fn relation_count(&self) -> usize;
+}
+
+impl RelationCount for StructuredInput {
+    fn relation_count(&self) -> usize {
+        self.input.relations.iter().filter(|rel| rel.enabled).count()
+    }
+}
+
+impl RelationCount for BytesInput {
+    fn relation_count(&self) -> usize {
+        0
+    }
+}
+
+/// Periodically appends a line of fuzzing statistics to a `plot_data` file, for plotting and comparing campaigns.
+///
+/// `plot_data` has the columns of AFL++ (the executions per second are since the previous line), so afl-plot reads
+/// it. The search statistics go to `frameshift_plot_data` next to it: the time since the start (in seconds), the
+/// searched inputs and those with relations, the enabled relations across the corpus, and the share of the time spent
+/// in search probes (in %). The baseline fuzzer writes the same files, without searches and relations.
+pub struct StatsStage<S> {
+    path: PathBuf,
+    search_path: PathBuf,
+    interval: Duration,
+    /// Time and executions at the previous line.
+    last: Option<(Duration, u64)>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> StatsStage<S> {
+    /// Writes to `path` every `interval` (never if it is zero).
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        Self {
+            search_path: path.with_file_name("frameshift_plot_data"),
+            path,
+            interval,
+            last: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> Named for StatsStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StatsStage")
+    }
+}
+
+impl<S> UsesState for StatsStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for StatsStage<S>
+where
+    S: State + HasCorpus + HasSolutions + HasExecutions + HasStartTime + HasMetadata + HasNamedMetadata,
+    S::Input: RelationCount,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: UsesState<State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        if self.interval.is_zero() {
+            return Ok(());
+        }
+
+        let now = current_time();
+        let execs = *state.executions();
+        let (last_time, last_execs) = *self.last.get_or_insert((*state.start_time(), 0));
+        if now < last_time + self.interval {
+            return Ok(());
+        }
+        self.last = Some((now, execs));
+
+        let elapsed = now.saturating_sub(*state.start_time());
+        let execs_per_sec = (execs - last_execs) as f64 / (now - last_time).as_secs_f64();
+
+        let mut relations = 0;
+        let (mut pending_total, mut pending_favs) = (0, 0);
+        for id in state.corpus().ids() {
+            let entry = state.corpus().get(id)?.borrow();
+            relations += entry.input().as_ref().map_or(0, |inner| inner.relation_count());
+            if entry.scheduled_count() == 0 {
+                pending_total += 1;
+                pending_favs += usize::from(entry.has_metadata::<IsFavoredMetadata>());
+            }
+        }
+
+        let (edges_found, map_size) = match state.named_metadata::<MapFeedbackMetadata<u8>>(EDGES_MAP) {
+            Ok(meta) => (meta.num_covered_map_indexes, meta.num_covered_map_indexes as f64 / meta.history_map.len().max(1) as f64 * 100.0),
+            Err(_) => (0, 0.0),
+        };
+
+        let line = PlotLine {
+            relative_time: elapsed.as_secs(),
+            cycles_done: state.metadata::<SchedulerMetadata>().map_or(0, |meta| meta.queue_cycles()),
+            cur_item: state.corpus().current().map_or(0, usize::from),
+            corpus_count: state.corpus().count(),
+            pending_total,
+            pending_favs,
+            map_size,
+            saved_crashes: state.solutions().count(),
+            execs_per_sec,
+            total_execs: execs,
+            edges_found,
+        };
+        append_line(&self.path, PLOT_HEADER, &line.format())?;
+
+        let (searched, found, overhead) = match state.metadata::<SearchMetadata>() {
+            Ok(meta) => (meta.num_searched, meta.num_found, meta.total_time_ms as f64 / elapsed.as_millis().max(1) as f64 * 100.0),
+            Err(_) => (0, 0, 0.0),
+        };
+        append_line(&self.search_path, SEARCH_HEADER,
+            &format!("{}, {}, {}, {}, {:.2}", elapsed.as_secs(), searched, found, relations, overhead))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plot_line_columns() {
+        let line = PlotLine { relative_time: 60, map_size: 12.5, total_execs: 1000, ..Default::default() }.format();
+        assert_eq!(line.split(", ").count(), PLOT_HEADER.split(", ").count());
+        assert_eq!(line, "60, 0, 0, 0, 0, 0, 12.50%, 0, 0, 0, 0.00, 1000, 0");
+    }
+}
@@ -0,0 +1,66 @@
+use std::borrow::Cow;
+
+use libafl::{prelude::{MutationResult, Mutator}, state::HasRand, Error};
+use libafl_bolts::{rands::Rand, Named};
+
+use super::structured_input::StructuredInput;
+
+
+/// Maximum number of characters added to a string by a single mutation.
+const MAX_STRING_EXTENSION: u64 = 16;
+
+/// Grows or shrinks the strings of an input (see `Structured::detect_strings`) in place: characters are appended or
+/// removed before the terminator, and the enclosing length fields are updated.
+///
+/// Generic insertions land anywhere in a string, often after its terminator (or inside a length-prefixed one without
+/// updating its length), so the target sees the same string followed by garbage.
+#[derive(Default)]
+pub struct StringMutator;
+
+impl StringMutator {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for StringMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StringMutator")
+    }
+}
+
+impl<S> Mutator<StructuredInput, S> for StringMutator
+where
+    S: HasRand
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        if input.input.fixed_size || input.input.strings.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+
+        let rand = state.rand_mut();
+        let k = (rand.next() % input.input.strings.len() as u64) as usize;
+        let len = input.input.strings[k].len;
+
+        let res = if rand.next() % 2 == 0 || len < 2 {
+            // Printable characters, repeating the string's own characters half of the time.
+            let count = 1 + rand.next() % MAX_STRING_EXTENSION;
+            let pos = input.input.strings[k].pos;
+            let data = (0..count)
+                .map(|i| match rand.next() % 2 {
+                    0 => input.input.get_raw()[pos + i as usize % len],
+                    _ => 0x20 + (rand.next() % 0x5f) as u8,
+                })
+                .collect::<Vec<_>>();
+            input.input.extend_string(k, &data)
+        } else {
+            let size = 1 + (rand.next() % (len as u64 - 1)) as usize;
+            input.input.shrink_string(k, size)
+        };
+
+        match res {
+            Ok(()) => Ok(MutationResult::Mutated),
+            Err(_) => Ok(MutationResult::Skipped),
+        }
+    }
+}
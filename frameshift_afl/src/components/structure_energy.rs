@@ -0,0 +1,173 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use libafl::{corpus::{Corpus, CorpusId, Testcase}, inputs::UsesInput, prelude::{MutationResult, Mutator}, schedulers::testcase_score::{CorpusPowerTestcaseScore, TestcaseScore}, state::HasCorpus, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, Named};
+use serde::{Deserialize, Serialize};
+
+use super::{structured_input::StructuredInput, target_distance::DirectedTestcaseScore};
+
+
+/// Number of relations past which an entry gets the full richness boost.
+const RICH_RELATIONS: usize = 8;
+
+/// Energy multiplier of the entries with the richest structure.
+const RICHNESS_BOOST: f64 = 1.0;
+
+/// Bounds of the energy multiplier from the yield of the structural mutations on an entry.
+const MIN_YIELD_FACTOR: f64 = 0.5;
+const MAX_YIELD_FACTOR: f64 = 2.0;
+
+/// Weight multiplier of the corpus entries with relations in the scheduler (`--structure-boost`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StructureBoostMetadata {
+    pub factor: f64,
+}
+
+impl_serdeany!(StructureBoostMetadata);
+
+/// Structural mutations of a corpus entry, and how many of them were added to the corpus.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StructuralYieldMetadata {
+    pub tries: u64,
+    pub finds: u64,
+}
+
+impl_serdeany!(StructuralYieldMetadata);
+
+/// Structural mutations of all the corpus entries (the state-wide `StructuralYieldMetadata`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StructuralYieldTotals {
+    pub tries: u64,
+    pub finds: u64,
+}
+
+impl_serdeany!(StructuralYieldTotals);
+
+/// Records the yield of the wrapped (structural) mutator on each corpus entry, for `StructuredPowerTestcaseScore`.
+pub struct StructuralYieldMutator<M> {
+    mutator: M,
+    name: Cow<'static, str>,
+    /// Whether the last mutation was not skipped.
+    pending: bool,
+}
+
+impl<M> StructuralYieldMutator<M>
+where
+    M: Named
+{
+    pub fn new(mutator: M) -> Self {
+        Self {
+            name: Cow::from(format!("yield<{}>", mutator.name())),
+            mutator,
+            pending: false,
+        }
+    }
+}
+
+impl<M> Named for StructuralYieldMutator<M> {
+    fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+}
+
+impl<M,S> Mutator<StructuredInput, S> for StructuralYieldMutator<M>
+where
+    M: Mutator<StructuredInput, S>,
+    S: HasCorpus + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, Error> {
+        let res = self.mutator.mutate(state, input)?;
+        self.pending = res == MutationResult::Mutated;
+        Ok(res)
+    }
+
+    fn post_exec(&mut self, state: &mut S, new_corpus_idx: Option<CorpusId>) -> Result<(), Error> {
+        self.mutator.post_exec(state, new_corpus_idx)?;
+        if !std::mem::take(&mut self.pending) {
+            return Ok(());
+        }
+
+        let found = new_corpus_idx.is_some() as u64;
+
+        if let Some(idx) = state.corpus().current() {
+            let mut entry = state.corpus().get(idx)?.borrow_mut();
+            if !entry.has_metadata::<StructuralYieldMetadata>() {
+                entry.add_metadata(StructuralYieldMetadata::default());
+            }
+            let meta = entry.metadata_mut::<StructuralYieldMetadata>()?;
+            meta.tries += 1;
+            meta.finds += found;
+        }
+
+        if !state.has_metadata::<StructuralYieldTotals>() {
+            state.add_metadata(StructuralYieldTotals::default());
+        }
+        let totals = state.metadata_mut::<StructuralYieldTotals>()?;
+        totals.tries += 1;
+        totals.finds += found;
+
+        Ok(())
+    }
+}
+
+/// Power schedule score (as used by `PowerMutationalStage`) concentrating the energy on the entries where the
+/// structure model gives leverage.
+///
+/// The `CorpusPowerTestcaseScore` of an entry is boosted with the number of relations of the entry (up to 2x), and
+/// scaled by the yield of the structural mutations on the entry relative to the whole corpus (between 0.5x and 2x).
+#[derive(Debug, Clone)]
+pub struct StructuredPowerTestcaseScore<S> {
+    phantom: PhantomData<S>,
+}
+
+impl<S> TestcaseScore<S> for StructuredPowerTestcaseScore<S>
+where
+    S: HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+{
+    fn compute(state: &S, entry: &mut Testcase<<S::Corpus as Corpus>::Input>) -> Result<f64, Error> {
+        let score = CorpusPowerTestcaseScore::compute(state, entry)?;
+
+        let relations = entry.input().as_ref()
+            .map_or(0, |inner| inner.input.relations.iter().filter(|rel| rel.enabled).count());
+        if relations == 0 {
+            return Ok(score);
+        }
+        let richness = 1.0 + RICHNESS_BOOST * relations.min(RICH_RELATIONS) as f64 / RICH_RELATIONS as f64;
+
+        // Smoothed yield, so entries without structural mutations yet keep a factor of 1.
+        let factor = match (entry.metadata::<StructuralYieldMetadata>(), state.metadata::<StructuralYieldTotals>()) {
+            (Ok(meta), Ok(totals)) => {
+                let rate = (meta.finds + 1) as f64 / (meta.tries + 1) as f64;
+                let average = (totals.finds + 1) as f64 / (totals.tries + 1) as f64;
+                (rate / average).clamp(MIN_YIELD_FACTOR, MAX_YIELD_FACTOR)
+            }
+            _ => 1.0,
+        };
+
+        Ok(score * richness * factor)
+    }
+}
+
+/// Corpus weight (as used by `StdWeightedScheduler`) of `DirectedTestcaseScore`, multiplied by the
+/// `StructureBoostMetadata` factor for the entries with enabled relations, so that the structural mutations and
+/// stages run more often on the entries they have leverage on.
+#[derive(Debug, Clone)]
+pub struct StructureBoostTestcaseScore<S> {
+    phantom: PhantomData<S>,
+}
+
+impl<S> TestcaseScore<S> for StructureBoostTestcaseScore<S>
+where
+    S: HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+{
+    fn compute(state: &S, entry: &mut Testcase<<S::Corpus as Corpus>::Input>) -> Result<f64, Error> {
+        let weight = DirectedTestcaseScore::compute(state, entry)?;
+
+        let structured = entry.input().as_ref()
+            .is_some_and(|inner| inner.input.relations.iter().any(|rel| rel.enabled));
+        match state.metadata::<StructureBoostMetadata>() {
+            Ok(boost) if structured => Ok(weight * boost.factor),
+            _ => Ok(weight),
+        }
+    }
+}
@@ -1,19 +1,45 @@
 use ahash::RandomState;
 use libafl::{corpus::CorpusId, inputs::{HasMutatorBytes, HasTargetBytes, Input}, Error};
-use libafl_bolts::{fs::write_file_atomic, prelude::OwnedSlice, HasLen};
+use libafl_bolts::{prelude::OwnedSlice, HasLen};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::{hash::{BuildHasher, Hasher}, io::Read, path::Path};
+use std::{hash::{BuildHasher, Hasher}, io::Read, path::Path, sync::Mutex};
 use std::fmt::Debug;
 
-use crate::core::structured::Structured;
+pub use crate::core::annotation::{annotation_path, corpus_files, parts_path};
+use crate::core::{annotation::{decode_annotation, sidecar_path, write_annotation_file, AnnotationFormat}, parts::part_names, search::{RawDigest, SearchProgress}, structured::Structured};
 
 
+/// Format of the annotations written (set once from `--annotation-format`).
+static ANNOTATION_FORMAT: Mutex<AnnotationFormat> = Mutex::new(AnnotationFormat::Json);
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct StructuredInput {
     pub input: Structured,
     pub status: InputStatus,
     pub seed: u64,
+
+    /// Digest of the raw bytes at the time of the last search (inherited by mutated copies), used to only re-scan
+    /// changed bytes.
+    #[serde(default)]
+    pub searched_digest: Option<RawDigest>,
+
+    /// Point reached by an interrupted search (truncated by its budget or killed by a probe), which the next search
+    /// resumes from. The relations found before it are already in `input`.
+    #[serde(default)]
+    pub search_progress: Option<SearchProgress>,
+
+    /// Additional files of a multi-file input (e.g. the index accompanying a data file), delivered to the target
+    /// through `@@<name>` arguments (see `file_set`).
+    #[serde(default)]
+    pub parts: Vec<InputPart>,
+}
+
+/// A named file of a multi-file input, with its own structure.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InputPart {
+    pub name: String,
+    pub input: Structured,
 }
 
 impl Debug for StructuredInput {
@@ -30,6 +56,9 @@ impl StructuredInput {
             input: Structured::raw(bytes.to_vec()),
             status: InputStatus::New,
             seed: 0,
+            searched_digest: None,
+            search_progress: None,
+            parts: Vec::new(),
         }
     }
 
@@ -38,12 +67,46 @@ impl StructuredInput {
             input,
             status: InputStatus::New,
             seed: 0,
+            searched_digest: None,
+            search_progress: None,
+            parts: Vec::new(),
         }
     }
 
     pub fn set_seed(&mut self, seed: u64) {
         self.seed = seed;
     }
+
+    /// Number of files of the input: the primary one (`input`) and its parts.
+    pub fn part_count(&self) -> usize {
+        1 + self.parts.len()
+    }
+
+    /// Swaps the structure of file `k` (0 being the primary one) into `input`, so that mutations and searches of
+    /// `input` apply to it. Swapping the same file again restores the input.
+    pub fn swap_part(&mut self, k: usize) {
+        if k > 0 {
+            std::mem::swap(&mut self.input, &mut self.parts[k - 1].input);
+        }
+    }
+
+    /// Returns a relation-less copy of the input where file `k` (0 being the primary one) holds `bytes`, e.g. to probe
+    /// the target with a mutated file next to the other files of the input.
+    pub fn probe(&self, k: usize, bytes: &[u8]) -> Self {
+        let mut probe = Self::new_raw(self.input.get_raw());
+        probe.parts = self.parts.iter()
+            .map(|part| InputPart { name: part.name.clone(), input: Structured::raw(part.input.get_raw().to_vec()) })
+            .collect();
+        probe.swap_part(k);
+        probe.input = Structured::raw(bytes.to_vec());
+        probe.swap_part(k);
+        probe
+    }
+
+    /// Raw bytes of the part named `name`, if the input has it.
+    pub fn part(&self, name: &str) -> Option<&[u8]> {
+        self.parts.iter().find(|part| part.name == name).map(|part| part.input.get_raw())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -59,12 +122,37 @@ pub enum InputStatus {
 
     /// A searched grammar (corpus entry should match the entry here).
     Searched(CorpusId),
+
+    /// A manually verified grammar which is never searched. Bound to its corpus entry (like `Searched`) the first time
+    /// it comes up, so that its mutated copies are searched as usual.
+    Frozen(Option<CorpusId>),
+}
+
+/// Sets the format of the `.annotated` sidecars written from now on (both formats are always read).
+pub fn set_annotation_format(format: AnnotationFormat) {
+    *ANNOTATION_FORMAT.lock().unwrap() = format;
+}
+
+/// Reads the `.annotated` sidecar at `path` (JSON or binary).
+pub fn read_annotation(path: &Path) -> Result<Structured, Error> {
+    let bytes = std::fs::read(path)?;
+    decode_annotation(&bytes).map_err(|e| Error::serialize(format!("{:?}: {}", path, e)))
+}
+
+/// Writes `input` to the `.annotated` sidecar at `path`, in the format set by `set_annotation_format`.
+pub fn write_annotation(path: &Path, input: &Structured) -> Result<(), Error> {
+    let format = *ANNOTATION_FORMAT.lock().unwrap();
+    Ok(write_annotation_file(path, input, format)?)
 }
 
 impl Input for StructuredInput {
     fn generate_name(&self, _idx: usize) -> String {
         let mut hasher = RandomState::with_seeds(0, 0, 0, 0).build_hasher();
         hasher.write(&self.input.get_raw());
+        for part in self.parts.iter() {
+            hasher.write(part.name.as_bytes());
+            hasher.write(part.input.get_raw());
+        }
         format!("{:016x}", hasher.finish())
     }
 
@@ -75,13 +163,13 @@ impl Input for StructuredInput {
         // Write raw data to file
         write_file_atomic(&path, &self.input.get_raw())?;
 
-        let parent = path.as_ref().parent().unwrap();
-        let file_name = path.as_ref().file_name().unwrap();
-        let full_path = parent.join(format!(".{}.annotated", file_name.to_string_lossy()));
-
         // Write annotated data to file
-        let json = serde_json::to_string(&self.input).unwrap();
-        write_file_atomic(full_path, json.as_bytes())?;
+        write_annotation(&annotation_path(path.as_ref()), &self.input)?;
+
+        if !self.parts.is_empty() {
+            let json = serde_json::to_string(&self.parts).unwrap();
+            write_file_atomic(parts_path(path.as_ref()), json.as_bytes())?;
+        }
 
         Ok(())
     }
@@ -91,17 +179,37 @@ impl Input for StructuredInput {
     where
         P: AsRef<Path>,
     {
-        let parent = path.as_ref().parent().unwrap();
-        let file_name = path.as_ref().file_name().unwrap();
-        let full_path = parent.join(format!(".{}.annotated", file_name.to_string_lossy()));
+        let mut input = Self::load_primary(path.as_ref())?;
+
+        // The other files of a multi-file input: structured in a `.parts` sidecar, or raw (for seeds) in hidden
+        // `.<name>.<part>` files next to it.
+        let parts = parts_path(path.as_ref());
+        if parts.exists() {
+            input.parts = serde_json::from_str(&std::fs::read_to_string(parts)?)?;
+        } else {
+            for name in part_names() {
+                if let Ok(bytes) = std::fs::read(sidecar_path(path.as_ref(), &name)) {
+                    input.parts.push(InputPart { name, input: Structured::raw(bytes) });
+                }
+            }
+        }
+        Ok(input)
+    }
+}
+
+impl StructuredInput {
+    /// Loads the primary file of the input at `path`, with its `.annotated` structure if any.
+    fn load_primary(path: &Path) -> Result<Self, Error> {
+        let full_path = annotation_path(path);
 
         // Check if annotated file exists
         if full_path.exists() {
             // Load annotated data
-            let json = std::fs::read_to_string(full_path)?;
-            let structure: Structured = serde_json::from_str(&json)?;
-
-            Ok(StructuredInput::new_structured(structure))
+            let mut input = StructuredInput::new_structured(read_annotation(&full_path)?);
+            if input.input.frozen {
+                input.status = InputStatus::Frozen(None);
+            }
+            Ok(input)
         } else {
             // Load raw data
             let mut file = std::fs::File::open(path)?;
@@ -158,6 +266,7 @@ impl HasMutatorBytes for StructuredInput {
         self.input.insert_disabling(self.input.get_raw().len(), &data);
     }
 
+    // Only the donor bytes are known here, so the donor relations are lost (`HavocSpliceMutator` keeps them).
     fn splice<R, I>(&mut self, range: R, replace_with: I) -> Option<std::vec::Splice<'_, I::IntoIter>>
     where
         R: std::ops::RangeBounds<usize>,
@@ -0,0 +1,157 @@
+use std::{borrow::Cow, collections::HashSet, fs, marker::PhantomData, path::PathBuf, time::Duration};
+
+use libafl::{corpus::Corpus, fuzzer::{Evaluator, ExecuteInputResult}, inputs::{Input, UsesInput}, stages::Stage, state::{HasCorpus, State, UsesState}, HasMetadata};
+use libafl_bolts::{current_time, impl_serdeany, Named};
+use serde::{Deserialize, Serialize};
+
+use super::{monitor::stage_line, structured_input::corpus_files};
+
+
+/// Inputs exchanged through the sync directory, kept in the state so that a restart neither exports nor imports them
+/// again.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncMetadata {
+    /// Names (see `Input::generate_name`) of the corpus entries exported, in export order (the AFL++ id of each).
+    pub exported: Vec<String>,
+    /// Files of the queues of the other fuzzers already evaluated.
+    pub imported: HashSet<PathBuf>,
+}
+
+impl_serdeany!(SyncMetadata);
+
+/// Exchanges inputs with other fuzzers through an AFL++ sync directory (the `-o` directory of `afl-fuzz -M/-S`).
+///
+/// Every `interval`, the new corpus entries are exported to `<sync_dir>/<name>/queue` under AFL++ names
+/// (`id:000042,orig:<entry name>`, the entry name being stable across runs), along with their hidden sidecars, which
+/// AFL++ ignores but other FrameShift instances load with the entry. Then the new files of the queues of the other
+/// fuzzers (`<sync_dir>/*/queue`) are evaluated: the interesting ones join the corpus, where they are searched like
+/// any new entry.
+pub struct AflSyncStage<S> {
+    sync_dir: PathBuf,
+    name: String,
+    interval: Duration,
+    /// Time of the previous sync.
+    last: Option<Duration>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S> AflSyncStage<S> {
+    /// Syncs through `sync_dir` as the fuzzer `name` every `interval` (never if `sync_dir` is unset).
+    pub fn new(sync_dir: Option<PathBuf>, name: String, interval: Duration) -> Self {
+        Self {
+            sync_dir: sync_dir.unwrap_or_default(),
+            name,
+            interval,
+            last: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Files of the queues of the other fuzzers, in name order (hence AFL++ id order) within each queue.
+    fn foreign_queue_files(&self) -> Vec<PathBuf> {
+        let Ok(fuzzers) = fs::read_dir(&self.sync_dir) else {
+            return vec![];
+        };
+
+        let mut files = vec![];
+        for fuzzer in fuzzers.filter_map(|entry| entry.ok()) {
+            if fuzzer.file_name().to_string_lossy() == self.name {
+                continue;
+            }
+            files.extend(corpus_files(&fuzzer.path().join("queue")).unwrap_or_default());
+        }
+        files
+    }
+}
+
+impl<S> Named for AflSyncStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("AflSyncStage")
+    }
+}
+
+impl<S> UsesState for AflSyncStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for AflSyncStage<S>
+where
+    S: State + HasCorpus + HasMetadata,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: Evaluator<E,EM,State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        if self.sync_dir.as_os_str().is_empty() {
+            return Ok(());
+        }
+
+        let now = current_time();
+        if self.last.is_some_and(|last| now < last + self.interval) {
+            return Ok(());
+        }
+        self.last = Some(now);
+
+        if !state.has_metadata::<SyncMetadata>() {
+            state.add_metadata(SyncMetadata::default());
+        }
+
+        // Export the new corpus entries.
+        let queue = self.sync_dir.join(&self.name).join("queue");
+        fs::create_dir_all(&queue)?;
+        let mut exported = state.metadata::<SyncMetadata>()?.exported.iter().cloned().collect::<HashSet<_>>();
+        let mut names = vec![];
+        for id in state.corpus().ids() {
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            let input = testcase.load_input(state.corpus())?;
+            let name = input.generate_name(id.0);
+            if exported.insert(name.clone()) {
+                let afl_id = state.metadata::<SyncMetadata>()?.exported.len() + names.len();
+                input.to_file(queue.join(format!("id:{:06},orig:{}", afl_id, name)))?;
+                names.push(name);
+            }
+        }
+        state.metadata_mut::<SyncMetadata>()?.exported.extend(names);
+
+        // Import the new files of the other fuzzers.
+        let mut imported = 0;
+        let mut interesting = 0;
+        for path in self.foreign_queue_files() {
+            if !state.metadata_mut::<SyncMetadata>()?.imported.insert(path.clone()) {
+                continue;
+            }
+            let Ok(input) = <S as UsesInput>::Input::from_file(&path) else {
+                continue;
+            };
+
+            imported += 1;
+            let (res, _) = fuzzer.evaluate_input(state, executor, manager, input)?;
+            if res != ExecuteInputResult::None {
+                interesting += 1;
+            }
+        }
+
+        if imported > 0 {
+            stage_line(&format!("  [synced: {} interesting of {} imported]", interesting, imported));
+        }
+
+        Ok(())
+    }
+}
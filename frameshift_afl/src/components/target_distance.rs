@@ -0,0 +1,172 @@
+use std::{borrow::Cow, collections::HashSet, marker::PhantomData};
+
+use libafl::{corpus::{Corpus, Testcase}, events::EventFirer, executors::ExitKind, feedbacks::Feedback, prelude::{MapObserver, ObserversTuple}, schedulers::testcase_score::{CorpusWeightTestcaseScore, TestcaseScore}, state::{HasCorpus, State}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, tuples::{Handle, Handled, MatchNameRef}, AsSlice, Named};
+use serde::{Deserialize, Serialize};
+
+use super::{crash_proximity::{crash_distance, edge_pcs}, monitor::stage_line};
+
+
+/// Weight multiplier of the corpus entry closest to the target sites (the farthest one keeps its weight).
+const DIRECTED_BOOST: f64 = 4.0;
+
+/// Layout distance (in bytes, by pc-table address) between the coverage of a corpus entry and the closest target site.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TargetDistanceMetadata {
+    pub distance: usize,
+}
+
+impl_serdeany!(TargetDistanceMetadata);
+
+/// Range of the distances of the corpus entries.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TargetDistanceBounds {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl_serdeany!(TargetDistanceBounds);
+
+/// Measures how close each execution gets to user-specified target sites (edges) in the code layout.
+///
+/// The distance between two edges is that of their addresses (see `crash_distance`), a heuristic which needs no
+/// control-flow graph: code near a target in the binary is often, but not always, on the way to it.
+///
+/// Inputs getting closer than any input before are interesting, and every corpus entry records its distance in
+/// `TargetDistanceMetadata` so that `DirectedTestcaseScore` can favor the entries closest to the targets. Without
+/// target sites, this feedback does nothing.
+pub struct TargetDistanceFeedback<C,O> {
+    pub map_handle: Handle<C>,
+    /// Distance of each edge to the closest target site (`usize::MAX` if unknown).
+    edge_distance: Vec<usize>,
+    /// Distance of the last execution.
+    last: Option<usize>,
+    _phantom: PhantomData<O>,
+}
+
+impl<C,O> TargetDistanceFeedback<C,O>
+where
+    C: Named
+{
+    pub fn new(observer: &C, targets: &HashSet<usize>) -> Self {
+        let edge_distance = if targets.is_empty() {
+            vec![]
+        } else {
+            let pcs = edge_pcs();
+            if pcs.is_empty() {
+                stage_line(&format!("No pc-table registered, target sites are ignored (build the target with -fsanitize-coverage=pc-table)"));
+            }
+            (0..pcs.len()).map(|e| crash_distance(&pcs, targets, &[e]).unwrap_or(usize::MAX)).collect()
+        };
+
+        Self {
+            map_handle: observer.handle(),
+            edge_distance,
+            last: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<C,O> Named for TargetDistanceFeedback<C,O> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("TargetDistanceFeedback")
+    }
+}
+
+impl<S,C,O> Feedback<S> for TargetDistanceFeedback<C,O>
+where
+    S: State + HasMetadata,
+    C: Named + AsRef<O>,
+    O: MapObserver + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.last = None;
+        if self.edge_distance.is_empty() {
+            return Ok(false);
+        }
+
+        let obs = observers.get(&self.map_handle).ok_or_else(|| Error::key_not_found("edges observer not found"))?.as_ref();
+        let distance = obs.as_slice().iter()
+            .zip(self.edge_distance.iter())
+            .filter(|(v, _)| **v != 0)
+            .map(|(_, d)| *d)
+            .min()
+            .filter(|d| *d != usize::MAX);
+
+        let Some(distance) = distance else {
+            return Ok(false);
+        };
+        self.last = Some(distance);
+
+        let closer = match state.metadata::<TargetDistanceBounds>() {
+            Ok(bounds) => distance < bounds.min,
+            Err(_) => true,
+        };
+        Ok(closer)
+    }
+
+    fn append_metadata<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+        EM: EventFirer<State = S>,
+    {
+        let Some(distance) = self.last.take() else {
+            return Ok(());
+        };
+
+        match state.metadata_mut::<TargetDistanceBounds>() {
+            Ok(bounds) => {
+                bounds.min = bounds.min.min(distance);
+                bounds.max = bounds.max.max(distance);
+            }
+            Err(_) => state.add_metadata(TargetDistanceBounds { min: distance, max: distance }),
+        }
+
+        testcase.add_metadata(TargetDistanceMetadata { distance });
+        Ok(())
+    }
+}
+
+/// Corpus weight (as used by `StdWeightedScheduler`) boosted for the entries closest to the target sites, so the
+/// structural mutations and the search concentrate on them.
+#[derive(Debug, Clone)]
+pub struct DirectedTestcaseScore<S> {
+    phantom: PhantomData<S>,
+}
+
+impl<S> TestcaseScore<S> for DirectedTestcaseScore<S>
+where
+    S: HasCorpus + HasMetadata,
+{
+    fn compute(state: &S, entry: &mut Testcase<<S::Corpus as Corpus>::Input>) -> Result<f64, Error> {
+        let weight = CorpusWeightTestcaseScore::compute(state, entry)?;
+
+        let (Ok(bounds), Ok(meta)) = (state.metadata::<TargetDistanceBounds>(), entry.metadata::<TargetDistanceMetadata>()) else {
+            return Ok(weight);
+        };
+        if bounds.max == bounds.min {
+            return Ok(weight * (1.0 + DIRECTED_BOOST));
+        }
+
+        let closeness = (bounds.max - meta.distance.min(bounds.max)) as f64 / (bounds.max - bounds.min) as f64;
+        Ok(weight * (1.0 + DIRECTED_BOOST * closeness))
+    }
+}
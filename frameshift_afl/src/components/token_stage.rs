@@ -0,0 +1,108 @@
+use std::{borrow::Cow, marker::PhantomData};
+
+use libafl::{corpus::Corpus, inputs::UsesInput, mutators::Tokens, stages::Stage, state::{HasCorpus, State, UsesState}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, ErrorBacktrace, Named};
+use serde::{Deserialize, Serialize};
+
+use super::{monitor::stage_line, structured_input::{InputStatus, StructuredInput}};
+
+
+/// Maximum length of an extracted token.
+const MAX_TOKEN_LEN: usize = 16;
+
+/// Marks a corpus entry whose tokens were already extracted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokensExtractedMetadata;
+
+impl_serdeany!(TokensExtractedMetadata);
+
+/// Feeds the constant bytes around discovered size fields (magic values, chunk type tags) into the `Tokens`
+/// metadata, so that the token mutators learn the keywords of the format.
+pub struct StructureTokensStage<S> {
+    _phantom: PhantomData<S>,
+}
+
+impl<S> StructureTokensStage<S> {
+    pub fn new() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<S> Default for StructureTokensStage<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Named for StructureTokensStage<S> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StructureTokensStage")
+    }
+}
+
+impl<S> UsesState for StructureTokensStage<S>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,E,EM,Z> Stage<E,EM,Z> for StructureTokensStage<S>
+where
+    S: State + HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+    E: UsesState<State = S>,
+    EM: UsesState<State = S>,
+    Z: UsesState<State = S>,
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        let corpus_idx = state.corpus().current().ok_or(Error::Empty("missing current".to_string(), ErrorBacktrace {}))?;
+
+        // Only extract from searched (or frozen) entries with structure, once.
+        let tokens = {
+            let entry = state.corpus().get(corpus_idx)?.borrow();
+            if entry.has_metadata::<TokensExtractedMetadata>() {
+                return Ok(());
+            }
+
+            let inner = entry.input().as_ref().unwrap();
+            let searched = matches!(inner.status, InputStatus::Searched(id) | InputStatus::Frozen(Some(id)) if id == corpus_idx);
+            if !searched || inner.input.relations.is_empty() {
+                return Ok(());
+            }
+            inner.input.tokens(MAX_TOKEN_LEN)
+        };
+
+        if !state.has_metadata::<Tokens>() {
+            state.add_metadata(Tokens::default());
+        }
+
+        let added = {
+            let dict = state.metadata_mut::<Tokens>().unwrap();
+            tokens.iter().filter(|token| dict.add_token(token)).count()
+        };
+
+        if added > 0 {
+            stage_line(&format!("  ({}) [+{} tokens]", corpus_idx, added));
+        }
+
+        state.corpus().get(corpus_idx)?.borrow_mut().add_metadata(TokensExtractedMetadata);
+
+        Ok(())
+    }
+}
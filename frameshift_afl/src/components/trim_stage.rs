@@ -0,0 +1,160 @@
+use std::{borrow::Cow, collections::HashSet, marker::PhantomData};
+
+use libafl::{corpus::Corpus, inputs::UsesInput, prelude::{Executor, HasObservers, MapObserver, ObserversTuple}, stages::Stage, state::{HasCorpus, State, UsesState}, Error, HasMetadata};
+use libafl_bolts::{impl_serdeany, tuples::{Handle, Handled}, AsIter, AsSlice, ErrorBacktrace, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::core::search::RawDigest;
+
+use super::{monitor::stage_line, structured_input::{InputStatus, StructuredInput}};
+
+
+/// Marks a corpus entry as already trimmed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrimmedMetadata;
+
+impl_serdeany!(TrimmedMetadata);
+
+/// Shrinks searched corpus entries by removing relation-delimited chunks.
+///
+/// Chunks are removed with `Structured::remove` so that all size fields stay consistent, and a removal is kept only
+/// if the trimmed input still reaches all of the coverage of the original input.
+pub struct StructuredTrimStage<S,C,O> {
+    pub map_handle: Handle<C>,
+    pub max_attempts: usize,
+    _phantom: PhantomData<(S,O)>,
+}
+
+impl<S,C,O> StructuredTrimStage<S,C,O>
+where
+    S: State + UsesInput<Input = StructuredInput>,
+    O: MapObserver + for<'it> AsIter<'it, Item = u8> + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
+    C: Named + AsMut<O> + AsRef<O>
+{
+    pub fn new(observer: &C, max_attempts: usize) -> Self {
+        Self {
+            map_handle: observer.handle(),
+            max_attempts,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Runs the input and returns the set of hit map indices.
+    fn coverage<E,EM,Z,OT>(&self, fuzzer: &mut Z, executor: &mut E, state: &mut S, mgr: &mut EM, input: &StructuredInput) -> Result<HashSet<usize>, Error>
+    where
+        E: Executor<EM,Z,State = S> + HasObservers<Observers = OT>,
+        Z: UsesState<State = E::State>,
+        EM: UsesState<State = E::State>,
+        OT: ObserversTuple<E::State>
+    {
+        {
+            let mut ot = executor.observers_mut();
+            let obs = ot[&self.map_handle].as_mut();
+            obs.reset_map()?;
+        }
+        executor.run_target(fuzzer, state, mgr, input)?;
+        let ot = executor.observers();
+        let obs = ot[&self.map_handle].as_ref();
+
+        Ok(obs.as_slice().iter().enumerate().filter(|(_, v)| **v != 0).map(|(i, _)| i).collect())
+    }
+}
+
+impl<S,C,O> Named for StructuredTrimStage<S,C,O> {
+    fn name(&self) -> &Cow<'static, str> {
+        &Cow::Borrowed("StructuredTrimStage")
+    }
+}
+
+impl<S,C,O> UsesState for StructuredTrimStage<S,C,O>
+where
+    S: State
+{
+    type State = S;
+}
+
+impl<S,C,O,E,EM,Z> Stage<E,EM,Z> for StructuredTrimStage<S,C,O>
+where
+    S: State + HasCorpus + HasMetadata + UsesInput<Input = StructuredInput>,
+    C: Named + AsMut<O> + AsRef<O>,
+    O: MapObserver + for<'it> AsIter<'it, Item = u8> + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
+    E: Executor<EM,Z> + UsesState<State = S> + HasObservers,
+    Z: UsesState<State = S>,
+    EM: UsesState<State = S>
+{
+    fn restart_progress_should_run(&mut self, _state: &mut Self::State) -> Result<bool, libafl::Error> {
+        Ok(true)
+    }
+
+    fn clear_restart_progress(&mut self, _state: &mut Self::State) -> Result<(), libafl::Error> {
+        Ok(())
+    }
+
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+    ) -> Result<(), libafl::Error> {
+        let corpus_idx = state.corpus().current().ok_or(Error::Empty("missing current".to_string(), ErrorBacktrace {}))?;
+
+        // Only trim searched entries with structure that haven't been trimmed yet.
+        let testcase = {
+            let entry = state.corpus().get(corpus_idx)?.borrow();
+            if entry.has_metadata::<TrimmedMetadata>() {
+                return Ok(());
+            }
+
+            let inner = entry.input().as_ref().unwrap();
+            if inner.status != InputStatus::Searched(corpus_idx) || inner.input.relations.is_empty() {
+                return Ok(());
+            }
+            inner.clone()
+        };
+
+        let orig_cov = self.coverage(fuzzer, executor, state, manager, &testcase)?;
+
+        let mut current = testcase.input.clone();
+        let mut chunks = current.chunks();
+        let mut attempts = 0;
+
+        // Try chunks from the end so that removals don't shift the chunks which are still left to try.
+        while let Some(chunk) = chunks.pop() {
+            if attempts >= self.max_attempts {
+                break;
+            }
+
+            if chunk.is_empty() || chunk.len() == current.get_raw().len() {
+                continue;
+            }
+
+            let mut candidate = current.clone();
+            if candidate.remove(chunk.start, chunk.len()).is_err() {
+                continue;
+            }
+            attempts += 1;
+
+            let cov = self.coverage(fuzzer, executor, state, manager, &StructuredInput::new_structured(candidate.clone()))?;
+            if orig_cov.is_subset(&cov) {
+                current = candidate;
+            }
+        }
+
+        let mut entry = state.corpus().get(corpus_idx)?.borrow().clone();
+        entry.add_metadata(TrimmedMetadata);
+
+        let orig_len = testcase.input.get_raw().len();
+        let new_len = current.get_raw().len();
+        if new_len < orig_len {
+            let inner = entry.input_mut().as_mut().unwrap();
+            inner.searched_digest = Some(RawDigest::new(current.get_raw()));
+            inner.input = current;
+            stage_line(&format!("  ({}) [trimmed {} -> {} bytes]", corpus_idx, orig_len, new_len));
+        }
+
+        state.corpus_mut().replace(corpus_idx, entry)?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,32 @@
 use std::{borrow::Cow, marker::PhantomData, rc::Rc};
 
 use libafl::{prelude::{MutationResult, Mutator}, state::{HasRand, State, UsesState}};
+use clap::ValueEnum;
 use libafl_bolts::{rands::Rand, Named};
 
+use crate::core::structured::Structured;
+
 use super::structured_input::StructuredInput;
 
 
+/// How mutations treat the constant bytes (e.g. format signatures) found by the search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ConstantMode {
+    /// Constants are mutated like any other byte (and not searched for).
+    #[default]
+    Off,
+    /// Mutations changing a constant are undone and skipped.
+    Avoid,
+    /// Constants are written back after every mutation.
+    Repair,
+}
+
 pub struct WrappedMutator<M,S> {
     mutator: M,
     name: Cow<'static, str>,
+    constants: ConstantMode,
+    /// Mutate a view of the input without the bytes of its relation fields (see `lock_fields`).
+    lock_fields: bool,
     _state: PhantomData<S>,
 }
 
@@ -17,12 +35,28 @@ where
     M: Named
 {
     pub fn new(mutator: M) -> Self {
+        Self::with_constants(mutator, ConstantMode::Off)
+    }
+
+    pub fn with_constants(mutator: M, constants: ConstantMode) -> Self {
         Self {
             name: Cow::from(format!("wrapped<{}>", mutator.name())),
             mutator,
+            constants,
+            lock_fields: false,
             _state: PhantomData,
         }
     }
+
+    /// Hides the bytes of the enabled relation fields from the wrapped mutator, which then mutates the rest of the
+    /// input as if the fields were not there, instead of overwriting them and having `sanitize` write them back.
+    /// Insertions and removals still move the fields and update their values.
+    ///
+    /// Only meant for mutators of raw bytes (havoc, I2S): structural mutators need the fields to be visible.
+    pub fn lock_fields(mut self, lock: bool) -> Self {
+        self.lock_fields = lock;
+        self
+    }
 }
 
 impl<M,S> Named for WrappedMutator<M,S>
@@ -47,17 +81,176 @@ where
     S: HasRand
 {
     fn mutate(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, libafl::Error> {
+        // Multi-file inputs get one of their files mutated.
+        let part = if input.parts.is_empty() { 0 } else { state.rand_mut().below(input.part_count()) };
 
+        input.swap_part(part);
+        let res = self.mutate_part(state, input);
+        input.swap_part(part);
+        res
+    }
+}
+
+impl<M,S> WrappedMutator<M,S>
+where 
+    M: Mutator<StructuredInput, S>,
+    S: HasRand
+{
+    /// Mutates the file of `input` swapped into `input.input`.
+    fn mutate_part(&mut self, state: &mut S, input: &mut StructuredInput) -> Result<MutationResult, libafl::Error> {
         let seed = state.rand_mut().next();
         input.set_seed(seed);
 
-        let res = self.mutator.mutate(state, input)?;
+        // Fixed-size inputs only support in-place mutations, so keep a copy to undo length changes (or changes to the
+        // constants when avoiding them).
+        let avoid = self.constants == ConstantMode::Avoid && !input.input.constants.is_empty();
+        let backup = if input.input.fixed_size || avoid { Some(input.input.clone()) } else { None };
+
+        let res = if self.lock_fields && input.input.relations.iter().any(|rel| rel.enabled) {
+            self.mutate_unlocked(state, input, seed)?
+        } else {
+            self.mutator.mutate(state, input)?
+        };
         if res == MutationResult::Skipped {
             return Ok(res);
         }
 
+        if let Some(backup) = backup {
+            let resized = input.input.fixed_size && input.input.get_raw().len() != backup.get_raw().len();
+            let clobbered = avoid && (input.input.constants.len() != backup.constants.len() || !input.input.constants_intact());
+            if resized || clobbered {
+                input.input = backup;
+                return Ok(MutationResult::Skipped);
+            }
+        }
+
         input.input.sanitize();
+        if self.constants == ConstantMode::Repair {
+            input.input.repair_constants();
+        }
+
+        Ok(res)
+    }
+
+    /// Mutates the bytes of `input.input` outside of the relation fields: the wrapped mutator gets a copy of them
+    /// without the fields, and its changes are then mapped back to the input.
+    fn mutate_unlocked(&mut self, state: &mut S, input: &mut StructuredInput, seed: u64) -> Result<MutationResult, libafl::Error> {
+        let (map, old) = unlocked_view(&input.input);
+
+        let mut view = StructuredInput::new_raw(&old);
+        view.set_seed(seed);
+        let res = self.mutator.mutate(state, &mut view)?;
+        if res == MutationResult::Skipped {
+            return Ok(res);
+        }
+        apply_view(&mut input.input, &map, &old, view.input.get_raw());
 
         Ok(res)
     }
 }
+
+/// The bytes of `input` outside of the fields of its enabled relations, along with the position in the input of each
+/// of them.
+fn unlocked_view(input: &Structured) -> (Vec<usize>, Vec<u8>) {
+    let raw = input.get_raw();
+    let mut locked = vec![false; raw.len()];
+    for rel in input.relations.iter().filter(|rel| rel.enabled) {
+        for pos in rel.field_positions() {
+            for lock in locked.iter_mut().skip(pos).take(rel.size) {
+                *lock = true;
+            }
+        }
+    }
+
+    let map = (0..raw.len()).filter(|i| !locked[*i]).collect::<Vec<_>>();
+    let view = map.iter().map(|i| raw[*i]).collect();
+    (map, view)
+}
+
+/// Maps the changes from the `old` view of `input` (see `unlocked_view`) to the `new` one back to the input.
+fn apply_view(input: &mut Structured, map: &[usize], old: &[u8], new: &[u8]) {
+    // The bytes which differ between the views: a window between a common prefix and a common suffix.
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+
+    // Overwrite the bytes the views have in common, then insert or remove the rest of the window.
+    let common = (old_end - prefix).min(new_end - prefix);
+    for i in prefix..prefix + common {
+        input.get_raw_mut()[map[i]] = new[i];
+    }
+
+    if new_end > old_end {
+        let at = map.get(old_end).copied().unwrap_or(input.get_raw().len());
+        input.insert_disabling(at, &new[prefix + common..new_end]);
+    } else if old_end > prefix + common {
+        // Remove the window one run of adjacent bytes at a time (from the end), leaving the fields in between.
+        let mut end = old_end;
+        while end > prefix + common {
+            let mut start = end - 1;
+            while start > prefix + common && map[start - 1] + 1 == map[start] {
+                start -= 1;
+            }
+            input.remove_disabling(map[start], end - start);
+            end = start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::structured::Relation;
+
+    /// AB|L|CDE, the field L counting the bytes after it.
+    fn locked_input() -> Structured {
+        let mut input = Structured::raw(vec![0x41, 0x42, 3, 0x43, 0x44, 0x45]);
+        input.add_relation(Relation::new(2, 3, 1, true, 3, 6));
+        input
+    }
+
+    fn mutated(new: &[u8]) -> Structured {
+        let mut input = locked_input();
+        let (map, old) = unlocked_view(&input);
+        apply_view(&mut input, &map, &old, new);
+        input
+    }
+
+    #[test]
+    fn test_unlocked_view() {
+        let (map, view) = unlocked_view(&locked_input());
+        assert_eq!(map, [0, 1, 3, 4, 5]);
+        assert_eq!(view, [0x41, 0x42, 0x43, 0x44, 0x45]);
+    }
+
+    #[test]
+    fn test_apply_view_overwrite() {
+        let input = mutated(&[0x41, 0x42, 0x58, 0x44, 0x45]);
+        assert_eq!(input.get_raw(), [0x41, 0x42, 3, 0x58, 0x44, 0x45]);
+    }
+
+    #[test]
+    fn test_apply_view_insert() {
+        // Right after the field, in its region.
+        let input = mutated(&[0x41, 0x42, 0x5a, 0x5a, 0x43, 0x44, 0x45]);
+        assert_eq!(input.get_raw(), [0x41, 0x42, 5, 0x5a, 0x5a, 0x43, 0x44, 0x45]);
+
+        // Before the field, which moves.
+        let input = mutated(&[0x41, 0x5a, 0x42, 0x43, 0x44, 0x45]);
+        assert_eq!(input.get_raw(), [0x41, 0x5a, 0x42, 3, 0x43, 0x44, 0x45]);
+        assert_eq!(input.relations[0].pos, 3);
+    }
+
+    #[test]
+    fn test_apply_view_remove() {
+        // Around the field, which stays in place.
+        let input = mutated(&[0x41, 0x44, 0x45]);
+        assert_eq!(input.get_raw(), [0x41, 2, 0x44, 0x45]);
+        assert_eq!(input.relations[0].pos, 1);
+
+        // At the end of the region.
+        let input = mutated(&[0x41, 0x42, 0x43, 0x44]);
+        assert_eq!(input.get_raw(), [0x41, 0x42, 2, 0x43, 0x44]);
+    }
+}
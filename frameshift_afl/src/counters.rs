@@ -0,0 +1,54 @@
+//! Edge counters of targets instrumented in several modules (e.g. a binary and its shared libraries built with
+//! `-fsanitize-coverage=inline-8bit-counters`), which register one counters region each.
+
+use libafl::observers::StdMapObserver;
+use libafl_bolts::{ownedref::OwnedMutSlice, AsSliceMut};
+use libafl_targets::extra_counters;
+
+/// Counters regions of the target, mirrored into one concatenated map after each execution so that the feedbacks and
+/// the search observe the coverage of every module.
+pub struct CounterRegions {
+    regions: Vec<(*mut u8, usize)>,
+    map: *mut u8,
+}
+
+impl CounterRegions {
+    /// Copies the counters of every region to the concatenated map and clears them for the next execution.
+    pub fn collect(&self) {
+        let mut offset = 0;
+        for (ptr, len) in &self.regions {
+            unsafe {
+                let region = std::slice::from_raw_parts_mut(*ptr, *len);
+                std::slice::from_raw_parts_mut(self.map.add(offset), *len).copy_from_slice(region);
+                region.fill(0);
+            }
+            offset += len;
+        }
+    }
+}
+
+/// Returns the edges observer over all counters regions of the target.
+///
+/// A single region is observed in place. With several regions, the observer gets a concatenated map, which must be
+/// filled with `CounterRegions::collect` after each execution of the harness.
+pub fn counters_observer() -> (StdMapObserver<'static, u8, false>, Option<CounterRegions>) {
+    let mut regions = unsafe { extra_counters() };
+    if regions.len() == 1 {
+        let obs = StdMapObserver::from_mut_slice("edges", regions.pop().unwrap());
+        return (obs, None);
+    }
+
+    let regions = regions.iter_mut()
+        .map(|region| {
+            let slice = region.as_slice_mut();
+            (slice.as_mut_ptr(), slice.len())
+        })
+        .collect::<Vec<_>>();
+    let map_len = regions.iter().map(|(_, len)| len).sum::<usize>();
+    println!("Observing {} counters regions ({} counters)", regions.len(), map_len);
+
+    let map = Box::leak(vec![0u8; map_len].into_boxed_slice());
+    let counters = CounterRegions { regions, map: map.as_mut_ptr() };
+    let obs = StdMapObserver::from_mut_slice("edges", OwnedMutSlice::from(map));
+    (obs, Some(counters))
+}
@@ -2,23 +2,21 @@ use core::{cell::RefCell, time::Duration};
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::{
+    collections::HashSet,
     env,
     fs::{File, OpenOptions},
     io::{self, Write},
     path::PathBuf,
-    process,
 };
 
 use libafl::{
-    corpus::{Corpus, InMemoryOnDiskCorpus, OnDiskCorpus}, events::SimpleRestartingEventManager, executors::{inprocess::InProcessExecutor, ExitKind}, feedback_or, feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback}, fuzzer::{Fuzzer, StdFuzzer}, inputs::{BytesInput, HasTargetBytes}, monitors::SimpleMonitor, mutators::{
+    corpus::{InMemoryOnDiskCorpus, OnDiskCorpus}, events::SimpleRestartingEventManager, executors::{inprocess::InProcessExecutor, ExitKind}, feedbacks::MaxMapFeedback, fuzzer::StdFuzzer, inputs::{BytesInput, HasTargetBytes}, mutators::{
         scheduled::havoc_mutations, token_mutations::I2SRandReplace, tokens_mutations,
-        StdMOptMutator, StdScheduledMutator, Tokens,
-    }, observers::{CanTrack, HitcountsMapObserver, TimeObserver}, prelude::StdMapObserver, schedulers::{
-        powersched::PowerSchedule, IndexesLenTimeMinimizerScheduler, StdWeightedScheduler,
-    }, stages::{
+        StdMOptMutator, StdScheduledMutator,
+    }, observers::{BacktraceObserver, CanTrack, HarnessType, HitcountsMapObserver, TimeObserver}, prelude::StdMapObserver, stages::{
         calibrate::CalibrationStage, power::StdPowerMutationalStage, StdMutationalStage,
         TracingStage,
-    }, state::{HasCorpus, StdState}, Error, HasMetadata
+    }, state::StdState, Error
 };
 use libafl_bolts::{
     current_time,
@@ -28,15 +26,13 @@ use libafl_bolts::{
     tuples::{tuple_list, Merge},
     AsSlice,
 };
-#[cfg(any(target_os = "linux", target_vendor = "apple"))]
-use libafl_targets::autotokens;
 use libafl_targets::{
     libfuzzer_initialize, CmpLogObserver,
 };
 #[cfg(unix)]
 use nix::unistd::dup;
 
-use crate::components::gen::BytesGenerator;
+use crate::{fuzz_common::{coverage_feedback, crash_objective, fuzz_loop, load_seeds, load_tokens, weighted_scheduler, RunLimits}, components::{gen::GrammarGenerator, monitor::{self, FrameShiftMonitor}, stats_stage::StatsStage, sync_stage::AflSyncStage, target_distance::DirectedTestcaseScore}};
 
 /// The actual fuzzer
 #[allow(clippy::too_many_lines)]
@@ -49,11 +45,23 @@ pub fn fuzz_afl<F>(
     tokenfile: Option<PathBuf>,
     logfile: &PathBuf,
     timeout: Duration,
+    target_edges: HashSet<usize>,
+    dedup_crashes: bool,
+    stats_file: PathBuf,
+    stats_interval: Duration,
+    sync_dir: Option<PathBuf>,
+    sync_name: String,
+    sync_interval: Duration,
+    tui: bool,
+    limits: RunLimits,
 ) -> Result<(), Error>
 where 
     F: Fn(&[u8]) -> i32
 {
     let log = RefCell::new(OpenOptions::new().append(true).create(true).open(logfile)?);
+    if tui {
+        monitor::log_stage_lines(logfile)?;
+    }
 
     #[cfg(unix)]
     let mut stdout_cpy = unsafe {
@@ -64,11 +72,13 @@ where
     let file_null = File::open("/dev/null")?;
 
     // 'While the monitor are state, they are usually used in the broker - which is likely never restarted
-    let monitor = SimpleMonitor::with_user_monitor(|s| {
-        #[cfg(unix)]
-        writeln!(&mut stdout_cpy, "{s}").unwrap();
-        #[cfg(windows)]
-        println!("{s}");
+    let monitor = FrameShiftMonitor::new(tui, |s| {
+        if !tui {
+            #[cfg(unix)]
+            writeln!(&mut stdout_cpy, "{s}").unwrap();
+            #[cfg(windows)]
+            println!("{s}");
+        }
         writeln!(log.borrow_mut(), "{:?} {s}", current_time()).unwrap();
     });
 
@@ -100,21 +110,16 @@ where
 
     let cmplog_observer = CmpLogObserver::new("cmplog", true);
 
+    // Hash of the call stack of crashing inputs, to deduplicate the crashes
+    let backtrace_observer = BacktraceObserver::owned("BacktraceObserver", HarnessType::InProcess);
+
     let map_feedback = MaxMapFeedback::new(&edges_observer);
 
     let calibration = CalibrationStage::new(&map_feedback);
 
-    // Feedback to rate the interestingness of an input
-    // This one is composed by two Feedbacks in OR
-    let mut feedback = feedback_or!(
-        // New maximization map feedback linked to the edges observer and the feedback state
-        map_feedback,
-        // Time feedback, this one does not need a feedback state
-        TimeFeedback::new(&time_observer)
-    );
+    let mut feedback = coverage_feedback(map_feedback, &edges_observer, &time_observer, &target_edges);
 
-    // A feedback to choose if an input is a solution or not
-    let mut objective = CrashFeedback::new();
+    let mut objective = crash_objective(objective_dir.clone(), dedup_crashes, &backtrace_observer);
 
     // If not restarting, create a State from scratch
     let mut state = state.unwrap_or_else(|| {
@@ -150,11 +155,8 @@ where
 
     let power = StdPowerMutationalStage::new(mutator);
 
-    // A minimization+queue policy to get testcasess from the corpus
-    let scheduler = IndexesLenTimeMinimizerScheduler::new(
-        &edges_observer,
-        StdWeightedScheduler::with_schedule(&mut state, &edges_observer, Some(PowerSchedule::FAST)),
-    );
+    // Favoring the entries closest to the target sites (if any)
+    let scheduler = weighted_scheduler::<DirectedTestcaseScore<_>, _, _, _>(&mut state, &edges_observer);
 
     // A fuzzer with feedbacks and a corpus scheduler
     let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
@@ -163,6 +165,9 @@ where
     let mut harness = |input: &BytesInput| {
         let target = input.target_bytes();
         let buf = target.as_slice();
+        if !limits.admits(buf.len()) {
+            return ExitKind::Ok;
+        }
         fuzz_fn(buf);
         ExitKind::Ok
     };
@@ -172,7 +177,7 @@ where
     // Create the executor for an in-process function with one observer for edge coverage and one for the execution time
     let mut executor = InProcessExecutor::with_timeout(
         &mut harness,
-        tuple_list!(edges_observer, time_observer),
+        tuple_list!(edges_observer, time_observer, backtrace_observer),
         &mut fuzzer,
         &mut state,
         &mut mgr,
@@ -193,40 +198,17 @@ where
     );
 
     // The order of the stages matter!
-    let mut stages = tuple_list!(calibration, tracing, i2s, power);
-
-    // Read tokens
-    if state.metadata_map().get::<Tokens>().is_none() {
-        let mut toks = Tokens::default();
-        if let Some(tokenfile) = tokenfile {
-            toks.add_from_file(tokenfile)?;
-        }
-        #[cfg(any(target_os = "linux", target_vendor = "apple"))]
-        {
-            toks += autotokens()?;
-        }
-
-        if !toks.is_empty() {
-            state.add_metadata(toks);
-        }
-    }
-
-    // In case the corpus is empty (on first run), reset
-    if state.must_load_initial_inputs() {
-        state
-            .load_initial_inputs(&mut fuzzer, &mut executor, &mut mgr, &[seed_dir.clone()])
-            .unwrap_or_else(|_| {
-                println!("Failed to load initial corpus at {:?}", &seed_dir);
-                process::exit(0);
-            });
-        println!("We imported {} inputs from disk.", state.corpus().count());
-    }
+    let mut stages = tuple_list!(
+        calibration,
+        tracing,
+        i2s,
+        power,
+        AflSyncStage::new(sync_dir, sync_name, sync_interval),
+        StatsStage::new(stats_file, stats_interval)
+    );
 
-    // If corpus is empty, add a seed
-    if state.corpus().count() == 0 {
-        let mut generator = BytesGenerator;
-        state.generate_initial_inputs_forced(&mut fuzzer, &mut executor, &mut generator, &mut mgr, 1).unwrap();
-    }
+    load_tokens(&mut state, tokenfile)?;
+    load_seeds(&mut state, &mut fuzzer, &mut executor, &mut mgr, &mut GrammarGenerator::new(), seed_dir)?;
 
     // Remove target output (logs still survive)
     #[cfg(unix)]
@@ -240,7 +222,7 @@ where
     // reopen file to make sure we're at the end
     log.replace(OpenOptions::new().append(true).create(true).open(logfile)?);
 
-    fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut mgr)?;
+    fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, limits)?;
 
     // Never reached
     Ok(())
@@ -0,0 +1,185 @@
+//! Construction code shared by `fuzz_frameshift` and `fuzz_afl`, so that the baseline only differs from FrameShift
+//! by the search and the structured input.
+
+use std::{collections::HashSet, path::PathBuf, process};
+
+use libafl::{
+    corpus::{Corpus, HasTestcase}, events::{EventFirer, EventRestarter, ProgressReporter}, feedback_and_fast, feedback_or,
+    feedback_or_fast, feedbacks::{ConstFeedback, CrashFeedback, Feedback, NewHashFeedback, TimeFeedback},
+    fuzzer::{Evaluator, Fuzzer}, generators::Generator, inputs::{HasLen, Input}, mutators::Tokens,
+    observers::{CanTrack, MapObserver, ObserverWithHashField, TimeObserver},
+    schedulers::{powersched::PowerSchedule, testcase_score::TestcaseScore, IndexesLenTimeMinimizerScheduler, RemovableScheduler, WeightedScheduler},
+    stages::StagesTuple, state::{HasCorpus, HasExecutions, HasRand, State, StdState, UsesState}, Error, HasMetadata,
+    HasNamedMetadata
+};
+use libafl_bolts::{rands::Rand, AsSlice, Named};
+#[cfg(any(target_os = "linux", target_vendor = "apple"))]
+use libafl_targets::autotokens;
+
+use crate::components::{repro_bundle::ReproBundleFeedback, target_distance::TargetDistanceFeedback};
+
+/// Number of inputs synthesized from the tokens when the corpus is empty.
+const GENERATED_SEEDS: usize = 64;
+
+/// Limits of a campaign, like the `-runs` and `-max_len` flags of libFuzzer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunLimits {
+    /// Executions of the target after which the campaign ends.
+    pub runs: Option<u64>,
+
+    /// Largest input the target runs on.
+    pub max_len: Option<usize>,
+}
+
+impl RunLimits {
+    /// Whether an input of `len` bytes may run. Longer ones are skipped (reaching no coverage), so they are never
+    /// stored either.
+    pub fn admits(&self, len: usize) -> bool {
+        self.max_len.map_or(true, |max_len| len <= max_len)
+    }
+}
+
+/// Feedback to rate the interestingness of an input, composed of three feedbacks in OR: the coverage (`map_feedback`,
+/// on the edges observer), the execution time and getting closer to the target sites (if any), which also records the
+/// distance of every corpus entry.
+pub fn coverage_feedback<S, F, C, O>(
+    map_feedback: F,
+    edges_observer: &C,
+    time_observer: &TimeObserver,
+    target_edges: &HashSet<usize>,
+) -> impl Feedback<S>
+where
+    S: State + HasMetadata,
+    F: Feedback<S>,
+    C: Named + AsRef<O>,
+    O: MapObserver + for<'it> AsSlice<'it, SliceRef = &'it [u8]>,
+{
+    feedback_or!(
+        map_feedback,
+        TimeFeedback::new(time_observer),
+        TargetDistanceFeedback::new(edges_observer, target_edges)
+    )
+}
+
+/// Feedback to choose if an input is a solution: it crashes. With `dedup_crashes`, only crashes with a new call stack
+/// (hashed by `backtrace_observer`) are kept, and each kept crash gets a reproduction bundle in `objective_dir`.
+pub fn crash_objective<S, O>(objective_dir: PathBuf, dedup_crashes: bool, backtrace_observer: &O) -> impl Feedback<S>
+where
+    S: State + HasCorpus + HasNamedMetadata,
+    O: ObserverWithHashField + Named,
+{
+    feedback_and_fast!(
+        CrashFeedback::new(),
+        feedback_or_fast!(ConstFeedback::new(!dedup_crashes), NewHashFeedback::new(backtrace_observer)),
+        ReproBundleFeedback::new(objective_dir)
+    )
+}
+
+/// A minimization+queue policy to get testcases from the corpus, weighting the entries with the score `F` under the
+/// FAST power schedule.
+pub fn weighted_scheduler<F, C, O, S>(state: &mut S, edges_observer: &C) -> impl RemovableScheduler<State = S>
+where
+    F: TestcaseScore<S>,
+    C: AsRef<O> + CanTrack + Named,
+    O: MapObserver,
+    S: State + HasCorpus + HasMetadata + HasRand + HasTestcase,
+    S::Input: HasLen,
+{
+    IndexesLenTimeMinimizerScheduler::new(
+        edges_observer,
+        WeightedScheduler::<_, F, _, _>::with_schedule(state, edges_observer, Some(PowerSchedule::FAST)),
+    )
+}
+
+/// Runs the fuzz loop until the executions (counted in the state, so across restarts) reach `limits.runs`, then tells
+/// the restarting manager to exit instead of restarting the fuzzer.
+pub fn fuzz_loop<Z, E, EM, ST>(
+    fuzzer: &mut Z,
+    stages: &mut ST,
+    executor: &mut E,
+    state: &mut EM::State,
+    mgr: &mut EM,
+    limits: RunLimits,
+) -> Result<(), Error>
+where
+    Z: Fuzzer<E, EM, ST> + UsesState<State = EM::State>,
+    E: UsesState<State = EM::State>,
+    EM: ProgressReporter + EventRestarter,
+    EM::State: HasExecutions,
+    ST: StagesTuple<E, EM, EM::State, Z>,
+{
+    let Some(runs) = limits.runs else {
+        return fuzzer.fuzz_loop(stages, executor, state, mgr);
+    };
+
+    while *state.executions() < runs {
+        fuzzer.fuzz_one(stages, executor, state, mgr)?;
+    }
+    println!("Done {} runs", state.executions());
+    mgr.send_exiting()
+}
+
+/// Loads the tokens of `tokenfile` and the target's autotokens (unless restarting).
+pub fn load_tokens<S>(state: &mut S, tokenfile: Option<PathBuf>) -> Result<(), Error>
+where
+    S: HasMetadata
+{
+    if state.metadata_map().get::<Tokens>().is_none() {
+        let mut toks = Tokens::default();
+        if let Some(tokenfile) = tokenfile {
+            toks.add_from_file(tokenfile)?;
+        }
+        #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+        {
+            toks += autotokens()?;
+        }
+
+        if !toks.is_empty() {
+            state.add_metadata(toks);
+        }
+    }
+    Ok(())
+}
+
+/// Loads the initial corpus from `seed_dir` (on first run). If the corpus is empty, synthesizes seeds from the tokens
+/// (keeping the interesting ones), or adds a placeholder seed.
+pub fn load_seeds<I, C, R, SC, Z, E, EM, G>(
+    state: &mut StdState<I, C, R, SC>,
+    fuzzer: &mut Z,
+    executor: &mut E,
+    mgr: &mut EM,
+    generator: &mut G,
+    seed_dir: &PathBuf,
+) -> Result<(), Error>
+where
+    I: Input,
+    C: Corpus<Input = I>,
+    R: Rand,
+    SC: Corpus<Input = I>,
+    Z: Evaluator<E, EM, State = StdState<I, C, R, SC>>,
+    E: UsesState<State = StdState<I, C, R, SC>>,
+    EM: EventFirer<State = StdState<I, C, R, SC>>,
+    G: Generator<I, StdState<I, C, R, SC>>,
+{
+    // In case the corpus is empty (on first run), reset
+    if state.must_load_initial_inputs() {
+        state
+            .load_initial_inputs(fuzzer, executor, mgr, &[seed_dir.clone()])
+            .unwrap_or_else(|_| {
+                println!("Failed to load initial corpus at {:?}", &seed_dir);
+                process::exit(0);
+            });
+        println!("We imported {} inputs from disk.", state.corpus().count());
+    }
+
+    if state.corpus().count() == 0 {
+        if state.has_metadata::<Tokens>() {
+            state.generate_initial_inputs(fuzzer, executor, generator, mgr, GENERATED_SEEDS)?;
+            println!("We generated {} inputs from tokens.", state.corpus().count());
+        }
+        if state.corpus().count() == 0 {
+            state.generate_initial_inputs_forced(fuzzer, executor, generator, mgr, 1)?;
+        }
+    }
+    Ok(())
+}
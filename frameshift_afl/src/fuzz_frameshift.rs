@@ -2,23 +2,22 @@ use core::{cell::RefCell, time::Duration};
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::{
+    collections::HashSet,
     env,
     fs::{File, OpenOptions},
     io::{self, Write},
     path::PathBuf,
-    process, rc::Rc,
+    rc::Rc,
 };
 
 use libafl::{
-    corpus::{Corpus, InMemoryOnDiskCorpus, OnDiskCorpus}, events::SimpleRestartingEventManager, executors::{inprocess::InProcessExecutor, ExitKind}, feedback_or, feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback}, fuzzer::{Fuzzer, StdFuzzer}, inputs::HasTargetBytes, monitors::SimpleMonitor, mutators::{
+    corpus::{InMemoryOnDiskCorpus, OnDiskCorpus}, events::SimpleRestartingEventManager, executors::{inprocess::InProcessExecutor, InProcessForkExecutor, ExitKind}, feedback_or, feedbacks::MaxMapFeedback, fuzzer::StdFuzzer, inputs::HasTargetBytes, mutators::{
         scheduled::havoc_mutations, token_mutations::I2SRandReplace, tokens_mutations,
-        StdMOptMutator, StdScheduledMutator, Tokens,
-    }, observers::{CanTrack, HitcountsMapObserver, TimeObserver}, prelude::StdMapObserver, schedulers::{
-        powersched::PowerSchedule, IndexesLenTimeMinimizerScheduler, StdWeightedScheduler,
-    }, stages::{
-        calibrate::CalibrationStage, power::StdPowerMutationalStage, StdMutationalStage,
+        StdMOptMutator, StdScheduledMutator,
+    }, observers::{BacktraceObserver, CanTrack, HarnessType, HitcountsMapObserver, TimeObserver}, prelude::StdMapObserver, stages::{
+        calibrate::CalibrationStage, power::PowerMutationalStage, StdMutationalStage,
         TracingStage,
-    }, state::{HasCorpus, StdState}, Error, HasMetadata
+    }, state::StdState, Error, HasMetadata
 };
 use libafl_bolts::{
     current_time,
@@ -26,18 +25,15 @@ use libafl_bolts::{
     rands::StdRand,
     shmem::{ShMemProvider, StdShMemProvider},
     tuples::{tuple_list, Merge},
-    AsSlice,
+    AsSlice, AsSliceMut,
 };
-
-#[cfg(any(target_os = "linux", target_vendor = "apple"))]
-use libafl_targets::autotokens;
 use libafl_targets::{
     libfuzzer_initialize, CmpLogObserver
 };
 #[cfg(unix)]
 use nix::unistd::dup;
 
-use crate::components::{gen::GrammarGenerator, search_stage::{SearchStage, SearchStageArgs}, structured_input::StructuredInput, wrapped_mutator::WrappedMutator};
+use crate::{fuzz_common::{coverage_feedback, crash_objective, fuzz_loop, load_seeds, load_tokens, weighted_scheduler, RunLimits}, components::{aging_stage::AgingStage, crash_proximity::CrashEdgesFeedback, crossover::{HavocSpliceMutator, StructuredCrossoverMutator, StructuredSpliceMutator}, cull_stage::CorpusCullStage, field_value::FieldValueMutator, field_value_stage::FieldValueMutationalStage, file_set::write_parts, gen::{GrammarGenerator, StructureGenerationStage}, length_extension::LengthExtensionMutator, monitor::{self, FrameShiftMonitor}, overread::{self, OverreadFeedback}, search_executor::SearchExecutor, search_stage::{SearchStage, SearchStageArgs}, stage_weights::{StageWeights, WeightedStage}, stats_stage::StatsStage, sync_stage::AflSyncStage, string_mutator::StringMutator, structure_energy::{StructuralYieldMutator, StructureBoostMetadata, StructureBoostTestcaseScore, StructuredPowerTestcaseScore}, structured_input::StructuredInput, token_stage::StructureTokensStage, trim_stage::StructuredTrimStage, wrapped_mutator::{ConstantMode, WrappedMutator}}};
 
 /// The actual fuzzer
 #[allow(clippy::too_many_lines)]
@@ -50,12 +46,34 @@ pub fn fuzz_frameshift<F>(
     tokenfile: Option<PathBuf>,
     logfile: &PathBuf,
     timeout: Duration,
+    search_timeout: Duration,
     search_args: SearchStageArgs,
+    max_corpus_entries: Option<usize>,
+    max_idle_cycles: Option<usize>,
+    field_value_ood: f64,
+    constant_mode: ConstantMode,
+    lock_fields: bool,
+    detect_overreads: bool,
+    structure_boost: f64,
+    generate_below: usize,
+    stage_weights: StageWeights,
+    target_edges: HashSet<usize>,
+    dedup_crashes: bool,
+    stats_file: PathBuf,
+    stats_interval: Duration,
+    sync_dir: Option<PathBuf>,
+    sync_name: String,
+    sync_interval: Duration,
+    tui: bool,
+    limits: RunLimits,
 ) -> Result<(), Error> 
 where
     F: Fn(&[u8]) -> i32,
 {
     let log = RefCell::new(OpenOptions::new().append(true).create(true).open(logfile)?);
+    if tui {
+        monitor::log_stage_lines(logfile)?;
+    }
 
     #[cfg(unix)]
     let mut stdout_cpy = unsafe {
@@ -66,11 +84,13 @@ where
     let file_null = File::open("/dev/null")?;
 
     // 'While the monitor are state, they are usually used in the broker - which is likely never restarted
-    let monitor = SimpleMonitor::with_user_monitor(|s| {
-        #[cfg(unix)]
-        writeln!(&mut stdout_cpy, "{s}").unwrap();
-        #[cfg(windows)]
-        println!("{s}");
+    let monitor = FrameShiftMonitor::new(tui, |s| {
+        if !tui {
+            #[cfg(unix)]
+            writeln!(&mut stdout_cpy, "{s}").unwrap();
+            #[cfg(windows)]
+            println!("{s}");
+        }
         writeln!(log.borrow_mut(), "{:?} {s}", current_time()).unwrap();
     });
 
@@ -92,31 +112,51 @@ where
         },
     };
 
+    // Further observation channels on the same coverage map, for the executor running the search probes
+    let (map_ptr, map_len) = (obs.as_slice().as_ptr() as *mut u8, obs.as_slice().len());
+    let search_edges_observer = || {
+        HitcountsMapObserver::new(unsafe { StdMapObserver::from_mut_ptr("edges", map_ptr, map_len) })
+    };
+
+    // The value profile of the comparisons (filled by the trace-cmp hooks), which the search measures along with the
+    // edges with --search-value-profile
+    let (cmp_map_ptr, cmp_map_len) = value_profile_map();
+    let search_value_profile_observer = || {
+        HitcountsMapObserver::new(unsafe { StdMapObserver::from_mut_ptr("value_profile", cmp_map_ptr, cmp_map_len) })
+    };
+
     // Create an observation channel using the coverage map
     // We don't use the hitcounts (see the Cargo.toml, we use pcguard_edges)
     let edges_observer =
-        HitcountsMapObserver::new(obs).track_indices();
+        HitcountsMapObserver::new(obs).track_indices().track_novelties();
 
     // Create an observation channel to keep track of the execution time
     let time_observer = TimeObserver::new("time");
 
     let cmplog_observer = CmpLogObserver::new("cmplog", true);
 
+    // Hash of the call stack of crashing inputs, to deduplicate the crashes
+    let backtrace_observer = BacktraceObserver::owned("BacktraceObserver", HarnessType::InProcess);
+
     let map_feedback = MaxMapFeedback::new(&edges_observer);
 
     let calibration = CalibrationStage::new(&map_feedback);
 
-    // Feedback to rate the interestingness of an input
-    // This one is composed by two Feedbacks in OR
-    let mut feedback = feedback_or!(
-        // New maximization map feedback linked to the edges observer and the feedback state
-        map_feedback,
-        // Time feedback, this one does not need a feedback state
-        TimeFeedback::new(&time_observer)
-    );
+    let mut feedback = coverage_feedback(map_feedback, &edges_observer, &time_observer, &target_edges);
+
+    // With `detect_overreads`, inputs are followed by poisoned bytes, so that ASAN reports the targets reading past
+    // the end of the input (i.e. trusting a length larger than the data)
+    if detect_overreads && !overread::enable() {
+        println!("The target is not built with ASAN, over-reads will not be detected");
+    }
 
-    // A feedback to choose if an input is a solution or not
-    let mut objective = CrashFeedback::new();
+    // The crash edges are recorded so the search can be prioritized near crashes.
+    // Over-reads are kept regardless of `dedup_crashes`.
+    let mut objective = feedback_or!(
+        crash_objective(objective_dir.clone(), dedup_crashes, &backtrace_observer),
+        OverreadFeedback::default(),
+        CrashEdgesFeedback::new(&edges_observer)
+    );
 
     // If not restarting, create a State from scratch
     let mut state = state.unwrap_or_else(|| {
@@ -137,47 +177,104 @@ where
         .unwrap()
     });
 
+    // Read by the scheduler weights (also after a restart, in case the option changed)
+    state.add_metadata(StructureBoostMetadata { factor: structure_boost });
+
     println!("Let's fuzz :)");
 
-    let w = WrappedMutator::new(
+    let w = WrappedMutator::with_constants(
         StdScheduledMutator::new(tuple_list!(I2SRandReplace::new())),
-    );
+        constant_mode,
+    ).lock_fields(lock_fields);
 
     // Setup a randomic Input2State stage
-    let i2s = StdMutationalStage::new(
+    // The mutational stages run a number of times on each entry, fixed or following their recent corpus additions.
+    let i2s = WeightedStage::new("i2s", StdMutationalStage::new(
         w
-    );
-
-    // Setup a MOPT mutator
-    let mutator = WrappedMutator::new(
+    ), &stage_weights);
+
+    // Setup a stage of structure-aware mutations: appending trailing data inside nested regions, splicing whole
+    // regions or ranges of chunks from other corpus entries (with their relations), setting length fields to values
+    // seen in the corpus and resizing strings
+    // Their yield on each entry is recorded for the power schedule.
+    let structural = WeightedStage::new("structural", StdMutationalStage::new(
+        StructuralYieldMutator::new(WrappedMutator::with_constants(StdScheduledMutator::new(tuple_list!(
+            LengthExtensionMutator::new(),
+            StructuredCrossoverMutator::new(),
+            StructuredSpliceMutator::new(),
+            FieldValueMutator::new(field_value_ood),
+            StringMutator::new()
+        )), constant_mode)),
+    ), &stage_weights);
+
+    // Setup a MOPT mutator, whose splice inherits the donor relations
+    let mutator = WrappedMutator::with_constants(
         StdMOptMutator::new(
             &mut state,
-            havoc_mutations().merge(tokens_mutations()),
+            havoc_mutations().merge(tokens_mutations()).merge(tuple_list!(HavocSpliceMutator::new())),
             7,
             5,
         )?,
-    );
+        constant_mode,
+    ).lock_fields(lock_fields);
 
-    let power = StdPowerMutationalStage::new(mutator);
+    // The energy of an entry also grows with its structure and the yield of the structural mutations on it
+    let power = WeightedStage::new("havoc", PowerMutationalStage::<_, StructuredPowerTestcaseScore<_>, _, _, _, _>::new(mutator), &stage_weights);
 
-    // A minimization+queue policy to get testcasess from the corpus
-    let scheduler = IndexesLenTimeMinimizerScheduler::new(
-        &edges_observer,
-        StdWeightedScheduler::with_schedule(&mut state, &edges_observer, Some(PowerSchedule::FAST)),
-    );
+    // Favoring the entries closest to the target sites (if any) and the entries with relations
+    let scheduler = weighted_scheduler::<StructureBoostTestcaseScore<_>, _, _, _>(&mut state, &edges_observer);
 
     // A fuzzer with feedbacks and a corpus scheduler
     let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
 
-    // The wrapped harness function, calling out to the LLVM-style harness
-    let mut harness = |input: &StructuredInput| {
+    // The wrapped harness function, calling out to the LLVM-style harness (after writing the other files of
+    // multi-file inputs). With `detect_overreads`, the fuzzed inputs first run cut at the end of the regions of their
+    // lying fields (see `overread::guard_regions`), whose coverage is then cleared so that only the whole input's is
+    // observed.
+    let run = |input: &StructuredInput, guard_regions: bool| {
         let target = input.target_bytes();
-        let buf = target.as_slice();
-        fuzz_fn(buf);
+        if !limits.admits(target.as_slice().len()) {
+            return ExitKind::Ok;
+        }
+        write_parts(input);
+        if guard_regions && overread::guard_regions(&input.input, |buf| { fuzz_fn(buf); }) {
+            unsafe { std::slice::from_raw_parts_mut(map_ptr, map_len) }.fill(0);
+        }
+        overread::guarded(target.as_slice(), |buf| fuzz_fn(buf));
         ExitKind::Ok
     };
+    let mut harness = |input: &StructuredInput| run(input, true);
+
+    let mut tracing_harness = |input: &StructuredInput| run(input, false);
+    let mut search_harness = tracing_harness;
+
+    // In fork mode, the probes run in a child process: it copies the coverage it reaches to a shared map, which is
+    // observed by the search instead of the edges map (and likewise for the value profile)
+    let use_value_profile = search_args.options.use_value_profile;
+    let mut fork_map = shmem_provider.new_shmem(map_len)?;
+    let fork_map_ptr = fork_map.as_slice_mut().as_mut_ptr();
+    let mut fork_cmp_map = shmem_provider.new_shmem(cmp_map_len)?;
+    let fork_cmp_map_ptr = fork_cmp_map.as_slice_mut().as_mut_ptr();
+    let mut fork_harness = |input: &StructuredInput| {
+        let edges = unsafe { std::slice::from_raw_parts_mut(map_ptr, map_len) };
+        edges.fill(0);
+        let cmp_map = unsafe { std::slice::from_raw_parts_mut(cmp_map_ptr, cmp_map_len) };
+        if use_value_profile {
+            cmp_map.fill(0);
+        }
 
-    let mut tracing_harness = harness;
+        let target = input.target_bytes();
+        if limits.admits(target.as_slice().len()) {
+            write_parts(input);
+            fuzz_fn(target.as_slice());
+        }
+
+        unsafe { std::slice::from_raw_parts_mut(fork_map_ptr, map_len) }.copy_from_slice(edges);
+        if use_value_profile {
+            unsafe { std::slice::from_raw_parts_mut(fork_cmp_map_ptr, cmp_map_len) }.copy_from_slice(cmp_map);
+        }
+        ExitKind::Ok
+    };
 
     // Setup a tracing stage in which we log comparisons
     let tracing = TracingStage::new(
@@ -192,57 +289,67 @@ where
         // Give it more time!
     );
 
+    // Search probes run on their own executor, so that slow targets can get a longer timeout
+    let search_executor = if search_args.fork {
+        SearchExecutor::Fork(InProcessForkExecutor::new(
+            &mut fork_harness,
+            tuple_list!(
+                HitcountsMapObserver::new(unsafe { StdMapObserver::from_mut_ptr("edges", fork_map_ptr, map_len) }),
+                HitcountsMapObserver::new(unsafe { StdMapObserver::from_mut_ptr("value_profile", fork_cmp_map_ptr, cmp_map_len) })
+            ),
+            &mut fuzzer,
+            &mut state,
+            &mut mgr,
+            search_timeout,
+            shmem_provider.clone(),
+        )?)
+    } else {
+        SearchExecutor::InProcess(InProcessExecutor::with_timeout(
+            &mut search_harness,
+            tuple_list!(search_edges_observer(), search_value_profile_observer()),
+            &mut fuzzer,
+            &mut state,
+            &mut mgr,
+            search_timeout,
+        )?)
+    };
+    let mut search = SearchStage::new(&search_edges_observer(), search_executor, search_args.clone());
+    if use_value_profile {
+        search = search.with_value_profile(&search_value_profile_observer());
+    }
+
     // The order of the stages matter!
+    // Tracing runs before the search so the search can use the cmplog values of the current testcase.
+    // Aging runs first so the structure of a demoted testcase is restored before anything uses it.
     let mut stages = tuple_list!(
-        SearchStage::new(&edges_observer, search_args.clone()),
-        calibration,
+        AgingStage::new(max_idle_cycles),
         tracing,
+        search,
+        StructureTokensStage::new(),
+        StructuredTrimStage::new(&edges_observer, 64),
+        FieldValueMutationalStage::new(16),
+        StructureGenerationStage::new(generate_below),
+        calibration,
         i2s,
-        power
+        structural,
+        power,
+        CorpusCullStage::new(max_corpus_entries),
+        AflSyncStage::new(sync_dir, sync_name, sync_interval),
+        StatsStage::new(stats_file, stats_interval)
     );
 
     // Create the executor for an in-process function with one observer for edge coverage and one for the execution time
     let mut executor = InProcessExecutor::with_timeout(
         &mut harness,
-        tuple_list!(edges_observer, time_observer),
+        tuple_list!(edges_observer, time_observer, backtrace_observer),
         &mut fuzzer,
         &mut state,
         &mut mgr,
         timeout,
     )?;
 
-    // Read tokens
-    if state.metadata_map().get::<Tokens>().is_none() {
-        let mut toks = Tokens::default();
-        if let Some(tokenfile) = tokenfile {
-            toks.add_from_file(tokenfile)?;
-        }
-        #[cfg(any(target_os = "linux", target_vendor = "apple"))]
-        {
-            toks += autotokens()?;
-        }
-
-        if !toks.is_empty() {
-            state.add_metadata(toks);
-        }
-    }
-
-    // In case the corpus is empty (on first run), reset
-    if state.must_load_initial_inputs() {
-        state
-            .load_initial_inputs(&mut fuzzer, &mut executor, &mut mgr, &[seed_dir.clone()])
-            .unwrap_or_else(|_| {
-                println!("Failed to load initial corpus at {:?}", &seed_dir);
-                process::exit(0);
-            });
-        println!("We imported {} inputs from disk.", state.corpus().count());
-    }
-
-    // If corpus is empty, add a seed
-    if state.corpus().count() == 0 {
-        let mut generator = GrammarGenerator;
-        state.generate_initial_inputs_forced(&mut fuzzer, &mut executor, &mut generator, &mut mgr, 1).unwrap();
-    }
+    load_tokens(&mut state, tokenfile)?;
+    load_seeds(&mut state, &mut fuzzer, &mut executor, &mut mgr, &mut GrammarGenerator::new(), seed_dir)?;
 
     // Remove target output (logs still survive)
     #[cfg(unix)]
@@ -256,8 +363,21 @@ where
     // reopen file to make sure we're at the end
     log.replace(OpenOptions::new().append(true).create(true).open(logfile)?);
 
-    fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut mgr)?;
+    fuzz_loop(&mut fuzzer, &mut stages, &mut executor, &mut state, &mut mgr, limits)?;
 
     // Never reached
     Ok(())
 }
+
+/// The value profile map filled by the trace-cmp hooks. It is only linked in with the `value_profile` feature, as
+/// the hooks cost every execution; otherwise the search observes an empty map.
+#[cfg(feature = "value_profile")]
+fn value_profile_map() -> (*mut u8, usize) {
+    (unsafe { std::ptr::addr_of_mut!(libafl_targets::CMP_MAP) as *mut u8 }, libafl_targets::CMP_MAP_SIZE)
+}
+
+#[cfg(not(feature = "value_profile"))]
+fn value_profile_map() -> (*mut u8, usize) {
+    static mut NO_VALUE_PROFILE: [u8; 1] = [0];
+    (unsafe { std::ptr::addr_of_mut!(NO_VALUE_PROFILE) as *mut u8 }, 1)
+}
@@ -1,144 +1,1120 @@
 //! A singlethreaded libfuzzer-like fuzzer that can auto-restart.
-use components::search_stage::SearchStageArgs;
+use components::{search_stage::{SearchBudget, SearchStageArgs, Warmup}, stage_weights::StageWeights, structured_input::{annotation_path, corpus_files, read_annotation, set_annotation_format, write_annotation}, wrapped_mutator::ConstantMode};
 use libafl::prelude::{MapObserver, StdMapObserver};
-use libafl_targets::{extra_counters, libfuzzer_initialize, libfuzzer_test_one_input, std_edges_map_observer};
+use libafl_targets::{libfuzzer_initialize, libfuzzer_test_one_input, std_edges_map_observer};
 use libafl_bolts::{AsIter, AsSlice};
 use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-use core::{search::{SearchContext, SearchOptions}, structured::Structured};
+use core::{annotation::AnnotationFormat, filler::Filler, gates::{field_gates, FieldGates}, search::{merge_heuristic_stats, CoverageSnapshot, SearchContext, SearchOptions, SearchResult}, structured::Structured};
+use modes::{coverage_export::{symbolize_edges, EdgeSymbol}, external_coverage::coverage_backend};
 use std::{
-    collections::HashSet, env, fs::{self}, path::PathBuf, time::{Duration, Instant}
+    collections::{BTreeMap, HashMap}, env, fs::{self}, path::{Path, PathBuf}, time::{Duration, Instant}
 };
 
-use clap::{Args, Parser};
+use clap::{error::ErrorKind, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 
 
-pub mod core;
+pub use ::frameshift_core as core;
 pub mod components;
+pub mod counters;
 pub mod fuzz_afl;
+pub mod fuzz_common;
 pub mod fuzz_frameshift;
+pub mod modes;
+pub mod preflight;
+
+/// LibAFL-independent entry point for structure inference (the `frameshift_core` crate).
+pub use core::api as frameshift_core;
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
     /// Positional arguments that can appear before or after named arguments
     pub args: Vec<String>,
 
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Options without a command, which accept every option along with the mode flags replaced by the commands
+    /// (`-i`/`-o`, `-a`, `--validate`, ...), hidden from the help. A command only takes its own options.
     #[command(flatten)]
     pub options: Options,
 }
 
+impl Cli {
+    /// Returns the options with the arguments of the command folded into the corresponding mode flags, which
+    /// `entrypoint` dispatches on. The options which the command does not take keep their defaults.
+    pub fn into_options(self) -> Options {
+        let Some(command) = self.command else {
+            // Options before the command make clap take the command for a positional argument.
+            if let Some(name) = self.args.iter().find(|arg| Cli::command().find_subcommand(arg).is_some()) {
+                Cli::command().error(ErrorKind::ArgumentConflict, format!("the options of `{}` go after the command", name)).exit();
+            }
+            return self.options;
+        };
+
+        let mut options = defaults::<Options>();
+        match command {
+            Command::Fuzz(args) => {
+                options.modes.input = Some(args.input);
+                options.modes.out = Some(args.out);
+                options.target = args.target;
+                options.search = args.search;
+                options.fuzz = args.fuzz;
+                options.sidecar = args.sidecar;
+            }
+            Command::Analyze(args) => {
+                match Path::new(&args.path).is_dir() {
+                    true => options.modes.analyze_dir = Some(args.path),
+                    false => options.modes.analyze = Some(args.path),
+                }
+                options.target = args.target;
+                options.coverage = args.coverage;
+                options.search = args.search;
+                options.analysis = args.analysis;
+                options.sidecar = args.sidecar;
+            }
+            Command::Triage(args) => {
+                options.modes.triage = Some(args.crash);
+                options.target = args.target;
+            }
+            Command::Cmin(args) => {
+                options.modes.cmin = Some(args.corpus);
+                options.modes.out = Some(args.out);
+                options.target = args.target;
+                options.sidecar = args.sidecar;
+            }
+            Command::Bench(args) => {
+                options.modes.bench_ground_truth = Some(args.spec_dir);
+                options.target = args.target;
+                options.search = args.search;
+            }
+            Command::Explain(args) => {
+                options.modes.explain = Some(vec![args.input, args.pos]);
+                options.target = args.target;
+                options.search = args.search;
+            }
+            Command::Annotate(args) => {
+                options.modes.annotate = Some(args.input);
+                options.target = args.target;
+                options.coverage = args.coverage;
+                options.search = args.search;
+            }
+            Command::Edit(args) => {
+                options.modes.edit = Some(args.input);
+                options.modes.edit_patch = args.patch;
+                options.target = args.target;
+                options.sidecar = args.sidecar;
+            }
+            Command::Freeze(args) => {
+                options.modes.freeze = Some(args.input);
+                options.sidecar = args.sidecar;
+            }
+            Command::Unfreeze(args) => {
+                options.modes.unfreeze = Some(args.input);
+                options.sidecar = args.sidecar;
+            }
+            Command::Diff(args) => options.modes.diff = Some(vec![args.old, args.new]),
+            Command::Import010(args) => {
+                options.modes.import_010 = Some(vec![args.export, args.seed]);
+                options.sidecar = args.sidecar;
+            }
+            Command::ExportCoverage(args) => {
+                options.modes.export_coverage = Some(vec![args.corpus, args.out]);
+                options.modes.export_coverage_format = args.format;
+                options.target = args.target;
+            }
+            Command::Validate(args) => {
+                options.modes.validate = Some(args.corpus);
+                options.modes.validate_threshold = args.threshold;
+                options.target = args.target;
+            }
+            Command::FieldValues(args) => options.modes.field_values = Some(args.corpus),
+            Command::Strip(args) => {
+                options.modes.strip = Some(vec![args.input, args.out]);
+                options.modes.embed_annotations = args.embed_annotations;
+                options.sidecar = args.sidecar;
+            }
+            Command::Wrap(args) => {
+                options.modes.wrap = Some(vec![args.input, args.out]);
+                options.modes.embed_annotations = args.embed_annotations;
+                options.sidecar = args.sidecar;
+            }
+            Command::ShiftReport(args) => {
+                options.modes.shift_report = Some(args.input);
+                options.modes.shift_sizes = args.sizes;
+                options.modes.shift_report_out = args.out;
+                options.target = args.target;
+            }
+            Command::Mode(args) => {
+                options.modes.mode = Some(args.name);
+                options.modes.mode_args = args.args;
+                options.target = args.target;
+            }
+            Command::ListModes => options.modes.list_modes = true,
+        }
+        options
+    }
+}
+
+/// The default values of a group of options, as parsed from an empty command line.
+fn defaults<T: Args + FromArgMatches>() -> T {
+    let matches = T::augment_args(clap::Command::new("defaults")).get_matches_from(["defaults"]);
+    T::from_arg_matches(&matches).unwrap()
+}
+
+// Parsed once, the size of the fuzz arguments does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fuzz the target, starting from a directory of seeds.
+    Fuzz(FuzzArgs),
+    /// Infer the structure of a file and print it, or of every file of a directory and write their `.annotated`
+    /// sidecars.
+    Analyze(AnalyzeArgs),
+    /// Replay a crash with its `.annotated` structure and report which relations are needed to reproduce it.
+    Triage(TriageArgs),
+    /// Minimize a corpus, keeping the most structured file for each edge along with its `.annotated` sidecar.
+    Cmin(CminArgs),
+    /// Report precision/recall of the search against the `<seed>.truth.json` field specs of a directory.
+    Bench(BenchArgs),
+    /// Re-run only the search hypotheses for the field at a position of an input, with extra-verbose output.
+    Explain(ExplainArgs),
+    /// Print an input as a colorized hexdump of its relations (from its `.annotated` sidecar, or searched).
+    Annotate(AnnotateArgs),
+    /// Add, remove or change the relations of the `.annotated` structure of an input from an interactive prompt,
+    /// validating each edit against the target.
+    Edit(EditArgs),
+    /// Mark the `.annotated` structure of an input as manually verified, so it is never searched again.
+    Freeze(FreezeArgs),
+    /// Clear the frozen flag of the `.annotated` structure of an input.
+    Unfreeze(FreezeArgs),
+    /// Print a structural diff of two annotated inputs (e.g. the `parent` and the `crash` of a reproduction bundle).
+    Diff(DiffArgs),
+    /// Convert the fields of an 010 Editor template result (CSV or JSON export) into relations on a seed.
+    #[command(name = "import-010")]
+    Import010(Import010Args),
+    /// Run every file of a corpus and write the code it reaches (by pc-table address).
+    ExportCoverage(ExportCoverageArgs),
+    /// Check the `.annotated` structures of a corpus against the target with canned structural mutations.
+    Validate(ValidateArgs),
+    /// Report the values held by each field across the `.annotated` structures of a corpus.
+    FieldValues(FieldValuesArgs),
+    /// Copy the raw files of an annotated corpus, dropping the `.annotated` sidecars.
+    Strip(ConvertArgs),
+    /// Copy raw files as an annotated corpus (with relation-less `.annotated` sidecars).
+    Wrap(ConvertArgs),
+    /// Write the coverage shared with an input after corrupting each of its bytes and inserting bytes at each
+    /// position, as a CSV file and heatmaps.
+    ShiftReport(ShiftReportArgs),
+    /// Run a mode registered by the crate embedding FrameShift (see `modes::registry`).
+    Mode(RunModeArgs),
+    /// List the registered modes.
+    ListModes,
+}
+
 #[derive(Args)]
-pub struct Options {
+pub struct FuzzArgs {
+    /// Directory of seeds.
     #[arg(short, long)]
-    pub out: Option<String>,
+    pub input: String,
 
+    /// Output directory (corpus, crashes and logs).
     #[arg(short, long)]
-    pub input: Option<String>,
+    pub out: String,
 
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+
+    #[command(flatten)]
+    pub fuzz: FuzzOptions,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct AnalyzeArgs {
+    /// File or directory to analyze.
+    pub path: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub coverage: CoverageArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+
+    #[command(flatten)]
+    pub analysis: AnalysisArgs,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct TriageArgs {
+    /// Crashing input.
+    pub crash: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+}
+
+#[derive(Args)]
+pub struct CminArgs {
+    /// Corpus directory to minimize.
+    pub corpus: String,
+
+    /// Output directory of the minimized corpus.
     #[arg(short, long)]
+    pub out: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Directory of seeds with `<seed>.truth.json` field specs.
+    pub spec_dir: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+}
+
+#[derive(Args)]
+pub struct ExplainArgs {
+    /// Input holding the field.
+    pub input: String,
+
+    /// Position of the field in the input.
+    pub pos: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+}
+
+#[derive(Args)]
+pub struct AnnotateArgs {
+    /// Input to annotate.
+    pub input: String,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub coverage: CoverageArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+}
+
+#[derive(Args)]
+pub struct EditArgs {
+    /// Input whose structure is edited.
+    pub input: String,
+
+    /// Apply the edit commands of this file instead of prompting.
+    #[arg(long)]
+    pub patch: Option<String>,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct FreezeArgs {
+    /// Input with an `.annotated` structure.
+    pub input: String,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Old input (with its `.annotated` structure).
+    pub old: String,
+
+    /// New input (with its `.annotated` structure).
+    pub new: String,
+}
+
+#[derive(Args)]
+pub struct Import010Args {
+    /// Template result exported by 010 Editor (CSV or JSON).
+    pub export: String,
+
+    /// Seed the template ran on, which gets the relations.
+    pub seed: String,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct ExportCoverageArgs {
+    /// Corpus directory to run.
+    pub corpus: String,
+
+    /// Output file.
+    pub out: String,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = CoverageFormat::Drcov)]
+    pub format: CoverageFormat,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// Annotated corpus directory.
+    pub corpus: String,
+
+    /// Share of an entry's coverage each mutation must retain.
+    #[arg(long, default_value_t = 0.9)]
+    pub threshold: f64,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+}
+
+#[derive(Args)]
+pub struct FieldValuesArgs {
+    /// Annotated corpus directory.
+    pub corpus: String,
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    /// Input corpus directory.
+    pub input: String,
+
+    /// Output directory.
+    pub out: String,
+
+    /// Carry over the up-to-date `.annotated` sidecars of the input directory (wrap only).
+    #[arg(long)]
+    pub embed_annotations: bool,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+#[derive(Args)]
+pub struct ShiftReportArgs {
+    /// Input to shift.
+    pub input: String,
+
+    /// Shift sizes: the value added to the corrupted byte and the number of bytes inserted.
+    #[arg(long, value_delimiter = ',', default_value = "32")]
+    pub sizes: Vec<usize>,
+
+    /// Output file (default: `<input>.shift.csv`), the heatmaps being written next to it.
+    #[arg(long)]
+    pub out: Option<String>,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+}
+
+#[derive(Args)]
+pub struct RunModeArgs {
+    /// Name of the registered mode.
+    pub name: String,
+
+    /// Arguments passed to the mode.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+}
+
+/// All the options, by group. Without a command, every option is accepted; each command flattens the groups it uses.
+#[derive(Args)]
+pub struct Options {
+    #[command(flatten)]
+    pub modes: ModeFlags,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(flatten)]
+    pub coverage: CoverageArgs,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+
+    #[command(flatten)]
+    pub fuzz: FuzzOptions,
+
+    #[command(flatten)]
+    pub analysis: AnalysisArgs,
+
+    #[command(flatten)]
+    pub sidecar: SidecarArgs,
+}
+
+/// The mode flags of the command line without a command, replaced by the commands (hidden from the help).
+#[derive(Args)]
+pub struct ModeFlags {
+    /// Output directory (same as `fuzz -o`, or `cmin -o` with --cmin).
+    #[arg(short, long, hide = true)]
+    pub out: Option<String>,
+
+    /// Directory of seeds (same as `fuzz -i`).
+    #[arg(short, long, hide = true)]
+    pub input: Option<String>,
+
+    /// Analyze this file (same as `analyze`).
+    #[arg(short, long, hide = true)]
     pub analyze: Option<String>,
 
+    /// Analyze every file in this directory and write `.annotated` sidecars next to them.
+    #[arg(long, hide = true)]
+    pub analyze_dir: Option<String>,
+
+    /// Re-run only the search hypotheses for the field at POS in INPUT, with extra-verbose output.
+    #[arg(long, num_args = 2, value_names = ["INPUT", "POS"], hide = true)]
+    pub explain: Option<Vec<String>>,
+
+    /// Mark the `.annotated` structure of this input as manually verified, so it is never searched again.
+    #[arg(long, hide = true)]
+    pub freeze: Option<String>,
+
+    /// Clear the frozen flag of the `.annotated` structure of this input.
+    #[arg(long, hide = true)]
+    pub unfreeze: Option<String>,
+
+    /// Print a structural diff of two annotated inputs (e.g. the `parent` and the `crash` of a reproduction bundle):
+    /// matched, removed, inserted and changed chunks, and the relations whose value, field or region changed.
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], hide = true)]
+    pub diff: Option<Vec<String>>,
+
+    /// Add, remove or change the relations of the `.annotated` structure of this input from an interactive prompt,
+    /// validating each edit against the target.
+    #[arg(long, hide = true)]
+    pub edit: Option<String>,
+
+    /// Apply the edit commands of this file instead of prompting (see --edit).
+    #[arg(long, hide = true)]
+    pub edit_patch: Option<String>,
+
+    /// Convert the fields of an 010 Editor template result (CSV or JSON export) into relations on SEED.
+    #[arg(long = "import-010", num_args = 2, value_names = ["EXPORT", "SEED"], hide = true)]
+    pub import_010: Option<Vec<String>>,
+
+    /// Replay a crash with its `.annotated` structure and report which relations are needed to reproduce it.
+    #[arg(long, hide = true)]
+    pub triage: Option<String>,
+
+    /// Run every file of CORPUS and write the code it reaches (by pc-table address) to OUT.
+    #[arg(long, num_args = 2, value_names = ["CORPUS", "OUT"], hide = true)]
+    pub export_coverage: Option<Vec<String>>,
+
+    /// Output format of the coverage export.
+    #[arg(long, value_enum, default_value_t = CoverageFormat::Drcov, hide = true)]
+    pub export_coverage_format: CoverageFormat,
+
+    /// Minimize the corpus in this directory into the output directory (-o), keeping the most structured file
+    /// for each edge along with its `.annotated` sidecar.
+    #[arg(long, hide = true)]
+    pub cmin: Option<String>,
+
+    /// Check the `.annotated` structures of this corpus directory against the target: each entry is run through
+    /// canned structural mutations (an insertion at each insertion point, the removal of each nested chunk), which
+    /// must retain its coverage.
+    #[arg(long, hide = true)]
+    pub validate: Option<String>,
+
+    /// Share of an entry's coverage each mutation of --validate must retain.
+    #[arg(long, default_value_t = 0.9, hide = true)]
+    pub validate_threshold: f64,
+
+    /// Report the values held by each field across the `.annotated` structures of this corpus directory.
+    #[arg(long, hide = true)]
+    pub field_values: Option<String>,
+
+    /// Copy the raw files of an annotated CORPUS to OUT, dropping the `.annotated` sidecars.
+    #[arg(long, num_args = 2, value_names = ["CORPUS", "OUT"], hide = true)]
+    pub strip: Option<Vec<String>>,
+
+    /// Copy the raw files of RAW_DIR to OUT as an annotated corpus (with relation-less `.annotated` sidecars).
+    #[arg(long, num_args = 2, value_names = ["RAW_DIR", "OUT"], hide = true)]
+    pub wrap: Option<Vec<String>>,
+
+    /// With --wrap, carry over the up-to-date `.annotated` sidecars of the input directory.
+    #[arg(long, hide = true)]
+    pub embed_annotations: bool,
+
+    /// Report precision/recall of the search against the `<seed>.truth.json` field specs in this directory.
+    #[arg(long, hide = true)]
+    pub bench_ground_truth: Option<String>,
+
+    /// Print this input as a colorized hexdump of its relations (from its `.annotated` sidecar, or searched).
+    #[arg(long, hide = true)]
+    pub annotate: Option<String>,
+
     // Something like start:end:<hexstring>
     #[arg(short, long)]
     pub mutate_splice: Option<String>,
 
-    #[arg(short, long)]
-    pub tokens: Option<String>,
+    /// Write the coverage shared with INPUT after corrupting each of its bytes and inserting bytes at each position,
+    /// as a CSV file and a heatmap per shift size, to spot length fields by eye.
+    #[arg(long, value_name = "INPUT", hide = true)]
+    pub shift_report: Option<String>,
 
-    #[arg(short, long, default_value = "libafl.log")]
-    pub logfile: String,
+    /// Shift sizes of the shift report: the value added to the corrupted byte and the number of bytes inserted.
+    #[arg(long, value_delimiter = ',', default_value = "32", hide = true)]
+    pub shift_sizes: Vec<usize>,
 
-    #[arg(short, long, default_value = "1200")]
-    pub timeout: String,
+    /// Output file of the shift report (default: `<input>.shift.csv`), the heatmaps being written next to it.
+    #[arg(long, hide = true)]
+    pub shift_report_out: Option<String>,
 
-    #[arg(short, long, default_value_t = false)]
-    pub disable_frameshift: bool,
+    /// Run a mode registered by the crate embedding FrameShift (see `modes::registry`).
+    #[arg(long, hide = true)]
+    pub mode: Option<String>,
+
+    /// Argument passed to the registered mode (repeatable).
+    #[arg(long = "mode-arg", allow_hyphen_values = true, hide = true)]
+    pub mode_args: Vec<String>,
+
+    /// List the registered modes.
+    #[arg(long, hide = true)]
+    pub list_modes: bool,
+}
 
+/// How the target runs.
+#[derive(Args)]
+pub struct TargetArgs {
+    /// Timeout of a single execution, in milliseconds.
+    #[arg(short = 't', long, visible_alias = "timeout", default_value = "1200")]
+    pub exec_timeout: String,
+
+    /// Run an uninstrumented binary under QEMU (only supported by the `frameshift_qemu` frontend). Must come last.
+    #[arg(long, num_args = 1.., allow_hyphen_values = true, value_names = ["TARGET", "ARGS"])]
+    pub qemu: Option<Vec<String>>,
+
+    /// Run the harness of an uninstrumented shared library under Frida (only supported by the `frameshift_frida`
+    /// frontend): the library, then the harness function (`LLVMFuzzerTestOneInput` by default).
+    #[arg(long, num_args = 1..=2, value_names = ["LIBRARY", "FUNCTION"])]
+    pub frida: Option<Vec<String>>,
+}
+
+/// An external coverage collector for the analysis and the annotate command, instead of the linked target.
+#[derive(Args)]
+pub struct CoverageArgs {
+    /// Collect the coverage of analyze probes with this shell command instead of the linked target (`@@` is replaced
+    /// by the input file, e.g. "afl-showmap -q -o /dev/stdout -- ./target @@").
+    #[arg(long)]
+    pub coverage_cmd: Option<String>,
+
+    /// Format of the coverage report written by --coverage-cmd to its stdout: `showmap` (`id:count` lines), `kcov`
+    /// (the Cobertura XML report) or `drcov` (a drcov log, binary or text).
+    #[arg(long, default_value_t = core::coverage::CoverageFormat::Showmap, value_name = "FORMAT")]
+    pub coverage_format: core::coverage::CoverageFormat,
+
+    /// Like --coverage-cmd, with a persistent worker which receives length-prefixed inputs on stdin and writes their
+    /// coverage to a shared memory map (see `core::collector::CoverageWorker`), restarted whenever it crashes.
+    #[arg(long)]
+    pub coverage_worker: Option<String>,
+
+    /// Another file of the analyzed input, substituted for `@@NAME` in the coverage command (repeatable). Each part
+    /// is searched too (only when analyzing a single file).
+    #[arg(long, value_name = "NAME=FILE")]
+    pub part: Vec<String>,
+}
+
+/// Options of the structure search.
+#[derive(Args)]
+pub struct SearchArgs {
     #[arg(short, long, default_value_t = false)]
     pub verbose_search: bool,
 
-    #[arg(short, long, default_value_t = false)]
+    #[arg(long, default_value_t = false)]
     pub verbose_search_extra: bool,
 
     #[arg(short, long, default_value_t = 100)]
     pub search_max_iters: usize,
 
-    #[arg(short, long, default_value_t = 0.05)]
+    #[arg(long, default_value_t = 0.05)]
     pub search_loss_threshold: f64,
 
-    #[arg(short, long, default_value_t = 0.2)]
+    #[arg(long, default_value_t = 0.2)]
     pub search_recover_threshold: f64,
 
-    #[arg(short, long, default_value_t = 0)]
+    /// Only test candidate fields whose value appears as a cmplog operand.
+    #[arg(long, default_value_t = false)]
+    pub search_cmplog_only: bool,
+
+    /// Also search for lengths packed into some bits of a byte (nibbles and 6-bit fields).
+    #[arg(long)]
+    pub search_bitfields: bool,
+
+    /// Do not probe whether the target rejects any change in input length before searching (the probes and
+    /// mutations of such inputs are then no longer kept length-preserving).
+    #[arg(long)]
+    pub search_no_fixed_size: bool,
+
+    /// Also search for lengths written as ASCII decimal digits (e.g. an HTTP Content-Length).
+    #[arg(long)]
+    pub search_ascii: bool,
+
+    /// Also search for lengths counting 2, 4 or 8-byte units (e.g. a number of words).
+    #[arg(long)]
+    pub search_strides: bool,
+
+    /// Also search for offsets pointing past their field (e.g. to a directory at the end of the input) by moving the
+    /// pointed-to data, for targets which only dereference them lazily.
+    #[arg(long)]
+    pub search_offsets: bool,
+
+    /// Mark the NUL-terminated and length-prefixed strings of searched inputs, which are then resized in place.
+    #[arg(long)]
+    pub search_strings: bool,
+
+    /// Skip candidate fields unlikely to be lengths without probing them (inside high-entropy data or known strings,
+    /// values larger than the input).
+    #[arg(long)]
+    pub search_prefilter: bool,
+
+    /// Also probe relations by removing bytes at the end of their region: refutes insertion points where the region
+    /// does not end, and finds lengths whose larger values the target tolerates.
+    #[arg(long)]
+    pub search_shrink_probes: bool,
+
+    /// Also try regions which extend past the length by the size of the field or a header of up to 16 bytes, for
+    /// lengths of a payload which exclude its header.
+    #[arg(long)]
+    pub search_header_bias: bool,
+
+    /// When no anchor matches, binary-search the insertion points of the field (about log2 of the input length probes)
+    /// for regions starting far from every anchor candidate.
+    #[arg(long)]
+    pub search_bisect_anchors: bool,
+
+    /// Once this many multi-byte relations were found and 3/4 of them agree on an endianness, only test candidate
+    /// fields of that endianness (0 disables).
+    #[arg(long, default_value_t = 0)]
+    pub search_endian_vote: usize,
+
+    /// Run each search probe this many times and keep the coverage reached by most runs, leaving out the edges whose
+    /// coverage changes across runs of the seed (for targets with nondeterministic coverage).
+    #[arg(long, default_value_t = 1)]
+    pub search_stability_runs: usize,
+
+    /// Also measure the loss and recovery of the search probes on the value profile of the comparisons, for fields
+    /// whose corruption reaches the same edges with wrong values (fuzzing only).
+    #[arg(long)]
+    pub search_value_profile: bool,
+
+    /// Order in which the search tests candidate fields: linear (input order), cmplog (comparison operands first),
+    /// entropy (low-entropy surroundings first) or bisect (bytes whose corruption loses coverage first).
+    #[arg(long, default_value = "cmplog", value_parser = core::strategy::STRATEGIES)]
+    pub search_strategy: String,
+
+    /// Search inputs shorter than this many bytes exhaustively (every anchor, several shift amounts; 0 disables).
+    #[arg(long, default_value_t = 256)]
+    pub search_exhaustive_below: usize,
+
+    /// Search for constant bytes (e.g. magic signatures) and avoid mutating them or repair them after mutations.
+    #[arg(long, value_enum, default_value_t = ConstantMode::Off)]
+    pub protect_constants: ConstantMode,
+
+    /// Remove relations whose confidence (re-validated on every search pass) drops below this (0 disables).
+    #[arg(long, default_value_t = 0.0)]
+    pub search_prune_threshold: f64,
+
+    /// Only optimize the structure for reaching these edges: a file with one edge index or function name (resolved
+    /// through the pc-table) per line.
+    #[arg(long)]
+    pub focus_edges: Option<String>,
+
+    /// Known length fields of the format, added to every input before searching it so that the search only looks for
+    /// the others: a file with one field per line, e.g. `offset=4 size=4 le anchor=8` (see `core::hints`).
+    #[arg(long)]
+    pub hints: Option<String>,
+
+    /// Fallback fillers for insertion probes, tried in order when the default one does not recover coverage
+    /// (comma-separated: `zeros`, `random`, `repeat` or a hex byte).
+    #[arg(long, value_delimiter = ',')]
+    pub search_fill: Vec<Filler>,
+}
+
+/// Options of the fuzzing campaign.
+#[derive(Args)]
+pub struct FuzzOptions {
+    #[arg(short = 'x', long)]
+    pub tokens: Option<String>,
+
+    #[arg(short, long, default_value = "libafl.log")]
+    pub logfile: String,
+
+    /// Timeout of a single search probe, in milliseconds (defaults to the execution timeout).
+    #[arg(long)]
+    pub search_exec_timeout: Option<u64>,
+
+    #[arg(short, long, default_value_t = false)]
+    pub disable_frameshift: bool,
+
+    /// Start fuzzing without checking the setup first (timeout, seeds, tokens, and whether the coverage depends on
+    /// the input).
+    #[arg(long)]
+    pub skip_preflight: bool,
+
+    /// Probability that a field value mutation uses a value outside of the values seen in the corpus (and leaves
+    /// the length inconsistent) instead of resizing the region to a value seen in the corpus.
+    #[arg(long, default_value_t = 0.1)]
+    pub field_value_ood: f64,
+
+    /// Weight multiplier in the scheduler of the corpus entries with relations, whose structure-aware mutations are
+    /// more productive (1 schedules them like the other entries).
+    #[arg(long, default_value_t = 2.0)]
+    pub structure_boost: f64,
+
+    /// While the corpus has fewer entries than this, generate inputs from the structures learned so far (by varying the
+    /// chunks of entries with relations) at each fuzzing iteration (0 disables).
+    #[arg(long, default_value_t = 16)]
+    pub generate_below: usize,
+
+    /// Fixed runs per corpus entry of the mutational stages, e.g. `i2s=1,structural=4,havoc=2` (0 disables a stage).
+    /// The stages left out run in proportion to the corpus entries they found recently.
+    #[arg(long)]
+    pub stage_weights: Option<StageWeights>,
+
+    /// Cull the corpus (keeping the most structured entries) when it grows beyond this many entries.
+    #[arg(long)]
+    pub max_corpus_entries: Option<usize>,
+
+    /// Detach the structure (to its sidecar) of corpus entries selected this many times in a row without producing a
+    /// new entry, until they are selected again.
+    #[arg(long)]
+    pub max_idle_cycles: Option<usize>,
+
+    /// Only keep crashes whose call stack differs from the crashes found before.
+    #[arg(long)]
+    pub dedup_crashes: bool,
+
+    /// Append a line of statistics to `<out>/plot_data` (AFL++ columns) and `<out>/frameshift_plot_data` every this many
+    /// seconds (0 disables).
+    #[arg(long, default_value_t = 60)]
+    pub stats_interval: u64,
+
+    /// Exchange inputs with other fuzzers through this AFL++ sync directory (the `-o` of `afl-fuzz -M/-S`): the corpus
+    /// is exported to `<dir>/<sync-name>/queue` with AFL++ names, and the other queues are imported.
+    #[arg(long)]
+    pub sync_dir: Option<String>,
+
+    /// Name of this fuzzer in the sync directory (unique among the fuzzers syncing through it).
+    #[arg(long, default_value = "frameshift")]
+    pub sync_name: String,
+
+    /// Sync every this many seconds.
+    #[arg(long, default_value_t = 60)]
+    pub sync_interval: u64,
+
+    /// Show the fuzzing status in libafl's terminal UI, with the structure of the corpus (annotated entries, relations
+    /// per entry, search overhead and last search), instead of status lines (which still go to the log).
+    #[arg(long)]
+    pub tui: bool,
+
+    /// End the campaign after this many executions of the target (like libFuzzer's `-runs`).
+    #[arg(long)]
+    pub runs: Option<u64>,
+
+    /// Largest input the target runs on (like libFuzzer's `-max_len`): longer inputs are skipped and never stored.
+    #[arg(long)]
+    pub max_len: Option<usize>,
+
+    /// Hide the bytes of the relation fields from the havoc and I2S mutations, which then only move them through
+    /// insertions and removals instead of overwriting them.
+    #[arg(long, default_value_t = false)]
+    pub lock_fields: bool,
+
+    /// Place poisoned bytes after every input of a target built with ASAN, and keep the inputs making it read them
+    /// (past the end of the region of a length field) as objectives, even if the read would not crash otherwise.
+    #[arg(long, default_value_t = false)]
+    pub detect_overreads: bool,
+
+    /// Run each search probe in a child forked from the initialized target, so that crashing probes do not restart
+    /// the fuzzer.
+    #[arg(long, default_value_t = false)]
+    pub fork_mode: bool,
+
+    /// Defer searches while more than this fraction of the fuzzing time was spent searching.
+    #[arg(long)]
+    pub search_time_fraction: Option<f64>,
+
+    /// Truncate searches after this many probes per input byte, resuming them when their entry comes up again.
+    #[arg(long)]
+    pub search_probes_per_byte: Option<usize>,
+
+    /// Do not search during the first this many seconds of the campaign.
+    #[arg(long)]
+    pub search_warmup_secs: Option<u64>,
+
+    /// Do not search until no new corpus entry was found for this many seconds.
+    #[arg(long)]
+    pub search_warmup_plateau_secs: Option<u64>,
+
+    /// Probe timeouts tolerated per search before it finishes with the relations found so far (the probes then run in
+    /// a forked child; 0 disables the budget and runs them in-process, unless --fork-mode is set).
+    #[arg(long, default_value_t = 0)]
+    pub search_max_timeouts: usize,
+
+    /// Loosely directed fuzzing: favor the corpus entries whose coverage is laid out closest (by pc-table address) to
+    /// these sites, a file with one edge index or function name per line. This is a code layout heuristic, not a
+    /// control-flow distance.
+    #[arg(long)]
+    pub layout_targets: Option<String>,
+
+    /// Prioritize searching inputs whose new coverage is within this many bytes of code of a crashing location.
+    #[arg(long)]
+    pub search_crash_distance: Option<usize>,
+}
+
+/// Output of the analysis.
+#[derive(Args)]
+pub struct AnalysisArgs {
+    /// Output format of the analyze result.
+    #[arg(long, value_enum, default_value_t = AnalyzeFormat::Debug)]
+    pub analyze_format: AnalyzeFormat,
+
+    /// Write the analyze result to this file instead of stdout.
+    #[arg(long)]
+    pub analyze_out: Option<String>,
+
+    #[arg(long, default_value_t = 0)]
     pub stress_analyze: u32,
 
-    #[arg(short, long, default_value_t = 0)]
+    #[arg(long, default_value_t = 0)]
     pub stress_mutate: u32,
+}
 
-    #[arg(short, long)]
-    pub tpm_experiment: Option<String>,
+/// The `.annotated` sidecars written.
+#[derive(Args)]
+pub struct SidecarArgs {
+    /// Format of the `.annotated` sidecars written (`json` or `binary`); both formats are read.
+    #[arg(long, default_value_t = AnnotationFormat::Json)]
+    pub annotation_format: AnnotationFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AnalyzeFormat {
+    /// Debug representation of the structure.
+    Debug,
+    /// Machine-readable JSON document.
+    Json,
+    /// Hexdump of the input followed by the relations.
+    Hexdump,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CoverageFormat {
+    /// drcov basic block list (e.g. for Lighthouse or bncov).
+    Drcov,
+    /// lcov tracefile, symbolized with `addr2line` (e.g. for genhtml).
+    Lcov,
 }
 
 /// The fuzzer main (as `no_mangle` C function)
+#[cfg(feature = "libafl_main")]
 #[no_mangle]
 pub extern "C" fn libafl_main() {
-    let res = Cli::parse();
-    
-    let edges = {
-        #[cfg(feature = "use_counters")]
-        {
-            let edges = unsafe { extra_counters() };
-            let obs = StdMapObserver::from_mut_slice(
-                "edges",
-                edges.into_iter().next().unwrap(),
-            );
-            obs
-        }
+    fuzzer_main();
+}
 
-        #[cfg(not(feature = "use_counters"))]
-        {
-            let edges = unsafe { std_edges_map_observer("edges") };
-            edges
-        }
-    };
+/// The body of `libafl_main`: parses the command line and runs the selected command on the linked target.
+///
+/// Crates embedding FrameShift build it without the `libafl_main` feature and export their own `libafl_main`, which
+/// sets them up (e.g. registers their modes with `modes::registry::register_mode`) then calls this.
+pub fn fuzzer_main() {
+    let (args, target_args) = split_args(&env::args().collect::<Vec<_>>());
+    let res = Cli::parse_from(args);
 
-    let args: Vec<String> = env::args().collect();
-    if libfuzzer_initialize(&args) == -1 {
+    // Files of multi-file inputs are passed to the target as `@@<name>` arguments.
+    let target_args = core::parts::register_parts(&target_args);
+    
+    // Modules loaded by `LLVMFuzzerInitialize` register their counters too.
+    if libfuzzer_initialize(&target_args) == -1 {
         println!("Warning: LLVMFuzzerInitialize failed with -1");
     }
 
-    entrypoint(res.options, &mut libfuzzer_test_one_input, edges);
+    #[cfg(feature = "use_counters")]
+    {
+        let (edges, regions) = counters::counters_observer();
+        let mut fuzz_fn = |data: &[u8]| -> i32 {
+            let ret = libfuzzer_test_one_input(data);
+            if let Some(regions) = &regions {
+                regions.collect();
+            }
+            ret
+        };
+        entrypoint(res.into_options(), &mut fuzz_fn, edges);
+    }
+
+    #[cfg(not(feature = "use_counters"))]
+    {
+        let edges = unsafe { std_edges_map_observer("edges") };
+        entrypoint(res.into_options(), &mut libfuzzer_test_one_input, edges);
+    }
+}
+
+/// Splits the command line at the first `--`.
+///
+/// Arguments before the separator are FrameShift options, arguments after it are forwarded to the target
+/// (`LLVMFuzzerInitialize`) untouched, prefixed by the program name. Without a separator, the target sees the full
+/// command line.
+pub fn split_args(args: &[String]) -> (Vec<String>, Vec<String>) {
+    match args.iter().position(|arg| arg == "--") {
+        Some(idx) => {
+            let target_args = args.first().into_iter().chain(args[idx + 1..].iter()).cloned().collect();
+            (args[..idx].to_vec(), target_args)
+        }
+        None => (args.to_vec(), args.to_vec()),
+    }
 }
 
 pub fn entrypoint<F>(res: Options, fuzz_fn: &mut F, obs: StdMapObserver<u8,false>,) 
 where 
     F: Fn(&[u8]) -> i32,
 {
-    if res.tpm_experiment.is_some() {
-        tpm_experiment(res, fuzz_fn, obs);
-    } else if res.analyze.is_some() {
+    set_annotation_format(res.sidecar.annotation_format);
+
+    if res.search.search_value_profile && !cfg!(feature = "value_profile") {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--search-value-profile needs a build with the `value_profile` feature").exit();
+    }
+
+    if res.modes.strip.is_some() && res.modes.embed_annotations {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--embed-annotations only applies to wrap: strip drops the sidecars").exit();
+    }
+
+    if res.modes.mode.is_some() || res.modes.list_modes {
+        modes::registry::run_mode(res, fuzz_fn, obs);
+    } else if res.modes.shift_report.is_some() {
+        modes::shift_report::shift_report(res, fuzz_fn, obs);
+    } else if res.modes.analyze.is_some() {
         analyze(res, fuzz_fn, obs);
-    } else if res.input.is_some() && res.out.is_some() {
+    } else if res.modes.analyze_dir.is_some() {
+        analyze_dir(res, fuzz_fn, obs);
+    } else if res.modes.annotate.is_some() {
+        modes::annotate::annotate(res, fuzz_fn, obs);
+    } else if res.modes.explain.is_some() {
+        modes::explain::explain(res, fuzz_fn, obs);
+    } else if res.modes.freeze.is_some() || res.modes.unfreeze.is_some() {
+        modes::freeze::freeze(res);
+    } else if res.modes.diff.is_some() {
+        modes::diff::diff(res);
+    } else if res.modes.edit.is_some() {
+        modes::edit::edit(res, fuzz_fn, obs);
+    } else if res.modes.import_010.is_some() {
+        modes::import_010::import_010(res);
+    } else if res.modes.triage.is_some() {
+        modes::triage::triage(res, fuzz_fn);
+    } else if res.modes.export_coverage.is_some() {
+        modes::coverage_export::export_coverage(res, fuzz_fn, obs);
+    } else if res.modes.cmin.is_some() {
+        modes::cmin::cmin(res, fuzz_fn, obs);
+    } else if res.modes.validate.is_some() {
+        modes::validate::validate(res, fuzz_fn, obs);
+    } else if res.modes.field_values.is_some() {
+        modes::field_values::field_values(res);
+    } else if res.modes.strip.is_some() || res.modes.wrap.is_some() {
+        modes::convert::convert(res);
+    } else if res.modes.bench_ground_truth.is_some() {
+        modes::bench::bench_ground_truth(res, fuzz_fn, obs);
+    } else if res.modes.input.is_some() && res.modes.out.is_some() {
         fuzz(res, fuzz_fn, obs);
+    } else if res.modes.input.is_some() || res.modes.out.is_some() {
+        Cli::command().error(ErrorKind::MissingRequiredArgument, "fuzzing needs both a seed directory (-i) and an output directory (-o)").exit();
     } else {
-        println!("Must specify (input and output) or (analyze) options");
+        Cli::command().error(ErrorKind::MissingSubcommand, "no command given (e.g. `fuzz -i <seeds> -o <out>` or `analyze <file>`)").exit();
+    }
+}
+
+pub(crate) fn search_options(args: &SearchArgs) -> SearchOptions {
+    SearchOptions {
+        verbose: args.verbose_search,
+        extra_verbose: args.verbose_search_extra,
+        max_iters: args.search_max_iters,
+        cmplog_only: args.search_cmplog_only,
+        detect_fixed_size: !args.search_no_fixed_size,
+        enable_bitfields: args.search_bitfields,
+        enable_ascii: args.search_ascii,
+        enable_strides: args.search_strides,
+        enable_offsets: args.search_offsets,
+        detect_constants: args.protect_constants != ConstantMode::Off,
+        detect_strings: args.search_strings,
+        prefilter: args.search_prefilter,
+        shrink_probes: args.search_shrink_probes,
+        header_bias: args.search_header_bias,
+        bisect_anchors: args.search_bisect_anchors,
+        endian_vote: args.search_endian_vote,
+        stability_runs: args.search_stability_runs,
+        use_value_profile: args.search_value_profile,
+        strategy: core::strategy::strategy_by_name(&args.search_strategy).unwrap(),
+        exhaustive_below: args.search_exhaustive_below,
+        fill_strategy: args.search_fill.clone(),
+        focus_edges: args.focus_edges.as_ref().map(|path| modes::coverage_export::load_edge_list(Path::new(path))),
+        hints: args.hints.as_ref().map(|path| {
+            let spec = fs::read_to_string(path).expect("Could not read hints");
+            core::hints::parse_hints(&spec).unwrap_or_else(|e| panic!("Invalid hints {:?}: {}", path, e))
+        }).unwrap_or_default(),
+        prune_threshold: args.search_prune_threshold,
+        loss_threshold: args.search_loss_threshold,
+        recover_threshold: args.search_recover_threshold,
+        ..Default::default()
     }
 }
 
-pub fn fuzz<F>(res: Options, fuzz_fn: &mut F, obs: StdMapObserver<u8,false>,) 
+pub fn fuzz<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,) 
 where 
     F: Fn(&[u8]) -> i32,
 {
+    if !res.fuzz.skip_preflight {
+        let problems = preflight::preflight(&res, fuzz_fn, &mut obs);
+        if !problems.is_empty() {
+            for problem in problems.iter() {
+                println!("Preflight: {}", problem);
+            }
+            println!("Aborting before fuzzing (--skip-preflight fuzzes anyway)");
+            return;
+        }
+    }
+
+    let search_options = search_options(&res.search);
+
     println!(
         "Workdir: {:?}",
         env::current_dir().unwrap().to_string_lossy().to_string()
     );
 
     // For fuzzbench, crashes and finds are inside the same `corpus` directory, in the "queue" and "crashes" subdir.
-    let mut out_dir = PathBuf::from(res.out.unwrap());
+    let mut out_dir = PathBuf::from(res.modes.out.unwrap());
     if fs::create_dir(&out_dir).is_err() {
         println!("Out dir at {:?} already exists.", &out_dir);
         if !out_dir.is_dir() {
@@ -148,91 +1124,107 @@ where
     }
     let mut crashes = out_dir.clone();
     crashes.push("crashes");
+    let stats_file = out_dir.join("plot_data");
     out_dir.push("queue");
 
-    let in_dir = PathBuf::from(res.input.unwrap());
+    let in_dir = PathBuf::from(res.modes.input.unwrap());
     if !in_dir.is_dir() {
         println!("In dir at {:?} is not a valid directory!", &in_dir);
         return;
     }
 
-    let tokens = res.tokens.map(PathBuf::from);
+    let tokens = res.fuzz.tokens.map(PathBuf::from);
 
-    let logfile = PathBuf::from(res.logfile);
+    let logfile = PathBuf::from(res.fuzz.logfile);
 
     let timeout = Duration::from_millis(
-        res.timeout
+        res.target.exec_timeout
             .parse()
             .expect("Could not parse timeout in milliseconds"),
     );
+    let search_timeout = res.fuzz.search_exec_timeout.map(Duration::from_millis).unwrap_or(timeout);
+    let limits = fuzz_common::RunLimits { runs: res.fuzz.runs, max_len: res.fuzz.max_len };
 
-    let search_options = SearchOptions {
-        verbose: res.verbose_search,
-        extra_verbose: res.verbose_search_extra,
-        max_iters: res.search_max_iters,
-        loss_threshold: res.search_loss_threshold,
-        recover_threshold: res.search_recover_threshold,
-    };
+    let target_edges = res.fuzz.layout_targets.as_ref()
+        .map(|path| modes::coverage_export::load_edge_list(Path::new(path)))
+        .unwrap_or_default();
 
-    match !res.disable_frameshift {
+    match !res.fuzz.disable_frameshift {
         true => {
             println!("Frameshift enabled");
             let search_args = SearchStageArgs {
                 options: search_options,
+                crash_distance: res.fuzz.search_crash_distance,
+                max_timeouts: res.fuzz.search_max_timeouts,
+                // The in-process executor exits on a timeout, so the timeout budget needs forked probes.
+                fork: res.fuzz.fork_mode || res.fuzz.search_max_timeouts > 0,
+                budget: SearchBudget {
+                    max_time_fraction: res.fuzz.search_time_fraction,
+                    max_probes_per_byte: res.fuzz.search_probes_per_byte,
+                },
+                warmup: Warmup {
+                    min_time: res.fuzz.search_warmup_secs.map(Duration::from_secs),
+                    plateau: res.fuzz.search_warmup_plateau_secs.map(Duration::from_secs),
+                },
             };
 
             fuzz_frameshift::fuzz_frameshift(fuzz_fn, obs, out_dir, crashes, &in_dir, tokens, 
-                &logfile, timeout, search_args)
+                &logfile, timeout, search_timeout, search_args, res.fuzz.max_corpus_entries, res.fuzz.max_idle_cycles, res.fuzz.field_value_ood, res.search.protect_constants, res.fuzz.lock_fields, res.fuzz.detect_overreads, res.fuzz.structure_boost, res.fuzz.generate_below, res.fuzz.stage_weights.unwrap_or_default(), target_edges, res.fuzz.dedup_crashes,
+                stats_file, Duration::from_secs(res.fuzz.stats_interval), res.fuzz.sync_dir.map(PathBuf::from), res.fuzz.sync_name,
+                Duration::from_secs(res.fuzz.sync_interval), res.fuzz.tui, limits)
                 .expect("An error occurred while fuzzing");
         }
         false => {
             println!("Frameshift disabled");
-            fuzz_afl::fuzz_afl(fuzz_fn, obs, out_dir, crashes, &in_dir, tokens, &logfile, timeout)
+            fuzz_afl::fuzz_afl(fuzz_fn, obs, out_dir, crashes, &in_dir, tokens, &logfile, timeout, target_edges, res.fuzz.dedup_crashes,
+                stats_file, Duration::from_secs(res.fuzz.stats_interval), res.fuzz.sync_dir.map(PathBuf::from), res.fuzz.sync_name,
+                Duration::from_secs(res.fuzz.sync_interval), res.fuzz.tui, limits)
                 .expect("An error occurred while fuzzing");
         }
     }
 }
 
+/// Runs `input` and returns the entries of the coverage map which it hit.
+pub(crate) fn run_coverage<F>(fuzz_fn: &F, obs: &mut StdMapObserver<u8,false>, input: &[u8]) -> CoverageSnapshot
+where
+    F: Fn(&[u8]) -> i32,
+{
+    obs.reset_map().unwrap();
+    fuzz_fn(input);
+    CoverageSnapshot::from_map(obs.as_ref().as_slice())
+}
+
 pub fn analyze<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,) 
 where 
     F: Fn(&[u8]) -> i32,
 {
-    let path = PathBuf::from(res.analyze.unwrap());
-    println!("Analyzing {:?}", path);
+    if res.coverage.coverage_cmd.is_some() || res.coverage.coverage_worker.is_some() {
+        modes::external_coverage::analyze_external(res);
+        return;
+    }
+
+    let search_options = search_options(&res.search);
 
-    let raw = fs::read(path).expect("Could not read testcase");
+    let path = PathBuf::from(res.modes.analyze.unwrap());
+    if res.analysis.analyze_format != AnalyzeFormat::Json || res.analysis.analyze_out.is_some() {
+        println!("Analyzing {:?}", path);
+    }
+
+    let raw = fs::read(&path).expect("Could not read testcase");
 
     // Setup base.
     obs.reset_map().unwrap();
     fuzz_fn(&[]);
 
-    let search_options = SearchOptions {
-        verbose: res.verbose_search,
-        extra_verbose: res.verbose_search_extra,
-        max_iters: res.search_max_iters,
-        loss_threshold: res.search_loss_threshold,
-        recover_threshold: res.search_recover_threshold,
-    };
-
-    if res.stress_analyze > 0 {
+    if res.analysis.stress_analyze > 0 {
         let start_time = Instant::now();
 
         let mut total_tests = 0;
         let mut target_ms = 0;
         let mut total_ms = 0;
 
-        for _ in 0..res.stress_analyze {
-            let mut oracle = |input: &[u8]| {
-                {
-                    obs.reset_map().unwrap();
-                }
-                fuzz_fn(input);
-                let obs = obs.as_ref();
-        
-                // Convert to static lifetime - this is unsafe but needed for the oracle
-                let slice = obs.as_slice();
-                unsafe { std::mem::transmute::<&[u8], &'static [u8]>(slice) }
-            };
+        for _ in 0..res.analysis.stress_analyze {
+            let mut oracle = |input: &[u8]| run_coverage(fuzz_fn, &mut obs, input);
 
             let testcase = Structured::raw(raw.clone());
             let search_res = SearchContext::search(&testcase, &mut oracle, search_options.clone());
@@ -251,25 +1243,29 @@ where
         return;
     }
 
-    let mut oracle = |input: &[u8]| {
-        {
-            obs.reset_map().unwrap();
-        }
-        fuzz_fn(input);
-        let obs = obs.as_ref();
-
-        // Convert to static lifetime - this is unsafe but needed for the oracle
-        let slice = obs.as_slice();
-        unsafe { std::mem::transmute::<&[u8], &'static [u8]>(slice) }
-    };
+    let mut oracle = |input: &[u8]| run_coverage(fuzz_fn, &mut obs, input);
 
     let testcase = Structured::raw(raw);
     let search_res = SearchContext::search(&testcase, &mut oracle, search_options);
-    println!("{:?}", search_res.input);
 
-    if res.stress_mutate > 0 {
+    // The coverage gated by each field costs two more probes per relation.
+    let gates = match res.search.verbose_search || res.analysis.analyze_format == AnalyzeFormat::Json {
+        true => field_gates(&search_res.input, &mut oracle, &search_res.focus_indices),
+        false => vec![],
+    };
+
+    let output = format_analysis(&path, &search_res, &gates, res.analysis.analyze_format);
+    match &res.analysis.analyze_out {
+        Some(out) => fs::write(out, output).expect("Could not write analyze output"),
+        None => println!("{}", output),
+    }
+    if res.search.verbose_search && res.analysis.analyze_format != AnalyzeFormat::Json {
+        print!("{}", format_gates(&search_res.input, &gates));
+    }
+
+    if res.analysis.stress_mutate > 0 {
         let start_time = Instant::now();
-        for _ in 0..res.stress_mutate {
+        for _ in 0..res.analysis.stress_mutate {
             for idx in 0..search_res.input.get_raw().len() {
                 let mut input = search_res.input.clone();
                 input.insert(idx, &vec![0x41; 5]);
@@ -281,59 +1277,216 @@ where
     }
 }
 
-
-pub fn tpm_experiment<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,) 
-where 
+/// Analyzes every file in a directory, writing the inferred structure to `.annotated` sidecar files.
+pub fn analyze_dir<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,)
+where
     F: Fn(&[u8]) -> i32,
 {
-    let path = PathBuf::from(res.tpm_experiment.unwrap());
-    println!("TPM experiment {:?}", path);
+    let search_options = search_options(&res.search);
+
+    let dir = PathBuf::from(res.modes.analyze_dir.unwrap());
+    let paths = corpus_files(&dir).expect("Could not read analyze directory");
+
+    println!("Analyzing {} files in {:?}", paths.len(), dir);
 
-    let raw = fs::read(path).expect("Could not read testcase");
+    // Probes run on the linked target, unless an external collector is given.
+    let mut coverage = coverage_backend(&res);
 
     // Setup base.
     obs.reset_map().unwrap();
     fuzz_fn(&[]);
 
-    let mut oracle = |input: &[u8]| {
-        {
-            obs.reset_map().unwrap();
+    let start_time = Instant::now();
+    let mut with_relations = 0;
+    let mut total_relations = 0;
+    let mut total_tests = 0;
+    let mut heuristics = BTreeMap::new();
+
+    for path in paths.iter() {
+        // Keep manually verified structures.
+        let frozen = read_annotation(&annotation_path(path)).is_ok_and(|input| input.frozen);
+        if frozen {
+            println!("{:?}: frozen, skipped", path.file_name().unwrap());
+            continue;
         }
-        fuzz_fn(input);
-        let obs = obs.as_ref();
 
-        let hit_indices = obs.iter().enumerate().filter(|(_, &v)| v != 0).map(|(i, _)| i).collect::<HashSet<_>>();
-        hit_indices
-    };
+        let raw = fs::read(path).expect("Could not read testcase");
+
+        let mut oracle = |input: &[u8]| match coverage.as_mut() {
+            Some(coverage) => CoverageSnapshot::from(coverage.run(input)),
+            None => run_coverage(fuzz_fn, &mut obs, input),
+        };
+        let search_res = SearchContext::search(&Structured::raw(raw), &mut oracle, search_options.clone());
 
-    let orig_coverage = oracle(&raw);
-    println!("Original coverage: {:?}", orig_coverage.len());
+        write_annotation(&annotation_path(path), &search_res.input).expect("Could not write annotation");
 
-    let shift_amt = 0x20;
+        println!("{:?}: {} relations ({} tests)", path.file_name().unwrap(), search_res.input.relations.len(), search_res.test_count);
 
-    for i in 0..raw.len() {
-        let mut input = raw.clone();
-        input[i] += shift_amt;
+        if search_res.found_any {
+            with_relations += 1;
+        }
+        total_relations += search_res.input.relations.len();
+        total_tests += search_res.test_count;
+        merge_heuristic_stats(&mut heuristics, &search_res.heuristics);
+    }
+
+    println!("Analyzed: {}", paths.len());
+    println!("With relations: {}", with_relations);
+    println!("Avg relations per file: {:.2}", total_relations as f64 / paths.len().max(1) as f64);
+    println!("Total tests: {}", total_tests);
+    for (heuristic, stats) in heuristics.iter() {
+        println!("  {:?}: {} relations ({} tests)", heuristic, stats.found, stats.tests);
+    }
+    println!("Total time: {:?}", start_time.elapsed());
+}
 
-        let coverage = oracle(&input);
-        let shared_coverage = orig_coverage.intersection(&coverage).count();
-        println!("IDX: {}, SHARED: {}", i, shared_coverage);
+/// Symbolizes the edges gated by the fields (see `field_gates`) through the pc-table, if the target has one.
+fn gate_symbols(gates: &[FieldGates]) -> HashMap<usize, EdgeSymbol> {
+    let edges = gates.iter().flat_map(|gate| gate.lost.iter().copied()).collect::<Vec<_>>();
+    match edges.is_empty() {
+        true => HashMap::new(),
+        false => symbolize_edges(&edges).unwrap_or_default(),
+    }
+}
 
-        for j in 0..=raw.len() {
-            let mut insert_input = input.clone();
-            insert_input.splice(j..j, vec![0x41; shift_amt as usize]);
-            let coverage = oracle(&insert_input);
-            let shared_coverage = orig_coverage.intersection(&coverage).count();
-            println!("INSERT: {}:{}, SHARED: {}", i, j, shared_coverage);
+/// Lists the edges gated by each field, with the function and line of their pc.
+fn format_gates(input: &Structured, gates: &[FieldGates]) -> String {
+    let symbols = gate_symbols(gates);
+    let mut out = String::new();
+    for gate in gates.iter() {
+        let rel = &input.relations[gate.relation];
+        out.push_str(&format!("Relation {} (field {}..{}): gates {} focus edges, {} recovered\n",
+            gate.relation, rel.pos, rel.pos + rel.size, gate.lost.len(), gate.recovered.len()));
+        for idx in gate.lost.iter() {
+            let mark = if gate.recovered.contains(idx) { "+" } else { "-" };
+            let place = match symbols.get(idx) {
+                Some(sym) => format!("{:#x} {} {}", sym.pc, sym.function.as_deref().unwrap_or("??"), sym.location.as_deref().unwrap_or("??")),
+                None => "(no pc-table)".to_string(),
+            };
+            out.push_str(&format!("  {} edge {} {}\n", mark, idx, place));
         }
+    }
+    out
+}
+
+/// Renders the result of analyzing the testcase at `path` in the requested format.
+///
+/// The edges gated by each field (`gates`, may be empty) are part of the JSON document.
+pub fn format_analysis(path: &Path, search_res: &SearchResult, gates: &[FieldGates], format: AnalyzeFormat) -> String {
+    let input = &search_res.input;
+    match format {
+        AnalyzeFormat::Debug => format!("{:?}", input),
+        AnalyzeFormat::Json => {
+            let symbols = gate_symbols(gates);
+            let gated = |idx: usize| gates.iter().find(|gate| gate.relation == idx).map(|gate| {
+                gate.lost.iter().map(|edge| {
+                    let sym = symbols.get(edge);
+                    serde_json::json!({
+                        "index": edge,
+                        "recovered": gate.recovered.contains(edge),
+                        "pc": sym.map(|sym| format!("{:#x}", sym.pc)),
+                        "function": sym.and_then(|sym| sym.function.clone()),
+                        "location": sym.and_then(|sym| sym.location.clone()),
+                    })
+                }).collect::<Vec<_>>()
+            });
 
-        for j in 0..=raw.len() {
-            let mut insert_input = input.clone();
-            insert_input[5] += shift_amt; // edit the commandsize
-            insert_input.splice(j..j, vec![0x41; shift_amt as usize]);
-            let coverage = oracle(&insert_input);
-            let shared_coverage = orig_coverage.intersection(&coverage).count();
-            println!("PROT_INSERT: {}:{}, SHARED: {}", i, j, shared_coverage);
+            let relations = input.relations.iter().enumerate().map(|(idx, rel)| serde_json::json!({
+                "pos": rel.pos,
+                "size": rel.size,
+                "le": rel.le,
+                "value": rel.value,
+                "stride": rel.stride,
+                "bias": rel.bias,
+                "ascii": rel.ascii,
+                "anchor": rel.anchor,
+                "insert": rel.insert,
+                "mirrors": rel.mirrors,
+                "group": rel.group,
+                "heuristics": rel.heuristics,
+                "gates": gated(idx),
+            })).collect::<Vec<_>>();
+
+            let report = serde_json::json!({
+                "file": path.to_string_lossy(),
+                "size": input.get_raw().len(),
+                "fixed_size": input.fixed_size,
+                "relations": relations,
+                "constants": input.constants,
+                "strings": input.strings,
+                "stats": {
+                    "test_count": search_res.test_count,
+                    "target_test_ms": search_res.target_test_ms,
+                    "total_test_ms": search_res.total_test_ms,
+                    "cache_hits": search_res.cache_hits,
+                    "heuristics": search_res.heuristics,
+                },
+                "focus_indices": search_res.focus_indices,
+                "unstable_indices": search_res.unstable_indices,
+            });
+            serde_json::to_string_pretty(&report).unwrap()
+        }
+        AnalyzeFormat::Hexdump => {
+            let mut out = String::new();
+            for (line, chunk) in input.get_raw().chunks(16).enumerate() {
+                let hex = chunk.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+                let ascii = chunk.iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect::<String>();
+                out.push_str(&format!("{:08x}: {:<47}  {}\n", line * 16, hex, ascii));
+            }
+            for rel in input.relations.iter() {
+                out.push_str(&format!(
+                    "REL pos={:#x} size={} le={} value={} anchor={:#x} insert={:#x}\n",
+                    rel.pos, rel.size, rel.le, rel.value, rel.anchor, rel.insert
+                ));
+            }
+            for c in input.constants.iter() {
+                out.push_str(&format!("CONST pos={:#x} size={}\n", c.pos, c.bytes.len()));
+            }
+            for s in input.strings.iter() {
+                out.push_str(&format!("STR pos={:#x} len={} terminated={}\n", s.pos, s.len, s.terminated));
+            }
+            out
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, clap::Error> {
+        Cli::try_parse_from(args).map(Cli::into_options)
+    }
+
+    #[test]
+    fn test_command_options() {
+        Cli::command().debug_assert();
+
+        let options = parse(&["fuzzer", "fuzz", "-i", "in", "-o", "out", "-t", "500", "--tui", "--search-max-iters", "5"]).unwrap();
+        assert_eq!((options.modes.input.as_deref(), options.modes.out.as_deref()), (Some("in"), Some("out")));
+        assert_eq!(options.target.exec_timeout, "500");
+        assert!(options.fuzz.tui);
+        assert_eq!(options.search.search_max_iters, 5);
+
+        // Each command only takes its own options, the others keeping their defaults.
+        assert!(parse(&["fuzzer", "triage", "crash", "--tui"]).is_err());
+        assert!(parse(&["fuzzer", "diff", "old", "new", "--exec-timeout", "500"]).is_err());
+        let options = parse(&["fuzzer", "validate", "corpus", "--threshold", "0.5"]).unwrap();
+        assert_eq!(options.modes.validate.as_deref(), Some("corpus"));
+        assert_eq!(options.modes.validate_threshold, 0.5);
+        assert_eq!(options.target.exec_timeout, "1200");
+
+        let options = parse(&["fuzzer", "annotate", "seed", "--coverage-cmd", "kcov-run @@", "--coverage-format", "kcov"]).unwrap();
+        assert_eq!(options.coverage.coverage_cmd.as_deref(), Some("kcov-run @@"));
+        assert_eq!(options.coverage.coverage_format, core::coverage::CoverageFormat::Kcov);
+        assert!(parse(&["fuzzer", "analyze", "seed", "--coverage-format", "gcov"]).is_err());
+
+        let options = parse(&["fuzzer", "mode", "experiment", "--flag", "value"]).unwrap();
+        assert_eq!(options.modes.mode.as_deref(), Some("experiment"));
+        assert_eq!(options.modes.mode_args, ["--flag", "value"]);
+
+        // Without a command, the legacy flags take every option.
+        let options = parse(&["fuzzer", "--tui", "-i", "in", "-o", "out"]).unwrap();
+        assert!(options.fuzz.tui && options.modes.input.is_some());
+    }
+}
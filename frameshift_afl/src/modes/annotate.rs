@@ -0,0 +1,45 @@
+use std::{fs, path::PathBuf};
+
+use libafl::prelude::{MapObserver, StdMapObserver};
+
+use super::external_coverage::coverage_backend;
+use crate::{components::structured_input::{annotation_path, read_annotation}, core::{search::{CoverageSnapshot, SearchContext}, structured::Structured}, run_coverage, search_options, Options};
+
+/// Prints an input as a colorized hexdump highlighting its size fields, anchors and regions.
+///
+/// The structure comes from the `.annotated` sidecar of the input if it is up to date, otherwise it is searched.
+pub fn annotate<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let path = PathBuf::from(res.modes.annotate.as_ref().unwrap());
+    let raw = fs::read(&path).expect("Could not read testcase");
+
+    let sidecar = read_annotation(&annotation_path(&path)).ok()
+        .filter(|s| s.get_raw() == raw);
+
+    let input = match sidecar {
+        Some(input) => {
+            println!("{:?} (from {:?})", path, annotation_path(&path));
+            input
+        }
+        None => {
+            println!("{:?} (searched)", path);
+
+            // Probes run on the linked target, unless an external collector is given.
+            let mut coverage = coverage_backend(&res);
+
+            // Setup base.
+            obs.reset_map().unwrap();
+            fuzz_fn(&[]);
+
+            let mut oracle = |input: &[u8]| match coverage.as_mut() {
+                Some(coverage) => CoverageSnapshot::from(coverage.run(input)),
+                None => run_coverage(fuzz_fn, &mut obs, input),
+            };
+            SearchContext::search(&Structured::raw(raw), &mut oracle, search_options(&res.search)).input
+        }
+    };
+
+    print!("{}", input.render_annotated());
+}
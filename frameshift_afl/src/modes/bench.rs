@@ -0,0 +1,137 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use libafl::prelude::{MapObserver, StdMapObserver};
+use serde::Deserialize;
+
+use crate::{core::{search::SearchContext, structured::Structured}, run_coverage, search_options, Options};
+
+/// Suffix of the ground truth file which describes the seed with the same name (e.g. `seed.png.truth.json`).
+const TRUTH_SUFFIX: &str = ".truth.json";
+
+/// A known size/offset field in a seed.
+#[derive(Deserialize, Debug)]
+struct TruthField {
+    pos: usize,
+    size: usize,
+    le: bool,
+    #[serde(default)]
+    anchor: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GroundTruth {
+    relations: Vec<TruthField>,
+}
+
+#[derive(Default, Debug)]
+struct KindStats {
+    true_pos: usize,
+    false_pos: usize,
+    false_neg: usize,
+    anchors_checked: usize,
+    anchors_correct: usize,
+}
+
+/// Relation kinds are identified by their encoding (e.g. `u32le`).
+fn kind(size: usize, le: bool) -> String {
+    if size == 1 {
+        "u8".to_string()
+    } else {
+        format!("u{}{}", size * 8, if le { "le" } else { "be" })
+    }
+}
+
+fn ratio(num: usize, den: usize) -> f64 {
+    if den == 0 { 0.0 } else { num as f64 / den as f64 }
+}
+
+/// Runs the search over seeds with known field specs and reports precision/recall of the discovered relations.
+///
+/// Each seed `<name>` in the spec directory is described by a `<name>.truth.json` file of the form
+/// `{"relations": [{"pos": 8, "size": 4, "le": false, "anchor": 16}, ...]}` (`anchor` is optional).
+pub fn bench_ground_truth<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let search_options = search_options(&res.search);
+
+    let spec_dir = PathBuf::from(res.modes.bench_ground_truth.unwrap());
+    let mut specs = fs::read_dir(&spec_dir)
+        .expect("Could not read spec directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(TRUTH_SUFFIX))
+        .collect::<Vec<_>>();
+    specs.sort();
+
+    // Setup base.
+    obs.reset_map().unwrap();
+    fuzz_fn(&[]);
+
+    let mut stats: BTreeMap<String, KindStats> = BTreeMap::new();
+    let mut total_tests = 0;
+
+    for spec in specs.iter() {
+        let spec_name = spec.to_string_lossy();
+        let seed = PathBuf::from(&spec_name[..spec_name.len() - TRUTH_SUFFIX.len()]);
+
+        let truth: GroundTruth = serde_json::from_str(&fs::read_to_string(spec).expect("Could not read ground truth"))
+            .expect("Could not parse ground truth");
+        let raw = fs::read(&seed).expect("Could not read seed");
+
+        let mut oracle = |input: &[u8]| run_coverage(fuzz_fn, &mut obs, input);
+        let search_res = SearchContext::search(&Structured::raw(raw), &mut oracle, search_options.clone());
+        total_tests += search_res.test_count;
+
+        // Every field position (including mirrors) counts as a separate discovered field.
+        let mut found = search_res.input.relations.iter()
+            .flat_map(|rel| rel.field_positions().map(move |pos| (pos, rel)))
+            .collect::<Vec<_>>();
+
+        let mut seed_tp = 0;
+        for field in truth.relations.iter() {
+            let entry = stats.entry(kind(field.size, field.le)).or_default();
+            match found.iter().position(|(pos, rel)| *pos == field.pos && rel.size == field.size && rel.le == field.le) {
+                Some(idx) => {
+                    let (_, rel) = found.swap_remove(idx);
+                    entry.true_pos += 1;
+                    seed_tp += 1;
+                    if let Some(anchor) = field.anchor {
+                        entry.anchors_checked += 1;
+                        if rel.anchor == anchor {
+                            entry.anchors_correct += 1;
+                        }
+                    }
+                }
+                None => entry.false_neg += 1,
+            }
+        }
+
+        for (_, rel) in found.iter() {
+            stats.entry(kind(rel.size, rel.le)).or_default().false_pos += 1;
+        }
+
+        println!("{:?}: {}/{} fields found, {} spurious ({} tests)", seed.file_name().unwrap(), seed_tp, truth.relations.len(), found.len(), search_res.test_count);
+    }
+
+    println!();
+    println!("{:<8} {:>6} {:>6} {:>6} {:>10} {:>10} {:>10}", "kind", "tp", "fp", "fn", "precision", "recall", "anchors");
+
+    let mut total = KindStats::default();
+    for (kind, s) in stats.iter() {
+        println!("{:<8} {:>6} {:>6} {:>6} {:>10.3} {:>10.3} {:>10}", kind, s.true_pos, s.false_pos, s.false_neg,
+            ratio(s.true_pos, s.true_pos + s.false_pos), ratio(s.true_pos, s.true_pos + s.false_neg),
+            format!("{}/{}", s.anchors_correct, s.anchors_checked));
+
+        total.true_pos += s.true_pos;
+        total.false_pos += s.false_pos;
+        total.false_neg += s.false_neg;
+        total.anchors_checked += s.anchors_checked;
+        total.anchors_correct += s.anchors_correct;
+    }
+
+    println!("{:<8} {:>6} {:>6} {:>6} {:>10.3} {:>10.3} {:>10}", "all", total.true_pos, total.false_pos, total.false_neg,
+        ratio(total.true_pos, total.true_pos + total.false_pos), ratio(total.true_pos, total.true_pos + total.false_neg),
+        format!("{}/{}", total.anchors_correct, total.anchors_checked));
+    println!("Seeds: {}", specs.len());
+    println!("Total tests: {}", total_tests);
+}
@@ -0,0 +1,93 @@
+use std::{cmp::Reverse, collections::HashSet, fs, path::PathBuf};
+
+use libafl::prelude::StdMapObserver;
+
+use crate::{components::structured_input::{annotation_path, corpus_files, read_annotation, write_annotation}, core::structured::Structured, run_coverage, Options};
+
+/// A corpus file along with its structure (if it has an up-to-date `.annotated` sidecar).
+struct Entry {
+    path: PathBuf,
+    len: usize,
+    structure: Option<Structured>,
+    edges: Vec<usize>,
+}
+
+impl Entry {
+    /// Entries with more relations, then smaller ones, are preferred.
+    fn rank(&self) -> (Reverse<usize>, usize) {
+        let relations = self.structure.as_ref().map(|s| s.relations.iter().filter(|rel| rel.enabled).count()).unwrap_or(0);
+        (Reverse(relations), self.len)
+    }
+}
+
+/// Selects a small subset of a corpus which covers all of its edges, and copies it to the output directory along
+/// with the `.annotated` sidecars of the selected files.
+///
+/// Like afl-cmin, every edge picks its best file (here, the most structured one, then the smallest) and files are
+/// kept until all edges are covered.
+pub fn cmin<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let in_dir = PathBuf::from(res.modes.cmin.unwrap());
+    let Some(out_dir) = res.modes.out.map(PathBuf::from) else {
+        println!("Must specify an output directory (-o) for --cmin");
+        return;
+    };
+    fs::create_dir_all(&out_dir).expect("Could not create output directory");
+
+    let paths = corpus_files(&in_dir).expect("Could not read corpus directory");
+
+    let mut entries = vec![];
+    for path in paths {
+        let raw = fs::read(&path).expect("Could not read testcase");
+
+        // Sidecars of modified files are stale.
+        let structure = read_annotation(&annotation_path(&path)).ok()
+            .filter(|s| s.get_raw() == raw);
+
+        let edges = run_coverage(fuzz_fn, &mut obs, &raw).hits().to_vec();
+
+        entries.push(Entry { path, len: raw.len(), structure, edges });
+    }
+
+    // Best entry of each edge.
+    let mut best: Vec<Option<usize>> = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        for edge in entry.edges.iter() {
+            if *edge >= best.len() {
+                best.resize(edge + 1, None);
+            }
+            match best[*edge] {
+                Some(j) if entries[j].rank() <= entry.rank() => {}
+                _ => best[*edge] = Some(i),
+            }
+        }
+    }
+
+    let mut covered = HashSet::new();
+    let mut selected = vec![];
+    for (edge, best) in best.iter().enumerate() {
+        let Some(i) = *best else {
+            continue;
+        };
+        if covered.contains(&edge) {
+            continue;
+        }
+        covered.extend(entries[i].edges.iter().cloned());
+        selected.push(i);
+    }
+
+    let mut annotated = 0;
+    for i in selected.iter() {
+        let entry = &entries[*i];
+        let dest = out_dir.join(entry.path.file_name().unwrap());
+        fs::copy(&entry.path, &dest).expect("Could not copy testcase");
+        if let Some(structure) = entry.structure.as_ref() {
+            write_annotation(&annotation_path(&dest), structure).expect("Could not write annotation");
+            annotated += 1;
+        }
+    }
+
+    println!("Kept {} of {} files ({} annotated) covering {} edges in {:?}", selected.len(), entries.len(), annotated, covered.len(), out_dir);
+}
@@ -0,0 +1,46 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::{components::structured_input::{annotation_path, corpus_files, read_annotation, write_annotation}, core::structured::Structured, Options};
+
+/// Loads the `.annotated` sidecar of a file, if it matches the content of the file.
+fn load_annotation(path: &Path, raw: &[u8]) -> Option<Structured> {
+    read_annotation(&annotation_path(path)).ok().filter(|s| s.get_raw() == raw)
+}
+
+/// Converts between annotated corpora and plain directories of raw inputs, keeping the file names.
+///
+/// `--strip` writes the raw bytes of an annotated corpus, without sidecars. `--wrap` writes an annotated corpus from
+/// raw files, with an empty structure (no relations) for every file, or with its up-to-date sidecar under
+/// `--embed-annotations` (rejected with `--strip`, which drops the sidecars).
+pub fn convert(res: Options) {
+    let (args, wrap) = match (res.modes.strip, res.modes.wrap) {
+        (Some(args), _) => (args, false),
+        (None, Some(args)) => (args, true),
+        (None, None) => unreachable!(),
+    };
+    let (in_dir, out_dir) = (PathBuf::from(&args[0]), PathBuf::from(&args[1]));
+    fs::create_dir_all(&out_dir).expect("Could not create output directory");
+
+    let mut annotated = 0;
+    let files = corpus_files(&in_dir).expect("Could not read corpus directory");
+    for path in files.iter() {
+        let raw = fs::read(path).expect("Could not read testcase");
+        let dest = out_dir.join(path.file_name().unwrap());
+
+        fs::write(&dest, &raw).expect("Could not write testcase");
+        if !wrap {
+            continue;
+        }
+
+        let structure = match res.modes.embed_annotations.then(|| load_annotation(path, &raw)).flatten() {
+            Some(structure) => {
+                annotated += 1;
+                structure
+            }
+            None => Structured::raw(raw),
+        };
+        write_annotation(&annotation_path(&dest), &structure).expect("Could not write annotation");
+    }
+
+    println!("Wrote {} files ({} with their annotations) to {:?}", files.len(), annotated, out_dir);
+}
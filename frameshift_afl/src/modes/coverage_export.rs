@@ -0,0 +1,282 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}};
+
+use libafl::prelude::StdMapObserver;
+
+use crate::{components::{crash_proximity::edge_pcs, structured_input::corpus_files}, run_coverage, CoverageFormat, Options};
+
+/// ELF type of non-relocatable executables.
+const ET_EXEC: u16 = 2;
+
+/// A loaded module, from `/proc/self/maps`.
+#[derive(Debug, Clone)]
+struct Module {
+    path: String,
+    base: usize,
+    end: usize,
+}
+
+impl Module {
+    /// Lists the file-backed modules of the current process.
+    fn load() -> Vec<Module> {
+        let maps = fs::read_to_string("/proc/self/maps").expect("Could not read /proc/self/maps");
+
+        let mut modules: Vec<Module> = vec![];
+        for line in maps.lines() {
+            let cols = line.split_whitespace().collect::<Vec<_>>();
+            let (Some(range), Some(path)) = (cols.first(), cols.get(5)) else {
+                continue;
+            };
+            if !path.starts_with('/') {
+                continue;
+            }
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) else {
+                continue;
+            };
+
+            match modules.iter_mut().find(|m| m.path == *path) {
+                Some(module) => {
+                    module.base = module.base.min(start);
+                    module.end = module.end.max(end);
+                }
+                None => modules.push(Module { path: path.to_string(), base: start, end }),
+            }
+        }
+        modules
+    }
+
+    /// Whether addresses in the module are relative to its load address (position-independent ELF).
+    fn is_relocatable(&self) -> bool {
+        fs::read(&self.path).ok()
+            .and_then(|elf| elf.get(16..18).map(|t| u16::from_le_bytes([t[0], t[1]])))
+            != Some(ET_EXEC)
+    }
+}
+
+/// Writes a drcov (version 2) file with one basic block of size 1 per covered pc.
+fn write_drcov(out: &Path, modules: &[Module], covered: &[(usize, u32)]) -> usize {
+    let mut blocks = vec![];
+    for (pc, _) in covered.iter() {
+        if let Some(id) = modules.iter().position(|m| m.base <= *pc && *pc < m.end) {
+            blocks.push(((*pc - modules[id].base) as u32, id as u16));
+        }
+    }
+
+    let mut data = format!("DRCOV VERSION: 2\nDRCOV FLAVOR: frameshift\nModule Table: version 2, count {}\n", modules.len());
+    data.push_str("Columns: id, base, end, entry, checksum, timestamp, path\n");
+    for (id, module) in modules.iter().enumerate() {
+        data.push_str(&format!("{}, 0x{:016x}, 0x{:016x}, 0x{:016x}, 0x00000000, 0x00000000, {}\n", id, module.base, module.end, 0, module.path));
+    }
+    data.push_str(&format!("BB Table: {} bbs\n", blocks.len()));
+
+    let mut bytes = data.into_bytes();
+    for (offset, id) in blocks.iter() {
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(id.to_le_bytes());
+    }
+
+    fs::write(out, bytes).expect("Could not write drcov file");
+    blocks.len()
+}
+
+/// Writes an lcov tracefile, symbolizing the covered pcs with `addr2line` (the target needs debug info).
+///
+/// The count of a line is the number of corpus entries reaching it.
+fn write_lcov(out: &Path, modules: &[Module], covered: &[(usize, u32)]) -> usize {
+    let mut lines: BTreeMap<String, BTreeMap<usize, u32>> = BTreeMap::new();
+
+    for module in modules.iter() {
+        let pcs = covered.iter().filter(|(pc, _)| module.base <= *pc && *pc < module.end).collect::<Vec<_>>();
+        if pcs.is_empty() {
+            continue;
+        }
+
+        let base = if module.is_relocatable() { module.base } else { 0 };
+        let addresses = pcs.iter().map(|(pc, _)| format!("0x{:x}\n", pc - base)).collect::<String>();
+
+        let mut child = Command::new("addr2line")
+            .arg("-e")
+            .arg(&module.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Could not run addr2line");
+        child.stdin.take().unwrap().write_all(addresses.as_bytes()).expect("Could not write to addr2line");
+        let output = child.wait_with_output().expect("Could not run addr2line");
+
+        // One `file:line` (possibly followed by a discriminator) per address.
+        for ((_, hits), location) in pcs.iter().zip(String::from_utf8_lossy(&output.stdout).lines()) {
+            let Some((file, line)) = location.rsplit_once(':') else {
+                continue;
+            };
+            let line = line.split_whitespace().next().and_then(|l| l.parse::<usize>().ok()).unwrap_or(0);
+            if file == "??" || line == 0 {
+                continue;
+            }
+
+            let count = lines.entry(file.to_string()).or_default().entry(line).or_default();
+            *count = (*count).max(*hits);
+        }
+    }
+
+    let mut data = String::from("TN:frameshift\n");
+    for (file, file_lines) in lines.iter() {
+        data.push_str(&format!("SF:{}\n", file));
+        for (line, count) in file_lines.iter() {
+            data.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        data.push_str(&format!("LH:{}\nLF:{}\nend_of_record\n", file_lines.len(), file_lines.len()));
+    }
+
+    fs::write(out, data).expect("Could not write lcov file");
+    lines.values().map(|l| l.len()).sum()
+}
+
+/// Function and `file:line` of each pc, symbolized with `addr2line -f` (None where unknown).
+fn pc_symbols(modules: &[Module], pcs: &[usize]) -> Vec<(Option<String>, Option<String>)> {
+    let mut symbols = vec![(None, None); pcs.len()];
+
+    for module in modules.iter() {
+        let idxs = (0..pcs.len()).filter(|i| module.base <= pcs[*i] && pcs[*i] < module.end).collect::<Vec<_>>();
+        if idxs.is_empty() {
+            continue;
+        }
+
+        let base = if module.is_relocatable() { module.base } else { 0 };
+        let addresses = idxs.iter().map(|i| format!("0x{:x}\n", pcs[*i] - base)).collect::<String>();
+
+        let mut child = Command::new("addr2line")
+            .args(["-f", "-C", "-e"])
+            .arg(&module.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Could not run addr2line");
+        child.stdin.take().unwrap().write_all(addresses.as_bytes()).expect("Could not write to addr2line");
+        let output = child.wait_with_output().expect("Could not run addr2line");
+
+        // A function name line followed by a `file:line` line per address.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines = stdout.lines().collect::<Vec<_>>();
+        for (i, entry) in idxs.iter().zip(lines.chunks(2)) {
+            let function = entry[0];
+            let location = entry.get(1).map(|l| l.split_whitespace().next().unwrap_or(""));
+            symbols[*i] = (
+                Some(function.to_string()).filter(|f| *f != "??"),
+                location.filter(|l| !l.starts_with("??")).map(str::to_string),
+            );
+        }
+    }
+    symbols
+}
+
+/// Name of the function containing each pc (None where unknown).
+fn pc_functions(modules: &[Module], pcs: &[usize]) -> Vec<Option<String>> {
+    pc_symbols(modules, pcs).into_iter().map(|(function, _)| function).collect()
+}
+
+/// Where an edge is in the target, according to the pc-table.
+#[derive(Debug, Clone)]
+pub struct EdgeSymbol {
+    pub pc: usize,
+    pub function: Option<String>,
+    /// `file:line` of the pc (needs debug info).
+    pub location: Option<String>,
+}
+
+/// Symbolizes edges through the pc-table, or returns None if the target has none (it must be built with
+/// `-fsanitize-coverage=pc-table`). Edges past the end of the table are left out.
+pub fn symbolize_edges(edges: &[usize]) -> Option<HashMap<usize, EdgeSymbol>> {
+    let pcs = edge_pcs();
+    if pcs.is_empty() {
+        return None;
+    }
+
+    let edges = edges.iter().copied().filter(|idx| *idx < pcs.len()).collect::<Vec<_>>();
+    let edge_pcs = edges.iter().map(|idx| pcs[*idx]).collect::<Vec<_>>();
+    let symbols = pc_symbols(&Module::load(), &edge_pcs);
+    Some(edges.into_iter().zip(edge_pcs).zip(symbols)
+        .map(|((idx, pc), (function, location))| (idx, EdgeSymbol { pc, function, location }))
+        .collect())
+}
+
+/// Reads a list of edges: one edge index or function name (resolved through the pc-table) per line, `#` starts a
+/// comment.
+pub fn load_edge_list(path: &Path) -> HashSet<usize> {
+    let list = fs::read_to_string(path).expect("Could not read focus edges");
+
+    let mut edges = HashSet::new();
+    let mut symbols = vec![];
+    for line in list.lines().map(|l| l.split('#').next().unwrap().trim()).filter(|l| !l.is_empty()) {
+        match line.parse::<usize>() {
+            Ok(idx) => {
+                edges.insert(idx);
+            }
+            Err(_) => symbols.push(line.to_string()),
+        }
+    }
+
+    if !symbols.is_empty() {
+        let pcs = edge_pcs();
+        if pcs.is_empty() {
+            println!("No pc-table registered, function names in {:?} are ignored (build the target with -fsanitize-coverage=pc-table)", path);
+            return edges;
+        }
+
+        let functions = pc_functions(&Module::load(), &pcs);
+        for symbol in symbols.iter() {
+            let before = edges.len();
+            edges.extend((0..pcs.len()).filter(|i| functions[*i].as_deref() == Some(symbol)));
+            if edges.len() == before {
+                println!("Warning: no edge found in function {}", symbol);
+            }
+        }
+    }
+
+    println!("Loaded {} edges from {:?}", edges.len(), path);
+    edges
+}
+
+/// Runs every file of a corpus and exports the reached code (by pc-table address) as drcov or lcov, so campaigns can
+/// be compared in standard coverage viewers.
+pub fn export_coverage<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let args = res.modes.export_coverage.unwrap();
+    let dir = PathBuf::from(&args[0]);
+    let out = PathBuf::from(&args[1]);
+
+    let pcs = edge_pcs();
+    if pcs.is_empty() {
+        println!("No pc-table registered, build the target with -fsanitize-coverage=pc-table");
+        return;
+    }
+
+    let paths = corpus_files(&dir).expect("Could not read corpus directory");
+
+    // Number of corpus entries reaching each edge.
+    let mut hits: HashMap<usize, u32> = HashMap::new();
+    for path in paths.iter() {
+        let raw = fs::read(path).expect("Could not read testcase");
+        for idx in run_coverage(fuzz_fn, &mut obs, &raw).hits() {
+            *hits.entry(*idx).or_default() += 1;
+        }
+    }
+
+    let mut covered = hits.iter()
+        .filter_map(|(idx, count)| pcs.get(*idx).map(|pc| (*pc, *count)))
+        .collect::<Vec<_>>();
+    covered.sort();
+
+    let modules = Module::load();
+    let written = match res.modes.export_coverage_format {
+        CoverageFormat::Drcov => write_drcov(&out, &modules, &covered),
+        CoverageFormat::Lcov => write_lcov(&out, &modules, &covered),
+    };
+
+    println!("Ran {} files: {} edges covered, {} entries written to {:?}", paths.len(), covered.len(), written, out);
+}
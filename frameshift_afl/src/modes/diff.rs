@@ -0,0 +1,106 @@
+use std::{fs, ops::Range, path::Path};
+
+use crate::{components::structured_input::{annotation_path, read_annotation}, core::{diff::{diff as structural_diff, ChunkDiff}, structured::{Relation, Structured}}, Options};
+
+/// Bytes of a chunk shown in the diff.
+const PREVIEW_LEN: usize = 16;
+
+/// Loads an input along with its `.annotated` structure, or without relations if it has no up-to-date sidecar.
+fn load(path: &Path) -> Structured {
+    let raw = fs::read(path).expect("Could not read testcase");
+    match read_annotation(&annotation_path(path)) {
+        Ok(input) if input.get_raw() == raw => input,
+        Ok(_) => {
+            println!("{:?}: stale annotation (the file changed since), comparing the raw bytes", path);
+            Structured::raw(raw)
+        }
+        Err(_) => Structured::raw(raw),
+    }
+}
+
+fn describe(rel: &Relation) -> String {
+    format!("field {}..{}, value {}, region {}..{}{}", rel.pos, rel.pos + rel.size, rel.value, rel.anchor, rel.insert,
+        if rel.enabled { "" } else { ", disabled" })
+}
+
+fn preview(data: &[u8], range: &Range<usize>) -> String {
+    let bytes = &data[range.clone()];
+    let hex = bytes.iter().take(PREVIEW_LEN).map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    match bytes.len() > PREVIEW_LEN {
+        true => format!("{} ...", hex),
+        false => hex,
+    }
+}
+
+/// Prints a structural diff of two annotated inputs: the chunks (see `Structured::chunks`) they share, the chunks
+/// removed, inserted or changed, and the relations whose field, value or region changed.
+///
+/// With the reproduction bundle of a crash, `--diff parent crash` shows what the mutations of the corpus entry did to
+/// produce the crash.
+pub fn diff(res: Options) {
+    let args = res.modes.diff.unwrap();
+    let (old_path, new_path) = (Path::new(&args[0]), Path::new(&args[1]));
+    let (old, new) = (load(old_path), load(new_path));
+
+    println!("--- {:?} ({} bytes, {} relations)", old_path, old.get_raw().len(), old.relations.len());
+    println!("+++ {:?} ({} bytes, {} relations)", new_path, new.get_raw().len(), new.relations.len());
+
+    let res = structural_diff(&old, &new);
+    if res.is_empty() {
+        println!("Same bytes and relations");
+        return;
+    }
+
+    let (mut matched, mut removed, mut inserted) = (0, 0, 0);
+    for chunk in res.chunks.iter() {
+        match chunk {
+            ChunkDiff::Matched(a, b) => {
+                matched += a.len();
+                println!("  {:>5}..{:<5} = {:>5}..{:<5} ({} bytes)", a.start, a.end, b.start, b.end, a.len());
+            }
+            ChunkDiff::Removed(a) => {
+                removed += a.len();
+                println!("- {:>5}..{:<5}                 {}", a.start, a.end, preview(old.get_raw(), a));
+            }
+            ChunkDiff::Inserted(b) => {
+                inserted += b.len();
+                println!("+                 {:>5}..{:<5} {}", b.start, b.end, preview(new.get_raw(), b));
+            }
+            ChunkDiff::Changed(a, b) => {
+                removed += a.len();
+                inserted += b.len();
+                println!("~ {:>5}..{:<5} ~ {:>5}..{:<5} {} -> {}", a.start, a.end, b.start, b.end,
+                    preview(old.get_raw(), a), preview(new.get_raw(), b));
+            }
+        }
+    }
+
+    println!();
+    for change in res.fields.iter() {
+        let (a, b) = (&change.old, &change.new);
+        let mut parts = vec![];
+        if a.value != b.value {
+            parts.push(format!("value {} -> {}", a.value, b.value));
+        }
+        if a.pos != b.pos {
+            parts.push(format!("field at {} -> {}", a.pos, b.pos));
+        }
+        if a.region != b.region {
+            parts.push(format!("region {}..{} -> {}..{}", a.region.start, a.region.end, b.region.start, b.region.end));
+        }
+        let index = match change.relation == change.new_relation {
+            true => change.relation.to_string(),
+            false => format!("{} (now {})", change.relation, change.new_relation),
+        };
+        println!("relation {}: {}", index, parts.join(", "));
+    }
+    for idx in res.removed.iter() {
+        println!("relation {}: only in the old input ({})", idx, describe(&old.relations[*idx]));
+    }
+    for idx in res.added.iter() {
+        println!("relation {}: only in the new input ({})", idx, describe(&new.relations[*idx]));
+    }
+
+    println!("{} bytes matched, {} removed, {} inserted; {} relations changed, {} removed, {} added",
+        matched, removed, inserted, res.fields.len(), res.removed.len(), res.added.len());
+}
@@ -0,0 +1,244 @@
+use std::{collections::HashSet, fs, io::{self, BufRead, Write}, path::PathBuf};
+
+use libafl::prelude::StdMapObserver;
+
+use crate::{components::structured_input::{annotation_path, read_annotation, write_annotation}, core::{search::{read_ascii_field, read_field}, structured::{Relation, Structured, MAX_ASCII_DIGITS}}, run_coverage, Options};
+
+/// Corruption of a field when validating a relation (capped by the field width).
+const VALIDATION_SHIFT: u64 = 0x20;
+
+/// Minimum fraction of the coverage lost by a corrupted field which a consistent insertion must recover.
+const MIN_RECOVERY: f64 = 0.2;
+
+const HELP: &str = "\
+list                                          show the input and its relations
+add POS SIZE le|be|ascii ANCHOR INSERT [STRIDE]
+                                              add a relation (its value is read from the input)
+rm IDX                                        remove relation IDX
+set IDX PROP V                                change PROP (pos, size, le, anchor, insert, stride or bias) of relation IDX
+                                              (le is one of le, be or ascii)
+check [IDX]                                   validate relation IDX (or all of them) with a few probes
+write                                         save the annotation and exit
+quit                                          exit without saving";
+
+/// Edits the relations of the `.annotated` sidecar of an input by hand, from an interactive prompt or from a patch
+/// file of the same commands (saved at its end).
+///
+/// Every added or changed relation is validated against the target: corrupting the field must lose coverage, and an
+/// insertion at the end of its region (updating all relations) must recover it. Failed validations are only reported,
+/// since the edit is the way to overrule the search.
+pub fn edit<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let path = PathBuf::from(res.modes.edit.unwrap());
+    let sidecar = annotation_path(&path);
+    let mut input = match sidecar.exists() {
+        true => read_annotation(&sidecar).expect("Could not parse annotation"),
+        false => Structured::raw(fs::read(&path).expect("Could not read testcase")),
+    };
+
+    let mut oracle = |data: &[u8]| -> HashSet<usize> { run_coverage(fuzz_fn, &mut obs, data).hits().iter().copied().collect() };
+
+    let (lines, interactive): (Box<dyn Iterator<Item = String>>, bool) = match &res.modes.edit_patch {
+        Some(patch) => {
+            let patch = fs::read_to_string(patch).expect("Could not read patch");
+            (Box::new(patch.lines().map(str::to_string).collect::<Vec<_>>().into_iter()), false)
+        }
+        None => {
+            println!("{}", HELP);
+            (Box::new(io::stdin().lock().lines().map_while(Result::ok)), true)
+        }
+    };
+
+    if interactive {
+        print_prompt();
+    }
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => {}
+            [cmd, ..] if cmd.starts_with('#') => {}
+            ["list"] => print!("{}", input.render_annotated()),
+            ["help"] => println!("{}", HELP),
+            ["write"] => break,
+            ["quit"] => return,
+            ["check"] => {
+                for idx in 0..input.relations.len() {
+                    report(&input, idx, &mut oracle);
+                }
+            }
+            ["check", idx] => match relation_index(&input, idx) {
+                Ok(idx) => report(&input, idx, &mut oracle),
+                Err(e) => println!("{}", e),
+            },
+            ["rm", idx] => match relation_index(&input, idx) {
+                Ok(idx) => println!("Removed {:?}", input.remove_relation(idx)),
+                Err(e) => println!("{}", e),
+            },
+            ["add", args @ ..] => match parse_relation(&input, args) {
+                Ok(rel) => {
+                    input.add_relation(rel);
+                    report(&input, input.relations.len() - 1, &mut oracle);
+                }
+                Err(e) => println!("{}", e),
+            },
+            ["set", idx, prop, value] => match relation_index(&input, idx).and_then(|idx| set(&mut input, idx, prop, value).map(|_| idx)) {
+                Ok(idx) => report(&input, idx, &mut oracle),
+                Err(e) => println!("{}", e),
+            },
+            _ => println!("Unknown command: {} (try help)", line),
+        }
+
+        if interactive {
+            print_prompt();
+        }
+    }
+
+    input.sanitize();
+    write_annotation(&sidecar, &input).expect("Could not write annotation");
+    println!("Wrote {} relations to {:?}", input.relations.len(), sidecar);
+}
+
+fn print_prompt() {
+    print!("edit> ");
+    io::stdout().flush().unwrap();
+}
+
+fn relation_index(input: &Structured, idx: &str) -> Result<usize, String> {
+    match idx.parse::<usize>() {
+        Ok(idx) if idx < input.relations.len() => Ok(idx),
+        _ => Err(format!("No relation {} (there are {})", idx, input.relations.len())),
+    }
+}
+
+fn parse_usize(value: &str) -> Result<usize, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("Invalid number: {}", value))
+}
+
+/// Parses the encoding of a field: `le`, `be` or `ascii` (decimal digits), into `(le, ascii)`.
+fn parse_le(value: &str) -> Result<(bool, bool), String> {
+    match value {
+        "le" => Ok((true, false)),
+        "be" => Ok((false, false)),
+        "ascii" => Ok((true, true)),
+        _ => Err(format!("Invalid encoding: {} (le, be or ascii)", value)),
+    }
+}
+
+/// Builds a relation from `add` arguments, with the value currently held by the field.
+fn parse_relation(input: &Structured, args: &[&str]) -> Result<Relation, String> {
+    if args.len() != 5 && args.len() != 6 {
+        return Err("Usage: add POS SIZE le|be|ascii ANCHOR INSERT [STRIDE]".to_string());
+    }
+
+    let (le, ascii) = parse_le(args[2])?;
+    let mut rel = Relation::new(parse_usize(args[0])?, 0, parse_usize(args[1])?, le, parse_usize(args[3])?, parse_usize(args[4])?);
+    rel.ascii = ascii;
+    if let Some(stride) = args.get(5) {
+        rel.stride = parse_usize(stride)?;
+    }
+    refresh(input, &mut rel)?;
+    Ok(rel)
+}
+
+/// Changes a property of relation `idx`, keeping the relation unchanged if the result is invalid.
+fn set(input: &mut Structured, idx: usize, prop: &str, value: &str) -> Result<(), String> {
+    let mut rel = input.relations[idx].clone();
+    match prop {
+        "pos" => rel.pos = parse_usize(value)?,
+        "size" => rel.size = parse_usize(value)?,
+        "le" => (rel.le, rel.ascii) = parse_le(value)?,
+        "anchor" => rel.anchor = parse_usize(value)?,
+        "insert" => rel.insert = parse_usize(value)?,
+        "stride" => rel.stride = parse_usize(value)?,
+        "bias" => rel.bias = parse_usize(value)?,
+        _ => return Err(format!("Unknown property: {}", prop)),
+    }
+    refresh(input, &mut rel)?;
+    input.relations[idx] = rel;
+    Ok(())
+}
+
+/// Checks that the relation fits the input, and reads the value of its field.
+fn refresh(input: &Structured, rel: &mut Relation) -> Result<(), String> {
+    let len = input.get_raw().len();
+    if rel.ascii && !(1..=MAX_ASCII_DIGITS).contains(&rel.size) {
+        return Err(format!("Unsupported number of digits: {} (at most {})", rel.size, MAX_ASCII_DIGITS));
+    }
+    if !rel.ascii && ![1, 2, 3, 4, 8].contains(&rel.size) {
+        return Err(format!("Unsupported field size: {}", rel.size));
+    }
+    if rel.stride == 0 {
+        return Err("The stride must be at least 1".to_string());
+    }
+    if rel.pos + rel.size > len || rel.anchor + rel.bias > rel.insert || rel.insert > len {
+        return Err(format!("The relation does not fit the input (pos + size <= {}, anchor + bias <= insert <= {})", len, len));
+    }
+
+    rel.value = if rel.ascii {
+        read_ascii_field(input.get_raw(), rel.pos, rel.size).ok_or("The field does not hold decimal digits")? as u64
+    } else {
+        read_field(input.get_raw(), rel.pos, rel.size, rel.le) as u64
+    };
+
+    let region = ((rel.insert - rel.anchor - rel.bias) / rel.stride) as u64;
+    if rel.value != region {
+        println!("Warning: the field holds {} but the region spans {} units", rel.value, region);
+    }
+    rel.old_pos = rel.pos;
+    rel.old_anchor = rel.anchor;
+    rel.old_insert = rel.insert;
+    rel.old_value = rel.value;
+    Ok(())
+}
+
+fn report<O>(input: &Structured, idx: usize, oracle: &mut O)
+where
+    O: FnMut(&[u8]) -> HashSet<usize>,
+{
+    match validate(input, idx, oracle) {
+        Ok(recovered) => println!("Relation {}: valid ({:.0}% of the lost coverage recovered)", idx, recovered * 100.0),
+        Err(e) => println!("Relation {}: not confirmed by the target, {}", idx, e),
+    }
+}
+
+/// Probes relation `idx`: corrupting its field must lose coverage, which an insertion of as many units at the end
+/// of its region (updating all relations) must recover. Returns the recovered fraction.
+fn validate<O>(input: &Structured, idx: usize, oracle: &mut O) -> Result<f64, String>
+where
+    O: FnMut(&[u8]) -> HashSet<usize>,
+{
+    let rel = &input.relations[idx];
+    // The corrupted digits of an ASCII field are written in place.
+    let max = if rel.ascii { 10u64.pow(rel.size as u32) - 1 } else { rel.max_value() };
+    let shift = VALIDATION_SHIFT.min(max.saturating_sub(rel.value));
+    if shift == 0 {
+        return Err("the field holds its maximum value".to_string());
+    }
+
+    let seed = oracle(input.get_raw());
+
+    let mut corrupted = input.get_raw().to_vec();
+    let mut lie = rel.clone();
+    lie.value += shift;
+    lie.apply(&mut corrupted);
+    let lost = seed.difference(&oracle(&corrupted)).copied().collect::<HashSet<_>>();
+    if lost.is_empty() {
+        return Err("corrupting the field loses no coverage".to_string());
+    }
+
+    let mut resized = input.clone();
+    if resized.insert(rel.insert, &vec![0x41; shift as usize * rel.stride]).is_err() {
+        return Err("an insertion at the end of the region breaks another relation".to_string());
+    }
+    let recovered = lost.intersection(&oracle(resized.get_raw())).count() as f64 / lost.len() as f64;
+    if recovered < MIN_RECOVERY {
+        return Err(format!("an insertion at the end of the region recovers {:.0}% of the lost coverage", recovered * 100.0));
+    }
+    Ok(recovered)
+}
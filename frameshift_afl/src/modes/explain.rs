@@ -0,0 +1,49 @@
+use std::{fs, path::PathBuf};
+
+use libafl::prelude::{MapObserver, StdMapObserver};
+
+use crate::{core::{search::{SearchContext, SearchGuidance}, structured::Structured}, run_coverage, search_options, Options};
+
+/// Re-runs the search hypotheses for a single byte position with extra-verbose output.
+///
+/// Prints every probe buffer along with the measured loss and recovery, which makes it possible to debug why a
+/// field was (or was not) detected without reading through a full verbose search log.
+pub fn explain<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>,)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let mut search_options = search_options(&res.search);
+    search_options.verbose = true;
+    search_options.extra_verbose = true;
+    search_options.max_iters = 1;
+
+    let args = res.modes.explain.unwrap();
+    let path = PathBuf::from(&args[0]);
+    let pos: usize = args[1].parse().expect("Could not parse position");
+
+    let raw = fs::read(&path).expect("Could not read testcase");
+    if pos >= raw.len() {
+        println!("Position {} is out of bounds (input size: {})", pos, raw.len());
+        return;
+    }
+
+    println!("Explaining position {} of {:?}", pos, path);
+
+    // Setup base.
+    obs.reset_map().unwrap();
+    fuzz_fn(&[]);
+
+    let guidance = SearchGuidance {
+        positions: Some(pos..pos + 1),
+        ..Default::default()
+    };
+
+    let mut oracle = |input: &[u8]| run_coverage(fuzz_fn, &mut obs, input);
+    let search_res = SearchContext::search_guided(&Structured::raw(raw), &mut oracle, search_options, &guidance);
+
+    match search_res.input.relations.iter().find(|rel| rel.pos == pos) {
+        Some(rel) => println!("Accepted: {:?}", rel),
+        None => println!("No relation accepted at position {}", pos),
+    }
+    println!("Tests: {}", search_res.test_count);
+}
@@ -0,0 +1,70 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use crate::{core::{collector::{CoverageBackend, CoverageWorker, ExternalCoverage}, search::{CoverageSnapshot, SearchContext}, structured::Structured}, format_analysis, search_options, AnalyzeFormat, Options};
+
+/// Creates the backend selected by the options (a persistent worker takes precedence over a command).
+pub fn coverage_backend(res: &Options) -> Option<Box<dyn CoverageBackend>> {
+    match (&res.coverage.coverage_worker, &res.coverage.coverage_cmd) {
+        (Some(cmd), _) => {
+            let timeout = Duration::from_millis(res.target.exec_timeout.parse().expect("Could not parse timeout in milliseconds"));
+            Some(Box::new(CoverageWorker::new(cmd, timeout)))
+        }
+        (None, Some(cmd)) => Some(Box::new(ExternalCoverage::new(cmd, res.coverage.coverage_format))),
+        (None, None) => None,
+    }
+}
+
+/// Analyzes a testcase with coverage from an external command or worker, for targets which can not be linked with FrameShift.
+pub fn analyze_external(res: Options) {
+    let search_options = search_options(&res.search);
+
+    let path = PathBuf::from(res.modes.analyze.clone().unwrap());
+    if res.analysis.analyze_format != AnalyzeFormat::Json || res.analysis.analyze_out.is_some() {
+        println!("Analyzing {:?} with {:?}", path, res.coverage.coverage_worker.as_ref().or(res.coverage.coverage_cmd.as_ref()).unwrap());
+    }
+
+    let mut coverage = coverage_backend(&res).unwrap();
+
+    let raw = fs::read(&path).expect("Could not read testcase");
+
+    let parts = res.coverage.part.iter()
+        .map(|spec| {
+            let (name, file) = spec.split_once('=').expect("Parts are given as NAME=FILE");
+            (name.to_string(), PathBuf::from(file), fs::read(file).expect("Could not read input part"))
+        })
+        .collect::<Vec<_>>();
+    for (name, _, data) in parts.iter() {
+        coverage.set_part(name, data);
+    }
+
+    let mut oracle = |input: &[u8]| CoverageSnapshot::from(coverage.run(input));
+    let search_res = SearchContext::search(&Structured::raw(raw.clone()), &mut oracle, search_options.clone());
+
+    let mut outputs = vec![format_analysis(&path, &search_res, &[], res.analysis.analyze_format)];
+
+    // Each part is searched next to the other files.
+    for (name, file, data) in parts.iter() {
+        let mut oracle = |input: &[u8]| {
+            coverage.set_part(name, input);
+            CoverageSnapshot::from(coverage.run(&raw))
+        };
+        let part_res = SearchContext::search(&Structured::raw(data.clone()), &mut oracle, search_options.clone());
+        coverage.set_part(name, data);
+
+        let analysis = format_analysis(file, &part_res, &[], res.analysis.analyze_format);
+        outputs.push(match res.analysis.analyze_format {
+            AnalyzeFormat::Json => analysis,
+            _ => format!("== part {} ==\n{}", name, analysis),
+        });
+    }
+
+    // The JSON documents of the files are put in an array.
+    let output = match (res.analysis.analyze_format, parts.is_empty()) {
+        (AnalyzeFormat::Json, false) => format!("[{}]", outputs.join(",\n")),
+        _ => outputs.join("\n"),
+    };
+    match &res.analysis.analyze_out {
+        Some(out) => fs::write(out, output).expect("Could not write analyze output"),
+        None => println!("{}", output),
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use crate::{components::structured_input::{annotation_path, corpus_files, read_annotation}, core::value_model::FieldValueModel, Options};
+
+/// Number of distinct values listed per field.
+const SHOWN_VALUES: usize = 8;
+
+/// Reports the values held by each field across the `.annotated` structures of a corpus.
+pub fn field_values(res: Options) {
+    let dir = PathBuf::from(res.modes.field_values.unwrap());
+
+    let mut model = FieldValueModel::default();
+    let mut annotated = 0;
+    for path in corpus_files(&dir).expect("Could not read corpus directory") {
+        let sidecar = annotation_path(&path);
+        if !sidecar.exists() {
+            continue;
+        }
+        let input = read_annotation(&sidecar).expect("Could not parse annotation");
+        model.observe(&input);
+        annotated += 1;
+    }
+
+    println!("{} annotated inputs in {:?}", annotated, dir);
+    println!("{:<10} {:>6} {:>5} {:>7} {:>12} {:>12}  values", "profile", "pos", "type", "count", "min", "max");
+    for (profile, pos, values) in model.fields() {
+        let ty = format!("u{}{}", values.size * 8, if values.size == 1 { "" } else if values.le { "le" } else { "be" });
+
+        let mut shown = values.values.iter().take(SHOWN_VALUES).map(|v| v.to_string()).collect::<Vec<_>>();
+        if values.saturated || values.values.len() > SHOWN_VALUES {
+            shown.push("...".to_string());
+        }
+
+        println!("{:<10} {:>6} {:>5} {:>7} {:>12} {:>12}  {}",
+            profile, pos, ty, values.count, values.min, values.max, shown.join(", "));
+    }
+}
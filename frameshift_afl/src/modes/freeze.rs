@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use crate::{components::structured_input::{annotation_path, read_annotation, write_annotation}, Options};
+
+/// Sets the `frozen` flag in the `.annotated` sidecar of an input, so the fuzzer keeps its structure as is.
+pub fn freeze(res: Options) {
+    let (path, frozen) = match (res.modes.freeze, res.modes.unfreeze) {
+        (Some(path), _) => (PathBuf::from(path), true),
+        (None, Some(path)) => (PathBuf::from(path), false),
+        (None, None) => unreachable!(),
+    };
+
+    let sidecar = annotation_path(&path);
+    if !sidecar.exists() {
+        println!("{:?} has no annotation at {:?}", path, sidecar);
+        return;
+    }
+
+    let mut input = read_annotation(&sidecar).expect("Could not parse annotation");
+    input.frozen = frozen;
+    write_annotation(&sidecar, &input).expect("Could not write annotation");
+
+    println!("{:?}: {}", path, if frozen { "frozen" } else { "unfrozen" });
+}
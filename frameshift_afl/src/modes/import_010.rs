@@ -0,0 +1,173 @@
+use std::{fs, path::PathBuf};
+
+use serde_json::Value;
+
+use crate::{components::structured_input::{annotation_path, write_annotation}, core::{search::read_field, structured::{Relation, Structured}}, Options};
+
+/// A single row of an 010 Editor template result.
+#[derive(Debug, Clone)]
+struct TemplateField {
+    name: String,
+    value: Option<u64>,
+    start: usize,
+    size: usize,
+}
+
+/// Parses a number as printed by 010 Editor (`16`, `10h` or `0x10`).
+fn parse_number(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_suffix('h').or_else(|| s.strip_suffix('H')) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Splits a CSV line, honoring double-quoted cells.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+/// Parses the "Export CSV" output of the template results (`Name,Value,Start,Size,...`).
+fn parse_csv(text: &str) -> Vec<TemplateField> {
+    let mut lines = text.lines();
+    let header = lines.next().map(split_csv).unwrap_or_default();
+    let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+
+    let (Some(name), Some(value), Some(start), Some(size)) = (column("name"), column("value"), column("start"), column("size")) else {
+        return vec![];
+    };
+
+    lines.filter_map(|line| {
+        let cells = split_csv(line);
+        Some(TemplateField {
+            name: cells.get(name)?.trim().to_string(),
+            value: parse_number(cells.get(value)?),
+            start: parse_number(cells.get(start)?)? as usize,
+            size: parse_number(cells.get(size)?)? as usize,
+        })
+    }).collect()
+}
+
+/// Parses a JSON list of `{"name", "value", "start", "size"}` objects (numbers may also be strings).
+fn parse_json(text: &str) -> Vec<TemplateField> {
+    let rows: Vec<Value> = serde_json::from_str(text).expect("Could not parse 010 JSON export");
+
+    let get = |row: &Value, key: &str| -> Option<String> {
+        let (_, v) = row.as_object()?.iter().find(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        match v {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    };
+
+    rows.iter().filter_map(|row| {
+        Some(TemplateField {
+            name: get(row, "name")?,
+            value: get(row, "value").and_then(|v| parse_number(&v)),
+            start: parse_number(&get(row, "start")?)? as usize,
+            size: parse_number(&get(row, "size")?)? as usize,
+        })
+    }).collect()
+}
+
+/// Converts a field with size or offset semantics (judged by its name) into a relation.
+///
+/// Size fields are anchored at a later field of exactly that size if there is one (otherwise right after the field),
+/// offset fields are anchored at the start of the file. The endianness is the one for which the bytes of the seed
+/// decode to the value reported by the template.
+fn to_relation(field: &TemplateField, fields: &[TemplateField], raw: &[u8]) -> Option<Relation> {
+    if ![1, 2, 3, 4, 8].contains(&field.size) || field.start + field.size > raw.len() {
+        return None;
+    }
+
+    let le = if field.size == 1 {
+        true
+    } else {
+        let value = field.value?;
+        if read_field(raw, field.start, field.size, true) as u64 == value {
+            true
+        } else if read_field(raw, field.start, field.size, false) as u64 == value {
+            false
+        } else {
+            return None;
+        }
+    };
+    let value = read_field(raw, field.start, field.size, le);
+
+    let name = field.name.to_lowercase();
+    let name = name.split('[').next().unwrap();
+    let field_end = field.start + field.size;
+
+    if ["offset", "ofs", "ptr", "pointer"].iter().any(|k| name.contains(k)) {
+        if value == 0 || value > raw.len() {
+            return None;
+        }
+        Some(Relation::new(field.start, value as u64, field.size, le, 0, value))
+    } else if ["size", "len"].iter().any(|k| name.contains(k)) {
+        let anchor = fields.iter()
+            .find(|f| f.start >= field_end && f.size == value)
+            .map(|f| f.start)
+            .unwrap_or(field_end);
+        if anchor + value > raw.len() {
+            return None;
+        }
+        Some(Relation::new(field.start, value as u64, field.size, le, anchor, anchor + value))
+    } else {
+        None
+    }
+}
+
+/// Converts an 010 Editor template result (CSV or JSON export) into relations on the matching seed and writes them
+/// to its `.annotated` sidecar.
+pub fn import_010(res: Options) {
+    let args = res.modes.import_010.unwrap();
+    let dump = PathBuf::from(&args[0]);
+    let seed = PathBuf::from(&args[1]);
+
+    let text = fs::read_to_string(&dump).expect("Could not read 010 export");
+    let raw = fs::read(&seed).expect("Could not read seed");
+
+    let fields = match dump.extension().and_then(|e| e.to_str()) {
+        Some("json") => parse_json(&text),
+        _ => parse_csv(&text),
+    };
+
+    let mut input = Structured::raw(raw);
+    for field in fields.iter() {
+        let Some(rel) = to_relation(field, &fields, input.get_raw()) else {
+            continue;
+        };
+
+        // Skip fields overlapping an already imported one.
+        if input.relations.iter().any(|other| other.pos < rel.pos + rel.size && rel.pos < other.pos + other.size) {
+            continue;
+        }
+
+        println!("{}: {:?}", field.name, rel);
+        input.add_relation(rel);
+    }
+
+    write_annotation(&annotation_path(&seed), &input).expect("Could not write annotation");
+
+    println!("Imported {} relations from {} fields into {:?}", input.relations.len(), fields.len(), annotation_path(&seed));
+}
@@ -0,0 +1,16 @@
+pub mod annotate;
+pub mod bench;
+pub mod cmin;
+pub mod convert;
+pub mod coverage_export;
+pub mod diff;
+pub mod edit;
+pub mod explain;
+pub mod external_coverage;
+pub mod field_values;
+pub mod freeze;
+pub mod import_010;
+pub mod registry;
+pub mod shift_report;
+pub mod triage;
+pub mod validate;
@@ -0,0 +1,117 @@
+use std::sync::Mutex;
+
+use libafl::prelude::StdMapObserver;
+
+use crate::Options;
+
+/// A mode registered by a crate embedding FrameShift (e.g. a target-specific experiment driver), selected with
+/// the `mode <name>` command.
+///
+/// The mode receives the parsed options (with its own arguments in `ModeFlags::mode_args`), the harness and the edges
+/// observer, like the built-in modes. Since `&dyn Fn` is itself a `Fn`, the harness can be passed on to the built-in
+/// helpers (e.g. `run_coverage(&mut fuzz_fn, ...)`).
+#[derive(Clone, Copy)]
+pub struct Mode {
+    pub name: &'static str,
+    /// One-line description shown by the `list-modes` command.
+    pub about: &'static str,
+    pub run: fn(Options, &dyn Fn(&[u8]) -> i32, StdMapObserver<u8,false>),
+}
+
+static MODES: Mutex<Vec<Mode>> = Mutex::new(Vec::new());
+
+/// Registers a mode, replacing any mode previously registered under the same name.
+///
+/// Must be called before `entrypoint`: the embedding crate depends on `frameshift_afl` without the `libafl_main`
+/// feature (or on `frameshift_afl_lib` without `run_driver`) and exports its own entry point, which registers its modes
+/// then calls `fuzzer_main` (or `run_driver`):
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub extern "C" fn libafl_main() {
+///     register_mode(Mode { name: "replay", about: "Replay a trace", run: replay });
+///     frameshift_afl::fuzzer_main();
+/// }
+/// ```
+pub fn register_mode(mode: Mode) {
+    let mut modes = MODES.lock().unwrap();
+    modes.retain(|m| m.name != mode.name);
+    modes.push(mode);
+}
+
+/// Returns the mode registered under `name`.
+pub fn find_mode(name: &str) -> Option<Mode> {
+    MODES.lock().unwrap().iter().find(|m| m.name == name).copied()
+}
+
+/// Returns all registered modes, sorted by name.
+pub fn registered_modes() -> Vec<Mode> {
+    let mut modes = MODES.lock().unwrap().clone();
+    modes.sort_by_key(|m| m.name);
+    modes
+}
+
+/// Runs the mode selected with the `mode` command, or lists the registered modes.
+pub fn run_mode<F>(res: Options, fuzz_fn: &mut F, obs: StdMapObserver<u8,false>)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    if res.modes.list_modes {
+        let modes = registered_modes();
+        if modes.is_empty() {
+            println!("No modes registered");
+        }
+        for mode in modes {
+            println!("{:<20} {}", mode.name, mode.about);
+        }
+        return;
+    }
+
+    let name = res.modes.mode.clone().unwrap();
+    match find_mode(&name) {
+        Some(mode) => (mode.run)(res, &*fuzz_fn, obs),
+        None => println!("Unknown mode {:?} (see the list-modes command)", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{entrypoint, Cli};
+
+    /// Arguments received by `record`.
+    static RECORDED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record(res: Options, fuzz_fn: &dyn Fn(&[u8]) -> i32, _obs: StdMapObserver<u8,false>) {
+        fuzz_fn(b"probe");
+        *RECORDED.lock().unwrap() = res.modes.mode_args;
+    }
+
+    fn ignore(_res: Options, _fuzz_fn: &dyn Fn(&[u8]) -> i32, _obs: StdMapObserver<u8,false>) {}
+
+    #[test]
+    fn test_register_mode() {
+        register_mode(Mode { name: "test-record", about: "old", run: ignore });
+        register_mode(Mode { name: "test-record", about: "Records its arguments", run: record });
+        register_mode(Mode { name: "test-a", about: "", run: ignore });
+
+        // Registering a name again replaces the mode.
+        assert_eq!(find_mode("test-record").unwrap().about, "Records its arguments");
+        let names = registered_modes().iter().map(|m| m.name).filter(|name| name.starts_with("test-")).collect::<Vec<_>>();
+        assert_eq!(names, ["test-a", "test-record"]);
+        assert!(find_mode("test-missing").is_none());
+
+        // The `mode` command runs it with its arguments and the harness.
+        let mut map = [0u8; 16];
+        let obs = unsafe { StdMapObserver::from_mut_ptr("edges", map.as_mut_ptr(), map.len()) };
+        let runs = std::cell::Cell::new(0);
+        let mut fuzz_fn = |_: &[u8]| { runs.set(runs.get() + 1); 0 };
+        let res = Cli::parse_from(["fuzzer", "mode", "test-record", "--flag", "value"]).into_options();
+        entrypoint(res, &mut fuzz_fn, obs);
+
+        assert_eq!(*RECORDED.lock().unwrap(), ["--flag", "value"]);
+        assert_eq!(runs.get(), 1);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use libafl::prelude::{MapObserver, StdMapObserver};
+
+use crate::{run_coverage, Options};
+
+/// Measures how much of the coverage of a seed survives shifting its bytes, for every pair of (corrupted byte,
+/// insertion point), and writes it as a CSV file along with one heatmap per shift size.
+///
+/// For each shift size `n`, every byte of the seed is corrupted in turn (by adding `n`, modulo 256), alone and then
+/// combined with an insertion of `n` bytes at every position. A length field shows up in the heatmap as a dark row
+/// (corrupting it loses coverage) with a bright spot at the end of its region (the insertion makes it consistent
+/// again). This takes `len * (len + 2)` executions per shift size, so it is meant for small seeds.
+pub fn shift_report<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let path = PathBuf::from(res.modes.shift_report.unwrap());
+    let out = res.modes.shift_report_out.map(PathBuf::from)
+        .unwrap_or_else(|| path.with_file_name(format!("{}.shift.csv", path.file_name().unwrap().to_string_lossy())));
+
+    let raw = fs::read(&path).expect("Could not read testcase");
+    println!("Shift report of {:?} ({} bytes, shift sizes {:?})", path, raw.len(), res.modes.shift_sizes);
+
+    // Setup base.
+    obs.reset_map().unwrap();
+    fuzz_fn(&[]);
+
+    let mut oracle = |input: &[u8]| -> HashSet<usize> {
+        run_coverage(fuzz_fn, &mut obs, input).hits().iter().copied().collect()
+    };
+
+    let orig = oracle(&raw);
+    println!("Original coverage: {}", orig.len());
+
+    let mut csv = String::from("shift,corrupt,insert,shared\n");
+    for &shift in res.modes.shift_sizes.iter() {
+        // Shared coverage of each corrupted byte (rows) for each insertion point (columns, the last one being the
+        // corruption alone).
+        let mut heatmap = vec![vec![0; raw.len() + 2]; raw.len()];
+        for i in 0..raw.len() {
+            let mut corrupted = raw.clone();
+            corrupted[i] = corrupted[i].wrapping_add(shift as u8);
+
+            let shared = orig.intersection(&oracle(&corrupted)).count();
+            heatmap[i][raw.len() + 1] = shared;
+            csv.push_str(&format!("{},{},,{}\n", shift, i, shared));
+
+            for j in 0..=raw.len() {
+                let mut input = corrupted.clone();
+                input.splice(j..j, vec![0x41; shift]);
+                let shared = orig.intersection(&oracle(&input)).count();
+                heatmap[i][j] = shared;
+                csv.push_str(&format!("{},{},{},{}\n", shift, i, j, shared));
+            }
+        }
+
+        let image = out.with_extension(format!("{}.pgm", shift));
+        fs::write(&image, heatmap_pgm(&heatmap, orig.len())).expect("Could not write heatmap");
+        println!("Shift {}: heatmap written to {:?}", shift, image);
+    }
+
+    fs::write(&out, csv).expect("Could not write shift report");
+    println!("Report written to {:?}", out);
+}
+
+/// Renders the shared coverage of each cell as a binary PGM image (white: all of the `max` coverage is kept).
+fn heatmap_pgm(heatmap: &[Vec<usize>], max: usize) -> Vec<u8> {
+    let width = heatmap.first().map_or(0, |row| row.len());
+    let mut image = format!("P5\n{} {}\n255\n", width, heatmap.len()).into_bytes();
+    for row in heatmap {
+        image.extend(row.iter().map(|shared| (shared * 255 / max.max(1)) as u8));
+    }
+    image
+}
@@ -0,0 +1,116 @@
+use std::{path::PathBuf, process, thread, time::{Duration, Instant}};
+
+use libafl::{executors::ExitKind, inputs::Input};
+use nix::{sys::{signal::{kill, Signal}, wait::{waitpid, WaitPidFlag, WaitStatus}}, unistd::{fork, ForkResult}};
+
+use crate::{components::{file_set::write_parts, structured_input::StructuredInput}, core::structured::Structured, Options};
+
+/// Runs the target on `data` in a forked child, which is killed if it runs longer than `timeout`.
+pub(crate) fn run_forked<F>(fuzz_fn: &F, data: &[u8], timeout: Duration) -> ExitKind
+where
+    F: Fn(&[u8]) -> i32,
+{
+    match unsafe { fork() }.expect("Could not fork") {
+        ForkResult::Child => {
+            fuzz_fn(data);
+            process::exit(0);
+        }
+        ForkResult::Parent { child } => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                match waitpid(child, Some(WaitPidFlag::WNOHANG)).expect("Could not wait for child") {
+                    WaitStatus::Exited(_, code) if code != 0 => return ExitKind::Crash,
+                    WaitStatus::Exited(..) => return ExitKind::Ok,
+                    WaitStatus::Signaled(..) => return ExitKind::Crash,
+                    _ if Instant::now() >= deadline => {
+                        let _ = kill(child, Signal::SIGKILL);
+                        let _ = waitpid(child, None);
+                        return ExitKind::Timeout;
+                    }
+                    _ => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+        }
+    }
+}
+
+/// Runs the target on `data` in a forked child and returns true if it crashed (a hang is not a crash).
+pub(crate) fn crashes<F>(fuzz_fn: &F, data: &[u8], timeout: Duration) -> bool
+where
+    F: Fn(&[u8]) -> i32,
+{
+    run_forked(fuzz_fn, data, timeout) == ExitKind::Crash
+}
+
+/// Breaks the consistency of the field of relation `idx`, leaving everything else in place.
+fn without_fixup(input: &Structured, idx: usize) -> Vec<u8> {
+    let mut rel = input.relations[idx].clone();
+    rel.value = if rel.value < rel.max_value() { rel.value + 1 } else { rel.value - 1 };
+
+    let mut raw = input.get_raw().to_vec();
+    rel.apply(&mut raw);
+    raw
+}
+
+/// Removes the region governed by relation `idx`, keeping all fields consistent.
+fn without_region(input: &Structured, idx: usize) -> Option<Vec<u8>> {
+    let rel = &input.relations[idx];
+    if rel.insert <= rel.anchor + rel.bias {
+        return None;
+    }
+
+    // The header left out of the value stays.
+    let mut input = input.clone();
+    let (start, len) = (rel.anchor + rel.bias, rel.insert - rel.anchor - rel.bias);
+    input.remove(start, len).ok()?;
+    Some(input.get_raw().to_vec())
+}
+
+/// Replays a crash along with its `.annotated` structure and reports which relations are needed to reproduce it.
+///
+/// For each relation, the crash is re-run once with the field no longer matching its region (is the consistent
+/// length necessary?) and once with the region removed (is its content necessary?).
+pub fn triage<F>(res: Options, fuzz_fn: &mut F)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let timeout = Duration::from_millis(res.target.exec_timeout.parse().expect("Could not parse timeout in milliseconds"));
+    let path = PathBuf::from(res.modes.triage.unwrap());
+    let crash = StructuredInput::from_file(&path).expect("Could not read crash");
+    // The other files of a multi-file crash stay as they are.
+    write_parts(&crash);
+    let input = crash.input;
+
+    if !crashes(fuzz_fn, input.get_raw(), timeout) {
+        println!("{:?} does not reproduce a crash", path);
+        return;
+    }
+
+    if input.relations.is_empty() {
+        println!("{:?} crashes, but has no annotated structure", path);
+        return;
+    }
+
+    println!("{:?} crashes ({} relations)", path, input.relations.len());
+
+    let necessity = |crashed: Option<bool>| match crashed {
+        Some(false) => "necessary",
+        Some(true) => "not necessary",
+        None => "n/a",
+    };
+
+    for (idx, rel) in input.relations.iter().enumerate() {
+        if !rel.enabled {
+            continue;
+        }
+
+        let fixup = crashes(fuzz_fn, &without_fixup(&input, idx), timeout);
+        let region = without_region(&input, idx).map(|raw| crashes(fuzz_fn, &raw, timeout));
+
+        println!(
+            "  [{}] field {}..{} (size={}, le={}, region {}..{}): consistent length {}, region content {}",
+            idx, rel.pos, rel.pos + rel.size, rel.size, rel.le, rel.anchor, rel.insert,
+            necessity(Some(fixup)), necessity(region),
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fs, path::PathBuf, process, time::Duration};
+
+use libafl::{executors::ExitKind, prelude::StdMapObserver};
+
+use crate::{components::structured_input::{annotation_path, corpus_files, read_annotation}, core::structured::Structured, modes::triage::run_forked, run_coverage, Options};
+
+/// Bytes inserted at each insertion point.
+const INSERT_LEN: usize = 16;
+
+/// A structural mutation applied to an annotated entry, and the share of its coverage which the result retained (0
+/// if it crashed or hung the target, as recorded in `exit_kind`).
+struct Outcome {
+    mutation: String,
+    retained: f64,
+    exit_kind: ExitKind,
+}
+
+/// The canned structural mutations of an input: inserting `INSERT_LEN` bytes at each insertion point, and removing
+/// each chunk (the field and region of a relation) nested in the region of another relation, so that the mutated
+/// inputs are well-formed if the structure is right.
+fn canned_mutations(input: &Structured) -> Vec<(String, Structured)> {
+    let mut mutations = vec![];
+    if input.fixed_size {
+        return mutations;
+    }
+
+    let mut points = input.insertion_points();
+    points.sort();
+    for point in points {
+        let mut mutated = input.clone();
+        if mutated.insert(point, &[0x41; INSERT_LEN]).is_ok() {
+            mutations.push((format!("insert {} bytes at {}", INSERT_LEN, point), mutated));
+        }
+    }
+
+    for idx in 0..input.relations.len() {
+        let Some(chunk) = input.region_of(idx) else {
+            continue;
+        };
+        let nested = input.relations.iter().enumerate().any(|(other, rel)| {
+            other != idx && rel.enabled && rel.anchor + rel.bias <= chunk.start && chunk.end <= rel.insert
+        });
+        if !nested {
+            continue;
+        }
+
+        // The relations of the chunk are removed with it.
+        let mut mutated = input.clone();
+        mutated.remove_disabling(chunk.start, chunk.len());
+        mutations.push((format!("remove chunk {}..{}", chunk.start, chunk.end), mutated));
+    }
+    mutations
+}
+
+/// Checks the `.annotated` structures of a corpus against the current target: each entry goes through a set of
+/// canned structural mutations, and every mutated input must retain most of the coverage of the entry (the edges it
+/// covers beyond the empty input).
+///
+/// Stale or wrong annotations (e.g. after an update of the target) produce inputs which the target rejects. Since
+/// these are likely malformed, every input first runs in a forked child with the execution timeout, so that a crash
+/// or hang fails its entry instead of the whole validation. Exits with an error if any entry fails.
+pub fn validate<F>(res: Options, fuzz_fn: &mut F, mut obs: StdMapObserver<u8,false>)
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let dir = PathBuf::from(res.modes.validate.unwrap());
+    let threshold = res.modes.validate_threshold;
+    let timeout = Duration::from_millis(res.target.exec_timeout.parse().expect("Could not parse timeout in milliseconds"));
+
+    let paths = corpus_files(&dir).expect("Could not read corpus directory");
+
+    let mut edges = |data: &[u8]| -> Result<Vec<usize>, ExitKind> {
+        match run_forked(fuzz_fn, data, timeout) {
+            ExitKind::Ok => Ok(run_coverage(fuzz_fn, &mut obs, data).hits().to_vec()),
+            kind => Err(kind),
+        }
+    };
+    let Ok(base) = edges(&[]) else {
+        println!("The target crashes or hangs on the empty input");
+        process::exit(1);
+    };
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let raw = fs::read(&path).expect("Could not read testcase");
+
+        let input = match read_annotation(&annotation_path(&path)) {
+            Ok(input) if input.get_raw() == raw => input,
+            Ok(_) => {
+                println!("{}: stale annotation (the file changed since)", name);
+                skipped += 1;
+                continue;
+            }
+            Err(_) => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let Ok(covered) = edges(&raw) else {
+            println!("{}: FAILED (the entry itself crashes or hangs the target)", name);
+            failed += 1;
+            continue;
+        };
+        let focus = covered.into_iter().filter(|edge| !base.contains(edge)).collect::<Vec<_>>();
+        let mutations = canned_mutations(&input);
+        if focus.is_empty() || mutations.is_empty() {
+            println!("{}: nothing to validate ({} relations)", name, input.relations.len());
+            skipped += 1;
+            continue;
+        }
+
+        let outcomes = mutations.into_iter().map(|(mutation, mutated)| match edges(mutated.get_raw()) {
+            Ok(covered) => {
+                let retained = focus.iter().filter(|edge| covered.contains(edge)).count() as f64 / focus.len() as f64;
+                Outcome { mutation, retained, exit_kind: ExitKind::Ok }
+            }
+            Err(exit_kind) => Outcome { mutation, retained: 0.0, exit_kind },
+        }).collect::<Vec<_>>();
+
+        let failures = outcomes.iter().filter(|outcome| outcome.retained < threshold).collect::<Vec<_>>();
+        let worst = outcomes.iter().map(|outcome| outcome.retained).fold(1.0, f64::min);
+        if failures.is_empty() {
+            println!("{}: ok ({} mutations, at least {:.0}% of the coverage retained)", name, outcomes.len(), worst * 100.0);
+            passed += 1;
+        } else {
+            println!("{}: FAILED ({} of {} mutations lost coverage)", name, failures.len(), outcomes.len());
+            for outcome in failures {
+                match outcome.exit_kind {
+                    ExitKind::Ok => println!("  {}: {:.0}% retained", outcome.mutation, outcome.retained * 100.0),
+                    ExitKind::Timeout => println!("  {}: hangs", outcome.mutation),
+                    _ => println!("  {}: crashes", outcome.mutation),
+                }
+            }
+            failed += 1;
+        }
+    }
+
+    println!("{} entries retained their coverage, {} failed, {} skipped (no annotation or nothing to mutate)", passed, failed, skipped);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::structured::Relation;
+
+    #[test]
+    fn test_canned_mutations() {
+        // L1|L2|..|.|T, the second chunk nested in the region of the first one.
+        let mut input = Structured::raw(vec![4, 2, 0x41, 0x41, 0x42, 0x54]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        input.add_relation(Relation::new(1, 2, 1, true, 2, 4));
+
+        let mutations = canned_mutations(&input);
+        let names = mutations.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["insert 16 bytes at 4", "insert 16 bytes at 5", "insert 16 bytes at 6", "remove chunk 1..4"]);
+
+        // The mutated inputs stay consistent.
+        let raws = mutations.iter().map(|(_, mutated)| mutated.get_raw().to_vec()).collect::<Vec<_>>();
+        assert_eq!(raws[0][..2], [20, 18]);
+        assert_eq!(raws[1][..2], [20, 2]);
+        assert_eq!(raws[2][..2], [4, 2]);
+        assert_eq!(raws[3], [1, 0x42, 0x54]);
+        assert_eq!(mutations[3].1.relations.len(), 1);
+
+        // The outer chunk is not nested, and fixed-size inputs are not mutated.
+        input.fixed_size = true;
+        assert!(canned_mutations(&input).is_empty());
+    }
+}
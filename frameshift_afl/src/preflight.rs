@@ -0,0 +1,149 @@
+//! Sanity checks of the fuzzing setup, run before the campaign starts so that a misconfiguration aborts with an
+//! actionable diagnostic instead of burning hours.
+
+use std::{fs, path::{Path, PathBuf}, time::Duration};
+
+use libafl::{executors::ExitKind, mutators::Tokens, observers::StdMapObserver};
+
+#[cfg(any(target_os = "linux", target_vendor = "apple"))]
+use libafl_targets::autotokens;
+
+use crate::{components::structured_input::corpus_files, core::hints::parse_hints, modes::triage::run_forked, run_coverage, Options};
+
+/// Number of seeds probed with an insertion to check that the target reacts to shifted data.
+const SHIFT_PROBE_SEEDS: usize = 8;
+
+/// Bytes inserted in the middle of a seed by the shift probe.
+const SHIFT_PROBE_SIZE: usize = 0x20;
+
+/// Timeout of the executions of the checks if `--exec-timeout` is invalid (the default of the option).
+const DEFAULT_TIMEOUT_MS: u64 = 1200;
+
+/// Checks the options, the seeds and the target, and returns a diagnostic for each problem found.
+pub fn preflight<F>(res: &Options, fuzz_fn: &F, obs: &mut StdMapObserver<u8,false>) -> Vec<String>
+where
+    F: Fn(&[u8]) -> i32,
+{
+    let mut problems = vec![];
+
+    let timeout = match res.target.exec_timeout.parse::<u64>() {
+        Ok(t) if t > 0 => Duration::from_millis(t),
+        _ => {
+            problems.push(format!("--exec-timeout {:?} is not a positive number of milliseconds", res.target.exec_timeout));
+            Duration::from_millis(DEFAULT_TIMEOUT_MS)
+        }
+    };
+
+    if let Some(tokens) = &res.fuzz.tokens {
+        if let Err(e) = Tokens::from_file(tokens) {
+            problems.push(format!("--tokens {:?} could not be parsed as an AFL dictionary ({})", tokens, e));
+        }
+    }
+
+    if let Some(hints) = &res.search.hints {
+        if let Err(e) = fs::read_to_string(hints).map_err(|e| e.to_string()).and_then(|spec| parse_hints(&spec)) {
+            problems.push(format!("--hints {:?} could not be parsed ({})", hints, e));
+        }
+    }
+
+    let in_dir = PathBuf::from(res.modes.input.as_ref().unwrap());
+    let seeds = read_seeds(&in_dir);
+    if !in_dir.is_dir() {
+        problems.push(format!("--input {:?} is not a directory", in_dir));
+    } else if seeds.is_empty() && res.fuzz.tokens.is_none() && !has_autotokens() {
+        problems.push(format!("--input {:?} holds no seed and the target has no autotokens: add at least one seed, or pass --tokens to synthesize seeds", in_dir));
+    }
+
+    if let Err(kind) = covered(fuzz_fn, obs, &[], timeout) {
+        problems.push(format!("the harness {} on an empty input: check that it handles short inputs (or the initialization of the target)", outcome(kind)));
+        return problems;
+    }
+
+    // Two very different inputs must reach different coverage, otherwise the target is not instrumented or the map
+    // is not the one it writes to.
+    let first = seeds.first().cloned().unwrap_or_else(|| b"FRAMESHIFT".to_vec());
+    let other = first.iter().rev().map(|b| !b).chain(std::iter::repeat(0xff).take(64)).collect::<Vec<_>>();
+    let first_cov = match covered(fuzz_fn, obs, &first, timeout) {
+        Ok(cov) => cov,
+        Err(kind) => {
+            problems.push(format!("the first seed {}: remove it from the seeds (or triage it)", outcome(kind)));
+            return problems;
+        }
+    };
+    if first_cov.is_empty() {
+        problems.push("the harness reaches no coverage at all: is the target instrumented (-fsanitize-coverage)?".to_string());
+        return problems;
+    }
+    if covered(fuzz_fn, obs, &other, timeout).is_ok_and(|cov| cov == first_cov) {
+        problems.push("two different inputs reach the same coverage: the coverage map does not depend on the input (is the parser linked, and instrumented?)".to_string());
+        return problems;
+    }
+
+    // The search relies on insertions changing the coverage of at least some seeds.
+    let mut probed = 0;
+    let mut shifted = false;
+    for (k, seed) in seeds.iter().enumerate().filter(|(_, seed)| seed.len() >= 2).take(SHIFT_PROBE_SEEDS) {
+        let seed_cov = match covered(fuzz_fn, obs, seed, timeout) {
+            Ok(cov) => cov,
+            Err(kind) => {
+                problems.push(format!("seed #{} {}: remove it from the seeds (or triage it)", k, outcome(kind)));
+                continue;
+            }
+        };
+
+        // A probe crashing or hanging where the seed did not reacts to the shift too.
+        let mut probe = seed.clone();
+        probe.splice(seed.len() / 2..seed.len() / 2, std::iter::repeat(0x41).take(SHIFT_PROBE_SIZE));
+        probed += 1;
+        if covered(fuzz_fn, obs, &probe, timeout).map_or(true, |cov| cov != seed_cov) {
+            shifted = true;
+            break;
+        }
+    }
+    if probed > 0 && !shifted {
+        problems.push(format!("inserting {} bytes in the middle of {} seeds never changes their coverage: the search cannot infer any structure (is the harness reading the input?)", SHIFT_PROBE_SIZE, probed));
+    }
+
+    problems
+}
+
+/// Whether seeds can be synthesized from the tokens embedded in the target (see `fuzz_common::load_seeds`).
+fn has_autotokens() -> bool {
+    #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+    {
+        autotokens().is_ok_and(|tokens| !tokens.is_empty())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_vendor = "apple")))]
+    {
+        false
+    }
+}
+
+/// Indices of the coverage map entries reached by `data`.
+///
+/// The input first runs in a forked child with the execution timeout, so that a crashing or hanging input is
+/// reported (as its exit kind) instead of killing or blocking the checks.
+fn covered<F>(fuzz_fn: &F, obs: &mut StdMapObserver<u8,false>, data: &[u8], timeout: Duration) -> Result<Vec<usize>, ExitKind>
+where
+    F: Fn(&[u8]) -> i32,
+{
+    match run_forked(fuzz_fn, data, timeout) {
+        ExitKind::Ok => Ok(run_coverage(fuzz_fn, obs, data).hits().to_vec()),
+        kind => Err(kind),
+    }
+}
+
+/// How a failed execution ended, for the diagnostics.
+fn outcome(kind: ExitKind) -> &'static str {
+    match kind {
+        ExitKind::Timeout => "hangs",
+        _ => "crashes",
+    }
+}
+
+/// Reads the seeds of `dir`, skipping the sidecars.
+fn read_seeds(dir: &Path) -> Vec<Vec<u8>> {
+    let paths = corpus_files(dir).unwrap_or_default();
+    paths.iter().filter_map(|path| fs::read(path).ok()).collect()
+}
@@ -0,0 +1,24 @@
+#!/bin/sh
+# Hides every symbol of the static library except the libFuzzer driver interface and the sanitizer coverage hooks, so
+# that it links into a cargo-fuzz target (CUSTOM_LIBFUZZER_PATH) without clashing with the Rust runtime of the target.
+#
+# Usage: ./localize.sh target/release/libframeshift_afl_lib.a [OUT]
+
+set -e
+
+LIB=$1
+OUT=${2:-$LIB}
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+
+# Link the archive into a single object first, so that references between its objects survive the localization.
+ld -r --whole-archive "$LIB" -o "$TMP/merged.o"
+
+nm --defined-only --extern-only --format=just-symbols "$TMP/merged.o" \
+    | grep -E '^(main|LLVMFuzzer|__sanitizer_|__cmplog_|__libafl_|libafl_)' \
+    | sort -u > "$TMP/keep.txt"
+
+objcopy --keep-global-symbols="$TMP/keep.txt" "$TMP/merged.o"
+
+rm -f "$OUT"
+ar crs "$OUT" "$TMP/merged.o"
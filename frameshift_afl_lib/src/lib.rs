@@ -1,36 +1,133 @@
-
-use std::os::raw::{c_char, c_int};
+use std::{
+    ffi::CStr, fs, os::raw::{c_char, c_int}, path::Path, time::Instant
+};
 
 use clap::Parser;
-use libafl::observers::StdMapObserver;
-use libafl_targets::extra_counters;
-use frameshift_afl::{entrypoint, Cli};
+use libafl_targets::libfuzzer_initialize;
+use frameshift_afl::{counters::counters_observer, entrypoint, split_args, Cli};
+
+pub mod libfuzzer_args;
+
+use libfuzzer_args::parse_libfuzzer_args;
 
+/// libFuzzer-compatible driver: `argc` and `argv` are the command line of the process (libFuzzer flags such as
+/// `-runs=N` and `-max_len=N`, FrameShift options, corpus directories), as passed to `main`.
+#[cfg(feature = "run_driver")]
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn LLVMFuzzerRunDriver(
-    _argc: *const c_int,
-    _argv: *const *const c_char,
+    argc: *const c_int,
+    argv: *const *const *const c_char,
+    harness_fn: Option<extern "C" fn(*const u8, usize) -> c_int>,
+) -> c_int {
+    unsafe { run_driver(argc, argv, harness_fn) }
+}
+
+/// The body of `LLVMFuzzerRunDriver`, for crates built without the `run_driver` feature which export their own driver
+/// (e.g. to register their modes with `frameshift_afl::modes::registry::register_mode` first).
+///
+/// # Safety
+///
+/// `argc` and `argv` must be null or point to the command line of the process, like the arguments of `main`.
+pub unsafe fn run_driver(
+    argc: *const c_int,
+    argv: *const *const *const c_char,
     harness_fn: Option<extern "C" fn(*const u8, usize) -> c_int>,
-) {
+) -> c_int {
     assert!(harness_fn.is_some(), "No harness callback provided");
     let harness_fn = harness_fn.unwrap();
 
-    // Ensure we see some coverage before starting fuzzing
-    let dummy = b"initial";
-    harness_fn(dummy.as_ptr(), dummy.len());
+    // Target-specific arguments (after `--`) go to `LLVMFuzzerInitialize`.
+    let (args, target_args) = split_args(&collect_args(argc, argv));
+    let lf_args = parse_libfuzzer_args(&args);
+
+    if libfuzzer_initialize(&target_args) == -1 {
+        println!("Warning: LLVMFuzzerInitialize failed with -1");
+    }
 
-    let res = Cli::parse();
+    let (edges, regions) = counters_observer();
 
+    // `-runs` and `-max_len` become `--runs` and `--max-len`, which the fuzz loop enforces.
     let mut fuzz_fn = |data: &[u8]| -> i32 {
-        harness_fn(data.as_ptr(), data.len() as usize)
+        let ret = harness_fn(data.as_ptr(), data.len());
+        if let Some(regions) = &regions {
+            regions.collect();
+        }
+        ret
     };
 
-    let edges = unsafe { extra_counters() };
-    let obs = StdMapObserver::from_mut_slice(
-        "edges",
-        edges.into_iter().next().unwrap(),
-    );
-    
-    entrypoint(res.options, &mut fuzz_fn, obs);
+    // Like libFuzzer, files given on the command line are run once each instead of fuzzing.
+    if !lf_args.inputs.is_empty() {
+        for path in &lf_args.inputs {
+            let data = fs::read(path).expect("Failed to read input");
+            println!("Running: {}", path);
+            let start = Instant::now();
+            fuzz_fn(&data);
+            println!("Executed {} in {} ms", path, start.elapsed().as_millis());
+        }
+        return 0;
+    }
+
+    // Ensure we see some coverage before starting fuzzing
+    fuzz_fn(b"initial");
+
+    // libFuzzer starts from an empty corpus (e.g. a new `cargo fuzz run` target) with a single input.
+    if let Some(corpus) = &lf_args.corpus {
+        seed_empty_corpus(Path::new(corpus));
+    }
+
+    let res = Cli::parse_from(lf_args.frameshift);
+
+    entrypoint(res.into_options(), &mut fuzz_fn, edges);
+    0
+}
+
+/// Writes a seed into `corpus` (created if needed) when it holds no file.
+fn seed_empty_corpus(corpus: &Path) {
+    let empty = fs::read_dir(corpus).map_or(true, |entries| {
+        entries.flatten().all(|entry| !entry.path().is_file())
+    });
+    if empty {
+        println!("Corpus {:?} is empty, starting from a single seed", corpus);
+        fs::create_dir_all(corpus).expect("Could not create corpus directory");
+        fs::write(corpus.join("seed"), b"initial").expect("Could not write seed");
+    }
+}
+
+/// Copies the command line given to `LLVMFuzzerRunDriver`, falling back to the process arguments without one.
+unsafe fn collect_args(argc: *const c_int, argv: *const *const *const c_char) -> Vec<String> {
+    if argc.is_null() || argv.is_null() || (*argv).is_null() {
+        return std::env::args().collect();
+    }
+
+    (0..*argc as usize)
+        .map(|idx| CStr::from_ptr(*(*argv).add(idx)).to_string_lossy().into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_empty_corpus() {
+        let corpus = std::env::temp_dir().join(format!("frameshift-corpus-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&corpus);
+
+        // A missing corpus is created, and a corpus without files seeded.
+        seed_empty_corpus(&corpus);
+        assert_eq!(fs::read(corpus.join("seed")).unwrap(), b"initial");
+        fs::remove_file(corpus.join("seed")).unwrap();
+        fs::create_dir(corpus.join("subdir")).unwrap();
+        seed_empty_corpus(&corpus);
+        assert!(corpus.join("seed").is_file());
+
+        // A corpus with files is left alone.
+        fs::remove_file(corpus.join("seed")).unwrap();
+        fs::write(corpus.join("entry"), b"data").unwrap();
+        seed_empty_corpus(&corpus);
+        assert!(!corpus.join("seed").exists());
+
+        fs::remove_dir_all(&corpus).unwrap();
+    }
 }
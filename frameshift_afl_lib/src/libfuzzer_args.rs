@@ -0,0 +1,228 @@
+//! Translation of libFuzzer command lines into FrameShift options.
+//!
+//! Arguments of the form `-name=value` are libFuzzer flags, other arguments starting with `-` are FrameShift options
+//! (with as many following values as the option takes), and the remaining arguments are corpus directories or inputs.
+
+use std::path::Path;
+
+use clap::{Command, CommandFactory};
+use frameshift_afl::Cli;
+
+#[derive(Debug, Default)]
+pub struct LibFuzzerArgs {
+    /// Files given as positionals: they are run once each instead of fuzzing.
+    pub inputs: Vec<String>,
+
+    /// Corpus directory the fuzzer starts from (the first directory, unless merging).
+    pub corpus: Option<String>,
+
+    /// Command line for `Cli`, including the translated libFuzzer flags and corpus directories.
+    pub frameshift: Vec<String>,
+}
+
+/// Splits a libFuzzer command line (program name included) into driver settings and FrameShift options.
+pub fn parse_libfuzzer_args(args: &[String]) -> LibFuzzerArgs {
+    let mut cmd = Cli::command();
+    cmd.build();
+
+    let mut res = LibFuzzerArgs::default();
+    res.frameshift.extend(args.first().cloned());
+
+    let mut dirs = Vec::new();
+    let mut runs = None;
+    let mut max_len = None;
+    let mut dict = None;
+    let mut timeout = None;
+    let mut artifact_prefix = None;
+    let mut merge = false;
+
+    let mut idx = 1;
+    while idx < args.len() {
+        let arg = &args[idx];
+        idx += 1;
+
+        if let Some((name, value)) = libfuzzer_flag(arg) {
+            match name {
+                "runs" => runs = value.parse::<u64>().ok(),
+                "max_len" => max_len = value.parse::<usize>().ok(),
+                "dict" => dict = Some(value.to_string()),
+                "timeout" => timeout = value.parse::<u64>().ok(),
+                "artifact_prefix" => artifact_prefix = Some(value.to_string()),
+                "merge" => merge = value == "1",
+                _ => println!("Ignoring libFuzzer flag: {}", arg),
+            }
+        } else if arg.starts_with('-') {
+            let num_values = option_values(&cmd, arg).min(args.len() - idx);
+            res.frameshift.push(arg.clone());
+            res.frameshift.extend(args[idx..idx + num_values].iter().cloned());
+            idx += num_values;
+        } else if Path::new(arg).is_file() {
+            res.inputs.push(arg.clone());
+        } else {
+            dirs.push(arg.clone());
+        }
+    }
+
+    if let Some(timeout) = timeout.filter(|_| !has_option(&res.frameshift, &["--exec-timeout", "--timeout", "-t"])) {
+        res.frameshift.extend(["--exec-timeout".to_string(), (timeout * 1000).to_string()]);
+    }
+    if let Some(runs) = runs.filter(|_| !has_option(&res.frameshift, &["--runs"])) {
+        res.frameshift.extend(["--runs".to_string(), runs.to_string()]);
+    }
+    if let Some(max_len) = max_len.filter(|_| !has_option(&res.frameshift, &["--max-len"])) {
+        res.frameshift.extend(["--max-len".to_string(), max_len.to_string()]);
+    }
+    if let Some(dict) = dict.filter(|_| !has_option(&res.frameshift, &["--tokens"])) {
+        res.frameshift.extend(["--tokens".to_string(), dict]);
+    }
+
+    // `-merge=1 <out> <corpus>` (e.g. `cargo fuzz cmin`) minimizes the corpus into the first directory.
+    if merge {
+        if dirs.len() < 2 {
+            println!("-merge=1 takes an output directory and a corpus directory");
+        } else if !has_option(&res.frameshift, &["--cmin"]) {
+            res.frameshift.extend(["--cmin".to_string(), dirs[1].clone(), "--out".to_string(), dirs[0].clone()]);
+        }
+        for dir in dirs.iter().skip(2) {
+            println!("Ignoring additional corpus directory: {}", dir);
+        }
+        return res;
+    }
+
+    // libFuzzer writes new entries into the first corpus directory, FrameShift keeps them in an output directory
+    // (`queue` and `crashes`) next to it.
+    if let Some(first) = dirs.first() {
+        if !has_option(&res.frameshift, &["--input", "-i"]) {
+            res.frameshift.extend(["--input".to_string(), first.clone()]);
+            res.corpus = Some(first.clone());
+        }
+        if !has_option(&res.frameshift, &["--out", "-o"]) {
+            let out = artifact_prefix.unwrap_or_else(|| format!("{}_frameshift", first.trim_end_matches('/')));
+            res.frameshift.extend(["--out".to_string(), out]);
+        }
+        for dir in &dirs[1..] {
+            println!("Ignoring additional corpus directory: {}", dir);
+        }
+    }
+
+    res
+}
+
+/// Whether `args` set one of the options `names`.
+fn has_option(args: &[String], names: &[&str]) -> bool {
+    args.iter().any(|arg| names.iter().any(|name| arg == name || arg.starts_with(&format!("{}=", name))))
+}
+
+/// Splits a libFuzzer flag (`-name=value`) into its name and value.
+fn libfuzzer_flag(arg: &str) -> Option<(&str, &str)> {
+    let flag = arg.strip_prefix('-').filter(|flag| !flag.starts_with('-'))?;
+    flag.split_once('=').filter(|(name, _)| name.len() > 1)
+}
+
+/// Number of values following a FrameShift option on the command line.
+fn option_values(cmd: &Command, arg: &str) -> usize {
+    if arg.contains('=') {
+        return 0;
+    }
+
+    let option = match arg.strip_prefix("--") {
+        Some(long) => cmd.get_arguments().find(|a| {
+            a.get_long_and_visible_aliases().is_some_and(|names| names.contains(&long))
+        }),
+        None => cmd.get_arguments().find(|a| a.get_short().is_some_and(|short| arg[1..] == short.to_string())),
+    };
+
+    match option {
+        Some(option) if option.get_action().takes_values() => {
+            option.get_num_args().map_or(1, |range| range.min_values())
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_libfuzzer_flag() {
+        assert_eq!(libfuzzer_flag("-runs=100"), Some(("runs", "100")));
+        assert_eq!(libfuzzer_flag("-dict=a=b"), Some(("dict", "a=b")));
+        assert_eq!(libfuzzer_flag("--exec-timeout=100"), None);
+        assert_eq!(libfuzzer_flag("-t"), None);
+        assert_eq!(libfuzzer_flag("-x=1"), None);
+        assert_eq!(libfuzzer_flag("corpus"), None);
+    }
+
+    #[test]
+    fn test_option_values() {
+        let mut cmd = Cli::command();
+        cmd.build();
+
+        assert_eq!(option_values(&cmd, "--exec-timeout"), 1);
+        assert_eq!(option_values(&cmd, "-t"), 1);
+        assert_eq!(option_values(&cmd, "--exec-timeout=100"), 0);
+        assert_eq!(option_values(&cmd, "--tui"), 0);
+        assert_eq!(option_values(&cmd, "--no-such-option"), 0);
+    }
+
+    #[test]
+    fn test_parse_libfuzzer_args() {
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-runs=100", "-max_len=64", "-timeout=2", "--tui", "-t", "500", "corpus_dir"]));
+
+        assert!(res.inputs.is_empty());
+        assert_eq!(res.corpus.as_deref(), Some("corpus_dir"));
+        assert_eq!(res.frameshift, args(&[
+            "fuzzer", "--tui", "-t", "500", "--runs", "100", "--max-len", "64",
+            "--input", "corpus_dir", "--out", "corpus_dir_frameshift",
+        ]));
+    }
+
+    #[test]
+    fn test_parse_libfuzzer_args_defaults() {
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-timeout=2", "-dict=tokens.dict", "-artifact_prefix=out/", "corpus/"]));
+
+        assert_eq!(res.frameshift, args(&[
+            "fuzzer", "--exec-timeout", "2000", "--tokens", "tokens.dict", "--input", "corpus/", "--out", "out/",
+        ]));
+    }
+
+    #[test]
+    fn test_parse_libfuzzer_args_merge() {
+        // Minimizes the second directory into the first one.
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-merge=1", "minimized/", "corpus/", "other/"]));
+        assert_eq!(res.corpus, None);
+        assert_eq!(res.frameshift, args(&["fuzzer", "--cmin", "corpus/", "--out", "minimized/"]));
+
+        // An explicit --cmin wins, and a single directory is not enough to merge.
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-merge=1", "--cmin", "seeds/", "minimized/", "corpus/"]));
+        assert_eq!(res.frameshift, args(&["fuzzer", "--cmin", "seeds/"]));
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-merge=1", "corpus/"]));
+        assert_eq!(res.frameshift, args(&["fuzzer"]));
+
+        // Only `-merge=1` merges.
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-merge=0", "corpus/"]));
+        assert_eq!(res.corpus.as_deref(), Some("corpus/"));
+    }
+
+    #[test]
+    fn test_parse_libfuzzer_args_corpus() {
+        // Files are inputs to run, and only the first directory is a corpus.
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "Cargo.toml", "corpus/", "other/"]));
+        assert_eq!(res.inputs, args(&["Cargo.toml"]));
+        assert_eq!(res.corpus.as_deref(), Some("corpus/"));
+        assert_eq!(res.frameshift, args(&["fuzzer", "--input", "corpus/", "--out", "corpus_frameshift"]));
+
+        // Explicit directories win over the corpus, which is then not seeded.
+        let res = parse_libfuzzer_args(&args(&["fuzzer", "-i", "seeds/", "-o", "out/", "corpus/"]));
+        assert_eq!(res.corpus, None);
+        assert_eq!(res.frameshift, args(&["fuzzer", "-i", "seeds/", "-o", "out/"]));
+
+        let res = parse_libfuzzer_args(&args(&["fuzzer"]));
+        assert_eq!((res.corpus, res.frameshift), (None, args(&["fuzzer"])));
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ * Structure inference and fixups of FrameShift, for C/C++ fuzzers (link with libframeshift_analyze).
+ */
+#ifndef FRAMESHIFT_H
+#define FRAMESHIFT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Executes `len` bytes at `data` and returns the coverage map of the execution (non-zero entries are covered),
+ * storing its length in `*map_len`. The map must stay valid until the next call, and keep the same length.
+ */
+typedef const uint8_t *(*frameshift_coverage_cb)(const uint8_t *data, size_t len, size_t *map_len);
+
+/*
+ * Infers the relations (length and offset fields) of `data`, probing the target through `coverage_cb`.
+ *
+ * On success, stores the annotated input as JSON in `*out_json` (free it with `frameshift_free_json`) and returns the
+ * number of relations found. Returns -1 on invalid arguments.
+ */
+int frameshift_analyze_buffer(const uint8_t *data, size_t len, frameshift_coverage_cb coverage_cb, char **out_json);
+
+/*
+ * Fixes the fields of `data` (`len` bytes, after the insertion) in place after `insert_len` bytes were inserted at
+ * `insert_pos`, given the annotation of the buffer before the insertion. With `insert_len` 0, only re-applies the
+ * relations. Returns 0 on success, or -1 (leaving the buffer unchanged) if the annotation does not match the buffer
+ * or the insertion breaks a relation.
+ */
+int frameshift_fixup_buffer(const char *json, uint8_t *data, size_t len, size_t insert_pos, size_t insert_len);
+
+/* Frees a JSON annotation returned by `frameshift_analyze_buffer`. */
+void frameshift_free_json(char *json);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,171 @@
+//! C interface to the structure inference, for fuzzers which only need the analysis and the fixups (e.g. AFL++
+//! plugins or custom C/C++ harnesses) without running the Rust fuzzer. See `frameshift.h`.
+//!
+//! Only `frameshift_core` is linked in: neither LibAFL, the libFuzzer driver nor the sanitizer coverage hooks, which
+//! would clash with the instrumentation of the fuzzer embedding the library.
+
+use std::{
+    ffi::{CStr, CString}, os::raw::{c_char, c_int}, slice
+};
+
+use frameshift_core::{search, CoverageSnapshot, SearchOptions, Structured};
+
+/// Executes `len` bytes at `data` and returns the coverage map of the execution (non-zero entries are covered),
+/// storing its length in `*map_len`. The map must stay valid until the next call, and keep the same length.
+pub type CoverageCallback = extern "C" fn(data: *const u8, len: usize, map_len: *mut usize) -> *const u8;
+
+/// Infers the relations of the `len` bytes at `data`, running a probe through `coverage_cb` per test.
+///
+/// On success, stores the annotated input as JSON in `*out_json` (to be freed with `frameshift_free_json`) and returns
+/// the number of relations found. Returns -1 on invalid arguments.
+///
+/// # Safety
+///
+/// `data` must be valid for `len` bytes and `out_json` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn frameshift_analyze_buffer(
+    data: *const u8,
+    len: usize,
+    coverage_cb: Option<CoverageCallback>,
+    out_json: *mut *mut c_char,
+) -> c_int {
+    let Some(coverage_cb) = coverage_cb else {
+        return -1;
+    };
+    if (data.is_null() && len > 0) || out_json.is_null() {
+        return -1;
+    }
+    let raw = if len == 0 { vec![] } else { slice::from_raw_parts(data, len).to_vec() };
+
+    let mut oracle = |probe: &[u8]| {
+        let mut map_len = 0;
+        let map = coverage_cb(probe.as_ptr(), probe.len(), &mut map_len);
+        if map.is_null() {
+            return CoverageSnapshot::default();
+        }
+        CoverageSnapshot::from_map(slice::from_raw_parts(map, map_len))
+    };
+    let (structured, _) = search(&Structured::raw(raw), &mut oracle, SearchOptions::default());
+
+    let Ok(json) = serde_json::to_string(&structured).map(|json| CString::new(json).unwrap()) else {
+        return -1;
+    };
+    *out_json = json.into_raw();
+    structured.relations.len() as c_int
+}
+
+/// Fixes the fields of a buffer in place after `insert_len` bytes were inserted at `insert_pos`, given the annotation
+/// of the buffer before the insertion (as returned by `frameshift_analyze_buffer`). With `insert_len` 0, only
+/// re-applies the relations (e.g. after in-place mutations).
+///
+/// `len` is the length of the buffer after the insertion. Returns 0 on success, or -1 (leaving the buffer unchanged)
+/// if the annotation is invalid, does not match the length of the buffer, or if the insertion breaks a relation.
+///
+/// # Safety
+///
+/// `json` must be a valid C string and `data` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn frameshift_fixup_buffer(
+    json: *const c_char,
+    data: *mut u8,
+    len: usize,
+    insert_pos: usize,
+    insert_len: usize,
+) -> c_int {
+    if json.is_null() || (data.is_null() && len > 0) {
+        return -1;
+    }
+    let Ok(mut structured) = serde_json::from_str::<Structured>(&CStr::from_ptr(json).to_string_lossy()) else {
+        return -1;
+    };
+
+    let original_len = structured.get_raw().len();
+    if original_len + insert_len != len || insert_pos > original_len {
+        return -1;
+    }
+    if insert_len > 0 && structured.on_insert(insert_pos, insert_len).is_err() {
+        return -1;
+    }
+
+    if len > 0 {
+        structured.sanitize_buffer(slice::from_raw_parts_mut(data, len));
+    }
+    0
+}
+
+/// Frees a JSON annotation returned by `frameshift_analyze_buffer`.
+///
+/// # Safety
+///
+/// `json` must come from `frameshift_analyze_buffer` (or be null) and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn frameshift_free_json(json: *mut c_char) {
+    if !json.is_null() {
+        drop(CString::from_raw(json));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, ptr};
+
+    use super::*;
+
+    thread_local! {
+        static MAP: RefCell<Vec<u8>> = RefCell::new(vec![0; 64]);
+    }
+
+    /// A toy target parsing a 1-byte length prefixed payload followed by a trailer.
+    extern "C" fn coverage(data: *const u8, len: usize, map_len: *mut usize) -> *const u8 {
+        let data = unsafe { slice::from_raw_parts(data, len) };
+        MAP.with_borrow_mut(|map| {
+            map.fill(0);
+            map[0] = 1;
+            if !data.is_empty() && (data[0] as usize) < data.len() {
+                map[1] = 1;
+                for b in data[1 + data[0] as usize..].iter() {
+                    map[2 + (*b as usize % 62)] = 1;
+                }
+            }
+            unsafe { *map_len = map.len() };
+            map.as_ptr()
+        })
+    }
+
+    fn analyze(data: &[u8]) -> (c_int, *mut c_char) {
+        let mut json = ptr::null_mut();
+        let found = unsafe { frameshift_analyze_buffer(data.as_ptr(), data.len(), Some(coverage), &mut json) };
+        (found, json)
+    }
+
+    #[test]
+    fn test_analyze_and_fixup() {
+        let (found, json) = analyze(&[4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        assert_eq!(found, 1);
+
+        // Two bytes inserted at the end of the payload: the length follows.
+        let mut buf = vec![4, 0x41, 0x41, 0x41, 0x41, 0x42, 0x42, 10, 20, 30];
+        assert_eq!(unsafe { frameshift_fixup_buffer(json, buf.as_mut_ptr(), buf.len(), 5, 2) }, 0);
+        assert_eq!(buf[0], 6);
+
+        // The length of the buffer does not match the insertion.
+        assert_eq!(unsafe { frameshift_fixup_buffer(json, buf.as_mut_ptr(), buf.len(), 5, 1) }, -1);
+
+        unsafe { frameshift_free_json(json) };
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let mut json = ptr::null_mut();
+        assert_eq!(unsafe { frameshift_analyze_buffer([1].as_ptr(), 1, None, &mut json) }, -1);
+        assert_eq!(unsafe { frameshift_analyze_buffer(ptr::null(), 1, Some(coverage), &mut json) }, -1);
+        assert!(json.is_null());
+
+        let invalid = CString::new("not json").unwrap();
+        let mut buf = vec![0; 4];
+        assert_eq!(unsafe { frameshift_fixup_buffer(invalid.as_ptr(), buf.as_mut_ptr(), buf.len(), 0, 0) }, -1);
+        assert_eq!(buf, vec![0; 4]);
+
+        unsafe { frameshift_free_json(ptr::null_mut()) };
+    }
+}
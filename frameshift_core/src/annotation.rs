@@ -0,0 +1,286 @@
+//! Encoding of the `.annotated` sidecars holding the structure of a corpus entry.
+//!
+//! Sidecars are JSON by default. The binary format (postcard, a few times smaller and faster to parse) starts with
+//! `ANNOTATION_MAGIC` and a version byte; files without the magic are read as JSON, so corpora written before the
+//! binary format (or by other tools) still load.
+
+use std::{fmt, fs, io, path::{Path, PathBuf}, process, str::FromStr};
+
+use serde::Deserialize;
+
+use super::structured::{Bitfield, Constant, Heuristic, Relation, RelationGroup, StringRegion, Structured};
+
+/// First bytes of a binary annotation (JSON annotations start with `{`).
+pub const ANNOTATION_MAGIC: &[u8; 4] = b"FSAN";
+
+/// Version of the binary layout. Postcard is not self-describing, so changing the fields of `Structured` (or of its
+/// members) changes the layout: bump the version and keep decoding the previous ones.
+pub const ANNOTATION_VERSION: u8 = 4;
+
+/// Format of the annotations written by the fuzzer and the modes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    #[default]
+    Json,
+    Binary,
+}
+
+impl FromStr for AnnotationFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(AnnotationFormat::Json),
+            "binary" => Ok(AnnotationFormat::Binary),
+            _ => Err(format!("invalid annotation format: {} (expected json or binary)", s)),
+        }
+    }
+}
+
+impl fmt::Display for AnnotationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnotationFormat::Json => f.write_str("json"),
+            AnnotationFormat::Binary => f.write_str("binary"),
+        }
+    }
+}
+
+/// Encodes `input` as an annotation in `format`.
+pub fn encode_annotation(input: &Structured, format: AnnotationFormat) -> Vec<u8> {
+    match format {
+        AnnotationFormat::Json => serde_json::to_vec(input).unwrap(),
+        AnnotationFormat::Binary => {
+            let mut bytes = ANNOTATION_MAGIC.to_vec();
+            bytes.push(ANNOTATION_VERSION);
+            postcard::to_extend(input, bytes).unwrap()
+        }
+    }
+}
+
+/// Decodes an annotation in either format.
+pub fn decode_annotation(bytes: &[u8]) -> Result<Structured, String> {
+    let Some(rest) = bytes.strip_prefix(ANNOTATION_MAGIC) else {
+        return serde_json::from_slice(bytes).map_err(|e| format!("invalid JSON annotation: {}", e));
+    };
+
+    match rest.split_first() {
+        Some((&ANNOTATION_VERSION, data)) => {
+            postcard::from_bytes(data).map_err(|e| format!("invalid binary annotation: {}", e))
+        }
+        Some((1, data)) => decode_legacy::<()>(data),
+        Some((2, data)) => decode_legacy::<usize>(data),
+        Some((3, data)) => decode_legacy::<(usize, bool)>(data),
+        Some((version, _)) => Err(format!("unsupported annotation version {} (expected at most {})", version, ANNOTATION_VERSION)),
+        None => Err("truncated binary annotation".to_string()),
+    }
+}
+
+/// Writes `input` to the annotation at `path` in `format`, through a temporary file renamed over it, so that the
+/// fuzzers reading the sidecars concurrently never see a partial annotation.
+pub fn write_annotation_file(path: &Path, input: &Structured, format: AnnotationFormat) -> io::Result<()> {
+    let tmp = sidecar_path(path, &format!("tmp{}", process::id()));
+    fs::write(&tmp, encode_annotation(input, format))?;
+    fs::rename(&tmp, path)
+}
+
+/// Path of the `.annotated` sidecar file holding the structure of the input at `path`.
+pub fn annotation_path(path: &Path) -> PathBuf {
+    sidecar_path(path, "annotated")
+}
+
+/// Path of the `.parts` sidecar file holding the other files (and their structures) of the multi-file input at `path`.
+pub fn parts_path(path: &Path) -> PathBuf {
+    sidecar_path(path, "parts")
+}
+
+/// Path of the hidden file `.<name>.<ext>` next to `path`.
+pub fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let parent = path.parent().unwrap();
+    let file_name = path.file_name().unwrap();
+    parent.join(format!(".{}.{}", file_name.to_string_lossy(), ext))
+}
+
+/// Files of a corpus directory, sorted by name, skipping the sidecars (`.annotated` and `.parts`) and other hidden
+/// files.
+pub fn corpus_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && !path.file_name().unwrap().to_string_lossy().starts_with('.'))
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths)
+}
+
+fn decode_legacy<X: AddedFields + for<'de> Deserialize<'de>>(data: &[u8]) -> Result<Structured, String> {
+    postcard::from_bytes::<LegacyStructured<X>>(data).map(Structured::from).map_err(|e| format!("invalid binary annotation: {}", e))
+}
+
+/// Layout of the previous versions, which lack `Structured::offsets` and whose relations lack the fields added since:
+/// `X` holds the ones they had among `Relation::bias` and `Relation::ascii` (which follow each other, so `()` for
+/// version 1, `usize` for version 2 and both for version 3).
+#[derive(Deserialize)]
+struct LegacyStructured<X> {
+    raw: Vec<u8>,
+    relations: Vec<LegacyRelation<X>>,
+    groups: Vec<RelationGroup>,
+    fixed_size: bool,
+    frozen: bool,
+    constants: Vec<Constant>,
+    strings: Vec<StringRegion>,
+}
+
+#[derive(Deserialize)]
+struct LegacyRelation<X> {
+    pos: usize,
+    value: u64,
+    size: usize,
+    le: bool,
+    anchor: usize,
+    insert: usize,
+    enabled: bool,
+    mirrors: Vec<usize>,
+    group: Option<usize>,
+    bits: Option<Bitfield>,
+    stride: usize,
+    whole_file: bool,
+    added: X,
+    heuristics: Vec<Heuristic>,
+    confirmed: u32,
+    refuted: u32,
+    old_pos: usize,
+    old_anchor: usize,
+    old_insert: usize,
+    old_value: u64,
+    old_mirrors: Vec<usize>,
+}
+
+/// Fields of `Relation` added after version 1, as `(bias, ascii)`.
+trait AddedFields {
+    fn added(&self) -> (usize, bool);
+}
+
+impl AddedFields for () {
+    fn added(&self) -> (usize, bool) {
+        (0, false)
+    }
+}
+
+impl AddedFields for usize {
+    fn added(&self) -> (usize, bool) {
+        (*self, false)
+    }
+}
+
+impl AddedFields for (usize, bool) {
+    fn added(&self) -> (usize, bool) {
+        *self
+    }
+}
+
+impl<X: AddedFields> From<LegacyStructured<X>> for Structured {
+    fn from(legacy: LegacyStructured<X>) -> Self {
+        let relations = legacy.relations.into_iter().map(|rel| {
+            let (bias, ascii) = rel.added.added();
+            Relation {
+                pos: rel.pos,
+                value: rel.value,
+                size: rel.size,
+                le: rel.le,
+                anchor: rel.anchor,
+                insert: rel.insert,
+                enabled: rel.enabled,
+                mirrors: rel.mirrors,
+                group: rel.group,
+                bits: rel.bits,
+                stride: rel.stride,
+                whole_file: rel.whole_file,
+                bias,
+                ascii,
+                heuristics: rel.heuristics,
+                confirmed: rel.confirmed,
+                refuted: rel.refuted,
+                old_pos: rel.old_pos,
+                old_anchor: rel.old_anchor,
+                old_insert: rel.old_insert,
+                old_value: rel.old_value,
+                old_mirrors: rel.old_mirrors,
+            }
+        }).collect();
+
+        Structured {
+            raw: legacy.raw,
+            relations,
+            groups: legacy.groups,
+            fixed_size: legacy.fixed_size,
+            frozen: legacy.frozen,
+            constants: legacy.constants,
+            strings: legacy.strings,
+            offsets: Vec::new(),
+            old_constants: Vec::new(),
+            old_strings: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structured::{Heuristic, Relation};
+
+    fn sample() -> Structured {
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 0xff]);
+        let mut rel = Relation::new(0, 4, 1, true, 1, 5);
+        rel.heuristics.push(Heuristic::LocalAnchor);
+        input.add_relation(rel);
+        input.fixed_size = true;
+        input
+    }
+
+    #[test]
+    fn test_annotation_roundtrip() {
+        let input = sample();
+        for format in [AnnotationFormat::Json, AnnotationFormat::Binary] {
+            let bytes = encode_annotation(&input, format);
+            assert_eq!(decode_annotation(&bytes).unwrap(), input);
+        }
+
+        let json = encode_annotation(&input, AnnotationFormat::Json);
+        let binary = encode_annotation(&input, AnnotationFormat::Binary);
+        assert!(binary.starts_with(ANNOTATION_MAGIC));
+        assert!(binary.len() < json.len());
+    }
+
+    #[test]
+    fn test_annotation_versions() {
+        // JSON written before the binary format, without the fields added since.
+        let legacy = r#"{"raw":[1,65],"relations":[]}"#;
+        assert_eq!(decode_annotation(legacy.as_bytes()).unwrap().get_raw(), &[1, 65]);
+
+        // Binary annotation written by version 1, before `Relation::bias`.
+        let v1 = [
+            70, 83, 65, 78, 1, 6, 4, 65, 65, 65, 65, 255, 1, 0, 4, 1, 1, 1, 5, 1, 0, 0, 0, 1, 0, 1, 1, 0, 0, 0, 1, 5, 4,
+            0, 0, 1, 0, 0, 0,
+        ];
+        assert_eq!(decode_annotation(&v1).unwrap(), sample());
+
+        // Version 2, before `Relation::ascii`.
+        let v2 = [
+            70, 83, 65, 78, 2, 6, 4, 65, 65, 65, 65, 255, 1, 0, 4, 1, 1, 1, 5, 1, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, 0, 1, 5,
+            4, 0, 0, 1, 0, 0, 0,
+        ];
+        assert_eq!(decode_annotation(&v2).unwrap(), sample());
+
+        // Version 3, before `Structured::offsets`.
+        let v3 = [
+            70, 83, 65, 78, 3, 6, 4, 65, 65, 65, 65, 255, 1, 0, 4, 1, 1, 1, 5, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 0, 1,
+            5, 4, 0, 0, 1, 0, 0, 0,
+        ];
+        assert_eq!(decode_annotation(&v3).unwrap(), sample());
+
+        let mut newer = encode_annotation(&sample(), AnnotationFormat::Binary);
+        newer[ANNOTATION_MAGIC.len()] = ANNOTATION_VERSION + 1;
+        assert!(decode_annotation(&newer).is_err());
+        assert!(decode_annotation(ANNOTATION_MAGIC).is_err());
+    }
+}
@@ -0,0 +1,951 @@
+//! Structure inference as a library, without any LibAFL types.
+//!
+//! The target is represented by a plain coverage callback, so the search can be driven from any harness:
+//!
+//! ```ignore
+//! let mut oracle = |data: &[u8]| CoverageSnapshot::from_map(&run_my_target(data));
+//! let (structured, report) = frameshift_core::search(&seed, &mut oracle, SearchOptions::default());
+//! ```
+
+pub use super::annotation::{decode_annotation, encode_annotation, AnnotationFormat};
+pub use super::filler::Filler;
+pub use super::gates::{field_gates, FieldGates};
+pub use super::hints::{apply_hints, parse_hints, Hint};
+pub use super::interop::{from_binary_template, to_binary_template};
+pub use super::search::{CoverageSnapshot, HeuristicStats, SearchCheckpoint, SearchGuidance, SearchOptions, SearchProgress, SearchStatus, SearchStatusFn, SearchStop};
+pub use super::strategy::{strategy_by_name, BisectStrategy, Candidate, CmplogStrategy, EntropyStrategy, LinearStrategy, SearchStrategy};
+pub use super::structured::{Bitfield, Constant, Heuristic, InvalidEdit, OffsetRelation, Relation, RelationGroup, StringRegion, Structured};
+
+use std::collections::BTreeMap;
+
+use super::search::{SearchContext, SearchResult};
+
+/// A coverage callback, invoked once per probe. It returns the indices covered by the probe (e.g. the non-zero
+/// entries of a coverage map, see `CoverageSnapshot::from_map`), owned so that it may reuse its map for the next probe.
+pub type CoverageFn<'a> = dyn FnMut(&[u8]) -> CoverageSnapshot + 'a;
+
+/// Statistics about a completed search.
+#[derive(Debug, Clone)]
+pub struct SearchReport {
+    /// Number of executions of the target.
+    pub test_count: usize,
+    /// Time spent in the target (ms).
+    pub target_test_ms: u64,
+    /// Total time spent searching (ms).
+    pub total_test_ms: u64,
+    /// Probes answered from the cache of the latest probes instead of executing the target.
+    pub cache_hits: usize,
+    /// Whether any relation was found.
+    pub found_any: bool,
+    /// Coverage indices hit by the input but not by an empty input.
+    pub focus_indices: Vec<usize>,
+    /// Value profile features hit by the input but not by an empty input (see `SearchOptions::use_value_profile`).
+    pub focus_features: Vec<usize>,
+    /// Coverage indices whose coverage changed across the runs of the input (see `SearchOptions::stability_runs`).
+    pub unstable_indices: Vec<usize>,
+    /// Probe budget and results of each search heuristic.
+    pub heuristics: BTreeMap<Heuristic, HeuristicStats>,
+    /// Whether the search stopped at `SearchOptions::max_tests` before finishing.
+    pub truncated: bool,
+    /// Where a truncated search stopped, to resume it with `SearchGuidance::resume`.
+    pub progress: Option<SearchProgress>,
+}
+
+impl SearchResult {
+    /// Splits the result into the annotated input and the search statistics.
+    pub fn into_parts(self) -> (Structured, SearchReport) {
+        let report = SearchReport {
+            test_count: self.test_count,
+            target_test_ms: self.target_test_ms,
+            total_test_ms: self.total_test_ms,
+            cache_hits: self.cache_hits,
+            found_any: self.found_any,
+            focus_indices: self.focus_indices,
+            focus_features: self.focus_features,
+            unstable_indices: self.unstable_indices,
+            heuristics: self.heuristics,
+            truncated: self.truncated,
+            progress: self.progress,
+        };
+        (self.input, report)
+    }
+}
+
+/// Infers the structure of `input` using `oracle` to measure coverage.
+pub fn search(input: &Structured, oracle: &mut CoverageFn, options: SearchOptions) -> (Structured, SearchReport) {
+    search_guided(input, oracle, options, &SearchGuidance::default())
+}
+
+/// Like `search`, with extra guidance (e.g. comparison operands or a restricted range of positions).
+pub fn search_guided(input: &Structured, mut oracle: &mut CoverageFn, options: SearchOptions, guidance: &SearchGuidance) -> (Structured, SearchReport) {
+    SearchContext::search_guided(input, &mut oracle, options, guidance).into_parts()
+}
+
+/// Like `search_guided`, reporting the status of the search to `on_status` at most once per
+/// `SearchOptions::status_interval`.
+pub fn search_reporting(input: &Structured, mut oracle: &mut CoverageFn, options: SearchOptions, guidance: &SearchGuidance, on_status: &mut SearchStatusFn) -> (Structured, SearchReport) {
+    SearchContext::search_reporting(input, &mut oracle, options, guidance, Some(on_status)).into_parts()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// The oracle of a target returning its coverage map.
+    fn snapshots(target: fn(&[u8]) -> Vec<u8>) -> impl FnMut(&[u8]) -> CoverageSnapshot {
+        move |data| CoverageSnapshot::from(target(data))
+    }
+
+    /// A toy target parsing a 1-byte length prefixed payload followed by a trailer.
+    fn target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.is_empty() || 1 + data[0] as usize > data.len() {
+            return cov;
+        }
+
+        cov[1] = 1;
+        for b in data[1 + data[0] as usize..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_length_prefix() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+        let (res, report) = search(&seed, &mut oracle, SearchOptions::default());
+
+        assert!(report.found_any);
+        assert_eq!(report.focus_indices, vec![1, 12, 22, 32]);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert_eq!(rel.heuristics, vec![Heuristic::LocalAnchor]);
+
+        let local = report.heuristics[&Heuristic::LocalAnchor];
+        assert_eq!(local.found, 1);
+        assert!(local.tests > 0 && local.tests < report.test_count);
+    }
+
+    /// Like `target`, for a parser which rejects any input of another length than 8 bytes.
+    fn fixed_target(data: &[u8]) -> Vec<u8> {
+        match data.len() {
+            8 => target(data),
+            _ => target(&[]),
+        }
+    }
+
+    #[test]
+    fn test_search_fixed_size() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(fixed_target);
+
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.fixed_size);
+
+        let options = SearchOptions { detect_fixed_size: false, ..Default::default() };
+        let (res, _) = search(&seed, &mut oracle, options);
+        assert!(!res.fixed_size);
+
+        // A variable-size target is never taken for a fixed-size one.
+        let (res, _) = search(&seed, &mut snapshots(target), SearchOptions::default());
+        assert!(!res.fixed_size);
+    }
+
+    #[test]
+    fn test_search_keeps_constants() {
+        // The probes insert and remove bytes before the trailer, which must not move its constant for good.
+        let mut seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        seed.constants.push(Constant { pos: 5, bytes: vec![10, 20, 30] });
+        seed.strings.push(StringRegion { pos: 1, len: 4, terminated: false });
+        let mut oracle = snapshots(target);
+        let (res, report) = search(&seed, &mut oracle, SearchOptions::default());
+
+        assert!(report.found_any);
+        assert_eq!(res.constants, seed.constants);
+        assert_eq!(res.strings, seed.strings);
+        assert!(res.constants_intact());
+    }
+
+    #[test]
+    fn test_search_cmplog_tag() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+        let guidance = SearchGuidance { cmp_values: [4].into(), ..Default::default() };
+        let (res, report) = search_guided(&seed, &mut oracle, SearchOptions::default(), &guidance);
+
+        assert_eq!(res.relations[0].heuristics, vec![Heuristic::Cmplog, Heuristic::LocalAnchor]);
+        assert_eq!(report.heuristics[&Heuristic::Cmplog].found, 1);
+        assert!(report.heuristics[&Heuristic::Cmplog].tests > 0);
+    }
+
+    #[test]
+    fn test_focus_edges() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+
+        let options = SearchOptions { focus_edges: Some([12, 22].into()), ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert_eq!(report.focus_indices, vec![12, 22]);
+        assert_eq!(res.relations.len(), 1);
+
+        // None of the targeted edges is reached by the seed.
+        let options = SearchOptions { focus_edges: Some([63].into()), ..Default::default() };
+        let (_, report) = search(&seed, &mut oracle, options);
+        assert!(report.focus_indices.is_empty());
+        assert!(!report.found_any);
+    }
+
+    #[test]
+    fn test_prune_spurious() {
+        // The payload byte at 1 is not a length, but was annotated as one (e.g. because of noisy coverage).
+        let mut seed = Structured::raw(vec![4, 1, 0x41, 0x41, 0x41, 10, 20, 30]);
+        seed.add_relation(Relation::new(1, 1, 1, true, 2, 3));
+
+        let mut oracle = snapshots(target);
+        let options = SearchOptions { prune_threshold: 0.5, ..Default::default() };
+        let (res, _) = search(&seed, &mut oracle, options);
+
+        assert_eq!(res.relations.len(), 1);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert_eq!(rel.refuted, 0);
+
+        // Re-validation is disabled by default.
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert_eq!(res.relations.len(), 2);
+    }
+
+    /// Like `target`, but with a 3-byte big-endian length prefix.
+    fn target24(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 3 {
+            return cov;
+        }
+        let len = u32::from_be_bytes([0, data[0], data[1], data[2]]) as usize;
+        if 3 + len > data.len() {
+            return cov;
+        }
+
+        cov[1] = 1;
+        for b in data[3 + len..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_u24() {
+        let seed = Structured::raw(vec![0, 0, 4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target24);
+        let (res, report) = search(&seed, &mut oracle, SearchOptions::default());
+
+        assert!(report.found_any);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.le, rel.anchor, rel.insert), (0, 3, false, 3, 7));
+    }
+
+    /// Like `target`, but the payload is rejected if it contains printable bytes.
+    fn strict_target(data: &[u8]) -> Vec<u8> {
+        if !data.is_empty() && data[1..].iter().take(data[0] as usize).any(|b| *b >= 0x20) {
+            let mut cov = vec![0; 64];
+            cov[0] = 1;
+            return cov;
+        }
+        target(data)
+    }
+
+    #[test]
+    fn test_search_fill_strategy() {
+        let seed = Structured::raw(vec![4, 1, 2, 3, 4, 10, 20, 30]);
+
+        let mut oracle = snapshots(strict_target);
+        let (_, report) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(!report.found_any);
+
+        let options = SearchOptions {
+            fill_strategy: vec![Filler::Random, Filler::Byte(0)],
+            ..Default::default()
+        };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert!(report.found_any);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert_eq!(rel.heuristics, vec![Heuristic::LocalAnchor, Heuristic::FallbackFiller]);
+    }
+
+    /// Like `target`, with the length in the low nibble of the first byte and flags in the high nibble.
+    fn nibble_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.is_empty() || data[0] >> 4 != 0xa || 1 + (data[0] & 0xf) as usize > data.len() {
+            return cov;
+        }
+
+        cov[1] = 1;
+        for b in data[1 + (data[0] & 0xf) as usize..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_bitfield() {
+        let seed = Structured::raw(vec![0xa4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(nibble_target);
+
+        let (_, report) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(!report.found_any);
+
+        let options = SearchOptions { enable_bitfields: true, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert!(report.found_any);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert_eq!(rel.bits, Some(Bitfield { shift: 0, width: 4 }));
+        assert!(rel.heuristics.contains(&Heuristic::Bitfield));
+    }
+
+    /// A 16-bit length counted in 2-byte words, followed by a trailer.
+    fn word_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 2 || 2 + u16::from_le_bytes([data[0], data[1]]) as usize * 2 > data.len() {
+            return cov;
+        }
+
+        cov[1] = 1;
+        for b in data[2 + u16::from_le_bytes([data[0], data[1]]) as usize * 2..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_stride() {
+        let seed = Structured::raw(vec![3, 0, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(word_target);
+
+        // Strides are only tried when enabled.
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.iter().all(|rel| rel.stride == 1));
+
+        let options = SearchOptions { enable_strides: true, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert!(report.found_any);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.le, rel.anchor, rel.insert), (0, 2, true, 2, 8));
+        assert_eq!(rel.stride, 2);
+    }
+
+    /// A 1-byte length of a payload which follows a 2-byte signature, followed by a 3-byte trailer.
+    fn signed_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        let end = 3 + data.first().copied().unwrap_or(0) as usize;
+        if data.len() < 3 || &data[1..3] != b"AB" || end > data.len() {
+            return cov;
+        }
+
+        for b in data[end..(end + 3).min(data.len())].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_exhaustive() {
+        let seed = Structured::raw(vec![1, b'A', b'B', 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(signed_target);
+
+        // Inserting after the field breaks the signature.
+        let (_, report) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(!report.found_any);
+
+        let options = SearchOptions { exhaustive_below: 256, ..Default::default() };
+        let (res, exhaustive) = search(&seed, &mut oracle, options);
+        assert!(exhaustive.found_any);
+        assert!(exhaustive.test_count + exhaustive.cache_hits > report.test_count + report.cache_hits);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size), (0, 1));
+        assert!(rel.anchor >= 2 && rel.insert <= 4);
+    }
+
+    /// A 16-bit length of a payload followed by a trailer.
+    fn payload_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        let end = 2 + u16::from_le_bytes([data.first().copied().unwrap_or(0), data.get(1).copied().unwrap_or(0)]) as usize;
+        if data.len() < 2 || end > data.len() {
+            return cov;
+        }
+
+        for b in data[end..(end + 3).min(data.len())].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_prefilter() {
+        // A pseudo-random (compressed-like) payload.
+        let mut seed = vec![200, 0];
+        let mut state = 0x1234u32;
+        seed.extend((0..200).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }));
+        seed.extend([10, 20, 30]);
+        let seed = Structured::raw(seed);
+        let mut oracle = snapshots(payload_target);
+
+        let (_, full) = search(&seed, &mut oracle, SearchOptions::default());
+        let options = SearchOptions { prefilter: true, ..Default::default() };
+        let (res, filtered) = search(&seed, &mut oracle, options);
+
+        assert!(filtered.test_count < full.test_count);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 2, 2, 202));
+    }
+
+    #[test]
+    fn test_search_loss_cache() {
+        // A tag byte which no insertion can fix: corrupting it as an 8-byte, 4-byte or 2-byte little-endian field
+        // modifies the same bytes and loses the same coverage.
+        let seed = Structured::raw(vec![3, 0, 0, 0, 0, 0, 0, 0, 0x41, 0x41]);
+        let mut probes = std::collections::HashMap::<Vec<u8>, usize>::new();
+        let mut oracle = |data: &[u8]| {
+            *probes.entry(data.to_vec()).or_default() += 1;
+            let mut cov = vec![0; 8];
+            cov[0] = 1;
+            cov[1] = (data.first() == Some(&3)) as u8;
+            CoverageSnapshot::from(cov)
+        };
+
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.is_empty());
+
+        // Every corruption of the seed (same length, different bytes) is probed once.
+        let corruptions = probes.iter().filter(|(data, _)| data.len() == 10 && data[..] != *seed.get_raw());
+        assert!(corruptions.into_iter().all(|(_, count)| *count == 1));
+    }
+
+    /// A 1-byte length prefixed payload followed by a 3-byte trailer starting with 10. Lengths beyond the input are
+    /// tolerated: the payload then spans everything up to the trailer.
+    fn tolerant_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 4 {
+            return cov;
+        }
+
+        let len = if 1 + data[0] as usize + 3 > data.len() { data.len() - 4 } else { data[0] as usize };
+        if data[1 + len] != 10 {
+            return cov;
+        }
+        cov[1] = 1;
+        for b in data[1 + len..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_shrink() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(tolerant_target);
+
+        // Larger lengths lose no coverage.
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.is_empty());
+
+        let options = SearchOptions { shrink_probes: true, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert!(rel.heuristics.contains(&Heuristic::Shrink));
+        assert_eq!(report.heuristics[&Heuristic::Shrink].found, 1);
+
+        // Re-searching the annotated input revalidates the relation with a smaller value, which it does not tolerate.
+        let options = SearchOptions { shrink_probes: true, prune_threshold: 0.5, ..Default::default() };
+        let (res, _) = search(&seed, &mut oracle, options.clone());
+        let (res, _) = search(&res, &mut oracle, options);
+        assert_eq!(res.relations.len(), 1);
+        assert_eq!((res.relations[0].confirmed, res.relations[0].refuted), (2, 0));
+
+        // The usual relations pass the shrink probe.
+        let (res, _) = search(&seed, &mut snapshots(target), SearchOptions { shrink_probes: true, ..Default::default() });
+        assert_eq!(res.relations[0].insert, 5);
+    }
+
+    /// A sequence of chunks, each a 16-bit little-endian length followed by its payload.
+    fn chunks_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        let mut pos = 0;
+        let mut k = 0;
+        while pos + 2 <= data.len() {
+            let len = u16::from_le_bytes([data[pos], data[pos+1]]) as usize;
+            if pos + 2 + len > data.len() {
+                break;
+            }
+            cov[1 + k] = 1;
+            for b in data[pos + 2..pos + 2 + len].iter() {
+                cov[16 + (*b as usize % 48)] = 1;
+            }
+            pos += 2 + len;
+            k += 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_endian_vote() {
+        // Payloads holding small big-endian values, which are tested as candidate fields.
+        let mut seed = vec![];
+        for k in 0..6u8 {
+            seed.extend([4, 0, 0, 3 + k, 0, 9]);
+        }
+        let seed = Structured::raw(seed);
+        let mut oracle = snapshots(chunks_target);
+
+        // The vote is disabled by default.
+        let options = SearchOptions { max_iters: 1, ..Default::default() };
+        let (all, full) = search(&seed, &mut oracle, options);
+        let options = SearchOptions { endian_vote: 3, max_iters: 1, ..Default::default() };
+        let (res, voted) = search(&seed, &mut oracle, options);
+
+        // The big-endian candidates after the first few relations are skipped, without losing any relation.
+        assert!(voted.test_count < full.test_count);
+        assert_eq!(res.relations, all.relations);
+        assert!(res.relations.iter().all(|rel| rel.le));
+    }
+
+    /// A format whose 32-bit little-endian header holds the size of the whole input (like a PE `SizeOfImage`).
+    fn image_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 4 || u32::from_le_bytes(data[..4].try_into().unwrap()) as usize != data.len() {
+            return cov;
+        }
+        cov[1] = 1;
+        for b in data[4..].iter() {
+            cov[2 + (*b as usize % 62)] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_whole_file() {
+        let seed = Structured::raw(vec![12, 0, 0, 0, 10, 20, 30, 40, 50, 60, 70, 80]);
+        let mut oracle = snapshots(image_target);
+        let (mut res, _) = search(&seed, &mut oracle, SearchOptions::default());
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert, rel.whole_file), (0, 4, 0, 12, true));
+        assert!(rel.heuristics.contains(&Heuristic::WholeFile));
+
+        // Bytes appended to the input are counted too.
+        res.raw.extend([90, 100]);
+        res.sanitize();
+        assert_eq!(res.raw[..4], [14, 0, 0, 0]);
+    }
+
+    /// A 16-bit length of the payload which follows a 12-byte header of `H`s (the header itself is not counted), followed
+    /// by a trailer starting with a tag byte. The coverage of the payload depends on the position of its bytes.
+    fn header_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 14 || data[2..14] != [0x48; 12] || 14 + u16::from_le_bytes([data[0], data[1]]) as usize > data.len() {
+            return cov;
+        }
+        cov[1] = 1;
+        let (payload, trailer) = data[14..].split_at(u16::from_le_bytes([data[0], data[1]]) as usize);
+        for (i, b) in payload.iter().enumerate() {
+            cov[2 + (i * 5 + *b as usize) % 31] = 1;
+        }
+        if trailer.first() == Some(&10) {
+            for b in trailer.iter() {
+                cov[33 + (*b as usize % 31)] = 1;
+            }
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_header_bias() {
+        let mut raw = vec![3, 0];
+        raw.extend([0x48; 12]);
+        raw.extend([0x41; 3]);
+        raw.extend(10..30);
+        let seed = Structured::raw(raw);
+        let mut oracle = snapshots(header_target);
+
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.iter().all(|rel| rel.pos != 0));
+
+        let options = SearchOptions { header_bias: true, ..Default::default() };
+        let (mut res, report) = search(&seed, &mut oracle, options);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.anchor, rel.insert, rel.bias), (0, 2, 17, 12));
+        assert!(rel.heuristics.contains(&Heuristic::HeaderBias));
+        assert_eq!(report.heuristics[&Heuristic::HeaderBias].found, 1);
+
+        // Only the payload is counted.
+        res.insert(17, &[0x41, 0x41]).unwrap();
+        assert_eq!(res.raw[..2], [5, 0]);
+        assert!(oracle(&res.raw).contains(1));
+    }
+
+    /// A message whose body length is written in decimal on its first line, followed by a trailer starting with a tag
+    /// byte.
+    fn ascii_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        let Some(line) = data.iter().position(|b| *b == b'\n') else {
+            return cov;
+        };
+        let Some(len) = std::str::from_utf8(&data[..line]).ok().and_then(|s| s.strip_prefix("L:")).and_then(|s| s.parse::<usize>().ok()) else {
+            return cov;
+        };
+        if line + 1 + len > data.len() {
+            return cov;
+        }
+        cov[1] = 1;
+        let trailer = &data[line + 1 + len..];
+        if trailer.first() == Some(&10) {
+            for b in trailer.iter() {
+                cov[2 + (*b as usize % 62)] = 1;
+            }
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_ascii() {
+        let mut raw = b"L:12\n".to_vec();
+        raw.extend([0x41; 12]);
+        raw.extend(10..30);
+        let seed = Structured::raw(raw);
+        let mut oracle = snapshots(ascii_target);
+
+        let options = SearchOptions { enable_ascii: true, ..Default::default() };
+        let (mut res, report) = search(&seed, &mut oracle, options);
+        let rel = res.relations.iter().find(|rel| rel.ascii).unwrap();
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert, rel.value), (2, 2, 5, 17, 12));
+        assert_eq!(rel.heuristics, vec![Heuristic::LocalAnchor, Heuristic::AsciiLength]);
+        assert_eq!(report.heuristics[&Heuristic::AsciiLength].found, 1);
+
+        // The value grows past 2 digits.
+        res.insert(17, &[0x41; 88]).unwrap();
+        assert!(res.get_raw().starts_with(b"L:100\n"));
+        assert_eq!(oracle(res.get_raw()), oracle(seed.get_raw()));
+    }
+
+    /// A length byte at the start of the input, counting a payload which starts after a 19-byte header of spaces (far
+    /// from every anchor candidate), followed by a trailer starting with a tag byte.
+    fn far_anchor_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 20 || data[1..20] != [0x20; 19] || 20 + data[0] as usize > data.len() {
+            return cov;
+        }
+        cov[1] = 1;
+        let (payload, trailer) = data[20..].split_at(data[0] as usize);
+        for (i, b) in payload.iter().enumerate() {
+            cov[2 + (i * 5 + *b as usize) % 31] = 1;
+        }
+        if trailer.first() == Some(&10) {
+            for b in trailer.iter() {
+                cov[33 + (*b as usize % 31)] = 1;
+            }
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_bisect_anchors() {
+        let mut raw = vec![8];
+        raw.extend([0x20; 19]);
+        raw.extend([0x41; 8]);
+        raw.extend(10..50);
+        let seed = Structured::raw(raw);
+        let mut oracle = snapshots(far_anchor_target);
+
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.iter().all(|rel| rel.pos != 0));
+
+        let options = SearchOptions { bisect_anchors: true, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        let rel = res.relations.iter().find(|rel| rel.pos == 0).unwrap();
+        assert_eq!((rel.anchor, rel.insert), (20, 28));
+        assert_eq!(rel.heuristics, vec![Heuristic::Bisection]);
+        assert_eq!(report.heuristics[&Heuristic::Bisection].found, 1);
+    }
+
+    /// A 4-byte offset at the start of the input pointing to a `DIR!` directory, whose entries follow it. Offsets past
+    /// the end of the input are not trusted: the directory is then looked for instead (like the end of a ZIP archive).
+    fn directory_target(data: &[u8]) -> Vec<u8> {
+        let mut cov = vec![0; 64];
+        cov[0] = 1;
+
+        if data.len() < 8 {
+            return cov;
+        }
+        let offset = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let dir = match data.get(offset..offset.saturating_add(4)) {
+            Some(magic) => (magic == b"DIR!").then_some(offset),
+            None => data.windows(4).position(|w| w == b"DIR!"),
+        };
+        let Some(dir) = dir else {
+            return cov;
+        };
+
+        cov[1] = 1;
+        for (i, b) in data[dir + 4..].iter().enumerate() {
+            cov[2 + (i * 5 + *b as usize) % 62] = 1;
+        }
+        cov
+    }
+
+    #[test]
+    fn test_search_offsets() {
+        let mut raw = vec![24, 0, 0, 0];
+        raw.extend([0x20; 20]);
+        raw.extend(b"DIR!");
+        raw.extend(0x60..0x68);
+        let seed = Structured::raw(raw);
+        let mut oracle = snapshots(directory_target);
+
+        // Corrupting the offset loses nothing.
+        let (res, _) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(res.relations.iter().all(|rel| rel.pos != 0));
+        assert!(res.offsets.is_empty());
+
+        let options = SearchOptions { enable_offsets: true, ..Default::default() };
+        let (mut res, report) = search(&seed, &mut oracle, options);
+        assert_eq!(res.offsets.len(), 1);
+        let offset = &res.offsets[0];
+        assert_eq!((offset.pos, offset.size, offset.le, offset.anchor, offset.insert), (0, 4, true, 0, 24));
+        assert_eq!(offset.heuristics, vec![Heuristic::Offset]);
+        assert_eq!(report.heuristics[&Heuristic::Offset].found, 1);
+
+        // Inserting before the directory moves it.
+        res.insert(24, &[0x20; 8]).unwrap();
+        assert_eq!(&res.get_raw()[..4], &[32, 0, 0, 0]);
+        assert_eq!(oracle(res.get_raw()), oracle(seed.get_raw()));
+    }
+
+    #[test]
+    fn test_search_status() {
+        let seed = Structured::raw(vec![3, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+        let mut statuses = vec![];
+        let mut on_status = |status: &SearchStatus| statuses.push(status.clone());
+
+        let options = SearchOptions { status_interval: std::time::Duration::ZERO, ..Default::default() };
+        let (res, report) = search_reporting(&seed, &mut oracle, options, &SearchGuidance::default(), &mut on_status);
+        assert_eq!(res.relations.len(), 1);
+
+        // Every probe is reported, and the pass moves through its candidates.
+        assert_eq!(statuses.len(), report.test_count);
+        assert!(statuses.windows(2).all(|w| w[0].probes < w[1].probes && (w[0].iteration, w[0].candidate) <= (w[1].iteration, w[1].candidate)));
+        assert!(statuses.iter().all(|status| status.scanned() < 1.0));
+    }
+
+    #[test]
+    fn test_search_probe_cache() {
+        let seed = Structured::raw(vec![1, b'A', b'B', 0x41, 10, 20, 30]);
+        let mut executed = HashSet::new();
+        let mut repeated = 0;
+        let mut oracle = |data: &[u8]| {
+            if !executed.insert(data.to_vec()) {
+                repeated += 1;
+            }
+            CoverageSnapshot::from(signed_target(data))
+        };
+
+        let options = SearchOptions { exhaustive_below: 256, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert_eq!(res.relations.len(), 1);
+
+        // The exhaustive anchors generate some probes again, which do not run the target.
+        assert!(report.cache_hits > 0);
+        assert_eq!(repeated, 0);
+    }
+
+    #[test]
+    fn test_search_hints() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+        let (_, report) = search(&seed, &mut oracle, SearchOptions::default());
+
+        let options = SearchOptions { hints: parse_hints("offset=0 size=1").unwrap(), ..Default::default() };
+        let (res, hinted) = search(&seed, &mut oracle, options);
+
+        // The hinted field is not searched again.
+        assert_eq!(res.relations.len(), 1);
+        assert_eq!(res.relations[0].heuristics, vec![Heuristic::Hint]);
+        assert_eq!((res.relations[0].anchor, res.relations[0].insert), (1, 5));
+        assert!(hinted.test_count < report.test_count);
+    }
+
+    /// A 1-byte length prefixed payload whose trailer is only compared against a few tags (one comparison site per
+    /// tag): it reaches no new edges, and the sites run whatever the length, but the matching bits of their operands
+    /// show in the value profile.
+    fn compared_target(data: &[u8]) -> CoverageSnapshot {
+        let mut profile = vec![0; 64];
+        let trailer = match !data.is_empty() && (data[0] as usize) < data.len() {
+            true => &data[1 + data[0] as usize..],
+            false => &data[data.len().min(1)..],
+        };
+        if !data.is_empty() {
+            for (site, tag) in [10u8, 20, 30].iter().enumerate() {
+                let operand = trailer.get(site).copied().unwrap_or_default();
+                profile[site] = 1 + (!(operand ^ tag)).count_ones() as u8;
+            }
+        }
+        CoverageSnapshot::from_map(&[1]).with_value_profile(&profile)
+    }
+
+    #[test]
+    fn test_search_value_profile() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = compared_target;
+        let (_, report) = search(&seed, &mut oracle, SearchOptions::default());
+        assert!(report.focus_indices.is_empty() && report.focus_features.is_empty());
+        assert!(!report.found_any);
+
+        // The corrupted field reaches the same sites with other operands.
+        let options = SearchOptions { use_value_profile: true, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert_eq!(report.focus_features, vec![9, 1 << 8 | 9, 2 << 8 | 9]);
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+    }
+
+    #[test]
+    fn test_search_stability_runs() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+
+        // Every other execution misses an edge of the trailer (e.g. a cache hit).
+        let mut runs = 0;
+        let mut oracle = |data: &[u8]| {
+            runs += 1;
+            let mut cov = target(data);
+            if runs % 2 == 0 {
+                cov[32] = 0;
+            }
+            CoverageSnapshot::from(cov)
+        };
+
+        let options = SearchOptions { stability_runs: 3, ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert_eq!(report.unstable_indices, vec![32]);
+        assert_eq!(report.focus_indices, vec![1, 12, 22]);
+
+        let rel = &res.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+    }
+
+    #[test]
+    fn test_search_max_tests() {
+        let seed = Structured::raw(vec![3, 0, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(word_target);
+
+        let (_, full) = search(&seed, &mut oracle, SearchOptions { enable_strides: true, ..Default::default() });
+        assert!(!full.truncated);
+
+        // The budget is checked between candidate fields, so the first one is still completed.
+        let options = SearchOptions { enable_strides: true, max_tests: Some(1), ..Default::default() };
+        let (res, report) = search(&seed, &mut oracle, options);
+        assert!(report.truncated);
+        assert!(report.test_count < full.test_count);
+        assert_eq!(res.relations.len(), 1);
+    }
+
+    #[test]
+    fn test_search_resume() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let mut oracle = snapshots(target);
+        let (full, full_report) = search(&seed, &mut oracle, SearchOptions::default());
+
+        let checkpoint = SearchCheckpoint::default();
+        let guidance = SearchGuidance { checkpoint: Some(checkpoint.clone()), ..Default::default() };
+        let options = SearchOptions { max_tests: Some(1), ..Default::default() };
+        let (partial, report) = search_guided(&seed, &mut oracle, options, &guidance);
+        let progress = report.progress.expect("truncated search has a progress");
+        // The checkpoint of the last started candidate already points past it.
+        assert_eq!(checkpoint.borrow().as_ref().unwrap().0, progress);
+
+        let guidance = SearchGuidance { resume: Some(progress), ..Default::default() };
+        let (resumed, resumed_report) = search_guided(&partial, &mut oracle, SearchOptions::default(), &guidance);
+        assert_eq!(resumed.relations, full.relations);
+        assert!(resumed_report.test_count < full_report.test_count);
+    }
+
+    #[test]
+    fn test_search_stop() {
+        let seed = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        let stop = SearchStop::default();
+        let guidance = SearchGuidance { stop: Some(stop.clone()), ..Default::default() };
+
+        // The oracle stops the search at its 3rd probe.
+        let mut probes = 0;
+        let mut oracle = |data: &[u8]| {
+            probes += 1;
+            stop.set(stop.get() || probes == 3);
+            CoverageSnapshot::from(target(data))
+        };
+        let (_, report) = search_guided(&seed, &mut oracle, SearchOptions::default(), &guidance);
+
+        // No probe runs afterwards, and the search is finished, not truncated.
+        assert_eq!(probes, 3);
+        assert!(!report.truncated);
+        assert!(report.progress.is_none());
+    }
+
+    /// `target` behind a 2-byte signature.
+    fn magic_target(data: &[u8]) -> Vec<u8> {
+        if data.len() < 2 || &data[..2] != b"FS" {
+            return vec![0; 64];
+        }
+        let mut cov = target(&data[2..]);
+        cov[63] = 1;
+        cov
+    }
+
+    #[test]
+    fn test_search_constants() {
+        let seed = Structured::raw(b"FS\x04AAAA\x0a\x14\x1e".to_vec());
+        let mut oracle = snapshots(magic_target);
+
+        let options = SearchOptions { detect_constants: true, ..Default::default() };
+        let (res, _) = search(&seed, &mut oracle, options);
+
+        assert_eq!(res.relations[0].pos, 2);
+        assert_eq!(res.constants, vec![Constant { pos: 0, bytes: b"FS".to_vec() }]);
+    }
+}
@@ -0,0 +1,299 @@
+//! Coverage of probes collected outside of the process, for targets which can not be linked with FrameShift: a
+//! command run once per probe (`ExternalCoverage`) or a persistent worker (`CoverageWorker`).
+
+use std::{
+    env, fs::{self, File, OpenOptions}, io::{ErrorKind, Read, Write}, os::{fd::AsRawFd, unix::fs::FileExt}, path::{Path, PathBuf}, process::{self, Child, ChildStdin, ChildStdout, Command, Stdio}, thread, time::{Duration, Instant}
+};
+
+use nix::{fcntl::{fcntl, FcntlArg, OFlag}, libc, sys::signal::{SigSet, SigmaskHow, Signal}};
+
+use super::{coverage::{coverage_map, CoverageFormat}, parts::{substitute_parts, write_part}};
+
+/// Size of the coverage map built from the collector output.
+pub const EXTERNAL_MAP_SIZE: usize = 1 << 16;
+
+/// Characters for which a coverage command is run by the shell (pipes, redirections, lists, quoting, expansions).
+const SHELL_SYNTAX: &[char] = &['|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', '*', '?', '[', '#', '~', '\n'];
+
+/// Collects the coverage of a probe outside of the process.
+pub trait CoverageBackend {
+    fn run(&mut self, input: &[u8]) -> Vec<u8>;
+
+    /// Sets the content of part `name` of the input, read by the command through its `@@<name>` file.
+    fn set_part(&mut self, name: &str, data: &[u8]) {
+        write_part(name, data);
+    }
+}
+
+/// Runs an external coverage collector once per probe.
+///
+/// `@@` in the command is replaced by the path of a file holding the input (without `@@`, the input is written to
+/// stdin) and `@@<name>` by the file of part `name` of a multi-file input. Its stdout is a coverage report in `format`
+/// (see `coverage`). Plain commands are run directly, the shell only runs those using its syntax (e.g. a pipe).
+pub struct ExternalCoverage {
+    /// The program and its arguments (`sh -c <cmd>` for shell commands).
+    argv: Vec<String>,
+    uses_file: bool,
+    format: CoverageFormat,
+    input_path: PathBuf,
+}
+
+impl ExternalCoverage {
+    pub fn new(cmd: &str, format: CoverageFormat) -> Self {
+        let cmd = substitute_parts(cmd);
+        // A leading `VAR=value` assignment needs the shell too.
+        let assigns = cmd.split_whitespace().next().is_some_and(|word| word.contains('='));
+        let argv = match cmd.contains(SHELL_SYNTAX) || assigns {
+            true => vec!["sh".to_string(), "-c".to_string(), cmd.clone()],
+            false => cmd.split_whitespace().map(str::to_string).collect(),
+        };
+
+        Self {
+            argv,
+            uses_file: cmd.contains("@@"),
+            format,
+            input_path: env::temp_dir().join(format!("frameshift-cov-{}", process::id())),
+        }
+    }
+}
+
+impl CoverageBackend for ExternalCoverage {
+    fn run(&mut self, input: &[u8]) -> Vec<u8> {
+        if self.uses_file {
+            fs::write(&self.input_path, input).expect("Could not write probe input");
+        }
+        let input_path = self.input_path.to_string_lossy();
+        let argv = self.argv.iter().map(|arg| arg.replace("@@", &input_path)).collect::<Vec<_>>();
+
+        let mut child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(if self.uses_file { Stdio::null() } else { Stdio::piped() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Could not run coverage command");
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The target may exit without reading all of its input.
+            let _ = without_sigpipe(|| stdin.write_all(input));
+        }
+
+        // The exit status is ignored: crashing inputs still produce coverage.
+        let output = child.wait_with_output().expect("Could not run coverage command");
+        coverage_map(self.format, &output.stdout, EXTERNAL_MAP_SIZE)
+    }
+}
+
+impl Drop for ExternalCoverage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.input_path);
+    }
+}
+
+/// Runs probes on a persistent external worker, so they don't pay the process startup cost.
+///
+/// Protocol: the worker is started once with `sh -c`, with `FRAMESHIFT_MAP_PATH` naming a shared memory file of
+/// `FRAMESHIFT_MAP_SIZE` bytes (in `/dev/shm` when available) which it maps as its coverage map. For each probe, the
+/// input is written to the worker's stdin as a frame (u32 little-endian length, then the bytes). The worker runs it,
+/// records its coverage in the map (any non-zero entry is covered) and answers with a u32 little-endian status on
+/// stdout (ignored). The map is cleared before every probe.
+///
+/// If the worker dies during a probe (e.g. the input crashed it) or does not answer within the timeout, the coverage
+/// recorded so far is used for the probe and the worker is restarted before the next one. `examples/coverage_worker.sh`
+/// is a reference worker.
+pub struct CoverageWorker {
+    cmd: String,
+    timeout: Duration,
+    map_path: PathBuf,
+    map: File,
+    worker: Option<(Child, ChildStdin, ChildStdout)>,
+    buf: Vec<u8>,
+    /// Number of times the worker was restarted.
+    pub restarts: usize,
+}
+
+impl CoverageWorker {
+    pub fn new(cmd: &str, timeout: Duration) -> Self {
+        let shm = Path::new("/dev/shm");
+        let dir = if shm.is_dir() { shm.to_path_buf() } else { std::env::temp_dir() };
+        let map_path = dir.join(format!("frameshift-map-{}", process::id()));
+
+        let map = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&map_path)
+            .expect("Could not create the coverage map");
+        map.set_len(EXTERNAL_MAP_SIZE as u64).expect("Could not create the coverage map");
+
+        Self {
+            cmd: substitute_parts(cmd),
+            timeout,
+            map_path,
+            map,
+            worker: None,
+            buf: vec![0; EXTERNAL_MAP_SIZE],
+            restarts: 0,
+        }
+    }
+
+    fn spawn(&mut self) -> &mut (Child, ChildStdin, ChildStdout) {
+        if self.worker.is_none() {
+            let mut child = Command::new("sh")
+                .arg("-c")
+                .arg(&self.cmd)
+                .env("FRAMESHIFT_MAP_PATH", &self.map_path)
+                .env("FRAMESHIFT_MAP_SIZE", EXTERNAL_MAP_SIZE.to_string())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Could not start coverage worker");
+
+            let stdin = child.stdin.take().unwrap();
+            let stdout = child.stdout.take().unwrap();
+
+            // The status is polled, so that a hanging worker times out.
+            let flags = fcntl(stdout.as_raw_fd(), FcntlArg::F_GETFL).expect("Could not get the worker's stdout flags");
+            fcntl(stdout.as_raw_fd(), FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK))
+                .expect("Could not set the worker's stdout non-blocking");
+            self.worker = Some((child, stdin, stdout));
+        }
+        self.worker.as_mut().unwrap()
+    }
+
+    /// Sends a frame and waits for the status, returns false if the worker died or timed out.
+    fn exchange(&mut self, input: &[u8]) -> bool {
+        let timeout = self.timeout;
+        let (_, stdin, stdout) = self.spawn();
+
+        let sent = without_sigpipe(|| {
+            stdin.write_all(&(input.len() as u32).to_le_bytes())
+                .and_then(|_| stdin.write_all(input))
+                .and_then(|_| stdin.flush())
+        });
+        if sent.is_err() {
+            return false;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut status = [0; 4];
+        let mut read = 0;
+        while read < status.len() {
+            match stdout.read(&mut status[read..]) {
+                Ok(0) => return false,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => thread::sleep(Duration::from_micros(100)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn restart(&mut self) {
+        if let Some((mut child, _, _)) = self.worker.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.restarts += 1;
+    }
+}
+
+/// Runs `write` with SIGPIPE blocked in the current thread, so that writing to a worker which died fails (with EPIPE)
+/// instead of killing the fuzzer. A SIGPIPE raised meanwhile is consumed before the signal mask is restored.
+fn without_sigpipe<T>(write: impl FnOnce() -> T) -> T {
+    let mut pipe = SigSet::empty();
+    pipe.add(Signal::SIGPIPE);
+    let old = pipe.thread_swap_mask(SigmaskHow::SIG_BLOCK).expect("Could not block SIGPIPE");
+
+    let res = write();
+
+    if !old.contains(Signal::SIGPIPE) && sigpipe_pending() {
+        let _ = pipe.wait();
+    }
+    old.thread_set_mask().expect("Could not restore the signal mask");
+    res
+}
+
+fn sigpipe_pending() -> bool {
+    let mut set = std::mem::MaybeUninit::uninit();
+    unsafe { libc::sigpending(set.as_mut_ptr()) == 0 && libc::sigismember(set.as_ptr(), libc::SIGPIPE) == 1 }
+}
+
+impl CoverageBackend for CoverageWorker {
+    fn run(&mut self, input: &[u8]) -> Vec<u8> {
+        self.buf.fill(0);
+        self.map.write_all_at(&self.buf, 0).expect("Could not clear the coverage map");
+
+        if !self.exchange(input) {
+            self.restart();
+        }
+
+        self.map.read_exact_at(&mut self.buf, 0).expect("Could not read the coverage map");
+        self.buf.clone()
+    }
+}
+
+impl Drop for CoverageWorker {
+    fn drop(&mut self) {
+        if let Some((mut child, stdin, _)) = self.worker.take() {
+            // Closing stdin lets the worker exit on its own.
+            drop(stdin);
+            let _ = child.wait();
+        }
+        let _ = fs::remove_file(&self.map_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::CoverageFormat;
+
+    fn covered(map: &[u8]) -> Vec<usize> {
+        map.iter().enumerate().filter(|(_, v)| **v != 0).map(|(idx, _)| idx).collect()
+    }
+
+    #[test]
+    fn test_external_coverage() {
+        // Run directly, with the input on stdin, then in a file.
+        let mut cat = ExternalCoverage::new("cat", CoverageFormat::Showmap);
+        assert_eq!(cat.argv, ["cat"]);
+        assert_eq!(covered(&cat.run(b"000003:1\n000010:2\n")), vec![3, 10]);
+
+        let mut cat = ExternalCoverage::new("cat @@", CoverageFormat::Showmap);
+        assert_eq!(covered(&cat.run(b"000042:1\n")), vec![42]);
+
+        // Shell syntax goes through the shell.
+        let mut piped = ExternalCoverage::new("cat @@ | grep -v 000005", CoverageFormat::Showmap);
+        assert_eq!(piped.argv[..2], ["sh", "-c"]);
+        assert_eq!(covered(&piped.run(b"000005:1\n000006:1\n")), vec![6]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cmd = format!("sh {}/../frameshift_afl/examples/coverage_worker.sh", env!("CARGO_MANIFEST_DIR"));
+        let mut worker = CoverageWorker::new(&cmd, Duration::from_secs(10));
+
+        assert_eq!(covered(&worker.run(&[7, 1, 2])), vec![7]);
+        assert_eq!(covered(&worker.run(&[200])), vec![200]);
+        assert_eq!(covered(&worker.run(&[])), Vec::<usize>::new());
+        assert_eq!(worker.restarts, 0);
+    }
+
+    #[test]
+    fn test_dead_worker() {
+        // The worker exits right away: the writes fail (without SIGPIPE killing the test) or the status never comes.
+        let mut worker = CoverageWorker::new("exit 0", Duration::from_secs(10));
+        let input = vec![0x41; 1 << 20];
+        assert!(covered(&worker.run(&input)).is_empty());
+        assert!(covered(&worker.run(&input)).is_empty());
+        assert_eq!(worker.restarts, 2);
+    }
+
+    #[test]
+    fn test_hanging_worker() {
+        let mut worker = CoverageWorker::new("sleep 60", Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(covered(&worker.run(&[1])).is_empty());
+        assert_eq!(worker.restarts, 1);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
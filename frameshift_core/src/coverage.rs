@@ -0,0 +1,274 @@
+//! Coverage reports of external collectors (`--coverage-cmd`), read into a coverage map.
+//!
+//! Each covered element of a report (an edge of `afl-showmap`, a line of kcov, a basic block of drcov) is identified
+//! by its id, or by its file and line, or by its module and offset, and the identity is hashed into the map. Elements
+//! are only compared between runs of the same collector, so the hashing just has to be stable within the process.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap}, fmt, hash::{Hash, Hasher}, str::FromStr
+};
+
+/// Format of the report written by the coverage command to its stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoverageFormat {
+    /// `id:count` lines, as written by `afl-showmap -o /dev/stdout`.
+    #[default]
+    Showmap,
+    /// The Cobertura XML report of kcov (`cobertura.xml`): each line with hits is covered.
+    Kcov,
+    /// A drcov log (`drrun -t drcov`), in the binary or text (`-dump_text`) layout: each basic block is covered.
+    Drcov,
+}
+
+impl FromStr for CoverageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "showmap" => Ok(CoverageFormat::Showmap),
+            "kcov" => Ok(CoverageFormat::Kcov),
+            "drcov" => Ok(CoverageFormat::Drcov),
+            _ => Err(format!("invalid coverage format: {} (expected showmap, kcov or drcov)", s)),
+        }
+    }
+}
+
+impl fmt::Display for CoverageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoverageFormat::Showmap => f.write_str("showmap"),
+            CoverageFormat::Kcov => f.write_str("kcov"),
+            CoverageFormat::Drcov => f.write_str("drcov"),
+        }
+    }
+}
+
+/// A covered element of a report.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CoverageElement {
+    /// An entry of the AFL++ map.
+    Edge(usize),
+    /// A source line, by file.
+    Line(String, usize),
+    /// A basic block, by module path and offset from the module base.
+    Block(String, u32),
+}
+
+/// Parses the covered elements of a report. Parts of the report which do not match the format are skipped.
+pub fn parse_coverage(format: CoverageFormat, report: &[u8]) -> Vec<CoverageElement> {
+    match format {
+        CoverageFormat::Showmap => parse_showmap(&String::from_utf8_lossy(report)),
+        CoverageFormat::Kcov => parse_kcov(&String::from_utf8_lossy(report)),
+        CoverageFormat::Drcov => parse_drcov(report),
+    }
+}
+
+/// Builds a coverage map of `size` entries from a report.
+pub fn coverage_map(format: CoverageFormat, report: &[u8], size: usize) -> Vec<u8> {
+    let mut map = vec![0; size];
+    for element in parse_coverage(format, report) {
+        let idx = match element {
+            CoverageElement::Edge(id) => id,
+            element => {
+                let mut hasher = DefaultHasher::new();
+                element.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+        };
+        map[idx % size] = 1;
+    }
+    map
+}
+
+fn parse_showmap(report: &str) -> Vec<CoverageElement> {
+    report.lines()
+        .filter_map(|line| {
+            let (id, count) = line.trim().split_once(':')?;
+            count.parse::<u64>().ok().filter(|count| *count > 0)?;
+            Some(CoverageElement::Edge(id.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Value of attribute `name` in the XML tag `tag`.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn parse_kcov(report: &str) -> Vec<CoverageElement> {
+    let mut file = String::new();
+    let mut covered = vec![];
+    for tag in report.split('<').skip(1) {
+        if tag.starts_with("class ") {
+            file = attribute(tag, "filename").unwrap_or_default().to_string();
+        } else if tag.starts_with("line ") {
+            let line = attribute(tag, "number").and_then(|n| n.parse().ok());
+            let hits = attribute(tag, "hits").and_then(|n| n.parse::<u64>().ok());
+            if let (Some(line), Some(hits)) = (line, hits) {
+                if hits > 0 {
+                    covered.push(CoverageElement::Line(file.clone(), line));
+                }
+            }
+        }
+    }
+    covered
+}
+
+/// Size of an entry of the binary BB table: the offset (u32), the size (u16) and the module id (u16).
+const DRCOV_BB_SIZE: usize = 8;
+
+fn parse_drcov(report: &[u8]) -> Vec<CoverageElement> {
+    let mut modules = HashMap::new();
+    let mut covered = vec![];
+
+    let mut rest = report;
+    let mut in_modules = false;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|b| *b == b'\n').map_or(rest.len(), |i| i + 1);
+        let line = String::from_utf8_lossy(&rest[..end]);
+        let line = line.trim();
+        rest = &rest[end..];
+
+        if line.starts_with("Module Table:") {
+            in_modules = true;
+        } else if let Some(header) = line.strip_prefix("BB Table:") {
+            let count = header.split_whitespace().next().and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+            let module = |id: u16| modules.get(&id).cloned().unwrap_or_else(|| id.to_string());
+
+            // The text layout has a column header, then `module[  0]: 0x00001234,   5` lines.
+            if rest.starts_with(b"module id") {
+                let text = String::from_utf8_lossy(rest);
+                for line in text.lines().skip(1) {
+                    let Some((id, bb)) = line.trim().strip_prefix("module[").and_then(|l| l.split_once("]:")) else {
+                        continue;
+                    };
+                    let start = bb.split(',').next().map(str::trim).and_then(|s| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok());
+                    if let (Ok(id), Some(start)) = (id.trim().parse(), start) {
+                        covered.push(CoverageElement::Block(module(id), start));
+                    }
+                }
+            } else {
+                for bb in rest.chunks_exact(DRCOV_BB_SIZE).take(count) {
+                    let start = u32::from_le_bytes(bb[0..4].try_into().unwrap());
+                    let id = u16::from_le_bytes(bb[6..8].try_into().unwrap());
+                    covered.push(CoverageElement::Block(module(id), start));
+                }
+            }
+            break;
+        } else if in_modules {
+            // Module lines are `id, base, end, ..., path` (the columns depend on the version), other lines are
+            // headers.
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            if let (Some(Ok(id)), Some(path)) = (fields.first().map(|id| id.parse::<u16>()), fields.last()) {
+                if fields.len() > 2 {
+                    modules.insert(id, path.to_string());
+                }
+            }
+        }
+    }
+    covered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A kcov report of two files, with one uncovered line.
+    const KCOV_REPORT: &str = r#"<?xml version="1.0" ?>
+<!DOCTYPE coverage SYSTEM 'http://cobertura.sourceforge.net/xml/coverage-03.dtd'>
+<coverage line-rate="0.750" version="1.9" timestamp="1700000000">
+	<sources>
+		<source>/src/</source>
+	</sources>
+	<packages>
+		<package name="target" line-rate="0.750" branch-rate="1.0" complexity="1.0">
+			<classes>
+				<class name="parse_c" filename="parse.c" line-rate="0.667" branch-rate="1.0" complexity="1.0">
+					<lines>
+						<line number="10" hits="1"/>
+						<line number="11" hits="3"/>
+						<line number="14" hits="0"/>
+					</lines>
+				</class>
+				<class name="main_c" filename="main.c" line-rate="1.000" branch-rate="1.0" complexity="1.0">
+					<lines>
+						<line number="10" hits="1"/>
+					</lines>
+				</class>
+			</classes>
+		</package>
+	</packages>
+</coverage>
+"#;
+
+    /// The header and module table of a drcov log of two modules.
+    const DRCOV_HEADER: &str = "DRCOV VERSION: 2
+DRCOV FLAVOR: drcov
+Module Table: version 2, count 2
+Columns: id, base, end, entry, checksum, timestamp, path
+  0, 0x0000555555554000, 0x0000555555558000, 0x0000000000000000, 0x00000000, 0x00000000, /work/target
+  1, 0x00007ffff7dd5000, 0x00007ffff7dfc000, 0x0000000000000000, 0x00000000, 0x00000000, /lib/ld-linux.so
+";
+
+    fn expected_blocks() -> Vec<CoverageElement> {
+        vec![
+            CoverageElement::Block("/work/target".to_string(), 0x1234),
+            CoverageElement::Block("/lib/ld-linux.so".to_string(), 0x40),
+            CoverageElement::Block("/work/target".to_string(), 0x1250),
+        ]
+    }
+
+    #[test]
+    fn test_coverage_format_from_str() {
+        for format in [CoverageFormat::Showmap, CoverageFormat::Kcov, CoverageFormat::Drcov] {
+            assert_eq!(format.to_string().parse::<CoverageFormat>(), Ok(format));
+        }
+        assert!("lcov".parse::<CoverageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_parse_showmap() {
+        let covered = parse_coverage(CoverageFormat::Showmap, b"000012:1\n000345:4\nnot an edge\n000007:0\n");
+        assert_eq!(covered, vec![CoverageElement::Edge(12), CoverageElement::Edge(345)]);
+    }
+
+    #[test]
+    fn test_parse_kcov() {
+        let covered = parse_coverage(CoverageFormat::Kcov, KCOV_REPORT.as_bytes());
+        assert_eq!(covered, vec![
+            CoverageElement::Line("parse.c".to_string(), 10),
+            CoverageElement::Line("parse.c".to_string(), 11),
+            CoverageElement::Line("main.c".to_string(), 10),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_drcov_text() {
+        let report = format!("{}BB Table: 3 bbs\nmodule id, start, size:\nmodule[  0]: 0x00001234,   5\nmodule[  1]: 0x00000040,  12\nmodule[  0]: 0x00001250,   3\n", DRCOV_HEADER);
+        assert_eq!(parse_coverage(CoverageFormat::Drcov, report.as_bytes()), expected_blocks());
+    }
+
+    #[test]
+    fn test_parse_drcov_binary() {
+        let mut report = format!("{}BB Table: 3 bbs\n", DRCOV_HEADER).into_bytes();
+        for (start, size, id) in [(0x1234u32, 5u16, 0u16), (0x40, 12, 1), (0x1250, 3, 0)] {
+            report.extend(start.to_le_bytes());
+            report.extend(size.to_le_bytes());
+            report.extend(id.to_le_bytes());
+        }
+        assert_eq!(parse_coverage(CoverageFormat::Drcov, &report), expected_blocks());
+    }
+
+    #[test]
+    fn test_coverage_map() {
+        let map = coverage_map(CoverageFormat::Showmap, b"000003:1\n000013:2\n", 8);
+        assert_eq!(map, vec![0, 0, 0, 1, 0, 1, 0, 0]);
+
+        // The same lines in another file are other elements.
+        let map = coverage_map(CoverageFormat::Kcov, KCOV_REPORT.as_bytes(), 1 << 16);
+        assert_eq!(map.iter().filter(|e| **e != 0).count(), 3);
+        assert_eq!(map, coverage_map(CoverageFormat::Kcov, KCOV_REPORT.as_bytes(), 1 << 16));
+    }
+}
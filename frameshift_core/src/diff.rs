@@ -0,0 +1,234 @@
+//! Structural diff of two annotated inputs, e.g. a crash and the corpus entry it was mutated from.
+
+use std::ops::Range;
+
+use super::structured::{Relation, Structured};
+
+/// A step of the alignment of the chunks of two inputs (see `Structured::chunks`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkDiff {
+    /// Chunks holding the same bytes, in the old and the new input.
+    Matched(Range<usize>, Range<usize>),
+    /// Chunk of the old input missing from the new one.
+    Removed(Range<usize>),
+    /// Chunk of the new input missing from the old one.
+    Inserted(Range<usize>),
+    /// Chunks at the same place of the alignment whose bytes differ.
+    Changed(Range<usize>, Range<usize>),
+}
+
+/// Field, value and region (`anchor..insert`) of a relation in one of the inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldState {
+    pub pos: usize,
+    pub value: u64,
+    pub region: Range<usize>,
+}
+
+impl From<&Relation> for FieldState {
+    fn from(rel: &Relation) -> Self {
+        Self { pos: rel.pos, value: rel.value, region: rel.anchor..rel.insert }
+    }
+}
+
+/// A relation of both inputs which moved, changed value or was resized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Index of the relation in the old input, and in the new one.
+    pub relation: usize,
+    pub new_relation: usize,
+    pub old: FieldState,
+    pub new: FieldState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StructuralDiff {
+    pub chunks: Vec<ChunkDiff>,
+    pub fields: Vec<FieldChange>,
+    /// Relations of the old input without a counterpart in the new one, and conversely.
+    pub removed: Vec<usize>,
+    pub added: Vec<usize>,
+}
+
+impl StructuralDiff {
+    /// Whether both inputs hold the same bytes and relations.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.removed.is_empty() && self.added.is_empty()
+            && self.chunks.iter().all(|chunk| matches!(chunk, ChunkDiff::Matched(..)))
+    }
+}
+
+/// Whether two relations have fields of the same encoding.
+fn same_field(old: &Relation, new: &Relation) -> bool {
+    old.size == new.size && old.le == new.le && old.bits == new.bits && old.ascii == new.ascii && old.enabled == new.enabled
+}
+
+/// Position in the new input of the byte at `pos` of the old one: bytes of a matched chunk map to their copy, and
+/// bytes between two matched chunks keep their offset from the previous one (the chunks of the two inputs need not
+/// have the same boundaries there, e.g. once a relation is dropped).
+fn map_pos(chunks: &[ChunkDiff], pos: usize) -> Option<usize> {
+    // The matched chunks and the gaps between them, as (old, new) ranges.
+    let mut spans: Vec<(Range<usize>, Range<usize>)> = vec![];
+    let mut in_gap = false;
+    let (mut old_end, mut new_end) = (0, 0);
+    for chunk in chunks.iter() {
+        let (a, b) = match chunk {
+            ChunkDiff::Matched(a, b) => {
+                spans.push((a.clone(), b.clone()));
+                in_gap = false;
+                (old_end, new_end) = (a.end, b.end);
+                continue;
+            }
+            ChunkDiff::Changed(a, b) => (a.clone(), b.clone()),
+            ChunkDiff::Removed(a) => (a.clone(), new_end..new_end),
+            ChunkDiff::Inserted(b) => (old_end..old_end, b.clone()),
+        };
+        if !in_gap {
+            spans.push((old_end..old_end, new_end..new_end));
+            in_gap = true;
+        }
+        let gap = spans.last_mut().unwrap();
+        (gap.0.end, gap.1.end) = (gap.0.end.max(a.end), gap.1.end.max(b.end));
+        (old_end, new_end) = (gap.0.end, gap.1.end);
+    }
+
+    let (a, b) = spans.iter().find(|(a, _)| a.contains(&pos))?;
+    Some(b.start + pos - a.start).filter(|p| b.contains(p))
+}
+
+/// Aligns the chunks of `old` and `new` on their longest common subsequence of identical chunks, and matches their
+/// relations.
+///
+/// A relation of `old` is matched with the relation of `new` whose field has the same encoding and sits where the
+/// alignment moved its field, so relations removed or added anywhere (e.g. by a splice) leave the others paired.
+///
+/// The unmatched chunks between two matched ones are paired up in order as changed chunks, the rest of them were
+/// removed or inserted.
+pub fn diff(old: &Structured, new: &Structured) -> StructuralDiff {
+    let (a, b) = (old.chunks(), new.chunks());
+    let same = |i: usize, j: usize| old.get_raw()[a[i].clone()] == new.get_raw()[b[j].clone()];
+
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match same(i, j) {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut chunks = vec![];
+    let (mut removed, mut inserted) = (vec![], vec![]);
+    let flush = |chunks: &mut Vec<ChunkDiff>, removed: &mut Vec<Range<usize>>, inserted: &mut Vec<Range<usize>>| {
+        let paired = removed.len().min(inserted.len());
+        for (old, new) in removed.drain(..paired).zip(inserted.drain(..paired)) {
+            chunks.push(ChunkDiff::Changed(old, new));
+        }
+        chunks.extend(removed.drain(..).map(ChunkDiff::Removed));
+        chunks.extend(inserted.drain(..).map(ChunkDiff::Inserted));
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && same(i, j) {
+            flush(&mut chunks, &mut removed, &mut inserted);
+            chunks.push(ChunkDiff::Matched(a[i].clone(), b[j].clone()));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(a[i].clone());
+            i += 1;
+        } else {
+            inserted.push(b[j].clone());
+            j += 1;
+        }
+    }
+    flush(&mut chunks, &mut removed, &mut inserted);
+
+    let mut fields = vec![];
+    let mut removed = vec![];
+    let mut paired = vec![false; new.relations.len()];
+    for (idx, a) in old.relations.iter().enumerate() {
+        let pos = map_pos(&chunks, a.pos);
+        let found = (0..new.relations.len())
+            .find(|j| !paired[*j] && Some(new.relations[*j].pos) == pos && same_field(a, &new.relations[*j]));
+        match found {
+            Some(j) => {
+                paired[j] = true;
+                let (old, new) = (FieldState::from(a), FieldState::from(&new.relations[j]));
+                if old != new {
+                    fields.push(FieldChange { relation: idx, new_relation: j, old, new });
+                }
+            }
+            None => removed.push(idx),
+        }
+    }
+    let added = (0..new.relations.len()).filter(|j| !paired[*j]).collect();
+
+    StructuralDiff { chunks, fields, removed, added }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Structured {
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        input
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = sample();
+        assert!(diff(&old, &old).is_empty());
+
+        // Growing the region updates the length: the field and the region changed, the trailer moved.
+        let mut new = old.clone();
+        new.insert(3, &[0x42, 0x42]).unwrap();
+        let res = diff(&old, &new);
+        assert_eq!(res.chunks, vec![
+            ChunkDiff::Changed(0..1, 0..1),
+            ChunkDiff::Changed(1..5, 1..7),
+            ChunkDiff::Matched(5..8, 7..10),
+        ]);
+        assert_eq!(res.fields, vec![FieldChange {
+            relation: 0,
+            new_relation: 0,
+            old: FieldState { pos: 0, value: 4, region: 1..5 },
+            new: FieldState { pos: 0, value: 6, region: 1..7 },
+        }]);
+
+        // A trailer byte removed from a raw input, and a relation only in the old one.
+        let mut new = Structured::raw(old.get_raw().to_vec());
+        new.raw.remove(6);
+        let res = diff(&old, &new);
+        assert_eq!(res.chunks, vec![ChunkDiff::Changed(0..1, 0..7), ChunkDiff::Removed(1..5), ChunkDiff::Removed(5..8)]);
+        assert_eq!((res.removed, res.added), (vec![0], vec![]));
+    }
+
+    #[test]
+    fn test_diff_removed_relation() {
+        // Three chunks, each a length and its payload.
+        let mut old = Structured::raw(vec![2, 10, 11, 2, 20, 21, 2, 30, 31]);
+        for pos in [0, 3, 6] {
+            old.add_relation(Relation::new(pos, 2, 1, true, pos + 1, pos + 3));
+        }
+
+        // The relation of the middle chunk is dropped, and the last payload grows.
+        let mut new = old.clone();
+        new.remove_relation(1);
+        new.insert(8, &[0x41]).unwrap();
+
+        let res = diff(&old, &new);
+        assert_eq!((res.removed, res.added), (vec![1], vec![]));
+        assert_eq!(res.fields, vec![FieldChange {
+            relation: 2,
+            new_relation: 1,
+            old: FieldState { pos: 6, value: 2, region: 7..9 },
+            new: FieldState { pos: 6, value: 3, region: 7..10 },
+        }]);
+    }
+}
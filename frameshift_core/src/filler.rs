@@ -0,0 +1,174 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of leading bytes identifying the format profile of an input.
+const PROFILE_LEN: usize = 4;
+
+/// How the gap opened by an insertion probe is filled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filler {
+    /// A fixed byte (e.g. zero padding).
+    Byte(u8),
+    /// Pseudo-random bytes (deterministic for a given gap, so probes are reproducible).
+    Random,
+    /// Repetition of the content preceding the gap (e.g. a copy of the previous record).
+    Repeat,
+}
+
+impl Default for Filler {
+    fn default() -> Self {
+        Filler::Byte(0x41)
+    }
+}
+
+impl FromStr for Filler {
+    type Err = String;
+
+    /// Parses `zeros`, `random`, `repeat` or a hex byte (e.g. `41`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zeros" => Ok(Filler::Byte(0)),
+            "random" => Ok(Filler::Random),
+            "repeat" => Ok(Filler::Repeat),
+            _ => u8::from_str_radix(s.trim_start_matches("0x"), 16)
+                .map(Filler::Byte)
+                .map_err(|_| format!("invalid filler: {} (expected zeros, random, repeat or a hex byte)", s)),
+        }
+    }
+}
+
+impl Filler {
+    pub const ALL: [Filler; 4] = [Filler::Byte(0x41), Filler::Byte(0), Filler::Random, Filler::Repeat];
+
+    /// Fills `gap`, which is inserted right after `before`.
+    pub fn fill(&self, gap: &mut [u8], before: &[u8]) {
+        match self {
+            Filler::Byte(b) => gap.fill(*b),
+            Filler::Random => {
+                // xorshift64, seeded by the position of the gap.
+                let mut x = 0x9e3779b97f4a7c15u64 ^ (before.len() as u64);
+                for b in gap.iter_mut() {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    *b = x as u8;
+                }
+            }
+            Filler::Repeat => {
+                if before.is_empty() {
+                    gap.fill(0x41);
+                    return;
+                }
+                let src = &before[before.len().saturating_sub(gap.len())..];
+                for (i, b) in gap.iter_mut().enumerate() {
+                    *b = src[i % src.len()];
+                }
+            }
+        }
+    }
+
+    fn index(&self) -> Option<usize> {
+        Self::ALL.iter().position(|f| f == self)
+    }
+}
+
+/// Outcomes of a filler on one format profile.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct FillerArm {
+    /// Candidate fields which lost coverage when corrupted.
+    pub trials: u64,
+    /// Candidate fields for which an insertion with this filler recovered coverage.
+    pub successes: u64,
+}
+
+/// Identifies the format of an input: inputs sharing the same leading bytes (e.g. a file magic) are considered the
+/// same format.
+pub fn format_profile(raw: &[u8]) -> String {
+    hex::encode(&raw[..raw.len().min(PROFILE_LEN)])
+}
+
+/// An online bandit (UCB1) choosing, per format profile, the filler which historically recovered coverage best.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FillerBandit {
+    profiles: HashMap<String, Vec<FillerArm>>,
+}
+
+impl FillerBandit {
+    /// Returns the filler to try for an input. Untried fillers are tried first.
+    pub fn choose(&self, raw: &[u8]) -> Filler {
+        let Some(arms) = self.profiles.get(&format_profile(raw)) else {
+            return Filler::default();
+        };
+
+        let arm = |i: usize| arms.get(i).cloned().unwrap_or_default();
+
+        if let Some(untried) = (0..Filler::ALL.len()).find(|i| arm(*i).trials == 0) {
+            return Filler::ALL[untried];
+        }
+
+        let total = arms.iter().map(|a| a.trials).sum::<u64>() as f64;
+        let score = |i: usize| {
+            let arm = arm(i);
+            arm.successes as f64 / arm.trials as f64 + (2.0 * total.ln() / arm.trials as f64).sqrt()
+        };
+
+        let best = (0..Filler::ALL.len()).max_by(|a, b| score(*a).total_cmp(&score(*b))).unwrap();
+        Filler::ALL[best]
+    }
+
+    /// Records the outcome of a search which used `filler`.
+    pub fn record(&mut self, raw: &[u8], filler: Filler, trials: u64, successes: u64) {
+        let Some(idx) = filler.index() else {
+            return;
+        };
+
+        let arms = self.profiles.entry(format_profile(raw)).or_default();
+        arms.resize(Filler::ALL.len(), FillerArm::default());
+        arms[idx].trials += trials;
+        arms[idx].successes += successes;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeat_fill() {
+        let mut gap = [0; 5];
+        Filler::Repeat.fill(&mut gap, &[1, 2, 3]);
+        assert_eq!(gap, [1, 2, 3, 1, 2]);
+
+        let mut gap = [0; 2];
+        Filler::Repeat.fill(&mut gap, &[1, 2, 3]);
+        assert_eq!(gap, [2, 3]);
+    }
+
+    #[test]
+    fn test_bandit() {
+        let mut bandit = FillerBandit::default();
+        let raw = b"RIFF....";
+        assert_eq!(bandit.choose(raw), Filler::Byte(0x41));
+
+        // Every filler is tried once before exploiting.
+        bandit.record(raw, Filler::Byte(0x41), 10, 0);
+        assert_eq!(bandit.choose(raw), Filler::Byte(0));
+        bandit.record(raw, Filler::Byte(0), 10, 9);
+        bandit.record(raw, Filler::Random, 10, 0);
+        bandit.record(raw, Filler::Repeat, 10, 1);
+        assert_eq!(bandit.choose(raw), Filler::Byte(0));
+
+        // Other profiles are independent.
+        assert_eq!(bandit.choose(b"\x89PNG"), Filler::Byte(0x41));
+    }
+
+    #[test]
+    fn test_parse_filler() {
+        assert_eq!("zeros".parse(), Ok(Filler::Byte(0)));
+        assert_eq!("ff".parse(), Ok(Filler::Byte(0xff)));
+        assert_eq!("repeat".parse(), Ok(Filler::Repeat));
+        assert!("nope".parse::<Filler>().is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Coverage gated by the fields of an analyzed input, to show which parts of the target a relation controls.
+
+use super::{search::CoverageSnapshot, structured::Structured};
+
+/// Units the probes add to a field: enough for most parsers to read past the end of the region.
+const GATE_SHIFT: u64 = 0x20;
+
+/// Focus indices gated by the field of a relation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldGates {
+    pub relation: usize,
+    /// Focus indices lost when the field alone is corrupted.
+    pub lost: Vec<usize>,
+    /// Lost indices recovered once the region grows to match the corrupted value.
+    pub recovered: Vec<usize>,
+}
+
+/// Probes each enabled relation of `input` like `--edit` validates them: the field is corrupted in place (losing the
+/// indices it gates), then as many units are inserted at the end of its region with all relations updated
+/// (recovering them).
+///
+/// Only the `focus_indices` covered by `input` count, the rest of the map is left out.
+pub fn field_gates<O>(input: &Structured, oracle: &mut O, focus_indices: &[usize]) -> Vec<FieldGates>
+where
+    O: FnMut(&[u8]) -> CoverageSnapshot,
+{
+    let seed = oracle(input.get_raw());
+    let focus = focus_indices.iter().copied().filter(|idx| seed.contains(*idx)).collect::<Vec<_>>();
+
+    let mut gates = vec![];
+    for (idx, rel) in input.relations.iter().enumerate().filter(|(_, rel)| rel.enabled) {
+        // The corrupted digits of an ASCII field are written in place.
+        let max = if rel.ascii { 10u64.pow(rel.size as u32) - 1 } else { rel.max_value() };
+        let shift = GATE_SHIFT.min(max.saturating_sub(rel.value));
+        if shift == 0 {
+            gates.push(FieldGates { relation: idx, ..Default::default() });
+            continue;
+        }
+
+        let mut corrupted = input.get_raw().to_vec();
+        let mut lie = rel.clone();
+        lie.value += shift;
+        lie.apply(&mut corrupted);
+        let cov = oracle(&corrupted);
+        let lost = focus.iter().copied().filter(|idx| !cov.contains(*idx)).collect::<Vec<_>>();
+
+        let mut resized = input.clone();
+        let recovered = match lost.is_empty() || resized.insert(rel.insert, &vec![0x41; shift as usize * rel.stride]).is_err() {
+            true => vec![],
+            false => {
+                let cov = oracle(resized.get_raw());
+                lost.iter().copied().filter(|idx| cov.contains(*idx)).collect()
+            }
+        };
+
+        gates.push(FieldGates { relation: idx, lost, recovered });
+    }
+    gates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structured::Relation;
+
+    /// A 1-byte length prefixed payload followed by a trailer, the trailer bytes being the edges.
+    fn target(data: &[u8]) -> CoverageSnapshot {
+        let mut cov = vec![0u8; 64];
+        cov[0] = 1;
+        if !data.is_empty() && (data[0] as usize) < data.len() {
+            cov[1] = 1;
+            for b in data[1 + data[0] as usize..].iter() {
+                cov[2 + (*b as usize % 62)] = 1;
+            }
+        }
+        CoverageSnapshot::from(cov)
+    }
+
+    #[test]
+    fn test_field_gates() {
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        let mut oracle = target;
+
+        let gates = field_gates(&input, &mut oracle, &[1, 12, 22, 32, 40]);
+        assert_eq!(gates, vec![FieldGates { relation: 0, lost: vec![1, 12, 22, 32], recovered: vec![1, 12, 22, 32] }]);
+
+        // A wrong region: the insertion lands in the trailer, which the field still skips.
+        input.relations[0].insert = 8;
+        let gates = field_gates(&input, &mut oracle, &[1, 12, 22, 32]);
+        assert_eq!(gates[0].lost, vec![1, 12, 22, 32]);
+        assert_eq!(gates[0].recovered, vec![1]);
+    }
+}
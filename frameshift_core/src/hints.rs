@@ -0,0 +1,143 @@
+//! Format hints: relations known in advance (e.g. from the specification of a header), which the search starts from
+//! instead of rediscovering them.
+//!
+//! A hints file describes one length field per line, as `key=value` pairs and flags:
+//!
+//! ```text
+//! # 32-bit little-endian length at 4, counting the bytes from 8
+//! offset=4 size=4 le anchor=8
+//! ```
+//!
+//! `offset` is required. The field is 1 byte by default (`size` may be 1, 2, 3, 4 or 8), little-endian unless `be` is
+//! given, its region starts right after it unless `anchor` is given, and its value counts bytes unless `stride` (bytes
+//! per unit) is given. Numbers may be written in hexadecimal (`0x...`). Empty lines and `#` comments are ignored.
+
+use super::{search::read_field, structured::{Heuristic, Relation, Structured}};
+
+/// A length field of the format, at a fixed position of the inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hint {
+    pub offset: usize,
+    pub size: usize,
+    pub le: bool,
+    /// Start of the region (right after the field if unset).
+    pub anchor: Option<usize>,
+    pub stride: usize,
+}
+
+fn parse_number(key: &str, value: &str) -> Result<usize, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse::<usize>(),
+    };
+    parsed.map_err(|_| format!("invalid {}: {:?}", key, value))
+}
+
+/// Parses a hints file (see the module documentation).
+pub fn parse_hints(spec: &str) -> Result<Vec<Hint>, String> {
+    let mut hints = vec![];
+    for (k, line) in spec.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut offset = None;
+        let mut hint = Hint { offset: 0, size: 1, le: true, anchor: None, stride: 1 };
+        for token in line.split_whitespace() {
+            let parse = |key: &str, value: &str| parse_number(key, value).map_err(|e| format!("line {}: {}", k + 1, e));
+            match token.split_once('=') {
+                Some(("offset", value)) => offset = Some(parse("offset", value)?),
+                Some(("size", value)) => {
+                    hint.size = parse("size", value)?;
+                    if ![1, 2, 3, 4, 8].contains(&hint.size) {
+                        return Err(format!("line {}: unsupported size {} (expected 1, 2, 3, 4 or 8)", k + 1, hint.size));
+                    }
+                }
+                Some(("anchor", value)) => hint.anchor = Some(parse("anchor", value)?),
+                Some(("stride", value)) => {
+                    hint.stride = parse("stride", value)?;
+                    if hint.stride == 0 {
+                        return Err(format!("line {}: stride must be positive", k + 1));
+                    }
+                }
+                None if token == "le" => hint.le = true,
+                None if token == "be" => hint.le = false,
+                _ => return Err(format!("line {}: unknown token {:?}", k + 1, token)),
+            }
+        }
+
+        hint.offset = offset.ok_or_else(|| format!("line {}: missing offset", k + 1))?;
+        hints.push(hint);
+    }
+    Ok(hints)
+}
+
+/// Adds the relations described by `hints` to `input`, tagged with `Heuristic::Hint`, and returns how many were added.
+///
+/// Hints which do not fit the input (a field or region past its end) or whose field overlaps a known relation are
+/// skipped.
+pub fn apply_hints(input: &mut Structured, hints: &[Hint]) -> usize {
+    let mut added = 0;
+    for hint in hints {
+        let len = input.get_raw().len();
+        if hint.offset + hint.size > len {
+            continue;
+        }
+
+        let field = hint.offset..hint.offset + hint.size;
+        let overlaps = input.relations.iter()
+            .flat_map(|rel| rel.field_positions().map(move |pos| pos..pos + rel.size))
+            .any(|other| other.start < field.end && field.start < other.end);
+        if overlaps {
+            continue;
+        }
+
+        let value = read_field(input.get_raw(), hint.offset, hint.size, hint.le);
+        let anchor = hint.anchor.unwrap_or(field.end);
+        let Some(insert) = value.checked_mul(hint.stride).and_then(|bytes| bytes.checked_add(anchor)) else {
+            continue;
+        };
+        if insert > len {
+            continue;
+        }
+
+        let mut rel = Relation::new(hint.offset, value as u64, hint.size, hint.le, anchor, insert);
+        rel.stride = hint.stride;
+        rel.heuristics.push(Heuristic::Hint);
+        input.add_relation(rel);
+        added += 1;
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hints() {
+        let hints = parse_hints("# header\noffset=4 size=4 le anchor=8\n\noffset=0x10 size=2 be stride=4 # table\n").unwrap();
+        assert_eq!(hints, vec![
+            Hint { offset: 4, size: 4, le: true, anchor: Some(8), stride: 1 },
+            Hint { offset: 16, size: 2, le: false, anchor: None, stride: 4 },
+        ]);
+
+        assert!(parse_hints("size=4").is_err());
+        assert!(parse_hints("offset=1 size=5").is_err());
+        assert!(parse_hints("offset=1 wide").is_err());
+    }
+
+    #[test]
+    fn test_apply_hints() {
+        let hints = parse_hints("offset=0 size=2\noffset=1\noffset=6 anchor=0\noffset=7").unwrap();
+        let mut input = Structured::raw(vec![3, 0, 0x41, 0x41, 0x41, 10, 8, 0xff]);
+
+        // The second hint overlaps the first field, the last one points past the end.
+        assert_eq!(apply_hints(&mut input, &hints), 2);
+        let rel = &input.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 2, 2, 5));
+        assert_eq!(rel.heuristics, vec![Heuristic::Hint]);
+        assert_eq!((input.relations[1].anchor, input.relations[1].insert), (0, 8));
+    }
+}
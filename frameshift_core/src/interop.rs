@@ -0,0 +1,698 @@
+//! Conversion between `Structured` and FormatFuzzer / 010 Editor binary templates (`.bt`).
+//!
+//! Exported templates describe the input as a flat sequence of fields, using the idioms generator-based fuzzers
+//! understand: a length field followed by `uchar data[length];`, a region ended by `FSeek(end)` where
+//! `local int64 end = FTell() + length;`, and an offset followed by `FSeek(base + offset);`.
+//!
+//! Imported templates are interpreted against a sample input (like running a template in 010 Editor), and every
+//! length or offset expression which references exactly one field becomes a relation. Only a subset of the
+//! template language is supported: declarations (also arrays and structs), `local` variables, `typedef struct`,
+//! `LittleEndian` / `BigEndian`, `FSeek`, `FSkip` and `FTell`. Control flow is rejected.
+
+use std::collections::HashMap;
+
+use super::{search::read_field, structured::{Relation, Structured}};
+
+/// Name of the template type with the given size in bytes.
+fn type_name(size: usize) -> Option<&'static str> {
+    match size {
+        1 => Some("uchar"),
+        2 => Some("ushort"),
+        4 => Some("uint"),
+        8 => Some("uint64"),
+        _ => None,
+    }
+}
+
+/// Size in bytes of a template type.
+fn type_size(name: &str) -> Option<usize> {
+    match name.to_lowercase().as_str() {
+        "char" | "uchar" | "byte" | "ubyte" | "int8" | "uint8" => Some(1),
+        "short" | "ushort" | "int16" | "uint16" | "word" => Some(2),
+        "int" | "uint" | "long" | "ulong" | "int32" | "uint32" | "dword" => Some(4),
+        "int64" | "uint64" | "quad" | "uquad" | "qword" => Some(8),
+        _ => None,
+    }
+}
+
+/// Formats `name + delta` (or `name - delta`).
+fn offset_expr(name: &str, delta: i64) -> String {
+    match delta {
+        0 => name.to_string(),
+        d if d > 0 => format!("{} + {}", name, d),
+        d => format!("{} - {}", name, -d),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A size field followed by its region: `[anchor, insert)`.
+    Size,
+    /// An offset, relative to `anchor`, pointing at `insert`.
+    Offset,
+}
+
+/// Renders `input` as a binary template.
+///
+/// Relations which can not be expressed in a sequential template are left out (their bytes are kept as data):
+/// disabled relations, fields of unsupported sizes, regions partially overlapping another region or cutting through
+/// a field, and offsets pointing before their own field. Mirrors and groups are not exported.
+pub fn to_binary_template(input: &Structured) -> String {
+    let raw = input.get_raw();
+
+    let mut order = (0..input.relations.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| input.relations[*i].pos);
+
+    // Select the relations to export.
+    let mut accepted: Vec<(&Relation, Kind)> = vec![];
+    for rel in order.iter().map(|i| &input.relations[*i]) {
+        let end = rel.pos + rel.size;
+        if !rel.enabled || rel.bits.is_some() || rel.ascii || rel.stride != 1 || type_name(rel.size).is_none() || end > raw.len() || rel.insert > raw.len() || rel.anchor > rel.insert {
+            continue;
+        }
+
+        let kind = if rel.anchor >= end {
+            Kind::Size
+        } else if rel.anchor <= rel.pos && end <= rel.insert {
+            Kind::Offset
+        } else {
+            continue;
+        };
+
+        let inside = |p: usize, other: &Relation| other.pos < p && p < other.pos + other.size;
+        let conflicts = accepted.iter().any(|(other, other_kind)| {
+            // Fields must not overlap.
+            if rel.pos < other.pos + other.size && other.pos < end {
+                return true;
+            }
+
+            // Region boundaries and seek targets must not cut through a field.
+            if inside(rel.insert, other) || (kind == Kind::Size && inside(rel.anchor, other)) {
+                return true;
+            }
+            if (1..rel.size).any(|i| other.insert == rel.pos + i || (*other_kind == Kind::Size && other.anchor == rel.pos + i)) {
+                return true;
+            }
+
+            // Regions must be disjoint or nested (and not identical).
+            if kind == Kind::Size && *other_kind == Kind::Size {
+                let disjoint = rel.insert <= other.anchor || other.insert <= rel.anchor;
+                let nested = (rel.anchor <= other.anchor && other.insert <= rel.insert) || (other.anchor <= rel.anchor && rel.insert <= other.insert);
+                let identical = rel.anchor == other.anchor && rel.insert == other.insert;
+                return !(disjoint || nested) || identical;
+            }
+            false
+        });
+
+        if !conflicts {
+            accepted.push((rel, kind));
+        }
+    }
+
+    // A region is emitted as an array if nothing else happens inside it.
+    let is_leaf = |rel: &Relation| {
+        rel.anchor == rel.insert || !accepted.iter().any(|(other, other_kind)| {
+            let within = |p: usize| rel.anchor < p && p < rel.insert;
+            (other.pos < rel.insert && rel.anchor < other.pos + other.size)
+                || within(other.insert)
+                || (*other_kind == Kind::Size && within(other.anchor))
+        })
+    };
+
+    let sizes = accepted.iter().filter(|(_, k)| *k == Kind::Size).map(|(r, _)| *r).collect::<Vec<_>>();
+    let offsets = accepted.iter().filter(|(_, k)| *k == Kind::Offset).map(|(r, _)| *r).collect::<Vec<_>>();
+    let (leaves, mut locals): (Vec<&Relation>, Vec<&Relation>) = sizes.iter().partition(|r| is_leaf(r));
+
+    let mut points = vec![0, raw.len()];
+    for (rel, kind) in accepted.iter() {
+        points.extend([rel.pos, rel.insert]);
+        if *kind == Kind::Size {
+            points.push(rel.anchor);
+        }
+    }
+    points.sort();
+    points.dedup();
+
+    // Outer regions first when opening, inner regions first when closing.
+    locals.sort_by_key(|r| (std::cmp::Reverse(r.insert), r.anchor));
+
+    let mut out = format!("// Generated by FrameShift from a {}-byte input.\n", raw.len());
+    let mut cursor = 0;
+    let mut le = None;
+
+    for p in points {
+        if p < cursor {
+            continue;
+        }
+        if p > cursor {
+            out.push_str(&format!("uchar d_{}[{}];\n", cursor, p - cursor));
+            cursor = p;
+        }
+
+        for rel in locals.iter().rev().filter(|r| r.insert == p) {
+            out.push_str(&format!("FSeek(r_{}_end);\n", rel.pos));
+        }
+        for rel in offsets.iter().filter(|r| r.insert == p) {
+            let base = rel.insert as i64 - rel.value as i64;
+            out.push_str(&format!("FSeek({});\n", offset_expr(&format!("f_{}", rel.pos), base)));
+        }
+        for rel in locals.iter().filter(|r| r.anchor == p) {
+            let delta = (rel.insert - rel.anchor) as i64 - rel.value as i64;
+            out.push_str(&format!("local int64 r_{}_end = FTell() + {};\n", rel.pos, offset_expr(&format!("f_{}", rel.pos), delta)));
+        }
+
+        // Empty regions first, so that a non-empty one can advance the cursor.
+        let mut here = leaves.iter().filter(|r| r.anchor == p).collect::<Vec<_>>();
+        here.sort_by_key(|r| r.insert);
+        for rel in here {
+            let delta = (rel.insert - rel.anchor) as i64 - rel.value as i64;
+            out.push_str(&format!("uchar r_{}[{}];\n", rel.pos, offset_expr(&format!("f_{}", rel.pos), delta)));
+            cursor = rel.insert;
+        }
+
+        if let Some((rel, _)) = accepted.iter().find(|(r, _)| r.pos == p && cursor == p) {
+            if rel.size > 1 && le != Some(rel.le) {
+                out.push_str(if rel.le { "LittleEndian();\n" } else { "BigEndian();\n" });
+                le = Some(rel.le);
+            }
+            out.push_str(&format!("{} f_{};\n", type_name(rel.size).unwrap(), rel.pos));
+            cursor += rel.size;
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Punct(char),
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, String> {
+    let chars = template.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            // Strings only appear in attributes and function arguments we skip.
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            tokens.push(Token::Ident(String::new()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let text = chars[start..i].iter().collect::<String>();
+            let text = text.trim_end_matches(['u', 'U', 'l', 'L']);
+            let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => text.parse(),
+            };
+            tokens.push(Token::Number(value.map_err(|_| format!("invalid number: {}", text))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A declared field.
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    pos: usize,
+    size: usize,
+    le: bool,
+    value: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Field(Field),
+    /// A local variable, possibly holding the end of a region sized by a field (`FTell() + field`).
+    Local { value: i64, region: Option<(Field, usize)> },
+}
+
+/// An evaluated expression. `field` is set if the expression is a constant plus exactly one field.
+#[derive(Debug, Clone, Copy)]
+struct Value {
+    value: i64,
+    field: Option<Field>,
+    fields: usize,
+    ftell: bool,
+    region: Option<(Field, usize)>,
+}
+
+impl Value {
+    fn constant(value: i64) -> Self {
+        Self { value, field: None, fields: 0, ftell: false, region: None }
+    }
+
+    fn combine(mut self, other: Value, sign: i64) -> Self {
+        self.value += sign * other.value;
+        self.fields += other.fields;
+        self.field = if sign > 0 && self.field.is_none() { other.field } else { self.field };
+        self.ftell |= other.ftell;
+        self.region = None;
+        self
+    }
+
+    /// The field this expression is linear in, if any.
+    fn linear_field(&self) -> Option<Field> {
+        if self.fields == 1 { self.field } else { None }
+    }
+}
+
+struct Interpreter<'a> {
+    raw: &'a [u8],
+    cursor: usize,
+    le: bool,
+    vars: HashMap<String, Var>,
+    structs: HashMap<String, Vec<Token>>,
+    out: Structured,
+}
+
+impl<'a> Interpreter<'a> {
+    fn add_relation(&mut self, field: Field, anchor: usize, insert: usize) {
+        if self.out.relations.iter().any(|r| r.pos == field.pos) || anchor > insert || insert > self.raw.len() {
+            return;
+        }
+        self.out.add_relation(Relation::new(field.pos, field.value, field.size, field.le, anchor, insert));
+    }
+
+    fn expect(tokens: &[Token], i: &mut usize, c: char) -> Result<(), String> {
+        match tokens.get(*i) {
+            Some(Token::Punct(p)) if *p == c => {
+                *i += 1;
+                Ok(())
+            }
+            other => Err(format!("expected '{}', found {:?}", c, other)),
+        }
+    }
+
+    fn ident(tokens: &[Token], i: &mut usize) -> Result<String, String> {
+        match tokens.get(*i) {
+            Some(Token::Ident(name)) => {
+                *i += 1;
+                Ok(name.clone())
+            }
+            other => Err(format!("expected identifier, found {:?}", other)),
+        }
+    }
+
+    fn is_punct(tokens: &[Token], i: usize, c: char) -> bool {
+        tokens.get(i) == Some(&Token::Punct(c))
+    }
+
+    /// Returns the tokens of a `{ ... }` block, starting at the opening brace.
+    fn block(tokens: &[Token], i: &mut usize) -> Result<Vec<Token>, String> {
+        Self::expect(tokens, i, '{')?;
+        let start = *i;
+        let mut depth = 1;
+        while depth > 0 {
+            match tokens.get(*i) {
+                Some(Token::Punct('{')) => depth += 1,
+                Some(Token::Punct('}')) => depth -= 1,
+                None => return Err("unterminated block".to_string()),
+                _ => {}
+            }
+            *i += 1;
+        }
+        Ok(tokens[start..*i - 1].to_vec())
+    }
+
+    fn term(&self, tokens: &[Token], i: &mut usize) -> Result<Value, String> {
+        match tokens.get(*i).cloned() {
+            Some(Token::Number(n)) => {
+                *i += 1;
+                Ok(Value::constant(n))
+            }
+            Some(Token::Punct('(')) => {
+                *i += 1;
+                let value = self.expr(tokens, i)?;
+                Self::expect(tokens, i, ')')?;
+                Ok(value)
+            }
+            Some(Token::Ident(mut name)) => {
+                *i += 1;
+                if Self::is_punct(tokens, *i, '(') {
+                    Self::expect(tokens, i, '(')?;
+                    Self::expect(tokens, i, ')')?;
+                    return match name.as_str() {
+                        "FTell" => Ok(Value { ftell: true, ..Value::constant(self.cursor as i64) }),
+                        "FileSize" => Ok(Value::constant(self.raw.len() as i64)),
+                        _ => Err(format!("unsupported function: {}", name)),
+                    };
+                }
+
+                // Struct members are flattened, `chunk.length` refers to the last `length`.
+                while Self::is_punct(tokens, *i, '.') {
+                    *i += 1;
+                    name = Self::ident(tokens, i)?;
+                }
+
+                match self.vars.get(&name) {
+                    Some(Var::Field(field)) => Ok(Value { field: Some(*field), fields: 1, ..Value::constant(field.value as i64) }),
+                    Some(Var::Local { value, region }) => Ok(Value { region: *region, ..Value::constant(*value) }),
+                    None => Err(format!("unknown variable: {}", name)),
+                }
+            }
+            other => Err(format!("unexpected {:?} in expression", other)),
+        }
+    }
+
+    fn expr(&self, tokens: &[Token], i: &mut usize) -> Result<Value, String> {
+        let mut value = self.term(tokens, i)?;
+        loop {
+            let sign = match tokens.get(*i) {
+                Some(Token::Punct('+')) => 1,
+                Some(Token::Punct('-')) => -1,
+                _ => return Ok(value),
+            };
+            *i += 1;
+            value = value.combine(self.term(tokens, i)?, sign);
+        }
+    }
+
+    fn seek(&mut self, target: Value) -> Result<(), String> {
+        if target.value < 0 || target.value as usize > self.raw.len() {
+            return Err(format!("seek out of bounds: {}", target.value));
+        }
+        let target_pos = target.value as usize;
+
+        if let Some((field, anchor)) = target.region {
+            self.add_relation(field, anchor, target_pos);
+        } else if let Some(field) = target.linear_field() {
+            let anchor = if target.ftell { self.cursor as i64 } else { target.value - field.value as i64 };
+            if anchor >= 0 {
+                self.add_relation(field, anchor as usize, target_pos);
+            }
+        }
+
+        self.cursor = target_pos;
+        Ok(())
+    }
+
+    /// Declares `count` elements of a type at the cursor (`length` is the expression of an array length).
+    fn declare(&mut self, ty: &str, name: &str, length: Option<Value>) -> Result<(), String> {
+        let count = match length {
+            Some(length) if length.value < 0 => return Err(format!("negative array length for {}", name)),
+            Some(length) => length.value as usize,
+            None => 1,
+        };
+
+        if let Some(body) = self.structs.get(ty).cloned() {
+            for _ in 0..count {
+                self.run(&body)?;
+            }
+            return Ok(());
+        }
+
+        let size = type_size(ty).ok_or_else(|| format!("unknown type: {}", ty))?;
+        let start = self.cursor;
+        let end = start + size * count;
+        if end > self.raw.len() {
+            return Err(format!("{} reads past the end of the input", name));
+        }
+
+        match length {
+            Some(length) => {
+                // Byte arrays sized by a field are regions.
+                if let (Some(field), 1) = (length.linear_field(), size) {
+                    self.add_relation(field, start, end);
+                }
+            }
+            None => {
+                let value = if size == 1 { self.raw[start] as u64 } else { read_field(self.raw, start, size, self.le) as u64 };
+                self.vars.insert(name.to_string(), Var::Field(Field { pos: start, size, le: size == 1 || self.le, value }));
+            }
+        }
+
+        self.cursor = end;
+        Ok(())
+    }
+
+    /// Skips attributes (`<...>`) and the terminating semicolon of a declaration.
+    fn end_declaration(tokens: &[Token], i: &mut usize) -> Result<(), String> {
+        if Self::is_punct(tokens, *i, '<') {
+            while !Self::is_punct(tokens, *i, '>') {
+                if *i >= tokens.len() {
+                    return Err("unterminated attributes".to_string());
+                }
+                *i += 1;
+            }
+            *i += 1;
+        }
+        Self::expect(tokens, i, ';')
+    }
+
+    fn run(&mut self, tokens: &[Token]) -> Result<(), String> {
+        let mut i = 0;
+        while i < tokens.len() {
+            if Self::is_punct(tokens, i, ';') {
+                i += 1;
+                continue;
+            }
+
+            let word = Self::ident(tokens, &mut i)?;
+            match word.as_str() {
+                "LittleEndian" | "BigEndian" => {
+                    Self::expect(tokens, &mut i, '(')?;
+                    Self::expect(tokens, &mut i, ')')?;
+                    Self::expect(tokens, &mut i, ';')?;
+                    self.le = word == "LittleEndian";
+                }
+                "FSeek" | "FSkip" => {
+                    Self::expect(tokens, &mut i, '(')?;
+                    let mut target = self.expr(tokens, &mut i)?;
+                    Self::expect(tokens, &mut i, ')')?;
+                    Self::expect(tokens, &mut i, ';')?;
+                    if word == "FSkip" {
+                        target = Value { ftell: true, ..target }.combine(Value::constant(self.cursor as i64), 1);
+                        target.region = target.linear_field().map(|field| (field, self.cursor));
+                    }
+                    self.seek(target)?;
+                }
+                "local" => {
+                    let _ty = Self::ident(tokens, &mut i)?;
+                    let name = Self::ident(tokens, &mut i)?;
+                    let mut value = Value::constant(0);
+                    if Self::is_punct(tokens, i, '=') {
+                        i += 1;
+                        value = self.expr(tokens, &mut i)?;
+                    }
+                    Self::expect(tokens, &mut i, ';')?;
+
+                    // `FTell() + field` marks the end of a region starting here.
+                    let region = match (value.ftell, value.linear_field()) {
+                        (true, Some(field)) => Some((field, self.cursor)),
+                        _ => value.region,
+                    };
+                    self.vars.insert(name, Var::Local { value: value.value, region });
+                }
+                "typedef" => {
+                    if Self::ident(tokens, &mut i)? != "struct" {
+                        return Err("only struct typedefs are supported".to_string());
+                    }
+                    if matches!(tokens.get(i), Some(Token::Ident(_))) {
+                        i += 1;
+                    }
+                    let body = Self::block(tokens, &mut i)?;
+                    let name = Self::ident(tokens, &mut i)?;
+                    Self::expect(tokens, &mut i, ';')?;
+                    self.structs.insert(name, body);
+                }
+                "struct" => {
+                    let tag = match tokens.get(i) {
+                        Some(Token::Ident(tag)) => {
+                            i += 1;
+                            Some(tag.clone())
+                        }
+                        _ => None,
+                    };
+
+                    let ty = if Self::is_punct(tokens, i, '{') {
+                        let body = Self::block(tokens, &mut i)?;
+                        let ty = tag.unwrap_or_else(|| format!("struct#{}", i));
+                        self.structs.insert(ty.clone(), body);
+                        ty
+                    } else {
+                        tag.ok_or_else(|| "expected struct tag or body".to_string())?
+                    };
+
+                    if Self::is_punct(tokens, i, ';') {
+                        i += 1;
+                        continue;
+                    }
+
+                    let name = Self::ident(tokens, &mut i)?;
+                    let length = self.array_length(tokens, &mut i)?;
+                    Self::end_declaration(tokens, &mut i)?;
+                    self.declare(&ty, &name, length)?;
+                }
+                "if" | "else" | "while" | "for" | "do" | "switch" | "return" => {
+                    return Err(format!("unsupported statement: {}", word));
+                }
+                _ => {
+                    // Skip modifiers.
+                    let mut ty = word;
+                    while ["const", "unsigned", "signed"].contains(&ty.as_str()) {
+                        ty = Self::ident(tokens, &mut i)?;
+                    }
+
+                    let name = Self::ident(tokens, &mut i)?;
+                    let length = self.array_length(tokens, &mut i)?;
+                    Self::end_declaration(tokens, &mut i)?;
+                    self.declare(&ty, &name, length)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn array_length(&self, tokens: &[Token], i: &mut usize) -> Result<Option<Value>, String> {
+        if !Self::is_punct(tokens, *i, '[') {
+            return Ok(None);
+        }
+        *i += 1;
+        let length = self.expr(tokens, i)?;
+        Self::expect(tokens, i, ']')?;
+        Ok(Some(length))
+    }
+}
+
+/// Interprets a binary template on `raw` and returns the input with one relation per field which determines the
+/// length of an array, the end of a region (`FSeek` to `FTell() + field`) or the target of a seek.
+pub fn from_binary_template(template: &str, raw: Vec<u8>) -> Result<Structured, String> {
+    let tokens = tokenize(template)?;
+
+    let mut interpreter = Interpreter {
+        raw: &raw,
+        cursor: 0,
+        le: true,
+        vars: HashMap::new(),
+        structs: HashMap::new(),
+        out: Structured::raw(raw.clone()),
+    };
+    interpreter.run(&tokens)?;
+
+    Ok(interpreter.out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(rel: &Relation) -> (usize, u64, usize, bool, usize, usize) {
+        (rel.pos, rel.value, rel.size, rel.le, rel.anchor, rel.insert)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut raw = b"FS".to_vec();
+        raw.extend([10, 0]); // 2: outer length (u16 le), region 4..14
+        raw.extend([3, b'a', b'b', b'c']); // 4: inner length (u8), region 5..8
+        raw.extend([1, 2, 3, 4, 5, 6]);
+        raw.extend([0, 0, 0, 20]); // 14: offset (u32 be) of the tail
+        raw.extend([0, 0]);
+        raw.extend(b"tail");
+
+        let mut input = Structured::raw(raw.clone());
+        input.add_relation(Relation::new(2, 10, 2, true, 4, 14));
+        input.add_relation(Relation::new(4, 3, 1, true, 5, 8));
+        input.add_relation(Relation::new(14, 20, 4, false, 0, 20));
+
+        let template = to_binary_template(&input);
+        assert!(template.contains("local int64 r_2_end = FTell() + f_2;"));
+        assert!(template.contains("uchar r_4[f_4];"));
+        assert!(template.contains("FSeek(f_14);"));
+
+        let imported = from_binary_template(&template, raw).unwrap();
+        let mut expected = input.relations.iter().map(key).collect::<Vec<_>>();
+        let mut actual = imported.relations.iter().map(key).collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+
+        // Exporting again gives the same template.
+        assert_eq!(to_binary_template(&imported), template);
+    }
+
+    #[test]
+    fn test_skip_unsupported() {
+        let raw = vec![4, 0, 1, 2, 3, 4];
+        let mut input = Structured::raw(raw.clone());
+        input.add_relation(Relation::new(0, 4, 1, true, 2, 6));
+        input.add_relation(Relation::new(1, 0, 1, true, 3, 3)); // Empty region nested in the first one.
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5)); // Overlaps the first field.
+
+        let imported = from_binary_template(&to_binary_template(&input), raw).unwrap();
+        assert_eq!(imported.relations.len(), 2);
+    }
+
+    #[test]
+    fn test_import_chunks() {
+        let template = "
+            // PNG-like chunks.
+            typedef struct {
+                uint length;
+                char type[4];
+                uchar data[length];
+                uint crc <format=hex>;
+            } CHUNK;
+
+            BigEndian();
+            uchar signature[4];
+            CHUNK chunk;
+            CHUNK chunk2;
+        ";
+
+        let mut raw = b"\x89PNG".to_vec();
+        raw.extend([0, 0, 0, 2]);
+        raw.extend(b"IHDR");
+        raw.extend([1, 2]);
+        raw.extend([0; 4]);
+        raw.extend([0, 0, 0, 0]);
+        raw.extend(b"IEND");
+        raw.extend([0; 4]);
+
+        let imported = from_binary_template(template, raw).unwrap();
+        let relations = imported.relations.iter().map(key).collect::<Vec<_>>();
+        assert_eq!(relations, vec![(4, 2, 4, false, 12, 14), (18, 0, 4, false, 26, 26)]);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        assert!(from_binary_template("uint x;", vec![0, 0]).is_err());
+        assert!(from_binary_template("uchar x; if (x) { uchar y; }", vec![0, 0]).is_err());
+        assert!(from_binary_template("uchar x[y];", vec![0, 0]).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+//! FrameShift's structure inference, without any LibAFL types: the relation-aware input (`Structured`), the search
+//! driven by a plain coverage callback (see `api`), the annotation formats and the external coverage collectors. The
+//! fuzzer (`frameshift_afl`), the AFL++ custom mutator and the C analysis interface (`frameshift_analyze`) are built on
+//! top of it.
+
+pub mod annotation;
+pub mod api;
+pub mod collector;
+pub mod coverage;
+pub mod diff;
+pub mod filler;
+pub mod gates;
+pub mod hints;
+pub mod interop;
+pub mod parts;
+pub mod search;
+pub mod strategy;
+pub mod structured;
+pub mod value_model;
+
+pub use api::*;
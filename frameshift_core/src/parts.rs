@@ -0,0 +1,83 @@
+//! The files of multi-file inputs: each `@@<name>` placeholder in the target arguments (or in a coverage command)
+//! names a part, which is written to a temporary file before every execution.
+
+use std::{env, fs, path::PathBuf, process, sync::Mutex};
+
+/// Names and files of the parts the target reads, registered from its arguments by `register_parts`. The paths are
+/// fixed at registration: the restarting manager and the fork executors write the parts from child processes, which
+/// must use the paths substituted in the target arguments.
+static PARTS: Mutex<Vec<(String, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Path of the temporary file holding part `name` of the input being executed: the registered one, or else a new
+/// one for the current process.
+pub fn part_path(name: &str) -> PathBuf {
+    if let Some((_, path)) = PARTS.lock().unwrap().iter().find(|(part, _)| part == name) {
+        return path.clone();
+    }
+    env::temp_dir().join(format!("frameshift-part-{}-{}", process::id(), name))
+}
+
+/// Returns the names of the `@@<name>` placeholders in `arg` (a bare `@@` has no name).
+pub fn placeholders(arg: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut rest = arg;
+    while let Some(idx) = rest.find("@@") {
+        rest = &rest[idx + 2..];
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        if len > 0 && !names.iter().any(|n| n == &rest[..len]) {
+            names.push(rest[..len].to_string());
+        }
+        rest = &rest[len..];
+    }
+    names
+}
+
+/// Replaces the `@@<name>` placeholders of `arg` by the paths of their part files.
+pub fn substitute_parts(arg: &str) -> String {
+    let mut names = placeholders(arg);
+    // Longer names first, so that `@@idx` does not replace the prefix of `@@idx2`.
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+    let mut arg = arg.to_string();
+    for name in names {
+        arg = arg.replace(&format!("@@{}", name), &part_path(&name).to_string_lossy());
+    }
+    arg
+}
+
+/// Registers the parts named by `@@<name>` placeholders in the target arguments and returns the arguments with the
+/// placeholders replaced by the paths of the part files, which are rewritten before every execution.
+pub fn register_parts(args: &[String]) -> Vec<String> {
+    for name in args.iter().flat_map(|arg| placeholders(arg)) {
+        let path = part_path(&name);
+        let mut registered = PARTS.lock().unwrap();
+        if !registered.iter().any(|(part, _)| part == &name) {
+            registered.push((name, path));
+        }
+    }
+    args.iter().map(|arg| substitute_parts(arg)).collect()
+}
+
+/// Names of the registered parts.
+pub fn part_names() -> Vec<String> {
+    PARTS.lock().unwrap().iter().map(|(name, _)| name.clone()).collect()
+}
+
+/// Writes `data` to the file of part `name`.
+pub fn write_part(name: &str, data: &[u8]) {
+    fs::write(part_path(name), data).expect("Could not write input part");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_parts() {
+        assert_eq!(placeholders("./target @@ --index @@idx --other @@idx2,@@idx"), vec!["idx", "idx2"]);
+
+        let arg = substitute_parts("--index=@@idx --other=@@idx2 @@");
+        let (idx, idx2) = (part_path("idx"), part_path("idx2"));
+        assert_eq!(arg, format!("--index={} --other={} @@", idx.to_string_lossy(), idx2.to_string_lossy()));
+    }
+}
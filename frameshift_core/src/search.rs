@@ -0,0 +1,2348 @@
+use std::{cell::{Cell, RefCell}, collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, hash::{Hash, Hasher}, ops::Range, rc::Rc, sync::Arc, time::{Duration, Instant}};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use super::{filler::Filler, hints::{apply_hints, Hint}, strategy::{shannon_entropy, Candidate, CmplogStrategy, SearchStrategy}, structured::{Bitfield, Constant, Heuristic, OffsetRelation, Relation, Structured, MAX_ASCII_DIGITS}};
+
+/// Maximum number of entries considered when looking for a table of offsets.
+const MAX_TABLE_ENTRIES: usize = 64;
+
+/// Sub-byte length candidates tested when `enable_bitfields` is set: nibbles and 6-bit fields.
+const BITFIELD_LAYOUTS: [Bitfield; 4] = [
+    Bitfield { shift: 0, width: 4 }, Bitfield { shift: 4, width: 4 },
+    Bitfield { shift: 0, width: 6 }, Bitfield { shift: 2, width: 6 },
+];
+
+/// Units (in bytes) tried for a length field, in order, until one recovers the lost coverage.
+const STRIDES: [usize; 4] = [1, 2, 4, 8];
+
+/// Fraction of the focus coverage that must be lost by both growing and shrinking the input for it to be
+/// considered fixed-size.
+const FIXED_SIZE_COLLAPSE: f64 = 0.9;
+
+/// Probes whose coverage is kept during a search, to answer the probes it regenerates without running the target.
+const PROBE_CACHE_SIZE: usize = 64;
+
+/// Bytes at the start of an input searched for a length of the whole input, which makes it look fixed-size.
+const WHOLE_FILE_HEADER: usize = 16;
+
+/// Fraction of the focus coverage that must be lost by corrupting a byte for it to be considered a constant.
+const CONSTANT_COLLAPSE: f64 = 0.9;
+
+/// Minimum number of characters of a string region.
+const MIN_STRING_LEN: usize = 4;
+
+/// Bytes on each side of a candidate field whose entropy is measured by the pre-filter.
+const PREFILTER_WINDOW: usize = 32;
+
+/// Entropy (in bits per byte) above which the surroundings of a field are considered compressed or random data. A
+/// window of 64 bytes holds at most 6 bits, and random data about 5.7.
+const PREFILTER_MAX_ENTROPY: f64 = 5.4;
+
+/// Smaller corruptions tried by exhaustive searches when the regular shift loses no coverage (e.g. a target which
+/// clamps large lengths).
+const EXHAUSTIVE_SHIFTS: [usize; 2] = [0x08, 0x01];
+
+/// Units removed from the end of a region by the shrink probes.
+const SHRINK_UNITS: usize = 1;
+
+/// A relation is refuted if removing `SHRINK_UNITS` at the end of its region (and updating the field) loses more than
+/// this fraction of the coverage lost by the corrupted field: the region probably ends elsewhere.
+const SHRINK_MAX_LOSS: f64 = 0.5;
+
+/// Header sizes left out of the value by the header bias probes, after the size of the field itself (see
+/// `SearchOptions::header_bias`).
+const HEADER_BIASES: [usize; 4] = [4, 8, 12, 16];
+
+/// Bytes inserted before the pointed-to data by the offset probes (see `SearchOptions::enable_offsets`).
+const OFFSET_SHIFT: usize = 16;
+
+/// Field layouts tested as offsets.
+const OFFSET_TYPES: [(usize, bool); 6] = [(8, true), (8, false), (4, true), (4, false), (2, true), (2, false)];
+
+/// Share of the multi-byte relations which must agree on an endianness for the vote to settle on it.
+const ENDIAN_MAJORITY: f64 = 0.75;
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub verbose: bool,
+    pub extra_verbose: bool,
+    pub max_iters: usize,
+
+    /// Only test candidate fields whose value was observed as a comparison operand (if any were observed).
+    pub cmplog_only: bool,
+
+    /// Probe whether the target rejects any change in input length before searching.
+    pub detect_fixed_size: bool,
+
+    /// Relations whose confidence drops below this after re-validation are removed (0 disables re-validation).
+    pub prune_threshold: f64,
+
+    /// Fallback fillers tried at an anchor (after the guidance filler) before giving up on it.
+    pub fill_strategy: Vec<Filler>,
+
+    /// Also test lengths packed into some bits of a byte (nibbles and 6-bit fields), after the regular fields.
+    pub enable_bitfields: bool,
+
+    /// Also test lengths written as ASCII decimal digits (e.g. an HTTP `Content-Length`), after the regular fields.
+    pub enable_ascii: bool,
+
+    /// Also test lengths counting 2, 4 or 8-byte units when inserting bytes does not recover a field, each stride
+    /// costing another scan of the anchors (see `STRIDES`).
+    pub enable_strides: bool,
+
+    /// Also test offsets pointing past their field (e.g. to a directory at the end of the input) by moving the
+    /// pointed-to data, for targets which only dereference them lazily (see `find_offsets`).
+    pub enable_offsets: bool,
+
+    /// Only consider these coverage map indices (e.g. the edges of a targeted component) when measuring loss and
+    /// recovery.
+    pub focus_edges: Option<HashSet<usize>>,
+
+    /// Stop the search after this many probes, keeping the relations found so far.
+    pub max_tests: Option<usize>,
+
+    /// After the relations, look for constant bytes (e.g. magic signatures), with one probe per byte.
+    pub detect_constants: bool,
+
+    /// After the relations, mark the NUL-terminated and length-prefixed strings of the input (without probes).
+    pub detect_strings: bool,
+
+    /// Skip candidate fields which are unlikely to be lengths without probing them: fields inside high-entropy data
+    /// or known strings, and values larger than the input.
+    pub prefilter: bool,
+
+    /// Order in which the candidate fields of a pass are tested.
+    pub strategy: Arc<dyn SearchStrategy>,
+
+    /// Also probe relations by shrinking their region: an insertion point is only accepted if removing bytes before
+    /// it (and decrementing the field) keeps the coverage, and fields whose larger values are tolerated by the target
+    /// are tested with a smaller value instead.
+    pub shrink_probes: bool,
+
+    /// Once this many multi-byte relations were found and most of them agree on an endianness, only candidate fields
+    /// of that endianness are tested (0 disables).
+    pub endian_vote: usize,
+
+    /// Relations known in advance, added to the input before searching (see `hints`).
+    pub hints: Vec<Hint>,
+
+    /// Runs of each probe (and of the seed) for targets with nondeterministic coverage: a probe covers the focus
+    /// indices covered by a majority of its runs (all of them with 2 runs), and the indices whose coverage changes
+    /// across the runs of the seed are left out of the focus (1 runs each probe once).
+    pub stability_runs: usize,
+
+    /// Inputs shorter than this are searched exhaustively: every position is tried as an anchor, along with several
+    /// shift amounts (0 disables).
+    pub exhaustive_below: usize,
+
+    /// Also try regions which extend past the length by a constant (the field size or a header of up to 16 bytes),
+    /// for formats storing the length of a payload rather than of the whole region (see `Relation::bias`).
+    pub header_bias: bool,
+
+    /// When no anchor matches, binary-search the insertion points of the field using the recovered coverage as the
+    /// signal, with `O(log len)` probes per field (see `bisect_anchor`).
+    pub bisect_anchors: bool,
+
+    /// Minimum time between two reports of a running search to its status callback (see
+    /// `SearchContext::search_reporting`).
+    pub status_interval: Duration,
+
+    /// Also measure the loss and recovery of coverage on the value profile of the comparisons (see
+    /// `CoverageSnapshot::with_value_profile`), for fields whose corruption reaches the same edges with wrong values.
+    pub use_value_profile: bool,
+
+    // Thresholds.
+    pub loss_threshold: f64,
+    pub recover_threshold: f64,
+}
+
+impl SearchOptions {
+    pub fn verbose() -> Self {
+        Self {
+            verbose: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            extra_verbose: false,
+            max_iters: 10,
+            cmplog_only: false,
+            detect_fixed_size: true,
+            fill_strategy: vec![],
+            enable_bitfields: false,
+            enable_ascii: false,
+            enable_strides: false,
+            enable_offsets: false,
+            focus_edges: None,
+            max_tests: None,
+            detect_constants: false,
+            detect_strings: false,
+            prefilter: false,
+            strategy: Arc::new(CmplogStrategy),
+            shrink_probes: false,
+            endian_vote: 0,
+            exhaustive_below: 0,
+            header_bias: false,
+            bisect_anchors: false,
+            status_interval: Duration::from_secs(1),
+            use_value_profile: false,
+            stability_runs: 1,
+            hints: vec![],
+            prune_threshold: 0.0,
+            loss_threshold: 0.05,
+            recover_threshold: 0.2,
+        }
+    }
+}
+
+/// Extra per-testcase information used to guide the search.
+#[derive(Debug, Clone, Default)]
+pub struct SearchGuidance {
+    /// Operands observed in comparisons while executing the testcase (e.g. from CmpLog).
+    pub cmp_values: HashSet<u64>,
+
+    /// Restrict the candidate field positions to this range.
+    pub positions: Option<Range<usize>>,
+
+    /// How gaps opened by insertion probes are filled.
+    pub filler: Filler,
+
+    /// Resume an interrupted search from this point (the input must hold the relations found before it).
+    pub resume: Option<SearchProgress>,
+
+    /// Receives the progress of the search and its relations so far before each candidate field.
+    pub checkpoint: Option<SearchCheckpoint>,
+
+    /// Once set (e.g. by the oracle, when too many probes timed out), the search runs no more probes and finishes with
+    /// the relations found so far.
+    pub stop: Option<SearchStop>,
+}
+
+/// Point reached by a search, from which an interrupted search can resume.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchProgress {
+    /// Pass over the input (starting at 1).
+    pub iteration: usize,
+    /// Index (in the candidate order of the pass) of the first candidate field which was not started.
+    pub next: usize,
+    /// Byte offset of that candidate (the input length past the last one), to check that the order is unchanged.
+    pub offset: usize,
+}
+
+/// Snapshot of a running search, reported periodically to a status callback (see `SearchContext::search_reporting`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchStatus {
+    /// Pass over the input (starting at 1).
+    pub iteration: usize,
+    /// Index of the candidate field being tested (in the candidate order of the pass), and number of candidates.
+    pub candidate: usize,
+    pub candidates: usize,
+    /// Relations of the input so far, including those it had before the search.
+    pub relations: usize,
+    /// Probes run so far.
+    pub probes: usize,
+}
+
+impl SearchStatus {
+    /// Fraction of the candidate fields of the current pass which were tested.
+    pub fn scanned(&self) -> f64 {
+        self.candidate as f64 / self.candidates.max(1) as f64
+    }
+}
+
+/// Callback receiving the status of a running search.
+pub type SearchStatusFn<'a> = dyn FnMut(&SearchStatus) + 'a;
+
+/// Latest progress of a running search along with its relations at that point.
+pub type SearchCheckpoint = Rc<RefCell<Option<(SearchProgress, Vec<Relation>)>>>;
+
+/// Flag ending a running search (see `SearchGuidance::stop`).
+pub type SearchStop = Rc<Cell<bool>>;
+
+/// Probe budget spent by a search heuristic, and the number of relations it produced.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeuristicStats {
+    pub tests: usize,
+    pub found: usize,
+}
+
+/// Adds the statistics of `other` to `stats`.
+pub fn merge_heuristic_stats(stats: &mut BTreeMap<Heuristic, HeuristicStats>, other: &BTreeMap<Heuristic, HeuristicStats>) {
+    for (heuristic, other) in other.iter() {
+        let entry = stats.entry(*heuristic).or_default();
+        entry.tests += other.tests;
+        entry.found += other.found;
+    }
+}
+
+/// Coverage of a single execution: the indices of the non-zero entries of its coverage map, in increasing order.
+///
+/// Oracles hand out an owned snapshot rather than the map itself, so that nothing refers to the map of the target
+/// once the next execution overwrites it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageSnapshot {
+    hits: Vec<usize>,
+    /// Features of the value profile map (see `value_features`), if the oracle collects one.
+    value_profile: Vec<usize>,
+}
+
+impl CoverageSnapshot {
+    /// Takes the covered indices of `map`.
+    pub fn from_map(map: &[u8]) -> Self {
+        Self { hits: nonzero_indices(map), value_profile: vec![] }
+    }
+
+    /// Adds the value profile of the execution: a map with an entry per comparison site holding the (bucketed)
+    /// number of matching bits of its operands (like `-use_value_profile` of libFuzzer), which only counts with
+    /// `SearchOptions::use_value_profile`.
+    pub fn with_value_profile(mut self, map: &[u8]) -> Self {
+        self.value_profile = value_features(map);
+        self
+    }
+
+    /// The covered indices, in increasing order.
+    pub fn hits(&self) -> &[usize] {
+        &self.hits
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        self.hits.binary_search(&idx).is_ok()
+    }
+
+    /// The features of the value profile map, in increasing order.
+    pub fn value_profile(&self) -> &[usize] {
+        &self.value_profile
+    }
+}
+
+fn nonzero_indices(map: &[u8]) -> Vec<usize> {
+    map.iter().enumerate().filter(|(_, v)| **v != 0).map(|(idx, _)| idx).collect()
+}
+
+/// The executed comparison sites of a value profile map along with their value, as features `idx << 8 | value`.
+///
+/// A site whose operands differ still has a non-zero entry, so the value is part of the feature: a probe comparing
+/// other operands at the same site loses the feature of the seed.
+pub fn value_features(map: &[u8]) -> Vec<usize> {
+    map.iter().enumerate().filter(|(_, v)| **v != 0).map(|(idx, v)| (idx << 8) | *v as usize).collect()
+}
+
+impl From<&[u8]> for CoverageSnapshot {
+    fn from(map: &[u8]) -> Self {
+        Self::from_map(map)
+    }
+}
+
+impl From<Vec<u8>> for CoverageSnapshot {
+    fn from(map: Vec<u8>) -> Self {
+        Self::from_map(&map)
+    }
+}
+
+/// Coverage of the focus indices of a search, one bit per focus index (bit `k` stands for `focus_indices[k]`, followed
+/// by the focus features of the value profile).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FocusCoverage {
+    words: Vec<u64>,
+}
+
+impl FocusCoverage {
+    fn from_snapshot(cov: &CoverageSnapshot, focus_indices: &[usize], focus_features: &[usize]) -> Self {
+        let mut words = vec![0u64; (focus_indices.len() + focus_features.len()).div_ceil(64)];
+        let hits = focus_indices.iter().map(|idx| cov.contains(*idx));
+        let features = focus_features.iter().map(|idx| cov.value_profile().binary_search(idx).is_ok());
+        for (k, hit) in hits.chain(features).enumerate() {
+            if hit {
+                words[k / 64] |= 1 << (k % 64);
+            }
+        }
+        Self { words }
+    }
+
+    /// Number of covered focus indices.
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Number of focus indices covered by both.
+    pub fn count_common(&self, other: &FocusCoverage) -> usize {
+        self.words.iter().zip(other.words.iter()).map(|(a, b)| (a & b).count_ones() as usize).sum()
+    }
+
+    /// The focus indices (out of `len`) which are not covered.
+    pub fn missing(&self, len: usize) -> FocusCoverage {
+        let mut words = self.words.iter().map(|w| !w).collect::<Vec<_>>();
+        if let (Some(last), rem @ 1..) = (words.last_mut(), len % 64) {
+            *last &= (1 << rem) - 1;
+        }
+        Self { words }
+    }
+
+    /// The focus indices (out of `len`) covered by more than half of `runs`.
+    fn majority(runs: &[FocusCoverage], len: usize) -> Self {
+        let mut words = vec![0u64; len.div_ceil(64)];
+        for k in 0..len {
+            let votes = runs.iter().filter(|run| run.words[k / 64] & (1 << (k % 64)) != 0).count();
+            if 2 * votes > runs.len() {
+                words[k / 64] |= 1 << (k % 64);
+            }
+        }
+        Self { words }
+    }
+}
+
+/// Runs probes and converts their coverage into a `FocusCoverage` once, so that measuring loss and recovery is a
+/// popcount over a few words rather than a lookup of each focus index.
+struct CoverageOracle<'o,O> {
+    oracle: &'o mut O,
+    focus_indices: Vec<usize>,
+    focus_features: Vec<usize>,
+    /// Runs of each probe, whose coverage is the majority vote (see `SearchOptions::stability_runs`).
+    runs: usize,
+}
+
+impl<'o,O> CoverageOracle<'o,O>
+where
+    O: FnMut(&[u8]) -> CoverageSnapshot,
+{
+    fn test(&mut self, data: &[u8]) -> FocusCoverage {
+        let runs = (0..self.runs.max(1))
+            .map(|_| FocusCoverage::from_snapshot(&(self.oracle)(data), &self.focus_indices, &self.focus_features))
+            .collect::<Vec<_>>();
+        match runs.len() {
+            1 => runs.into_iter().next().unwrap(),
+            _ => FocusCoverage::majority(&runs, self.len()),
+        }
+    }
+
+    /// Number of focus indices and features.
+    fn len(&self) -> usize {
+        self.focus_indices.len() + self.focus_features.len()
+    }
+}
+
+/// Coverage of the latest probes of a search, the least recently used first.
+#[derive(Default)]
+struct ProbeCache {
+    entries: VecDeque<(u64, Vec<u8>, FocusCoverage)>,
+}
+
+impl ProbeCache {
+    fn hash(data: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&mut self, hash: u64, data: &[u8]) -> Option<FocusCoverage> {
+        let k = self.entries.iter().position(|(h, probe, _)| *h == hash && probe == data)?;
+        let entry = self.entries.remove(k).unwrap();
+        let cov = entry.2.clone();
+        self.entries.push_back(entry);
+        Some(cov)
+    }
+
+    fn insert(&mut self, hash: u64, data: &[u8], cov: FocusCoverage) {
+        if self.entries.len() == PROBE_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((hash, data.to_vec(), cov));
+    }
+}
+
+/// State shared by the probes testing the anchors of a candidate field: the input (whose relations are updated along
+/// with each probe), the seed and the buffer the probes are written to, the anchors already tried, and the corruption
+/// of the field.
+struct FieldProbe<'p> {
+    input: &'p mut Structured,
+    test_buffer: &'p mut Vec<u8>,
+    seed_data: &'p [u8],
+    anchor_visited_cache: &'p mut [u8],
+    /// Units added to the value of the field by the corruption, and the coverage it loses.
+    shift_amount: usize,
+    lost_indices: FocusCoverage,
+    /// Fraction of `lost_indices` an insertion must recover, raised to the best recovery so far.
+    curr_recover: f64,
+}
+
+pub struct SearchContext<'o,O> {
+    oracle: RefCell<CoverageOracle<'o,O>>,
+    pub options: SearchOptions,
+    pub guidance: SearchGuidance,
+    pub focus_indices: Vec<usize>,
+    /// Value profile features of the seed which the empty input does not reach, measured along with the focus indices
+    /// (see `SearchOptions::use_value_profile`).
+    pub focus_features: Vec<usize>,
+    /// Indices whose coverage changed across the runs of the seed, left out of the focus.
+    pub unstable_indices: Vec<usize>,
+    pub loss_threshold: usize,
+    /// If set, probes keep the input length constant (inserted bytes are truncated from the end).
+    pub fixed_size: bool,
+    /// The input is small enough for an exhaustive search (see `SearchOptions::exhaustive_below`).
+    pub exhaustive: bool,
+    pub test_count: RefCell<usize>,
+    pub target_test_ms: RefCell<u64>,
+    /// Probes answered by `probe_cache` instead of running the target (not counted in `test_count`).
+    pub cache_hits: RefCell<usize>,
+    probe_cache: RefCell<ProbeCache>,
+    /// Candidate fields which lost coverage when corrupted, and those for which an insertion recovered it.
+    pub filler_trials: RefCell<usize>,
+    pub filler_successes: RefCell<usize>,
+    /// Tests and relations of each heuristic.
+    pub heuristics: RefCell<BTreeMap<Heuristic, HeuristicStats>>,
+    /// The candidate field being tested was prioritized by cmplog.
+    cmplog_candidate: RefCell<bool>,
+    /// Where the search is.
+    progress: RefCell<SearchProgress>,
+    /// Endianness of the format (little-endian if true), once settled by the relations found (see `endian_vote`).
+    pub endianness: RefCell<Option<bool>>,
+    /// Receives the status of the search, along with the time of its latest report.
+    on_status: Option<RefCell<&'o mut SearchStatusFn<'o>>>,
+    status: RefCell<(SearchStatus, Instant)>,
+}
+
+pub struct SearchResult {
+    pub input: Structured,
+    pub test_count: usize,
+    pub target_test_ms: u64,
+    pub total_test_ms: u64,
+    /// Probes answered from the cache of the latest probes, without running the target.
+    pub cache_hits: usize,
+    pub found_any: bool,
+    pub focus_indices: Vec<usize>,
+    pub focus_features: Vec<usize>,
+    pub unstable_indices: Vec<usize>,
+    /// Outcome of the filler used by the search (see `FillerBandit`).
+    pub filler_trials: usize,
+    pub filler_successes: usize,
+    /// Probe budget and results of each heuristic.
+    pub heuristics: BTreeMap<Heuristic, HeuristicStats>,
+    /// Whether the search ran out of probes (`max_tests`) before finishing.
+    pub truncated: bool,
+    /// Where a truncated search stopped (see `SearchGuidance::resume`).
+    pub progress: Option<SearchProgress>,
+}
+
+/// The oracle returns the coverage of each probe as an owned `CoverageSnapshot`, which stays valid across probes.
+impl<'o,O> SearchContext<'o,O>
+where
+    O: FnMut(&[u8]) -> CoverageSnapshot,
+{
+    pub fn new(testcase: &Structured, oracle: &'o mut O, options: SearchOptions) -> Self {
+        // What coverage does the current test case get?
+        let seed_cov = oracle(testcase.get_raw());
+        let seed_indices = seed_cov.hits().to_vec();
+
+        // Indices whose coverage changes when running the seed again are noise (like the var_bytes of AFL).
+        let seed_hit = seed_indices.iter().copied().collect::<HashSet<_>>();
+        let seed_features = seed_cov.value_profile().iter().copied().collect::<HashSet<_>>();
+        let mut unstable_indices = BTreeSet::new();
+        let mut unstable_features = HashSet::new();
+        for _ in 1..options.stability_runs {
+            let cov = oracle(testcase.get_raw());
+            let hit = cov.hits().iter().copied().collect::<HashSet<_>>();
+            unstable_indices.extend(seed_hit.symmetric_difference(&hit));
+            let features = cov.value_profile().iter().copied().collect::<HashSet<_>>();
+            unstable_features.extend(seed_features.symmetric_difference(&features).copied());
+        }
+
+        // What coverage does an empty test case get (i.e. max loss)?
+        let base_cov = oracle(&[]);
+
+        // Pick out the interesting indices (found by current test case, but not by base case), restricted to the
+        // targeted edges if any.
+        let targeted = |idx: &usize| match &options.focus_edges {
+            Some(edges) => edges.contains(idx),
+            None => true,
+        };
+        let mut focus_indices = Vec::with_capacity(seed_indices.len());
+        for idx in seed_indices.iter() {
+            if !base_cov.contains(*idx) && targeted(idx) && !unstable_indices.contains(idx) {
+                focus_indices.push(*idx);
+            }
+        }
+
+        // The value profile separates probes reaching the same edges with different comparison operands.
+        let focus_features = match options.use_value_profile {
+            true => seed_cov.value_profile().iter().copied()
+                .filter(|idx| base_cov.value_profile().binary_search(idx).is_err() && !unstable_features.contains(idx))
+                .collect::<Vec<_>>(),
+            false => vec![],
+        };
+
+        if options.extra_verbose {
+            println!("seed_indices: {:?}", seed_indices);
+            println!("focus_indices: {:?}", focus_indices);
+            println!("unstable_indices: {:?}", unstable_indices);
+            if options.use_value_profile {
+                println!("focus_features: {:?}", focus_features);
+            }
+        }
+
+        // theta_0 = 5% of the losable coverage (at least 1 feature)
+        let loss_threshold = ((options.loss_threshold * (focus_indices.len() + focus_features.len()) as f64).ceil() as usize).max(1);
+
+        let mut oracle = CoverageOracle {
+            oracle,
+            focus_indices: focus_indices.clone(),
+            focus_features: focus_features.clone(),
+            runs: options.stability_runs,
+        };
+
+        let fixed_size = options.detect_fixed_size && Self::probe_fixed_size(testcase.get_raw(), &mut oracle);
+        let exhaustive = testcase.get_raw().len() < options.exhaustive_below;
+
+        Self {
+            oracle: RefCell::new(oracle),
+            options,
+            guidance: SearchGuidance::default(),
+            focus_indices,
+            focus_features,
+            unstable_indices: unstable_indices.into_iter().collect(),
+            loss_threshold,
+            fixed_size,
+            exhaustive,
+            test_count: RefCell::new(0),
+            target_test_ms: RefCell::new(0),
+            cache_hits: RefCell::new(0),
+            probe_cache: RefCell::new(ProbeCache::default()),
+            filler_trials: RefCell::new(0),
+            filler_successes: RefCell::new(0),
+            heuristics: RefCell::new(BTreeMap::new()),
+            cmplog_candidate: RefCell::new(false),
+            progress: RefCell::new(SearchProgress::default()),
+            endianness: RefCell::new(None),
+            on_status: None,
+            status: RefCell::new((SearchStatus::default(), Instant::now())),
+        }
+    }
+
+    pub fn search(testcase: &Structured, oracle: &'o mut O, options: SearchOptions) -> SearchResult {
+        Self::search_guided(testcase, oracle, options, &SearchGuidance::default())
+    }
+
+    pub fn search_guided(testcase: &Structured, oracle: &'o mut O, options: SearchOptions, guidance: &SearchGuidance) -> SearchResult {
+        Self::search_reporting(testcase, oracle, options, guidance, None)
+    }
+
+    /// Like `search_guided`, reporting the status of the search to `on_status` between probes, at most once per
+    /// `SearchOptions::status_interval` (e.g. to show the progress of long searches in a monitor).
+    pub fn search_reporting(testcase: &Structured, oracle: &'o mut O, options: SearchOptions, guidance: &SearchGuidance, on_status: Option<&'o mut SearchStatusFn<'o>>) -> SearchResult {
+        let mut search = Self::new(testcase, oracle, options);
+        search.guidance = guidance.clone();
+        search.on_status = on_status.map(RefCell::new);
+        
+        let mut input = testcase.clone();
+
+        search.log(&format!("Starting search: {:?}", input));
+
+        let hinted = apply_hints(&mut input, &search.options.hints);
+        if hinted > 0 {
+            search.log(&format!("Added {} relations from hints", hinted));
+        }
+
+        if search.fixed_size {
+            search.log("Input appears to be fixed-size, only searching for internal relations");
+        }
+        if search.exhaustive {
+            search.log("Input is small, searching exhaustively");
+        }
+        input.fixed_size = search.fixed_size;
+
+        let start = std::time::Instant::now();
+
+        search.find_relations(&mut input);
+
+        if search.options.detect_constants && !search.exhausted() {
+            search.find_constants(&mut input);
+        }
+        if search.options.detect_strings {
+            input.detect_strings(MIN_STRING_LEN);
+        }
+
+        let total_test_ms = start.elapsed().as_millis() as u64;
+        
+        let test_count = *search.test_count.borrow();
+        let target_test_ms = *search.target_test_ms.borrow();
+        let cache_hits = *search.cache_hits.borrow();
+        let filler_trials = *search.filler_trials.borrow();
+        let filler_successes = *search.filler_successes.borrow();
+
+        let found_any = !input.relations.is_empty();
+        // A stopped search is finished rather than truncated: it does not resume.
+        let truncated = search.exhausted() && !search.stopped();
+        let progress = if truncated { Some(search.progress.borrow().clone()) } else { None };
+
+        SearchResult {
+            input,
+            test_count,
+            target_test_ms,
+            total_test_ms,
+            cache_hits,
+            found_any,
+            focus_indices: search.focus_indices,
+            focus_features: search.focus_features,
+            unstable_indices: search.unstable_indices,
+            filler_trials,
+            filler_successes,
+            heuristics: search.heuristics.into_inner(),
+            truncated,
+            progress,
+        }
+    }
+
+    /// Checks whether both growing and shrinking the input by a single byte collapses the focus coverage.
+    fn probe_fixed_size(raw: &[u8], oracle: &mut CoverageOracle<'o,O>) -> bool {
+        if raw.is_empty() || oracle.len() == 0 || holds_own_size(raw) {
+            return false;
+        }
+
+        let max_retained = ((1.0 - FIXED_SIZE_COLLAPSE) * oracle.len() as f64) as usize;
+
+        let mut grown = raw.to_vec();
+        grown.push(0);
+        if oracle.test(&grown).count() > max_retained {
+            return false;
+        }
+
+        oracle.test(&raw[..raw.len() - 1]).count() <= max_retained
+    }
+
+    /// Performs multiple-passes over the input searching for relations.
+    /// 
+    /// Invokes `find_relations_inner` in a loop until no more relations are found or the max number of iterations is reached.
+    /// 
+    /// Returns true if any relations were found.
+    fn find_relations(&self, input: &mut Structured) {
+        self.log("Starting search...");
+
+        let start = std::time::Instant::now();
+
+        let mut resume = self.guidance.resume.clone();
+        let mut iter = resume.as_ref().map_or(0, |p| p.iteration.max(1) - 1);
+        while iter < self.options.max_iters {
+            iter += 1;
+            self.log(&format!("Iteration {}", iter));
+
+            let found = self.find_relations_inner(input, iter, resume.take());
+            if !found {
+                // Exit if no relations were found this iteration.
+                break;
+            }
+            if self.exhausted() {
+                self.log("Probe budget exhausted");
+                break;
+            }
+        }
+
+        let elapsed = start.elapsed().as_millis() as u64;
+
+        self.log(&format!("Search completed (total: {} ms) (target: {} ms)", elapsed, *self.target_test_ms.borrow()));
+    }
+
+    /// Performs a single-pass over the input searching for relations, starting from `resume` if it matches the
+    /// candidate order of this pass.
+    /// 
+    /// Returns true if any relations were found.
+    fn find_relations_inner(&self, input: &mut Structured, iteration: usize, resume: Option<SearchProgress>) -> bool {
+        self.status.borrow_mut().0 = SearchStatus { iteration, relations: input.relations.len(), ..Default::default() };
+
+        // A resumed pass was already revalidated when it started.
+        if self.options.prune_threshold > 0.0 && resume.is_none() {
+            self.revalidate(input);
+        }
+
+        // Efficiency
+        input.raw.reserve(0x100);
+        let mut anchor_visited_cache: Vec<u8> = vec![0; input.raw.len()];
+        let mut test_buffer = input.get_raw().to_vec();
+        test_buffer.reserve(0x100);
+
+        let mut blocked_points = vec![0; input.raw.len()];
+        for rel in input.relations.iter() {
+            blocked_points[rel.pos..rel.pos + rel.size].fill(1);
+        }
+        for offset in input.offsets.iter() {
+            blocked_points[offset.pos..offset.pos + offset.size].fill(1);
+        }
+
+        let mut found = false;
+
+        let seed_data = input.get_raw().to_vec();
+
+        self.vote_endianness(input);
+
+        let mut inflection_points = input.inflection_points();
+
+        // Coverage lost by each corruption of the seed, keyed by the bytes it actually modifies: wider fields at the
+        // same offset often only modify the same low bytes (e.g. 8-byte and 4-byte little-endian fields).
+        let mut loss_cache: HashMap<(usize, Vec<u8>), FocusCoverage> = HashMap::new();
+
+        let rel_types = vec![
+            (8, true), (8, false),
+            (4, true), (4, false),
+            (3, true), (3, false),
+            (2, true), (2, false),
+            (1, true),
+        ];
+
+        // Iterate over field placement, in the order of the search strategy.
+        let candidates = self.candidate_order(input, &seed_data, &rel_types);
+        let offset_of = |k: usize| candidates.get(k).map_or(seed_data.len(), |c| c.0);
+
+        let skip = match resume {
+            Some(p) if p.next <= candidates.len() && offset_of(p.next) == p.offset => p.next,
+            _ => 0,
+        };
+        // The relations found earlier in a resumed pass count for it.
+        found |= skip > 0 && !input.relations.is_empty();
+
+        'inner: for (k, (i, size, le)) in candidates.iter().map(|(i, size, le)| (*i, size, le)).enumerate().skip(skip) {
+            // Record the progress first, so that a search killed by this candidate resumes after it.
+            *self.progress.borrow_mut() = SearchProgress { iteration, next: k, offset: i };
+            if self.exhausted() {
+                break 'inner;
+            }
+            *self.progress.borrow_mut() = SearchProgress { iteration, next: k + 1, offset: offset_of(k + 1) };
+            if let Some(checkpoint) = &self.guidance.checkpoint {
+                *checkpoint.borrow_mut() = Some((self.progress.borrow().clone(), input.relations.clone()));
+            }
+            {
+                let status = &mut self.status.borrow_mut().0;
+                (status.iteration, status.candidate, status.candidates) = (iteration, k, candidates.len());
+                status.relations = input.relations.len();
+            }
+
+            let curr_size = read_field(&seed_data, i, *size, *le);
+            let cmplog = self.guidance.cmp_values.contains(&(curr_size as u64));
+            *self.cmplog_candidate.borrow_mut() = cmplog;
+    
+            // Does this look like a size/offset field?
+            if curr_size == 0 || curr_size > seed_data.len() {
+                continue 'inner;
+            }
+
+            // Check if the field is blocked.
+            for k in 0..*size {
+                if blocked_points[i+k] != 0 {
+                    continue 'inner;
+                }
+            }
+
+            // Skip the endianness the format does not use.
+            if *size > 1 && self.endianness.borrow().is_some_and(|endianness| endianness != *le) {
+                continue 'inner;
+            }
+
+            let mut potential = Relation {
+                pos: i,
+                value: curr_size as u64,
+                size: *size,
+                le: *le,
+                anchor: usize::MAX,
+                insert: usize::MAX,
+                enabled: true,
+                mirrors: vec![],
+                group: None,
+                bits: None,
+                stride: 1,
+                whole_file: false,
+                bias: 0,
+                ascii: false,
+                heuristics: if cmplog { vec![Heuristic::Cmplog] } else { vec![] },
+                confirmed: 0,
+                refuted: 0,
+                old_pos: 0,
+                old_anchor: 0,
+                old_insert: 0,
+                old_value: 0,
+                old_mirrors: vec![],
+            };
+
+            // Backup current state.
+            input.save_relations();
+
+            // Corrupt the field and measure lost features, then iterate over inflection points and try to find a
+            // suitable anchor/insertion, trying lengths counted in larger units only if plain byte lengths don't
+            // recover. Exhaustive searches fall back to smaller corruptions.
+            let mut found_shift = None;
+            for shift_amount in self.shift_amounts(*size, curr_size) {
+                potential.value = (curr_size as u64) + (shift_amount as u64);
+                potential.apply(&mut test_buffer);
+
+                let modified = changed_window(&seed_data[i..i+size], &test_buffer[i..i+size], 0);
+                let key = (i + modified.start, test_buffer[i + modified.start..i + modified.end].to_vec());
+                let lost_indices = match loss_cache.get(&key) {
+                    Some(lost) => lost.clone(),
+                    None => {
+                        let lost = self.test(&test_buffer).missing(self.focus_len());
+                        loss_cache.insert(key, lost.clone());
+                        lost
+                    }
+                };
+
+                if self.options.extra_verbose {
+                    println!("Testing relation (size={}, le={}, pos={}, value={}, shift={})", size, le, i, curr_size, shift_amount);
+                    self.print_buffer(&test_buffer);
+                    println!("lost: {:?} -- thresh: {:?}", lost_indices.count(), self.loss_threshold);
+                }
+
+                // Restore the original buffer.
+                test_buffer[i..i+size].copy_from_slice(&seed_data[i..i+size]);
+
+                if lost_indices.count() < self.loss_threshold {
+                    continue;
+                }
+
+                let mut probe = FieldProbe {
+                    input,
+                    test_buffer: &mut test_buffer,
+                    seed_data: &seed_data,
+                    anchor_visited_cache: &mut anchor_visited_cache,
+                    shift_amount,
+                    lost_indices,
+                    curr_recover: self.options.recover_threshold,
+                };
+
+                for stride in self.strides() {
+                    // The whole region must fit in the input.
+                    if curr_size * stride > seed_data.len() {
+                        break;
+                    }
+                    probe.anchor_visited_cache.fill(0);
+                    potential.stride = stride;
+                    self.find_anchor(&mut probe, &mut potential, &inflection_points);
+                    if potential.insert != usize::MAX {
+                        break;
+                    }
+                }
+
+                *self.filler_trials.borrow_mut() += 1;
+                if potential.insert != usize::MAX {
+                    found_shift = Some((shift_amount, probe.curr_recover, probe.lost_indices));
+                    break;
+                }
+            }
+
+            if found_shift.is_none() && self.options.shrink_probes && !self.fixed_size {
+                let tests = *self.test_count.borrow();
+                potential.value = curr_size as u64;
+                found_shift = self.find_shrink_anchor(input, &mut potential, &mut test_buffer, &seed_data, &mut anchor_visited_cache, &inflection_points)
+                    .map(|(curr_recover, lost_indices)| (0, curr_recover, lost_indices));
+                self.heuristics.borrow_mut().entry(Heuristic::Shrink).or_default().tests += *self.test_count.borrow() - tests;
+            }
+
+            let Some((shift_amount, curr_recover, lost_indices)) = found_shift else {
+                // No valid insertion point found.
+                continue 'inner;
+            };
+            *self.filler_successes.borrow_mut() += 1;
+
+            // Mirrors are probed with an insertion, which relations found by shrinking can't do.
+            if shift_amount > 0 {
+                let mut probe = FieldProbe {
+                    input,
+                    test_buffer: &mut test_buffer,
+                    seed_data: &seed_data,
+                    anchor_visited_cache: &mut anchor_visited_cache,
+                    shift_amount,
+                    lost_indices,
+                    curr_recover,
+                };
+                self.find_mirrors(&mut probe, &mut potential, &blocked_points);
+            }
+
+            // Reset and update the structure.
+            potential.value = curr_size as u64;
+            self.log_child("REL", &format!("found REL field at {} (size: {}, le: {}, anchor: {}, insert: {}, value: {})", i, size, le, potential.anchor, potential.insert, potential.value));
+            for heuristic in potential.heuristics.iter() {
+                self.heuristics.borrow_mut().entry(*heuristic).or_default().found += 1;
+            }
+            let mirrors = potential.mirrors.clone();
+            input.add_relation(potential);
+            self.vote_endianness(input);
+
+            // Update the field.
+            inflection_points = input.inflection_points();
+            
+            // Update the blocked points.
+            for start in std::iter::once(i).chain(mirrors) {
+                for k in 0..*size {
+                    blocked_points[start+k] = 1;
+                }
+            }
+
+            // Look for a table of offsets sharing the same anchor.
+            if *size >= 2 && !self.fixed_size && input.relations.last().unwrap().stride == 1 && !input.relations.last().unwrap().whole_file {
+                let members = self.find_table(input, input.relations.len() - 1, &seed_data, &blocked_points);
+                for pos in members.iter() {
+                    for k in 0..*size {
+                        blocked_points[pos+k] = 1;
+                    }
+                }
+                if !members.is_empty() {
+                    inflection_points = input.inflection_points();
+                }
+            }
+            
+            found = true;
+        }
+        *self.cmplog_candidate.borrow_mut() = false;
+
+        if self.options.enable_bitfields && !self.exhausted() {
+            let tests = *self.test_count.borrow();
+            found |= self.find_bitfields(input, &seed_data, &mut test_buffer, &mut anchor_visited_cache, &mut blocked_points);
+            self.heuristics.borrow_mut().entry(Heuristic::Bitfield).or_default().tests += *self.test_count.borrow() - tests;
+        }
+
+        if self.options.enable_ascii && !self.exhausted() {
+            let tests = *self.test_count.borrow();
+            found |= self.find_ascii_lengths(input, &seed_data, &mut test_buffer, &mut anchor_visited_cache, &mut blocked_points);
+            self.heuristics.borrow_mut().entry(Heuristic::AsciiLength).or_default().tests += *self.test_count.borrow() - tests;
+        }
+
+        if self.options.enable_offsets && !self.exhausted() {
+            let tests = *self.test_count.borrow();
+            found |= self.find_offsets(input, &seed_data, &mut blocked_points);
+            self.heuristics.borrow_mut().entry(Heuristic::Offset).or_default().tests += *self.test_count.borrow() - tests;
+        }
+
+        found
+    }
+
+    /// Corruptions tried on a candidate field of `size` bytes holding `value`, until one loses coverage.
+    fn shift_amounts(&self, size: usize, value: usize) -> Vec<usize> {
+        let shift_amount = if size == 1 {
+            let max_shift = 0xff - value;
+            if max_shift == 0 {
+                return vec![];
+            }
+            0x20.min(max_shift)
+        } else {
+            // Shift by 0xff so we overflow the first byte in most cases.
+            // This helps to differentiate between little and big endian.
+            0xff
+        };
+
+        let mut shifts = vec![shift_amount];
+        if self.exhaustive {
+            shifts.extend(EXHAUSTIVE_SHIFTS.iter().filter(|shift| **shift < shift_amount));
+        }
+        shifts
+    }
+
+    /// Tries the candidate anchors of `potential` (local ones first), keeping the insertion which recovers best.
+    ///
+    /// Exhaustive searches then try every other anchor, which replaces the first match only if it recovers more.
+    fn find_anchor(&self, probe: &mut FieldProbe, potential: &mut Relation, inflection_points: &HashSet<usize>) {
+        self.find_local_anchor(probe, potential, inflection_points);
+        if !self.exhaustive {
+            if potential.insert == usize::MAX && self.options.bisect_anchors {
+                self.bisect_anchor(probe, potential);
+            }
+            return;
+        }
+
+        let (anchor, insert, recover) = (potential.anchor, potential.insert, probe.curr_recover);
+        if insert != usize::MAX {
+            probe.curr_recover = recover + f64::EPSILON;
+        }
+        for anchor in 0..probe.seed_data.len() {
+            self.check_anchor(probe, anchor, potential);
+        }
+        if (potential.anchor, potential.insert) == (anchor, insert) {
+            probe.curr_recover = recover;
+        }
+        potential.whole_file &= potential.heuristics.contains(&Heuristic::WholeFile);
+    }
+
+    /// Tries the regular anchors: the local ones, then a length of the rest of the input, then the inflection points
+    /// and the header biases if none of them matched.
+    fn find_local_anchor(&self, probe: &mut FieldProbe, potential: &mut Relation, inflection_points: &HashSet<usize>) {
+        let (i, size) = (potential.pos, potential.size);
+        match size {
+            1 => {
+                self.check_anchor(probe, i+size, potential);
+                self.check_whole_file(probe, potential);
+            }
+            2 => {
+                self.check_anchor(probe, 0, potential);
+                self.check_anchor(probe, i, potential);
+                self.check_anchor(probe, i+size, potential);
+                self.check_whole_file(probe, potential);
+            }
+            _ => {
+                // Check local inflection points first.
+                self.check_anchor(probe, i+size+7, potential);
+                self.check_anchor(probe, i+size+6, potential);
+                self.check_anchor(probe, i+size+5, potential);
+                self.check_anchor(probe, i+size+4, potential);
+                self.check_anchor(probe, i+size+3, potential);
+                self.check_anchor(probe, i+size+2, potential);
+                self.check_anchor(probe, i+size+1, potential);
+                self.check_anchor(probe, 0, potential);
+                self.check_anchor(probe, i, potential);
+                self.check_anchor(probe, i+size, potential);
+                self.check_whole_file(probe, potential);
+            
+                // If we found a match here, bail early, otherwise search the rest of the inflection points.
+                if potential.insert == usize::MAX {
+                    for anchor in inflection_points.iter() {
+                        self.check_anchor(probe, *anchor, potential);
+                    }
+                }
+            }
+        }
+
+        if potential.insert == usize::MAX && self.options.header_bias {
+            self.check_biased_anchors(probe, potential);
+        }
+    }
+
+    /// Tries a length of the rest of the input (e.g. a RIFF size or a PE `SizeOfImage`) by appending at its end, from
+    /// wherever the region must start, if no anchor matched yet. Relations spanning the whole input from its start
+    /// are marked as whole-file lengths, which keep tracking the end of the input.
+    fn check_whole_file(&self, probe: &mut FieldProbe, potential: &mut Relation) {
+        if self.fixed_size {
+            return;
+        }
+
+        if potential.insert == usize::MAX {
+            let units = potential.value as usize - probe.shift_amount;
+            let Some(anchor) = probe.seed_data.len().checked_sub(units * potential.stride) else {
+                return;
+            };
+            self.check_anchor(probe, anchor, potential);
+            if potential.insert != usize::MAX {
+                potential.whole_file = true;
+                potential.heuristics.push(Heuristic::WholeFile);
+            }
+        } else if potential.anchor == 0 && potential.insert == probe.seed_data.len() {
+            potential.whole_file = true;
+            potential.heuristics.push(Heuristic::WholeFile);
+        }
+    }
+
+    /// Looks for lengths packed into some bits of a byte (e.g. the low nibble of a flags byte), which the regular
+    /// fields miss because corrupting the whole byte also changes the other bits.
+    ///
+    /// The corruption is scaled to the width of the field (1/8th of its range), and the region must follow the byte.
+    fn find_bitfields(&self, input: &mut Structured, seed_data: &[u8], test_buffer: &mut Vec<u8>, anchor_visited_cache: &mut [u8], blocked_points: &mut [u8]) -> bool {
+        let positions = match &self.guidance.positions {
+            Some(range) => range.start.min(seed_data.len())..range.end.min(seed_data.len()),
+            None => 0..seed_data.len(),
+        };
+
+        let mut found = false;
+        for i in positions {
+            for bits in BITFIELD_LAYOUTS {
+                if blocked_points[i] != 0 || self.exhausted() {
+                    break;
+                }
+
+                let curr = bits.read(seed_data[i]);
+                let max = bits.max_value();
+                if curr == 0 || curr == max || curr as usize > seed_data.len() {
+                    continue;
+                }
+                let shift_amount = ((max + 1) / 8).clamp(1, max - curr) as usize;
+
+                let mut potential = Relation::new(i, curr, 1, true, usize::MAX, usize::MAX);
+                potential.bits = Some(bits);
+
+                input.save_relations();
+
+                // Corrupt the bits and measure lost features.
+                potential.value = curr + shift_amount as u64;
+                potential.apply(test_buffer);
+                let lost_indices = self.test(test_buffer).missing(self.focus_len());
+                test_buffer[i] = seed_data[i];
+
+                if lost_indices.count() < self.loss_threshold {
+                    continue;
+                }
+
+                anchor_visited_cache.fill(0);
+                let mut probe = FieldProbe {
+                    input,
+                    test_buffer,
+                    seed_data,
+                    anchor_visited_cache,
+                    shift_amount,
+                    lost_indices,
+                    curr_recover: self.options.recover_threshold,
+                };
+                self.check_anchor(&mut probe, i + 1, &mut potential);
+                if potential.insert == usize::MAX {
+                    continue;
+                }
+
+                potential.value = curr;
+                potential.heuristics.push(Heuristic::Bitfield);
+                self.heuristics.borrow_mut().entry(Heuristic::Bitfield).or_default().found += 1;
+                self.log_child("REL", &format!("found bitfield REL at {} (bits: {}..{}, insert: {}, value: {})", i, bits.shift, bits.shift + bits.width, potential.insert, curr));
+
+                input.add_relation(potential);
+                blocked_points[i] = 1;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Tests the runs of ASCII digits (without leading zeros) as lengths written in decimal, such as an HTTP
+    /// `Content-Length` or the size of a chunk of a chunked encoding.
+    ///
+    /// The corruption keeps the number of digits, so that the field can be rewritten in place by the probes. The region
+    /// starts right after the digits (allowing for a line break) or after the next blank line (e.g. the body following
+    /// the headers).
+    fn find_ascii_lengths(&self, input: &mut Structured, seed_data: &[u8], test_buffer: &mut Vec<u8>, anchor_visited_cache: &mut [u8], blocked_points: &mut [u8]) -> bool {
+        let positions = match &self.guidance.positions {
+            Some(range) => range.start.min(seed_data.len())..range.end.min(seed_data.len()),
+            None => 0..seed_data.len(),
+        };
+
+        let mut found = false;
+        let mut i = positions.start;
+        while i < positions.end && !self.exhausted() {
+            let size = seed_data[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+            if size == 0 {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            i += size;
+
+            // Runs inside a longer number, with leading zeros (which the rewritten value would drop) or touching a
+            // known field are skipped.
+            if start > 0 && seed_data[start - 1].is_ascii_digit() || size > MAX_ASCII_DIGITS
+                || (size > 1 && seed_data[start] == b'0') || (start..start + size).any(|k| blocked_points[k] != 0) {
+                continue;
+            }
+
+            let Some(curr) = read_ascii_field(seed_data, start, size) else {
+                continue;
+            };
+            let max = 10usize.pow(size as u32) - 1;
+            if curr == max || curr > seed_data.len() {
+                continue;
+            }
+            let shift_amount = ((max - curr) / 2).max(1);
+
+            let mut potential = Relation::new(start, curr as u64, size, true, usize::MAX, usize::MAX);
+            potential.ascii = true;
+
+            input.save_relations();
+
+            // Corrupt the digits and measure lost features.
+            potential.value = (curr + shift_amount) as u64;
+            potential.apply(test_buffer);
+            let lost_indices = self.test(test_buffer).missing(self.focus_len());
+            test_buffer[start..start + size].copy_from_slice(&seed_data[start..start + size]);
+
+            if lost_indices.count() < self.loss_threshold {
+                continue;
+            }
+
+            let end = start + size;
+            let blank_line = [&b"\r\n\r\n"[..], b"\n\n"].iter()
+                .filter_map(|sep| seed_data[end..].windows(sep.len()).position(|w| w == *sep).map(|k| end + k + sep.len()))
+                .min();
+
+            anchor_visited_cache.fill(0);
+            let mut probe = FieldProbe {
+                input,
+                test_buffer,
+                seed_data,
+                anchor_visited_cache,
+                shift_amount,
+                lost_indices,
+                curr_recover: self.options.recover_threshold,
+            };
+            for anchor in [end, end + 1, end + 2].into_iter().chain(blank_line) {
+                self.check_anchor(&mut probe, anchor, &mut potential);
+            }
+            if potential.insert == usize::MAX {
+                continue;
+            }
+
+            potential.value = curr as u64;
+            potential.heuristics.push(Heuristic::AsciiLength);
+            self.heuristics.borrow_mut().entry(Heuristic::AsciiLength).or_default().found += 1;
+            self.log_child("REL", &format!("found ASCII REL at {} (digits: {}, anchor: {}, insert: {}, value: {})", start, size, potential.anchor, potential.insert, curr));
+
+            input.add_relation(potential);
+            blocked_points[start..end].fill(1);
+            found = true;
+        }
+        found
+    }
+
+    /// Looks for offset fields pointing past themselves (e.g. to the central directory of a ZIP archive), which targets
+    /// often only dereference lazily: corrupting such a field may lose no coverage (e.g. when the target then scans for
+    /// the data instead), so the regular probes never test it.
+    ///
+    /// Instead, the data at each candidate target (counted from the start of the input, the field or its end) is moved
+    /// wholesale by inserting `OFFSET_SHIFT` filler bytes right before it, with the known relations updated. If that
+    /// loses coverage and also patching the field recovers it, the field is added as an `OffsetRelation`.
+    fn find_offsets(&self, input: &mut Structured, seed_data: &[u8], blocked_points: &mut [u8]) -> bool {
+        if self.fixed_size {
+            return false;
+        }
+
+        let positions = match &self.guidance.positions {
+            Some(range) => range.start.min(seed_data.len())..range.end.min(seed_data.len()),
+            None => 0..seed_data.len(),
+        };
+
+        // Coverage lost by moving the data at each target (`None` if the known relations can't be updated).
+        let mut moved_loss: HashMap<usize, Option<FocusCoverage>> = HashMap::new();
+        let mut found = false;
+        for i in positions {
+            for (size, le) in OFFSET_TYPES {
+                if self.exhausted() {
+                    return found;
+                }
+                if i + size > seed_data.len() || blocked_points[i..i + size].iter().any(|b| *b != 0)
+                    || self.endianness.borrow().is_some_and(|endianness| endianness != le) {
+                    continue;
+                }
+
+                let value = read_field(seed_data, i, size, le);
+                let mut anchors = vec![0, i, i + size];
+                anchors.dedup();
+                for anchor in anchors {
+                    let Some(target) = anchor.checked_add(value).filter(|t| value > 0 && i + size < *t && *t < seed_data.len()) else {
+                        continue;
+                    };
+
+                    let mut filler = vec![0; OFFSET_SHIFT];
+                    self.guidance.filler.fill(&mut filler, &seed_data[..target]);
+
+                    let lost = moved_loss.entry(target).or_insert_with(|| {
+                        let mut moved = input.clone();
+                        moved.insert(target, &filler).ok()?;
+                        Some(self.test(moved.get_raw()).missing(self.focus_len()))
+                    });
+                    let Some(lost) = lost.as_ref().filter(|lost| lost.count() >= self.loss_threshold) else {
+                        continue;
+                    };
+
+                    let mut offset = OffsetRelation::new(i, size, le, anchor, target);
+                    let mut patched = input.clone();
+                    patched.offsets.push(offset.clone());
+                    if patched.insert(target, &filler).is_err() {
+                        continue;
+                    }
+                    let recovered = self.test(patched.get_raw()).count_common(lost) as f64 / lost.count() as f64;
+
+                    if self.options.extra_verbose {
+                        println!("Offset at {} (size={}, le={}, anchor={}, target={}): lost {}, recovered {}", i, size, le, anchor, target, lost.count(), recovered);
+                    }
+                    if recovered < self.options.recover_threshold {
+                        continue;
+                    }
+
+                    offset.heuristics.push(Heuristic::Offset);
+                    self.heuristics.borrow_mut().entry(Heuristic::Offset).or_default().found += 1;
+                    self.log_child("REL", &format!("found offset at {} (size: {}, le: {}, anchor: {}, pointing to: {})", i, size, le, anchor, target));
+
+                    input.offsets.push(offset);
+                    blocked_points[i..i + size].fill(1);
+                    // The data moves differently now.
+                    moved_loss.clear();
+                    found = true;
+                    break;
+                }
+            }
+        }
+        found
+    }
+
+    /// Looks for bytes the target checks for an exact value (e.g. a magic signature): flipping any of them loses nearly
+    /// all of the focus coverage, while they are not the field of a relation (whose loss an insertion recovers).
+    ///
+    /// Runs of such bytes are recorded as the constants of the input, replacing the ones in the scanned positions.
+    fn find_constants(&self, input: &mut Structured) {
+        if self.focus_len() == 0 {
+            return;
+        }
+
+        let seed_data = input.get_raw().to_vec();
+        let positions = match &self.guidance.positions {
+            Some(range) => range.start.min(seed_data.len())..range.end.min(seed_data.len()),
+            None => 0..seed_data.len(),
+        };
+        let fields = input.relations.iter()
+            .filter(|rel| rel.enabled)
+            .flat_map(|rel| rel.field_positions().flat_map(move |pos| pos..pos + rel.size))
+            .collect::<HashSet<_>>();
+        let max_retained = ((1.0 - CONSTANT_COLLAPSE) * self.focus_len() as f64) as usize;
+
+        input.constants.retain(|c| c.range().end <= positions.start || positions.end <= c.pos);
+
+        let mut test_buffer = seed_data.clone();
+        let mut runs = vec![];
+        let mut start = None;
+        for i in positions.clone() {
+            let constant = !fields.contains(&i) && !self.exhausted() && {
+                test_buffer[i] ^= 0xff;
+                let retained = self.test(&test_buffer).count();
+                test_buffer[i] = seed_data[i];
+                retained <= max_retained
+            };
+
+            match (constant, start) {
+                (true, None) => start = Some(i),
+                (false, Some(s)) => {
+                    runs.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        runs.extend(start.map(|s| s..positions.end));
+
+        for run in runs {
+            self.log_child("CONST", &format!("found constant at {} ({} bytes)", run.start, run.len()));
+            input.constants.push(Constant { pos: run.start, bytes: seed_data[run].to_vec() });
+        }
+        input.constants.sort_by_key(|c| c.pos);
+    }
+
+    /// Corrupts each existing relation again and checks that it still loses coverage, removing relations whose
+    /// confidence falls below the prune threshold (e.g. relations accepted because of noisy coverage).
+    fn revalidate(&self, input: &mut Structured) {
+        let seed_data = input.get_raw().to_vec();
+        let mut test_buffer = seed_data.clone();
+
+        let mut idx = 0;
+        while idx < input.relations.len() {
+            let rel = &mut input.relations[idx];
+            if !rel.enabled || rel.heuristics.contains(&Heuristic::Hint) {
+                idx += 1;
+                continue;
+            }
+
+            // Same corruption as when searching. Relations found by the shrink probe tolerate larger values, so they
+            // are corrupted downwards like in `find_shrink_anchor`.
+            let shift = if rel.size == 1 { 0x20 } else { 0xff };
+            let mut corrupted = rel.clone();
+            corrupted.value = if rel.heuristics.contains(&Heuristic::Shrink) {
+                rel.value.saturating_sub(SHRINK_UNITS as u64)
+            } else {
+                match rel.value.checked_add(shift) {
+                    Some(value) if value <= rel.max_value() => value,
+                    _ => rel.value.saturating_sub(shift),
+                }
+            };
+            corrupted.apply(&mut test_buffer);
+
+            let lost = self.focus_len() - self.test(&test_buffer).count();
+            test_buffer.copy_from_slice(&seed_data);
+
+            if lost >= self.loss_threshold {
+                rel.confirmed += 1;
+            } else {
+                rel.refuted += 1;
+            }
+
+            if rel.confidence() < self.options.prune_threshold {
+                self.log_child("PRUNE", &format!("removed REL field at {} (confidence: {:.2})", rel.pos, rel.confidence()));
+                input.remove_relation(idx);
+                continue;
+            }
+            idx += 1;
+        }
+    }
+
+    /// Lists the candidate fields (of each size and endianness) within the guided positions, and orders them with the
+    /// search strategy.
+    ///
+    /// With `cmplog_only`, only fields whose current value was observed as a comparison operand are candidates. The
+    /// pre-filter drops the fields which are unlikely to be lengths (see `SearchOptions::prefilter`).
+    fn candidate_order(&self, input: &Structured, seed_data: &[u8], rel_types: &[(usize, bool)]) -> Vec<Candidate> {
+        let cmp_values = &self.guidance.cmp_values;
+        let high_entropy = if self.options.prefilter { high_entropy_bytes(seed_data) } else { vec![] };
+        let mut filtered = 0;
+
+        let positions = match &self.guidance.positions {
+            Some(range) => range.start.min(seed_data.len())..range.end.min(seed_data.len()),
+            None => 0..seed_data.len(),
+        };
+
+        let mut candidates = vec![];
+        for i in positions {
+            for (size, le) in rel_types.iter() {
+                if i + size > seed_data.len() {
+                    continue;
+                }
+
+                let value = read_field(seed_data, i, *size, *le) as u64;
+                if self.options.prefilter && (value == 0 || value > seed_data.len() as u64 || high_entropy[i]
+                    || input.strings.iter().any(|s| s.range().start < i + size && i < s.range().end)) {
+                    filtered += 1;
+                    continue;
+                }
+                if cmp_values.contains(&value) || !self.options.cmplog_only || cmp_values.is_empty() {
+                    candidates.push((i, *size, *le));
+                }
+            }
+        }
+
+        if filtered > 0 {
+            self.log(&format!("Pre-filter skipped {} candidate fields", filtered));
+        }
+
+        let mut probe = |data: &[u8]| self.test(data).missing(self.focus_len()).count() >= self.loss_threshold;
+        let candidates = self.options.strategy.order(seed_data, candidates, &self.guidance, &mut probe);
+
+        if self.options.extra_verbose {
+            println!("candidate order: {:?}", candidates);
+        }
+        candidates
+    }
+
+    /// Inserts `shift_amount` filler bytes at `ins`, writes the (corrupted) `potential` field and measures which
+    /// fraction of `lost_indices` is recovered.
+    ///
+    /// Returns `None` if the insertion is invalid for the existing relations.
+    fn probe_insert(&self, probe: &mut FieldProbe, potential: &Relation, ins: usize, filler: Filler) -> Option<f64> {
+        let FieldProbe { input, test_buffer, seed_data, shift_amount, lost_indices, .. } = probe;
+
+        // The field counts units of `stride` bytes.
+        let shift_bytes = *shift_amount * potential.stride;
+
+        if input.on_insert(ins, shift_bytes).is_err() {
+            // Error happens before buffer resizing, but we need to fix relation state.
+            input.restore_relations();
+            return None;
+        }
+
+        // Update the buffer.
+        test_buffer.resize(seed_data.len() + shift_bytes, 0);
+
+        test_buffer[ins+shift_bytes..].copy_from_slice(&seed_data[ins..]); // Copy the shifted data.
+        let (before, gap) = test_buffer[..ins+shift_bytes].split_at_mut(ins);
+        filler.fill(gap, before); // Fill the gap.
+
+        // Update the relation.
+        {
+            let mut field = potential.clone();
+            if ins < field.pos { field.pos += shift_bytes; }
+            for mirror in field.mirrors.iter_mut() {
+                if ins <= *mirror { *mirror += shift_bytes; }
+            }
+            field.apply(test_buffer);
+        }
+        input.sanitize_buffer(test_buffer);
+
+        if self.fixed_size {
+            // Drop the shifted-out tail to keep the original length.
+            test_buffer.truncate(seed_data.len());
+        }
+
+        if self.options.extra_verbose {
+            self.print_buffer(test_buffer);
+        }
+
+        let ft = self.test(test_buffer);
+
+        // Restore the original state.
+        input.restore_relations();
+
+        // Restore the original buffer
+        test_buffer.resize(seed_data.len(), 0);
+        test_buffer.copy_from_slice(seed_data);
+
+        let recovered = ft.count_common(lost_indices);
+        let recovered_ratio = recovered as f64 / lost_indices.count() as f64;
+
+        if self.options.extra_verbose {
+            println!("Recovered: {:?} ({}%)", recovered, recovered_ratio * 100.0);
+        }
+
+        Some(recovered_ratio)
+    }
+
+    /// Removes `units` of the region of `potential` right before `ins`, writes the field (holding `value` in the seed)
+    /// decremented by `units` and returns the coverage of the result.
+    ///
+    /// Returns `None` if the removal does not fit the region or is invalid for the existing relations.
+    fn probe_remove(&self, probe: &mut FieldProbe, potential: &Relation, anchor: usize, ins: usize, value: usize, units: usize) -> Option<FocusCoverage> {
+        let FieldProbe { input, test_buffer, seed_data, .. } = probe;
+        let size = units * potential.stride;
+        if units > value || ins < anchor + size || ins > seed_data.len() {
+            return None;
+        }
+        let start = ins - size;
+
+        // The field itself must not be removed.
+        if potential.field_positions().any(|pos| start < pos + potential.size && pos < ins) {
+            return None;
+        }
+
+        if input.on_remove(start, size).is_err() {
+            input.restore_relations();
+            return None;
+        }
+
+        test_buffer.clear();
+        test_buffer.extend_from_slice(&seed_data[..start]);
+        test_buffer.extend_from_slice(&seed_data[ins..]);
+
+        {
+            let mut field = potential.clone();
+            field.value = (value - units) as u64;
+            if start < field.pos { field.pos -= size; }
+            for mirror in field.mirrors.iter_mut() {
+                if start < *mirror { *mirror -= size; }
+            }
+            field.apply(test_buffer);
+        }
+        input.sanitize_buffer(test_buffer);
+
+        if self.options.extra_verbose {
+            self.print_buffer(test_buffer);
+        }
+
+        let ft = self.test(test_buffer);
+
+        input.restore_relations();
+        test_buffer.clear();
+        test_buffer.extend_from_slice(seed_data);
+
+        Some(ft)
+    }
+
+    /// Whether shrinking the region of `potential` which ends at `ins` keeps the coverage (see `SHRINK_MAX_LOSS`).
+    /// Regions which can't be shrunk pass.
+    fn shrink_retains(&self, probe: &mut FieldProbe, potential: &Relation, anchor: usize, ins: usize, value: usize) -> bool {
+        let Some(ft) = self.probe_remove(probe, potential, anchor, ins, value, SHRINK_UNITS) else {
+            return true;
+        };
+
+        let lost = self.focus_len() - ft.count();
+        if self.options.extra_verbose {
+            println!("Shrink lost: {:?} (corruption lost {:?})", lost, probe.lost_indices.count());
+        }
+        lost as f64 <= SHRINK_MAX_LOSS * probe.lost_indices.count() as f64
+    }
+
+    /// Tests the candidate field of `potential` with a smaller value, for targets which tolerate larger ones (e.g.
+    /// which clamp lengths to the available data): if it loses coverage, the anchors are tried with a removal at the
+    /// end of the region instead of an insertion.
+    ///
+    /// Returns the recovered fraction and the lost coverage if an anchor matched.
+    fn find_shrink_anchor(&self, input: &mut Structured, potential: &mut Relation, test_buffer: &mut Vec<u8>, seed_data: &[u8], anchor_visited_cache: &mut [u8], inflection_points: &HashSet<usize>) -> Option<(f64, FocusCoverage)> {
+        let (i, size, value) = (potential.pos, potential.size, potential.value as usize);
+        if value < SHRINK_UNITS {
+            return None;
+        }
+
+        potential.value = (value - SHRINK_UNITS) as u64;
+        potential.apply(test_buffer);
+        let lost_indices = self.test(test_buffer).missing(self.focus_len());
+        test_buffer[i..i+size].copy_from_slice(&seed_data[i..i+size]);
+        potential.value = value as u64;
+
+        if lost_indices.count() < self.loss_threshold {
+            return None;
+        }
+
+        let mut anchors = vec![i + size, 0, i];
+        anchors.extend(inflection_points.iter());
+
+        let mut probe = FieldProbe {
+            input,
+            test_buffer,
+            seed_data,
+            anchor_visited_cache,
+            shift_amount: 0,
+            lost_indices,
+            curr_recover: self.options.recover_threshold,
+        };
+        for stride in self.strides() {
+            if value * stride > seed_data.len() {
+                break;
+            }
+            potential.stride = stride;
+            probe.anchor_visited_cache.fill(0);
+
+            for anchor in anchors.iter().copied() {
+                if anchor >= seed_data.len() || probe.anchor_visited_cache[anchor] != 0 {
+                    continue;
+                }
+                probe.anchor_visited_cache[anchor] = 1;
+
+                let ins = anchor + value * stride;
+                let Some(ft) = self.probe_remove(&mut probe, potential, anchor, ins, value, SHRINK_UNITS) else {
+                    continue;
+                };
+
+                let recovered = ft.count_common(&probe.lost_indices) as f64 / probe.lost_indices.count() as f64;
+                if recovered >= probe.curr_recover {
+                    potential.anchor = anchor;
+                    potential.insert = ins;
+                    probe.curr_recover = recovered;
+                    potential.heuristics.retain(|h| *h == Heuristic::Cmplog);
+                    potential.heuristics.extend([Heuristic::of_anchor(i, size, anchor), Heuristic::Shrink]);
+                    break;
+                }
+            }
+            if potential.insert != usize::MAX {
+                return Some((probe.curr_recover, probe.lost_indices));
+            }
+        }
+        potential.stride = 1;
+        None
+    }
+
+    /// Looks for copies of an accepted field elsewhere in the input which must hold the same value (e.g. a header
+    /// length repeated in a trailer).
+    ///
+    /// A copy is kept as a mirror if updating it together with the field recovers more coverage than updating the
+    /// field alone.
+    fn find_mirrors(&self, probe: &mut FieldProbe, potential: &mut Relation, blocked_points: &[u8]) {
+        let (seed_data, size) = (probe.seed_data, potential.size);
+        if size < 2 || seed_data.len() < size {
+            return;
+        }
+
+        let field = &seed_data[potential.pos..potential.pos + size];
+
+        'outer: for pos in 0..=seed_data.len() - size {
+            // Must not overlap the field or an existing mirror.
+            for other in std::iter::once(&potential.pos).chain(potential.mirrors.iter()) {
+                if pos < other + size && pos + size > *other {
+                    continue 'outer;
+                }
+            }
+
+            if seed_data[pos..pos + size] != *field || blocked_points[pos..pos + size].iter().any(|b| *b != 0) {
+                continue;
+            }
+
+            potential.mirrors.push(pos);
+            match self.probe_insert(probe, potential, potential.insert, self.guidance.filler) {
+                Some(ratio) if ratio > probe.curr_recover => {
+                    self.log_child("REL", &format!("found mirror of field {} at {} (recovered: {})", potential.pos, pos, ratio));
+                    probe.curr_recover = ratio;
+                }
+                _ => {
+                    potential.mirrors.pop();
+                }
+            }
+        }
+    }
+
+    /// Looks for a table of offsets around an accepted relation: adjacent fields with the same size and endianness
+    /// whose values are also valid offsets from the same anchor.
+    ///
+    /// The table is validated by inserting data at the closest pointed-to location and checking that updating all
+    /// entries recovers the coverage lost when only the known relations are updated. On success, the entries are
+    /// added as a group with the accepted relation and their positions are returned.
+    fn find_table(&self, input: &mut Structured, rel_idx: usize, seed_data: &[u8], blocked_points: &[u8]) -> Vec<usize> {
+        let tests = *self.test_count.borrow();
+        let positions = self.find_table_inner(input, rel_idx, seed_data, blocked_points);
+
+        let mut heuristics = self.heuristics.borrow_mut();
+        let stats = heuristics.entry(Heuristic::Table).or_default();
+        stats.tests += *self.test_count.borrow() - tests;
+        stats.found += positions.len();
+        positions
+    }
+
+    fn find_table_inner(&self, input: &mut Structured, rel_idx: usize, seed_data: &[u8], blocked_points: &[u8]) -> Vec<usize> {
+        let rel = input.relations[rel_idx].clone();
+
+        let mut members = vec![];
+        for forward in [false, true] {
+            for k in 1..=MAX_TABLE_ENTRIES {
+                let pos = if forward {
+                    rel.pos + k * rel.size
+                } else if k * rel.size <= rel.pos {
+                    rel.pos - k * rel.size
+                } else {
+                    break;
+                };
+
+                if pos + rel.size > seed_data.len() || blocked_points[pos..pos + rel.size].iter().any(|b| *b != 0) {
+                    break;
+                }
+
+                let value = read_field(seed_data, pos, rel.size, rel.le);
+                if value == 0 || rel.anchor + value > seed_data.len() {
+                    break;
+                }
+
+                let mut member = Relation::new(pos, value as u64, rel.size, rel.le, rel.anchor, rel.anchor + value);
+                member.heuristics.push(Heuristic::Table);
+                members.push(member);
+            }
+        }
+
+        if members.is_empty() {
+            return vec![];
+        }
+
+        // Insert right before the closest pointed-to location, which shifts every entry.
+        let ins = members.iter().map(|m| m.insert).chain(std::iter::once(rel.insert)).min().unwrap();
+        let mut filler = vec![0; rel.size.max(4)];
+        self.guidance.filler.fill(&mut filler, &seed_data[..ins]);
+
+        let mut without = input.clone();
+        without.insert_ignore_invalid(ins, &filler);
+
+        let mut with = input.clone();
+        for member in members.iter() {
+            with.add_relation(member.clone());
+        }
+        if with.insert(ins, &filler).is_err() {
+            return vec![];
+        }
+
+        let lost = self.test(without.get_raw()).missing(self.focus_len());
+        if lost.count() < self.loss_threshold {
+            return vec![];
+        }
+
+        let recovered = self.test(with.get_raw()).count_common(&lost);
+        let recovered_ratio = recovered as f64 / lost.count() as f64;
+
+        if self.options.extra_verbose {
+            println!("Table at {} ({} entries): lost {}, recovered {}", rel.pos, members.len() + 1, lost.count(), recovered);
+        }
+
+        if recovered_ratio < self.options.recover_threshold {
+            return vec![];
+        }
+
+        self.log_child("REL", &format!("found table of {} offsets around {} (anchor: {})", members.len() + 1, rel.pos, rel.anchor));
+
+        let positions = members.iter().map(|m| m.pos).collect();
+        input.add_group(rel_idx, members);
+        positions
+    }
+
+    #[inline]
+    fn check_anchor(&self, probe: &mut FieldProbe, anchor: usize, potential: &mut Relation) {
+        let (seed_len, shift_amount) = (probe.seed_data.len(), probe.shift_amount);
+        let ins = anchor + (potential.value as usize - shift_amount) * potential.stride;
+        
+        // Out of bounds (insertion).
+        if ins > seed_len {
+            return;
+        }
+
+        // For fixed-size inputs, the inserted bytes must fit before the end of the buffer.
+        if self.fixed_size && ins + shift_amount * potential.stride > seed_len {
+            return;
+        }
+
+        // Anchor already visited.
+        if anchor >= seed_len || probe.anchor_visited_cache[anchor] != 0 {
+            return;
+        }
+        probe.anchor_visited_cache[anchor] = 1;
+
+        let heuristic = Heuristic::of_anchor(potential.pos, potential.size, anchor);
+        let tests = *self.test_count.borrow();
+
+        self.check_insert(probe, anchor, ins, 0, heuristic, potential);
+
+        self.heuristics.borrow_mut().entry(heuristic).or_default().tests += *self.test_count.borrow() - tests;
+    }
+
+    /// Tries regions which extend past the length by a constant (see `SearchOptions::header_bias`), starting right
+    /// after the field, at the field and at the start of the input. Each insertion point is only probed once, so a
+    /// header is attributed to the closest of these anchors.
+    fn check_biased_anchors(&self, probe: &mut FieldProbe, potential: &mut Relation) {
+        let (i, size, shift_amount, seed_len) = (potential.pos, potential.size, probe.shift_amount, probe.seed_data.len());
+        let units = potential.value as usize - shift_amount;
+        let tests = *self.test_count.borrow();
+
+        // The insertion points of the anchors themselves were already probed.
+        let mut probed = [i + size, i, 0].map(|anchor| anchor + units * potential.stride).into_iter().collect::<HashSet<_>>();
+        let mut best = None;
+        'anchors: for anchor in [i + size, i, 0] {
+            for bias in std::iter::once(size).chain(HEADER_BIASES) {
+                let ins = anchor + units * potential.stride + bias;
+                if ins > seed_len || (self.fixed_size && ins + shift_amount * potential.stride > seed_len) || !probed.insert(ins) {
+                    continue;
+                }
+
+                // Any insertion inside a payload may recover some coverage, so keep the one which recovers most (the
+                // first one on ties).
+                let heuristic = Heuristic::of_anchor(i, size, anchor);
+                if self.check_insert(probe, anchor, ins, bias, heuristic, potential) {
+                    best = Some(probe.curr_recover);
+                    if probe.curr_recover >= 1.0 {
+                        break 'anchors;
+                    }
+                    probe.curr_recover += f64::EPSILON;
+                }
+            }
+        }
+        if let Some(recover) = best {
+            probe.curr_recover = recover;
+        }
+
+        self.heuristics.borrow_mut().entry(Heuristic::HeaderBias).or_default().tests += *self.test_count.borrow() - tests;
+    }
+
+    /// Looks for the end of a region whose anchor is none of the candidates (e.g. in the middle of data which no
+    /// relation covers yet) by binary-searching its insertion points, using the recovered coverage as the signal.
+    ///
+    /// Filler inserted anywhere inside the region keeps the data after it aligned, so the insertion points recovering
+    /// the lost coverage form an interval as long as the region, which ends at the end of the region. The midpoints of
+    /// the insertion points are probed breadth-first (1/2, 1/4, 3/4, ...) until one of them recovers, with about
+    /// `log2(len)` probes, then the end of its interval is binary-searched and checked like a regular anchor.
+    fn bisect_anchor(&self, probe: &mut FieldProbe, potential: &mut Relation) {
+        let span = (potential.value as usize - probe.shift_amount) * potential.stride;
+        let end = match self.fixed_size {
+            true => probe.seed_data.len().saturating_sub(probe.shift_amount * potential.stride),
+            false => probe.seed_data.len(),
+        };
+        if span > end {
+            return;
+        }
+        let tests = *self.test_count.borrow();
+
+        let bar = probe.curr_recover;
+        let recovers = |ins: usize, probe: &mut FieldProbe| {
+            self.probe_insert(probe, potential, ins, self.guidance.filler).is_some_and(|ratio| ratio >= bar)
+        };
+
+        let mut hit = None;
+        let mut ranges = VecDeque::from([(span, end)]);
+        for _ in 0..=(end - span + 1).ilog2() {
+            let Some((lo, hi)) = ranges.pop_front() else {
+                break;
+            };
+            let mid = lo + (hi - lo) / 2;
+            if recovers(mid, probe) {
+                hit = Some(mid);
+                break;
+            }
+            if lo < mid {
+                ranges.push_back((lo, mid - 1));
+            }
+            if mid < hi {
+                ranges.push_back((mid + 1, hi));
+            }
+        }
+
+        if let Some(hit) = hit {
+            // Last insertion point of the interval.
+            let (mut lo, mut hi) = (hit, (hit + span).min(end));
+            while lo < hi {
+                let mid = lo + (hi - lo).div_ceil(2);
+                if recovers(mid, probe) {
+                    lo = mid;
+                } else {
+                    hi = mid - 1;
+                }
+            }
+
+            if probe.anchor_visited_cache[lo - span] == 0 {
+                probe.anchor_visited_cache[lo - span] = 1;
+                self.check_insert(probe, lo - span, lo, 0, Heuristic::Bisection, potential);
+            }
+        }
+
+        self.heuristics.borrow_mut().entry(Heuristic::Bisection).or_default().tests += *self.test_count.borrow() - tests;
+    }
+
+    /// Probes an insertion at `ins` for the region of `potential` starting at `anchor` (with `bias` bytes left out of
+    /// the value), and records it if it recovers at least as much as the best one so far.
+    fn check_insert(&self, probe: &mut FieldProbe, anchor: usize, ins: usize, bias: usize, heuristic: Heuristic, potential: &mut Relation) -> bool {
+        if self.options.extra_verbose {
+            self.log_child("REL", &format!("Testing insertion at {} (anchor: {}, shift: {}, stride: {}, bias: {})", ins, anchor, probe.shift_amount, potential.stride, bias));
+        }
+
+        // Some targets validate the inserted content, so try each filler until one recovers the lost coverage.
+        for filler in self.fillers() {
+            let recovered_ratio = match self.probe_insert(probe, potential, ins, filler) {
+                Some(ratio) => ratio,
+                None => return false,
+            };
+
+            if recovered_ratio >= probe.curr_recover {
+                // The region must also end here when shrinking it.
+                if self.options.shrink_probes && !self.shrink_retains(probe, potential, anchor, ins, potential.value as usize - probe.shift_amount) {
+                    return false;
+                }
+
+                // Valid insertion point.
+                potential.insert = ins;
+                potential.anchor = anchor;
+                potential.bias = bias;
+                probe.curr_recover = recovered_ratio;
+
+                potential.heuristics.retain(|h| *h == Heuristic::Cmplog);
+                potential.heuristics.push(heuristic);
+                if filler != self.guidance.filler {
+                    potential.heuristics.push(Heuristic::FallbackFiller);
+                }
+                if bias > 0 {
+                    potential.heuristics.push(Heuristic::HeaderBias);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Fillers tried at an anchor: the guidance filler, then the fallbacks of the fill strategy.
+    fn fillers(&self) -> Vec<Filler> {
+        let mut fillers = vec![self.guidance.filler];
+        for filler in self.options.fill_strategy.iter() {
+            if !fillers.contains(filler) {
+                fillers.push(*filler);
+            }
+        }
+        fillers
+    }
+
+    fn log(&self, msg: &str) {
+        if self.options.verbose {
+            println!("[{}] (#{}) {}", "SEARCH".cyan(), self.test_count.borrow(), msg);
+        }
+    }
+
+    fn log_child(&self, sub: &str, msg: &str) {
+        if self.options.verbose {
+            println!("[{}][{}] (#{}) {}", "SEARCH".cyan(), sub.purple(), self.test_count.borrow(), msg);
+        }
+    }
+
+    fn print_buffer(&self, buffer: &[u8]) {
+        for (i, byte) in buffer.iter().enumerate() {
+            if i % 16 == 0 {
+                println!();
+            }
+            print!("{:02x} ", byte);
+        }
+        println!();
+    }
+
+    /// Settles the endianness of the format once enough multi-byte relations agree on it (see `endian_vote`).
+    fn vote_endianness(&self, input: &Structured) {
+        if self.options.endian_vote == 0 || self.endianness.borrow().is_some() {
+            return;
+        }
+
+        // The digits of an ASCII field have no byte order.
+        let votes = input.relations.iter()
+            .filter(|rel| rel.enabled && rel.size > 1 && rel.bits.is_none() && !rel.ascii)
+            .map(|rel| rel.le)
+            .collect::<Vec<_>>();
+        if votes.len() < self.options.endian_vote {
+            return;
+        }
+
+        let le = votes.iter().filter(|le| **le).count();
+        for (endianness, count) in [(true, le), (false, votes.len() - le)] {
+            if count as f64 >= ENDIAN_MAJORITY * votes.len() as f64 {
+                self.log(&format!("Format is {} ({} of {} relations), skipping the other endianness", if endianness { "little-endian" } else { "big-endian" }, count, votes.len()));
+                *self.endianness.borrow_mut() = Some(endianness);
+                return;
+            }
+        }
+    }
+
+    /// Units a field may count, tried in order until one recovers the field.
+    fn strides(&self) -> impl Iterator<Item = usize> {
+        let count = if self.options.enable_strides { STRIDES.len() } else { 1 };
+        STRIDES.into_iter().take(count)
+    }
+
+    /// Number of focus indices and features, over which the loss of coverage is measured.
+    fn focus_len(&self) -> usize {
+        self.focus_indices.len() + self.focus_features.len()
+    }
+
+    /// Whether the probe budget (`max_tests`) is used up, or the search was stopped.
+    fn exhausted(&self) -> bool {
+        self.stopped() || self.options.max_tests.is_some_and(|max| *self.test_count.borrow() >= max)
+    }
+
+    /// Whether the search was stopped (see `SearchGuidance::stop`).
+    fn stopped(&self) -> bool {
+        self.guidance.stop.as_ref().is_some_and(|stop| stop.get())
+    }
+
+    fn test(&self, data: &[u8]) -> FocusCoverage {
+        // The probes left in the current candidate field reach no coverage, and the search ends before the next one.
+        if self.stopped() {
+            return FocusCoverage::from_snapshot(&CoverageSnapshot::default(), &self.focus_indices, &self.focus_features);
+        }
+
+        // Some probes are generated again (e.g. the same insertion from different anchors), and get the coverage
+        // they had.
+        let hash = ProbeCache::hash(data);
+        if let Some(cov) = self.probe_cache.borrow_mut().get(hash, data) {
+            *self.cache_hits.borrow_mut() += 1;
+            return cov;
+        }
+
+        *self.test_count.borrow_mut() += 1;
+        if *self.cmplog_candidate.borrow() {
+            self.heuristics.borrow_mut().entry(Heuristic::Cmplog).or_default().tests += 1;
+        }
+        let start = std::time::Instant::now();
+        let res = self.oracle.borrow_mut().test(data);
+        let elapsed = start.elapsed().as_millis();
+        *self.target_test_ms.borrow_mut() += elapsed as u64;
+        self.probe_cache.borrow_mut().insert(hash, data, res.clone());
+        self.report_status();
+        res
+    }
+
+    /// Reports the status of the search if the latest report is older than `SearchOptions::status_interval`.
+    fn report_status(&self) {
+        let Some(on_status) = &self.on_status else {
+            return;
+        };
+        let mut status = self.status.borrow_mut();
+        if status.1.elapsed() < self.options.status_interval {
+            return;
+        }
+        status.0.probes = *self.test_count.borrow();
+        status.1 = Instant::now();
+        (on_status.borrow_mut())(&status.0);
+    }
+}
+
+/// Returns the window of `new` which differs from `old`, widened by `margin` bytes on each side.
+///
+/// The window spans everything between the longest common prefix and the longest common suffix, so it covers
+/// in-place changes as well as insertions and removals.
+pub fn changed_window(old: &[u8], new: &[u8], margin: usize) -> Range<usize> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old.iter().rev().zip(new.iter().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+
+    let end = new.len() - suffix;
+    if prefix == end && old.len() == new.len() {
+        return prefix..prefix;
+    }
+
+    prefix.saturating_sub(margin)..(end + margin).min(new.len())
+}
+
+/// Bytes per hashed block of a `RawDigest`.
+const DIGEST_BLOCK: usize = 64;
+
+/// Fingerprint of raw bytes which bounds their `changed_window` later on without keeping a copy: the hashes of their
+/// blocks, once aligned to the start and once to the end (so that both sides of an insertion or removal match).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RawDigest {
+    len: usize,
+    head: Vec<u64>,
+    tail: Vec<u64>,
+}
+
+impl RawDigest {
+    pub fn new(raw: &[u8]) -> Self {
+        Self { len: raw.len(), head: block_hashes(raw.chunks(DIGEST_BLOCK)), tail: block_hashes(raw.rchunks(DIGEST_BLOCK)) }
+    }
+
+    /// Like `changed_window` against the digested bytes, at the granularity of the blocks.
+    pub fn changed_window(&self, new: &[u8], margin: usize) -> Range<usize> {
+        let prefix = self.head.iter().zip(new.chunks(DIGEST_BLOCK))
+            .take_while(|(hash, block)| **hash == block_hash(block))
+            .map(|(_, block)| block.len())
+            .sum::<usize>();
+        let max_suffix = self.len.min(new.len()) - prefix.min(self.len.min(new.len()));
+        let suffix = self.tail.iter().zip(new.rchunks(DIGEST_BLOCK))
+            .take_while(|(hash, block)| **hash == block_hash(block))
+            .map(|(_, block)| block.len())
+            .sum::<usize>()
+            .min(max_suffix);
+
+        let prefix = prefix.min(new.len());
+        let end = new.len() - suffix;
+        if prefix >= end && self.len == new.len() {
+            return prefix..prefix;
+        }
+
+        prefix.saturating_sub(margin)..(end + margin).min(new.len())
+    }
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    block.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn block_hashes<'a>(blocks: impl Iterator<Item = &'a [u8]>) -> Vec<u64> {
+    blocks.map(block_hash).collect()
+}
+
+/// Marks the bytes whose surroundings look like compressed or random data (see `PREFILTER_MAX_ENTROPY`).
+fn high_entropy_bytes(data: &[u8]) -> Vec<bool> {
+    (0..data.len())
+        .map(|i| shannon_entropy(&data[i.saturating_sub(PREFILTER_WINDOW)..(i + PREFILTER_WINDOW).min(data.len())]) > PREFILTER_MAX_ENTROPY)
+        .collect()
+}
+
+/// Whether the header of `raw` holds a field counting the rest of the input from its start or from the end of the
+/// field: growing such an input breaks it, which must not be taken for a fixed size.
+fn holds_own_size(raw: &[u8]) -> bool {
+    (0..WHOLE_FILE_HEADER.min(raw.len())).any(|pos| {
+        [2, 4, 8].into_iter()
+            .filter(|size| pos + size <= raw.len())
+            .flat_map(|size| [(size, true), (size, false)])
+            .any(|(size, le)| {
+                let value = read_field(raw, pos, size, le);
+                value > pos + size && value <= raw.len() && raw.len() - value <= pos + size
+            })
+    })
+}
+
+/// Decodes the field of the given size and endianness at `pos`.
+pub fn read_field(data: &[u8], pos: usize, size: usize, le: bool) -> usize {
+    match (size, le) {
+        (2, false) => u16::from_be_bytes([data[pos], data[pos+1]]).into(),
+        (3, false) => u32::from_be_bytes([0, data[pos], data[pos+1], data[pos+2]]) as usize,
+        (4, false) => u32::from_be_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]) as usize,
+        (8, false) => u64::from_be_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3], data[pos+4], data[pos+5], data[pos+6], data[pos+7]]) as usize,
+        (1, _) => data[pos].into(),
+        (2, true) => u16::from_le_bytes([data[pos], data[pos+1]]).into(),
+        (3, true) => u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], 0]) as usize,
+        (4, true) => u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]) as usize,
+        (8, true) => u64::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3], data[pos+4], data[pos+5], data[pos+6], data[pos+7]]) as usize,
+        _ => panic!("Unsupported size")
+    }
+}
+
+/// Reads a field of `size` ASCII decimal digits, if it holds only digits.
+pub fn read_ascii_field(data: &[u8], pos: usize, size: usize) -> Option<usize> {
+    let digits = data.get(pos..pos + size)?;
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_features() {
+        assert_eq!(value_features(&[0, 3, 0, 0, 9]), vec![1 << 8 | 3, 4 << 8 | 9]);
+    }
+
+    #[test]
+    fn test_changed_window() {
+        let old = [0u8; 64];
+
+        assert!(changed_window(&old, &old, 4).is_empty());
+
+        let mut new = old;
+        new[20] = 1;
+        new[30] = 1;
+        assert_eq!(changed_window(&old, &new, 4), 16..35);
+
+        // Insertion of 3 bytes at 40.
+        let mut new = old.to_vec();
+        new.splice(40..40, [1, 2, 3]);
+        assert_eq!(changed_window(&old, &new, 0), 40..43);
+
+        // Removal at the end.
+        assert_eq!(changed_window(&old, &old[..60], 2), 58..60);
+    }
+
+    #[test]
+    fn test_digest_changed_window() {
+        let old = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        let digest = RawDigest::new(&old);
+        assert!(digest.changed_window(&old, 4).is_empty());
+
+        // From the changed block aligned to the start (256..320) to the one aligned to the end (296..360).
+        let mut new = old.clone();
+        new[300] ^= 1;
+        assert_eq!(digest.changed_window(&new, 0), 256..360);
+
+        // Insertion of 3 bytes at 500: the blocks before match from the start, the ones after from the end.
+        let mut new = old.clone();
+        new.splice(500..500, [1, 2, 3]);
+        let window = digest.changed_window(&new, 4);
+        assert!(window.contains(&500) && window.contains(&502));
+        assert!(window.len() <= 2 * DIGEST_BLOCK + 8);
+
+        // Removal at the end.
+        let window = digest.changed_window(&old[..990], 0);
+        assert!(window.end == 990 && window.start >= 896);
+
+        // An input grown from nothing.
+        assert_eq!(RawDigest::new(&[]).changed_window(&old[..10], 2), 0..10);
+    }
+
+    #[test]
+    fn test_focus_majority() {
+        let focus = [1, 2, 3];
+        let cov = |map: &[u8]| FocusCoverage::from_snapshot(&CoverageSnapshot::from_map(map), &focus, &[]);
+        let runs = [[0, 1, 1, 0], [0, 1, 0, 1], [0, 1, 1, 0]].map(|map| cov(&map));
+        assert_eq!(FocusCoverage::majority(&runs, 3), cov(&[0, 1, 1, 0]));
+
+        // With 2 runs, only the indices covered by both.
+        assert_eq!(FocusCoverage::majority(&runs[..2], 3), cov(&[0, 1, 0, 0]));
+    }
+
+    #[test]
+    fn test_focus_coverage() {
+        let focus = (0..100).map(|i| i * 2).collect::<Vec<_>>();
+        let mut map = vec![0u8; 200];
+        for idx in [0, 2, 130, 198] {
+            map[idx] = 1;
+        }
+        let snapshot = CoverageSnapshot::from_map(&map);
+        assert_eq!(snapshot.hits(), &[0, 2, 130, 198]);
+        assert!(snapshot.contains(130) && !snapshot.contains(131));
+
+        let cov = FocusCoverage::from_snapshot(&snapshot, &focus, &[]);
+        assert_eq!(cov.count(), 4);
+
+        let lost = cov.missing(focus.len());
+        assert_eq!(lost.count(), 96);
+        assert_eq!(cov.count_common(&lost), 0);
+
+        map[4] = 1;
+        map[5] = 1;
+        assert_eq!(FocusCoverage::from_snapshot(&CoverageSnapshot::from_map(&map), &focus, &[]).count_common(&lost), 1);
+    }
+
+    #[test]
+    fn test_vote_endianness() {
+        let mut input = Structured::raw(b"1234 5678 9012 ....".to_vec());
+        for pos in [0, 5, 10] {
+            let mut rel = Relation::new(pos, 0, 4, true, 15, 19);
+            rel.ascii = true;
+            input.add_relation(rel);
+        }
+        let mut oracle = |_: &[u8]| CoverageSnapshot::default();
+        let search = SearchContext::new(&input, &mut oracle, SearchOptions { endian_vote: 3, ..Default::default() });
+
+        // ASCII fields cast no vote.
+        search.vote_endianness(&input);
+        assert_eq!(*search.endianness.borrow(), None);
+
+        for rel in input.relations.iter_mut() {
+            rel.ascii = false;
+        }
+        search.vote_endianness(&input);
+        assert_eq!(*search.endianness.borrow(), Some(true));
+    }
+}
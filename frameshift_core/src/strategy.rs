@@ -0,0 +1,188 @@
+use std::{fmt::Debug, sync::Arc};
+
+use super::search::{read_field, SearchGuidance};
+
+/// Names accepted by `strategy_by_name`.
+pub const STRATEGIES: [&str; 4] = ["linear", "cmplog", "entropy", "bisect"];
+
+/// Candidate field of a search pass: position, size and endianness.
+pub type Candidate = (usize, usize, bool);
+
+/// Decides in which order a search pass tests the candidate fields.
+///
+/// The search tests the candidates in the returned order (dropped candidates are not tested). A strategy may run
+/// probes of its own through `probe`, which returns whether a modified copy of the seed loses coverage (as much as a
+/// corrupted field must) and counts against the probe budget of the search. The order must be deterministic for a
+/// given seed, so that interrupted searches can resume.
+pub trait SearchStrategy: Debug + Send + Sync {
+    fn order(&self, seed: &[u8], candidates: Vec<Candidate>, guidance: &SearchGuidance, probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<Candidate>;
+}
+
+/// Builds one of the built-in strategies from its name (see `STRATEGIES`).
+pub fn strategy_by_name(name: &str) -> Result<Arc<dyn SearchStrategy>, String> {
+    match name {
+        "linear" => Ok(Arc::new(LinearStrategy)),
+        "cmplog" => Ok(Arc::new(CmplogStrategy)),
+        "entropy" => Ok(Arc::new(EntropyStrategy::default())),
+        "bisect" => Ok(Arc::new(BisectStrategy::default())),
+        _ => Err(format!("invalid search strategy: {} (expected one of {})", name, STRATEGIES.join(", "))),
+    }
+}
+
+/// Tests the candidates from the start of the input to its end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinearStrategy;
+
+impl SearchStrategy for LinearStrategy {
+    fn order(&self, _seed: &[u8], candidates: Vec<Candidate>, _guidance: &SearchGuidance, _probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<Candidate> {
+        candidates
+    }
+}
+
+/// Tests first the fields whose current value was observed as a comparison operand: these are likely to be checked
+/// by the parser against the buffer size. The other candidates follow in input order (the default strategy).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CmplogStrategy;
+
+impl SearchStrategy for CmplogStrategy {
+    fn order(&self, seed: &[u8], candidates: Vec<Candidate>, guidance: &SearchGuidance, _probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<Candidate> {
+        let (mut prioritized, rest): (Vec<_>, Vec<_>) = candidates.into_iter()
+            .partition(|(i, size, le)| guidance.cmp_values.contains(&(read_field(seed, *i, *size, *le) as u64)));
+        prioritized.extend(rest);
+        prioritized
+    }
+}
+
+/// Tests first the fields in low-entropy surroundings (headers, tables of small integers), and last the ones inside
+/// high-entropy data (e.g. compressed payloads), which rarely holds lengths.
+#[derive(Debug, Clone, Copy)]
+pub struct EntropyStrategy {
+    /// Bytes on each side of a field taken into account.
+    pub window: usize,
+}
+
+impl Default for EntropyStrategy {
+    fn default() -> Self {
+        Self { window: 8 }
+    }
+}
+
+impl SearchStrategy for EntropyStrategy {
+    fn order(&self, seed: &[u8], mut candidates: Vec<Candidate>, _guidance: &SearchGuidance, _probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<Candidate> {
+        let entropy = (0..seed.len())
+            .map(|i| shannon_entropy(&seed[i.saturating_sub(self.window)..(i + self.window).min(seed.len())]))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| entropy[a.0].total_cmp(&entropy[b.0]));
+        candidates
+    }
+}
+
+/// Entropy of the byte distribution of `data`, in bits per byte.
+pub fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in data {
+        counts[*b as usize] += 1;
+    }
+    counts.iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Locates the bytes whose corruption loses coverage by bisection (flipping halves of the input down to
+/// `granularity` bytes), and tests the candidates overlapping them first.
+#[derive(Debug, Clone, Copy)]
+pub struct BisectStrategy {
+    pub granularity: usize,
+}
+
+impl Default for BisectStrategy {
+    fn default() -> Self {
+        Self { granularity: 16 }
+    }
+}
+
+impl BisectStrategy {
+    fn sensitive_bytes(&self, seed: &[u8], candidates: &[Candidate], probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<bool> {
+        let mut sensitive = vec![false; seed.len()];
+        let start = candidates.iter().map(|c| c.0).min().unwrap_or(0);
+        let end = candidates.iter().map(|c| c.0 + c.1).max().unwrap_or(0);
+
+        let mut buffer = seed.to_vec();
+        let mut regions = std::iter::once(start..end).collect::<Vec<_>>();
+        while let Some(region) = regions.pop() {
+            if region.is_empty() {
+                continue;
+            }
+
+            buffer[region.clone()].iter_mut().for_each(|b| *b ^= 0xff);
+            let lost = probe(&buffer);
+            buffer[region.clone()].copy_from_slice(&seed[region.clone()]);
+            if !lost {
+                continue;
+            }
+
+            if region.len() <= self.granularity.max(1) {
+                sensitive[region].fill(true);
+            } else {
+                let mid = region.start + region.len() / 2;
+                regions.push(mid..region.end);
+                regions.push(region.start..mid);
+            }
+        }
+        sensitive
+    }
+}
+
+impl SearchStrategy for BisectStrategy {
+    fn order(&self, seed: &[u8], candidates: Vec<Candidate>, _guidance: &SearchGuidance, probe: &mut dyn FnMut(&[u8]) -> bool) -> Vec<Candidate> {
+        let sensitive = self.sensitive_bytes(seed, &candidates, probe);
+        let (mut prioritized, rest): (Vec<_>, Vec<_>) = candidates.into_iter()
+            .partition(|(i, size, _)| sensitive[*i..*i + *size].iter().any(|s| *s));
+        prioritized.extend(rest);
+        prioritized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_order() {
+        // A header of small integers followed by varied data.
+        let mut seed = vec![0, 0, 0, 4, 0, 0, 0, 8];
+        seed.extend((0..32).map(|i| (i * 37 + 11) as u8));
+        let candidates = vec![(30, 1, true), (3, 1, true)];
+
+        let order = EntropyStrategy::default().order(&seed, candidates, &SearchGuidance::default(), &mut |_| false);
+        assert_eq!(order, vec![(3, 1, true), (30, 1, true)]);
+    }
+
+    #[test]
+    fn test_bisect_order() {
+        // Only the byte at 40 matters to the target.
+        let seed = vec![0x41; 64];
+        let candidates = (0..64).map(|i| (i, 1, true)).collect::<Vec<_>>();
+
+        let mut probes = 0;
+        let order = BisectStrategy { granularity: 4 }.order(&seed, candidates, &SearchGuidance::default(), &mut |data| {
+            probes += 1;
+            data[40] != 0x41
+        });
+        assert_eq!(order[..4], [(40, 1, true), (41, 1, true), (42, 1, true), (43, 1, true)]);
+        assert_eq!(order.len(), 64);
+        assert!(probes < 64);
+    }
+
+    #[test]
+    fn test_strategy_by_name() {
+        for name in STRATEGIES {
+            assert!(strategy_by_name(name).is_ok());
+        }
+        assert!(strategy_by_name("random").is_err());
+    }
+}
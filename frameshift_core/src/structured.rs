@@ -0,0 +1,2468 @@
+use std::{collections::HashSet, ops::Range};
+
+use colored::{Color, Colorize};
+use serde::{Deserialize, Serialize};
+
+use crate::search::{read_ascii_field, read_field};
+
+
+/// Maximum number of bytes added to a region by `boundary_variants`.
+const MAX_BOUNDARY_GROWTH: u64 = 0x1000;
+
+/// Maximum number of structural edits (region duplications, removals and resizes) of an input made by `generate`.
+const MAX_GENERATION_EDITS: usize = 4;
+
+/// Largest number of digits of an ASCII length field (see `Relation::ascii`).
+pub const MAX_ASCII_DIGITS: usize = 10;
+
+/// Colors of the relations in `render_annotated` (cycled through).
+const PALETTE: [Color; 6] = [Color::Red, Color::Green, Color::Yellow, Color::Blue, Color::Magenta, Color::Cyan];
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Structured {
+    pub raw: Vec<u8>,
+    pub relations: Vec<Relation>,
+
+    /// Tables of relations which are updated (and disabled) together.
+    #[serde(default)]
+    pub groups: Vec<RelationGroup>,
+
+    /// The target rejects any change in length, so only in-place mutations are useful.
+    #[serde(default)]
+    pub fixed_size: bool,
+
+    /// The structure was verified manually and must not be replaced by a search result.
+    #[serde(default)]
+    pub frozen: bool,
+
+    /// Byte sequences the target checks for an exact value (e.g. format signatures), sorted by position.
+    #[serde(default)]
+    pub constants: Vec<Constant>,
+
+    /// Strings delimited by a NUL terminator or a length prefix, sorted by position.
+    #[serde(default)]
+    pub strings: Vec<StringRegion>,
+
+    /// Fields holding the position of data they point to, which moves as a whole (see `OffsetRelation`).
+    #[serde(default)]
+    pub offsets: Vec<OffsetRelation>,
+
+    /// Used to restore the constants and strings along with the relations (tracking an edit can drop some of them).
+    #[serde(skip)]
+    pub old_constants: Vec<Constant>,
+    #[serde(skip)]
+    pub old_strings: Vec<StringRegion>,
+}
+
+/// An insertion, removal or splice which would break a relation (or a constant) of the input, so it is not applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidEdit;
+
+impl Structured {
+    pub fn raw(raw: Vec<u8>) -> Self {
+        Self {
+            raw,
+            relations: Vec::new(),
+            groups: Vec::new(),
+            fixed_size: false,
+            frozen: false,
+            constants: Vec::new(),
+            strings: Vec::new(),
+            offsets: Vec::new(),
+            old_constants: Vec::new(),
+            old_strings: Vec::new(),
+        }
+    }
+
+    pub fn add_relation(&mut self, rel: Relation) {
+        self.relations.push(rel);
+    }
+
+    /// Groups the relation at `idx` with `members` (added as new relations) into a single table.
+    pub fn add_group(&mut self, idx: usize, members: Vec<Relation>) -> usize {
+        let id = self.groups.iter().map(|g| g.id + 1).max().unwrap_or(0);
+
+        let rel = &mut self.relations[idx];
+        rel.group = Some(id);
+        self.groups.push(RelationGroup {
+            id,
+            size: rel.size,
+            le: rel.le,
+        });
+
+        for mut member in members {
+            member.group = Some(id);
+            self.relations.push(member);
+        }
+
+        id
+    }
+
+    /// Removes the relations at the given indices, along with all other members of their groups.
+    fn remove_relations(&mut self, mut indices: Vec<usize>) {
+        let groups = indices.iter().filter_map(|i| self.relations[*i].group).collect::<HashSet<_>>();
+        if !groups.is_empty() {
+            for (i, rel) in self.relations.iter().enumerate() {
+                if rel.group.is_some_and(|g| groups.contains(&g)) {
+                    indices.push(i);
+                }
+            }
+            self.groups.retain(|g| !groups.contains(&g.id));
+        }
+
+        // Removing from the back keeps the smaller indices valid.
+        indices.sort();
+        indices.dedup();
+        for i in indices.iter().rev() {
+            self.relations.swap_remove(*i);
+        }
+    }
+
+    pub fn get_raw_mut(&mut self) -> &mut [u8] {
+        &mut self.raw
+    }
+
+    pub fn get_raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn write(&mut self, idx: usize, data: &[u8]) {
+        self.raw.splice(idx..idx+data.len(), data.iter().cloned());
+        self.sanitize();
+    }
+
+    pub fn insert(&mut self, idx: usize, data: &[u8]) -> Result<(), InvalidEdit> {
+        if !self.track_insert(idx, data.len()).is_empty() || self.track_offsets(idx, data.len(), true).is_err() {
+            return Err(InvalidEdit);
+        }
+        self.track_regions(idx, data.len(), true);
+
+        self.raw.splice(idx..idx, data.iter().cloned());
+
+        self.sanitize();
+
+        Ok(())
+    }
+
+    // Track an insert without modifying a buffer.
+    pub fn on_insert(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if !self.track_insert(idx, size).is_empty() || self.track_offsets(idx, size, true).is_err() {
+            return Err(InvalidEdit);
+        }
+        self.track_regions(idx, size, true);
+
+        Ok(())
+    }
+
+    // Track a removal of the relations without modifying a buffer.
+    pub fn on_remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        for rel in self.relations.iter_mut().filter(|rel| rel.enabled) {
+            rel.on_remove(idx, size)?;
+        }
+        self.track_offsets(idx, size, false)
+    }
+
+    pub fn insert_ignore_invalid(&mut self, idx: usize, data: &[u8]) {
+        // Invalid relations are ignored.
+        self.track_insert(idx, data.len());
+        self.drop_offsets(idx, data.len(), true);
+        self.track_regions(idx, data.len(), true);
+
+        self.raw.splice(idx..idx, data.iter().cloned());
+
+        self.sanitize();
+    }
+
+    /// Inserts `data` right after the region of relation `idx` without growing that relation, so only the
+    /// relations enclosing it account for the extra bytes.
+    ///
+    /// This produces inputs whose outer lengths disagree with the inner ones, probing parsers which do not bound
+    /// their reads by the inner region. On error, the input is left unchanged.
+    pub fn extend_after(&mut self, idx: usize, data: &[u8]) -> Result<(), InvalidEdit> {
+        let pos = self.relations[idx].insert;
+        self.save_relations();
+
+        // Disabled relations are neither updated nor applied.
+        self.relations[idx].enabled = false;
+        let res = self.insert(pos, data);
+        self.relations[idx].enabled = true;
+
+        if res.is_err() {
+            self.restore_relations();
+        }
+
+        self.sanitize();
+        res
+    }
+
+    /// Grows or shrinks the region of relation `idx` (at its end) until the field holds `value`, updating all
+    /// relations. Grown regions are filled with `fill`.
+    ///
+    /// On error, the input is left unchanged.
+    pub fn resize_region(&mut self, idx: usize, value: u64, fill: u8) -> Result<(), InvalidEdit> {
+        let rel = &self.relations[idx];
+        let (cur, insert, anchor, stride) = (rel.value, rel.insert, rel.anchor, rel.stride);
+
+        self.save_relations();
+        let res = if value > cur {
+            self.insert(insert, &vec![fill; (value - cur) as usize * stride])
+        } else {
+            let size = (cur - value) as usize * stride;
+            if insert < anchor + size {
+                Err(InvalidEdit)
+            } else {
+                self.remove(insert - size, size)
+            }
+        };
+
+        if res.is_err() {
+            self.restore_relations();
+        }
+        res
+    }
+
+    /// Returns copies of the input where the field of relation `idx` holds a boundary value (0, 1, the maximum,
+    /// value-1, value+1, value*2), which are the values most likely to trigger length-handling bugs.
+    ///
+    /// Each value is written both as a lie (the relation is disabled, e.g. off by one over its region) and, for
+    /// variable-size inputs, consistently by resizing the region with `fill`. The other relations are kept consistent
+    /// in both cases. Duplicate variants are skipped.
+    pub fn boundary_variants(&self, idx: usize, fill: u8) -> Vec<Structured> {
+        let rel = &self.relations[idx];
+        if !rel.enabled {
+            return vec![];
+        }
+
+        let values = [0, 1, rel.max_value(), rel.value.saturating_sub(1), rel.value.saturating_add(1), rel.value.saturating_mul(2)];
+
+        let mut seen = HashSet::new();
+        seen.insert(self.raw.clone());
+        let mut variants = vec![];
+        for value in values.iter().map(|v| *v.min(&rel.max_value())) {
+            if value == rel.value {
+                continue;
+            }
+
+            let mut lie = self.clone();
+            lie.set_relation_enabled(idx, false);
+            let mut field = rel.clone();
+            field.value = value;
+            field.apply(lie.get_raw_mut());
+            lie.sanitize();
+            if seen.insert(lie.raw.clone()) {
+                variants.push(lie);
+            }
+
+            if self.fixed_size || value > rel.value.saturating_add(MAX_BOUNDARY_GROWTH) {
+                continue;
+            }
+            let mut resized = self.clone();
+            if resized.resize_region(idx, value, fill).is_ok() && seen.insert(resized.raw.clone()) {
+                variants.push(resized);
+            }
+        }
+        variants
+    }
+
+    /// Returns the field and region (`pos..insert`) of relation `idx` if it is a size field preceding its region.
+    pub fn region_of(&self, idx: usize) -> Option<Range<usize>> {
+        let rel = &self.relations[idx];
+        if !rel.enabled || rel.anchor < rel.pos + rel.size || rel.insert > self.raw.len() {
+            return None;
+        }
+        Some(rel.pos..rel.insert)
+    }
+
+    /// Returns how many bytes the field of relation `idx` claims past the end of its region, with the value it holds in
+    /// the input (which differs from `value` after a lie, e.g. one of the `boundary_variants`), if it claims more bytes
+    /// than the region holds.
+    pub fn overrun(&self, idx: usize) -> Option<usize> {
+        let rel = &self.relations[idx];
+        if rel.insert < rel.anchor || rel.insert > self.raw.len() || rel.pos + rel.size > self.raw.len() {
+            return None;
+        }
+        let value = match (&rel.bits, rel.ascii) {
+            (Some(bits), _) => bits.read(self.raw[rel.pos]),
+            (None, true) => read_ascii_field(&self.raw, rel.pos, rel.size)? as u64,
+            (None, false) => read_field(&self.raw, rel.pos, rel.size, rel.le) as u64,
+        };
+        let claimed = value.saturating_mul(rel.stride as u64).saturating_add(rel.bias as u64);
+        let over = claimed.saturating_sub((rel.insert - rel.anchor) as u64);
+        (over > 0).then(|| usize::try_from(over).unwrap_or(usize::MAX))
+    }
+
+    /// Inserts the field and region of relation `rel_idx` of `donor` at `idx`, along with the donor relations
+    /// contained in it. All relations of this input are updated for the insertion.
+    ///
+    /// On error, the input is left unchanged.
+    pub fn splice_region(&mut self, idx: usize, donor: &Structured, rel_idx: usize) -> Result<(), InvalidEdit> {
+        let region = donor.region_of(rel_idx).ok_or(InvalidEdit)?;
+        self.splice_from(idx, donor, region)
+    }
+
+    /// Inserts the bytes `range` of `donor` at `idx`, along with the donor relations contained in them (field, anchor
+    /// and insertion point), moved to their new position. All relations of this input are updated for the insertion.
+    ///
+    /// A donor relation is only carried over if it is consistent in the result: its field holds its value, which
+    /// matches the length of its region (stale donor relations would otherwise rewrite the spliced bytes).
+    ///
+    /// On error, the input is left unchanged.
+    pub fn splice_from(&mut self, idx: usize, donor: &Structured, range: Range<usize>) -> Result<(), InvalidEdit> {
+        if range.start > range.end || range.end > donor.raw.len() {
+            return Err(InvalidEdit);
+        }
+
+        self.save_relations();
+        if self.insert(idx, &donor.raw[range.clone()]).is_err() {
+            self.restore_relations();
+            return Err(InvalidEdit);
+        }
+
+        self.inherit(idx, donor, range);
+        self.sanitize();
+        Ok(())
+    }
+
+    /// Replaces the bytes from `at` on by those of `donor` (the havoc splice), carrying over the donor relations
+    /// contained in them. The relations of this input which the removed bytes break are dropped.
+    pub fn splice_tail(&mut self, at: usize, donor: &Structured) -> Result<(), InvalidEdit> {
+        if at > self.raw.len() || at > donor.raw.len() {
+            return Err(InvalidEdit);
+        }
+
+        // The donor bytes are appended: the regions ending at `at` keep their length.
+        self.remove_disabling(at, self.raw.len() - at);
+        self.raw.extend_from_slice(&donor.raw[at..]);
+        self.inherit(at, donor, at..donor.raw.len());
+        self.sanitize();
+        Ok(())
+    }
+
+    /// Adds the consistent donor relations contained in the bytes `range` of `donor`, which were inserted at `idx`.
+    fn inherit(&mut self, idx: usize, donor: &Structured, range: Range<usize>) {
+        let inside = |p: usize| range.start <= p && p <= range.end;
+        for rel in donor.relations.iter().filter(|rel| rel.enabled) {
+            if !rel.field_positions().all(|p| inside(p) && p + rel.size <= range.end) || !inside(rel.anchor) || !inside(rel.insert) {
+                continue;
+            }
+
+            let shift = |p: usize| p - range.start + idx;
+            let mut spliced = Relation::new(shift(rel.pos), rel.value, rel.size, rel.le, shift(rel.anchor), shift(rel.insert));
+            spliced.mirrors = rel.mirrors.iter().map(|m| shift(*m)).collect();
+            spliced.bits = rel.bits;
+            spliced.stride = rel.stride;
+            spliced.bias = rel.bias;
+            spliced.ascii = rel.ascii;
+            if self.is_consistent(&spliced) {
+                self.add_relation(spliced);
+            }
+        }
+    }
+
+    /// Checks that the field of `rel` holds its value, and that the value matches the length of its region.
+    fn is_consistent(&self, rel: &Relation) -> bool {
+        let span = (rel.value as usize).checked_mul(rel.stride).and_then(|span| span.checked_add(rel.bias));
+        if rel.insert < rel.anchor || span != Some(rel.insert - rel.anchor) || rel.field_positions().any(|p| p + rel.size > self.raw.len()) {
+            return false;
+        }
+
+        let mut applied = self.raw.clone();
+        rel.apply(&mut applied);
+        applied == self.raw
+    }
+
+    pub fn remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        for rel in self.relations.iter_mut() {
+            if !rel.enabled {
+                continue;
+            }
+
+            if rel.on_remove(idx, size).is_err() {
+                return Err(InvalidEdit);
+            }
+        }
+        self.track_offsets(idx, size, false)?;
+        self.track_regions(idx, size, false);
+
+        self.raw.drain(idx..idx + size);
+
+        self.sanitize();
+
+        Ok(())
+    }
+
+    pub fn insert_disabling(&mut self, idx: usize, data: &[u8]) {
+        let disabled = self.track_insert(idx, data.len());
+        self.drop_offsets(idx, data.len(), true);
+        self.track_regions(idx, data.len(), true);
+
+        self.raw.splice(idx..idx, data.iter().cloned());
+
+        self.remove_relations(disabled);
+
+        self.sanitize();
+    }
+
+    pub fn remove_disabling(&mut self, idx: usize, size: usize) {
+        let mut disabled = vec![];
+        for (i, rel) in self.relations.iter_mut().enumerate() {
+            if !rel.enabled {
+                continue;
+            }
+
+            if rel.on_remove(idx, size).is_err() {
+                disabled.push(i);
+            }
+        }
+        self.drop_offsets(idx, size, false);
+        self.track_regions(idx, size, false);
+
+        self.raw.drain(idx..idx + size);
+
+        self.remove_relations(disabled);
+
+        self.sanitize();
+    }
+
+    /// Updates all enabled relations for an insertion of `size` bytes at `idx`, returning the indices of relations
+    /// which became invalid.
+    ///
+    /// A relation nested inside another (its field lies in the parent's region) can grow while the parent's
+    /// inferred region does not reach the insertion point. The size delta is then cascaded to every enclosing
+    /// relation so that outer lengths stay consistent with inner ones.
+    ///
+    /// The relations contained in a region which is moved by the insertion (see `moved_members`) are rebased with
+    /// it rather than grown.
+    fn track_insert(&mut self, idx: usize, size: usize) -> Vec<usize> {
+        let members = self.moved_members(idx);
+
+        let mut invalid = vec![];
+        let mut grown = vec![];
+        for (i, rel) in self.relations.iter_mut().enumerate() {
+            if !rel.enabled {
+                continue;
+            }
+
+            if members.contains(&i) {
+                rel.rebase(idx, size);
+                continue;
+            }
+
+            let value = rel.value;
+            if rel.on_insert(idx, size).is_err() {
+                invalid.push(i);
+            } else if rel.value != value {
+                grown.push(i);
+            }
+        }
+
+        let mut queue = grown.clone();
+        while let Some(child) = queue.pop() {
+            let Some(parent) = self.parent_of(child) else {
+                continue;
+            };
+
+            if grown.contains(&parent) || invalid.contains(&parent) {
+                continue;
+            }
+
+            // Like `Relation::on_insert`, the value can only count whole units and must fit the field.
+            let rel = &mut self.relations[parent];
+            if size % rel.stride != 0 || rel.value + (size / rel.stride) as u64 > rel.max_value() {
+                invalid.push(parent);
+                continue;
+            }
+            rel.value += (size / rel.stride) as u64;
+            rel.insert += size;
+
+            grown.push(parent);
+            queue.push(parent);
+        }
+
+        invalid
+    }
+
+    /// Returns the relations belonging to the region which starts at `idx`, when an insertion at `idx` is claimed by
+    /// another relation ending there (e.g. an offset pointing to the region, or the length of the preceding region).
+    ///
+    /// The inserted bytes then come before the region, which moves as a whole: its members (fields at or after its
+    /// start which are anchored at it, such as pointers relative to a table start) keep their value, even though
+    /// their own anchor is not crossed.
+    fn moved_members(&self, idx: usize) -> Vec<usize> {
+        let members = self.relations.iter()
+            .enumerate()
+            .filter(|(_, rel)| rel.enabled && rel.anchor == idx && rel.pos >= idx)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if members.is_empty() {
+            return members;
+        }
+
+        let claimed = self.relations.iter()
+            .enumerate()
+            .any(|(i, rel)| rel.enabled && rel.insert == idx && rel.anchor < idx && !members.contains(&i))
+            || self.offsets.iter().any(|offset| offset.insert == idx && offset.anchor < idx);
+        if claimed { members } else { vec![] }
+    }
+
+    /// Updates the constants and strings for an insertion (or removal) of `size` bytes at `idx`, dropping the ones
+    /// it splits.
+    fn track_regions(&mut self, idx: usize, size: usize, insert: bool) {
+        self.constants.retain_mut(|c| if insert { c.on_insert(idx, size) } else { c.on_remove(idx, size) }.is_ok());
+        self.strings.retain_mut(|s| if insert { s.on_insert(idx, size) } else { s.on_remove(idx, size) }.is_ok());
+    }
+
+    /// Updates the offsets for an insertion (or removal) of `size` bytes at `idx`, failing if one of them can't be
+    /// (the offsets are then partially updated, like the relations).
+    fn track_offsets(&mut self, idx: usize, size: usize, insert: bool) -> Result<(), InvalidEdit> {
+        for offset in self.offsets.iter_mut() {
+            if insert { offset.on_insert(idx, size) } else { offset.on_remove(idx, size) }?;
+        }
+        Ok(())
+    }
+
+    /// Like `track_offsets`, dropping the offsets which can't be updated.
+    fn drop_offsets(&mut self, idx: usize, size: usize, insert: bool) {
+        self.offsets.retain_mut(|o| if insert { o.on_insert(idx, size) } else { o.on_remove(idx, size) }.is_ok());
+    }
+
+    /// Looks for the strings of the input: runs of at least `min_len` printable characters followed by a NUL, and
+    /// printable regions of at least `min_len` bytes right after their 1 or 2-byte length field.
+    ///
+    /// Runs overlapping the field of a relation are skipped.
+    pub fn detect_strings(&mut self, min_len: usize) {
+        let fields = self.relations.iter()
+            .filter(|rel| rel.enabled)
+            .flat_map(|rel| rel.field_positions().flat_map(move |pos| pos..pos + rel.size))
+            .collect::<HashSet<_>>();
+        let printable = |b: &u8| b.is_ascii_graphic() || *b == b' ' || *b == b'\t';
+
+        self.strings.clear();
+
+        let mut start = 0;
+        while start < self.raw.len() {
+            let len = self.raw[start..].iter().take_while(|b| printable(b)).count();
+            let end = start + len;
+            if len >= min_len && self.raw.get(end) == Some(&0) && !(start..=end).any(|i| fields.contains(&i)) {
+                self.strings.push(StringRegion { pos: start, len, terminated: true });
+            }
+            start = end + 1;
+        }
+
+        for rel in self.relations.iter().filter(|rel| rel.enabled && rel.size <= 2 && rel.bits.is_none() && !rel.ascii && rel.stride == 1) {
+            let region = rel.anchor..rel.insert;
+            if rel.anchor != rel.pos + rel.size || region.len() < min_len || region.end > self.raw.len() {
+                continue;
+            }
+            if self.raw[region.clone()].iter().all(printable) && !self.strings.iter().any(|s| s.pos == region.start) {
+                self.strings.push(StringRegion { pos: region.start, len: region.len(), terminated: false });
+            }
+        }
+
+        self.strings.sort_by_key(|s| s.pos);
+    }
+
+    /// Appends `data` to string `k` (before its terminator), updating all relations.
+    pub fn extend_string(&mut self, k: usize, data: &[u8]) -> Result<(), InvalidEdit> {
+        let end = self.strings[k].pos + self.strings[k].len;
+        self.save_relations();
+        let res = self.insert(end, data);
+        if res.is_err() {
+            self.restore_relations();
+        }
+        res
+    }
+
+    /// Removes the last `size` characters of string `k` (keeping at least one and its terminator), updating all
+    /// relations.
+    pub fn shrink_string(&mut self, k: usize, size: usize) -> Result<(), InvalidEdit> {
+        let (pos, len) = (self.strings[k].pos, self.strings[k].len);
+        if size == 0 || size >= len {
+            return Err(InvalidEdit);
+        }
+        self.save_relations();
+        let res = self.remove(pos + len - size, size);
+        if res.is_err() {
+            self.restore_relations();
+        }
+        res
+    }
+
+    /// Whether every constant still holds its bytes.
+    pub fn constants_intact(&self) -> bool {
+        self.constants.iter().all(|c| self.raw.get(c.range()) == Some(c.bytes.as_slice()))
+    }
+
+    /// Writes back the bytes of every constant (e.g. after a mutation clobbered a signature).
+    pub fn repair_constants(&mut self) {
+        for c in self.constants.iter() {
+            if let Some(bytes) = self.raw.get_mut(c.range()) {
+                bytes.copy_from_slice(&c.bytes);
+            }
+        }
+    }
+
+    /// Returns the innermost enabled relation whose region contains the field of relation `idx`.
+    pub fn parent_of(&self, idx: usize) -> Option<usize> {
+        let child = &self.relations[idx];
+        self.relations.iter()
+            .enumerate()
+            .filter(|(i, rel)| {
+                *i != idx && rel.enabled
+                    && rel.anchor <= child.pos && child.pos + child.size <= rel.insert
+            })
+            .min_by_key(|(_, rel)| rel.insert - rel.anchor)
+            .map(|(i, _)| i)
+    }
+
+    /// Order in which relations are applied: nested relations come before the relations enclosing them.
+    ///
+    /// An enclosed region is never larger than its parent, so sorting by region size is a valid ordering.
+    fn apply_order(&self) -> Vec<usize> {
+        let mut order = (0..self.relations.len())
+            .filter(|i| self.relations[*i].enabled)
+            .collect::<Vec<_>>();
+        order.sort_by_key(|i| self.relations[*i].insert.saturating_sub(self.relations[*i].anchor));
+        order
+    }
+
+    pub fn sanitize(&mut self) {
+        self.fit_ascii_fields();
+
+        let len = self.raw.len();
+        for rel in self.relations.iter_mut().filter(|rel| rel.enabled && rel.whole_file) {
+            rel.track_end(len);
+        }
+
+        for i in self.apply_order() {
+            self.relations[i].apply(self.raw.as_mut());
+        }
+        for offset in self.offsets.iter() {
+            offset.apply(&mut self.raw);
+        }
+    }
+
+    /// Grows or shrinks the ASCII fields (see `Relation::ascii`) whose value no longer has as many digits as the field,
+    /// by inserting or removing digits at the end of the field. The other relations are updated as for any insertion
+    /// or removal there, and the ones which can't be are removed.
+    ///
+    /// Resizing a field can change the value of an enclosing ASCII field in turn, so this repeats until all of them fit.
+    fn fit_ascii_fields(&mut self) {
+        for _ in 0..self.relations.len() * MAX_ASCII_DIGITS {
+            let Some(i) = self.relations.iter().position(|rel| rel.enabled && rel.ascii && rel.digits() != rel.size) else {
+                return;
+            };
+            let (end, size, digits) = (self.relations[i].pos + self.relations[i].size, self.relations[i].size, self.relations[i].digits());
+
+            // The field itself is resized below.
+            self.relations[i].enabled = false;
+            let invalid = if digits > size {
+                let invalid = self.track_insert(end, digits - size);
+                self.drop_offsets(end, digits - size, true);
+                self.track_regions(end, digits - size, true);
+                self.raw.splice(end..end, std::iter::repeat_n(b'0', digits - size));
+                invalid
+            } else {
+                let start = end - (size - digits);
+                let invalid = self.relations.iter_mut()
+                    .enumerate()
+                    .filter(|(_, rel)| rel.enabled)
+                    .filter_map(|(j, rel)| rel.on_remove(start, size - digits).is_err().then_some(j))
+                    .collect::<Vec<_>>();
+                self.drop_offsets(start, size - digits, false);
+                self.track_regions(start, size - digits, false);
+                self.raw.drain(start..end);
+                invalid
+            };
+
+            self.relations[i].enabled = true;
+            self.relations[i].resize_digits(digits);
+            self.remove_relations(invalid);
+        }
+    }
+
+    pub fn sanitize_buffer(&self, buf: &mut [u8]) {
+        for i in self.apply_order() {
+            let rel = &self.relations[i];
+            if rel.whole_file && rel.insert != buf.len() {
+                let mut tracked = rel.clone();
+                tracked.track_end(buf.len());
+                tracked.apply(buf);
+            } else {
+                rel.apply(buf);
+            }
+        }
+        for offset in self.offsets.iter() {
+            offset.apply(buf);
+        }
+    }
+
+    pub fn inflection_points(&self) -> HashSet<usize> {
+        let mut points = HashSet::new();
+        for rel in self.relations.iter() {
+            // Only use 4 and 8 byte fields as indirect pointers.
+            if rel.size == 4 || rel.size == 8 {
+                points.insert(rel.pos);
+                points.insert(rel.anchor);
+                points.insert(rel.insert);
+            }
+        }
+        for offset in self.offsets.iter().filter(|offset| offset.size == 4 || offset.size == 8) {
+            points.extend([offset.pos, offset.anchor, offset.insert]);
+        }
+        points
+    }
+
+    pub fn insertion_points(&self) -> Vec<usize> {
+        let mut points = HashSet::new();
+        points.insert(self.raw.len());
+        for rel in self.relations.iter() {
+            points.insert(rel.insert);
+        }
+        // Insertions right before pointed-to data move it.
+        for offset in self.offsets.iter() {
+            points.insert(offset.insert);
+        }
+        // Strings grow before their terminator.
+        for s in self.strings.iter() {
+            points.insert(s.pos + s.len);
+        }
+        points.into_iter().collect()
+    }
+
+    /// Splits the input into chunks delimited by the fields, anchors and insertion points of all relations.
+    pub fn chunks(&self) -> Vec<Range<usize>> {
+        let len = self.raw.len();
+
+        let mut points = vec![0, len];
+        for rel in self.relations.iter().filter(|rel| rel.enabled) {
+            points.extend([rel.pos, rel.pos + rel.size, rel.anchor, rel.insert]);
+        }
+        for offset in self.offsets.iter() {
+            points.extend([offset.pos, offset.pos + offset.size, offset.anchor, offset.insert]);
+        }
+        points.retain(|p| *p <= len);
+        points.sort();
+        points.dedup();
+
+        points.windows(2).map(|w| w[0]..w[1]).collect()
+    }
+
+    /// Extracts constant byte sequences which likely identify the format or its chunks, up to `max_len` bytes each.
+    ///
+    /// These are the bytes between a size field and its anchor (e.g. a chunk type tag following a length) and the
+    /// bytes preceding the first field (e.g. a file magic). Sequences overlapping a field or consisting only of zeros
+    /// are skipped.
+    pub fn tokens(&self, max_len: usize) -> Vec<Vec<u8>> {
+        let fields = self.relations.iter()
+            .filter(|rel| rel.enabled)
+            .flat_map(|rel| rel.field_positions().map(move |pos| pos..pos + rel.size))
+            .collect::<Vec<_>>();
+
+        let mut regions = vec![];
+        if let Some(first) = fields.iter().map(|f| f.start).min() {
+            regions.push(0..first);
+        }
+        for rel in self.relations.iter().filter(|rel| rel.enabled) {
+            if rel.anchor > rel.pos + rel.size {
+                regions.push(rel.pos + rel.size..rel.anchor);
+            } else if rel.anchor < rel.pos {
+                regions.push(rel.anchor..rel.pos);
+            }
+        }
+
+        let mut tokens = vec![];
+        for region in regions {
+            if region.is_empty() || region.len() > max_len || region.end > self.raw.len() {
+                continue;
+            }
+            if fields.iter().any(|f| f.start < region.end && region.start < f.end) {
+                continue;
+            }
+
+            let token = self.raw[region].to_vec();
+            if token.iter().all(|b| *b == 0) || tokens.contains(&token) {
+                continue;
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    /// Generates a new input with the structure of this one (used as a template), where `below(n)` draws a random
+    /// number in `0..n`.
+    ///
+    /// A few regions are duplicated (a sibling chunk is inserted after them), dropped or resized, then the bytes which
+    /// do not carry structure (fields, tags between a field and its anchor, constants, strings) are randomized chunk
+    /// by chunk, and all fields are recomputed. Edits which would break a relation are skipped, so the result is
+    /// consistent with its relations.
+    pub fn generate<R>(&self, below: &mut R) -> Structured
+    where
+        R: FnMut(usize) -> usize
+    {
+        let mut out = self.clone();
+
+        for _ in 0..1 + below(MAX_GENERATION_EDITS) {
+            let regions = (0..out.relations.len()).filter(|i| out.region_of(*i).is_some()).collect::<Vec<_>>();
+            if regions.is_empty() || out.fixed_size {
+                break;
+            }
+            let idx = regions[below(regions.len())];
+            let region = out.region_of(idx).unwrap();
+
+            let mut edited = out.clone();
+            let res = match below(3) {
+                0 => edited.splice_region(region.end, &out, idx),
+                1 => {
+                    // The relations of the chunk go with it.
+                    let inner = (0..edited.relations.len())
+                        .filter(|i| edited.relations[*i].field_positions().all(|p| region.start <= p && p < region.end))
+                        .collect::<Vec<_>>();
+                    edited.remove_relations(inner);
+                    edited.remove(region.start, region.len())
+                }
+                _ => {
+                    let rel = &out.relations[idx];
+                    let value = (below(2 * rel.value as usize + 2) as u64).min(rel.max_value());
+                    edited.resize_region(idx, value, 0)
+                }
+            };
+            if res.is_ok() {
+                out = edited;
+            }
+        }
+
+        let mut structural = vec![false; out.raw.len()];
+        for rel in out.relations.iter().filter(|rel| rel.enabled) {
+            for pos in rel.field_positions() {
+                structural[pos..(pos + rel.size).min(out.raw.len())].fill(true);
+            }
+            if rel.anchor > rel.pos + rel.size {
+                structural[rel.pos + rel.size..rel.anchor.min(out.raw.len())].fill(true);
+            }
+        }
+        for offset in out.offsets.iter() {
+            structural[offset.pos.min(out.raw.len())..(offset.pos + offset.size).min(out.raw.len())].fill(true);
+        }
+        for range in out.constants.iter().map(Constant::range).chain(out.strings.iter().map(StringRegion::range)) {
+            structural[range.start.min(out.raw.len())..range.end.min(out.raw.len())].fill(true);
+        }
+
+        for chunk in out.chunks() {
+            if below(2) == 0 {
+                continue;
+            }
+            for i in chunk.filter(|i| !structural[*i]) {
+                out.raw[i] = below(0x100) as u8;
+            }
+        }
+
+        out.sanitize();
+        out
+    }
+
+    /// Removes the relation at `idx` (the indices of later relations shift down).
+    ///
+    /// The other members of its group stay grouped, unless a single one is left: the group is then dissolved.
+    pub fn remove_relation(&mut self, idx: usize) -> Relation {
+        let rel = self.relations.remove(idx);
+        if let Some(id) = rel.group {
+            let members = self.relations.iter().filter(|r| r.group == Some(id)).count();
+            if members < 2 {
+                for r in self.relations.iter_mut().filter(|r| r.group == Some(id)) {
+                    r.group = None;
+                }
+                self.groups.retain(|g| g.id != id);
+            }
+        }
+        rel
+    }
+
+    pub fn set_relation_enabled(&mut self, idx: usize, enabled: bool) {
+        self.relations[idx].enabled = enabled;
+    }
+
+    pub fn save_relations(&mut self) {
+        for rel in self.relations.iter_mut() {
+            rel.save();
+        }
+        for offset in self.offsets.iter_mut() {
+            offset.save();
+        }
+        self.old_constants.clone_from(&self.constants);
+        self.old_strings.clone_from(&self.strings);
+    }
+
+    pub fn restore_relations(&mut self) {
+        for rel in self.relations.iter_mut() {
+            rel.restore();
+        }
+        for offset in self.offsets.iter_mut() {
+            offset.restore();
+        }
+        self.constants.clone_from(&self.old_constants);
+        self.strings.clone_from(&self.old_strings);
+    }
+
+    /// Renders a colorized hexdump of the input followed by a legend of the enabled relations.
+    ///
+    /// Each relation gets its own color: the bytes of its field (and mirrors) are highlighted, its anchor is
+    /// underlined and the bytes between its anchor and insertion point are printed in its color (the innermost
+    /// region wins when regions are nested).
+    pub fn render_annotated(&self) -> String {
+        let len = self.raw.len();
+        let enabled = (0..self.relations.len()).filter(|i| self.relations[*i].enabled).collect::<Vec<_>>();
+        let color = |k: usize| PALETTE[k % PALETTE.len()];
+
+        let mut field = vec![None; len];
+        let mut anchor = vec![None; len];
+        let mut region: Vec<Option<(usize, usize)>> = vec![None; len];
+        for (k, idx) in enabled.iter().enumerate() {
+            let rel = &self.relations[*idx];
+            let (start, end) = (rel.anchor.min(rel.insert).min(len), rel.anchor.max(rel.insert).min(len));
+            for slot in region[start..end].iter_mut() {
+                if slot.is_none_or(|(_, width)| end - start < width) {
+                    *slot = Some((k, end - start));
+                }
+            }
+            if rel.anchor < len {
+                anchor[rel.anchor] = Some(k);
+            }
+            for pos in rel.field_positions() {
+                field[pos.min(len)..(pos + rel.size).min(len)].fill(Some(k));
+            }
+        }
+
+        let mut out = String::new();
+        for (line, chunk) in self.raw.chunks(16).enumerate() {
+            out.push_str(&format!("{:08x}: ", line * 16));
+            for i in 0..16 {
+                let b = line * 16 + i;
+                if i > 0 {
+                    out.push(' ');
+                }
+                if i >= chunk.len() {
+                    out.push_str("  ");
+                    continue;
+                }
+                let hex = format!("{:02x}", chunk[i]);
+                let hex = match (field[b], region[b], anchor[b]) {
+                    (Some(k), _, _) => hex.black().on_color(color(k)),
+                    (None, _, Some(a)) => hex.color(color(a)).underline().bold(),
+                    (None, Some((k, _)), None) => hex.color(color(k)),
+                    (None, None, None) => hex.normal(),
+                };
+                out.push_str(&hex.to_string());
+            }
+            let ascii = chunk.iter().map(|b| if b.is_ascii_graphic() { *b as char } else { '.' }).collect::<String>();
+            out.push_str(&format!("  {}\n", ascii));
+        }
+
+        out.push('\n');
+        for (k, idx) in enabled.iter().enumerate() {
+            let rel = &self.relations[*idx];
+            let mut desc = format!(
+                "#{} field {:#x} ({} bytes, {}) = {}, anchor {:#x}, insert {:#x}",
+                idx, rel.pos, rel.size, if rel.le { "le" } else { "be" }, rel.value, rel.anchor, rel.insert
+            );
+            if !rel.mirrors.is_empty() {
+                desc.push_str(&format!(", mirrors {:x?}", rel.mirrors));
+            }
+            if let Some(bits) = &rel.bits {
+                desc.push_str(&format!(", bits {}..{}", bits.shift, bits.shift + bits.width));
+            }
+            if rel.stride != 1 {
+                desc.push_str(&format!(", in units of {} bytes", rel.stride));
+            }
+            if rel.whole_file {
+                desc.push_str(", to the end of the input");
+            }
+            if let Some(group) = rel.group {
+                desc.push_str(&format!(", group {}", group));
+            }
+            out.push_str(&format!("{} {}\n", "  ".on_color(color(k)), desc.color(color(k))));
+        }
+        for offset in self.offsets.iter() {
+            out.push_str(&format!(
+                "   offset {:#x} ({} bytes, {}) = {}, anchor {:#x}, pointing to {:#x}\n",
+                offset.pos, offset.size, if offset.le { "le" } else { "be" }, offset.value(), offset.anchor, offset.insert
+            ));
+        }
+        for c in self.constants.iter() {
+            out.push_str(&format!("   constant {:#x} ({} bytes)\n", c.pos, c.bytes.len()));
+        }
+        for s in self.strings.iter() {
+            let kind = if s.terminated { "NUL-terminated" } else { "length-prefixed" };
+            out.push_str(&format!("   string {:#x} ({} bytes, {})\n", s.pos, s.len, kind));
+        }
+        let disabled = self.relations.len() - enabled.len();
+        if disabled > 0 {
+            out.push_str(&format!("({} disabled relations not shown)\n", disabled));
+        }
+        out
+    }
+}
+
+
+/// Bytes the target checks for an exact value (e.g. the `\x89PNG` signature): corrupting any of them loses nearly all
+/// coverage, and no length field accounts for the loss.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Constant {
+    pub pos: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl Constant {
+    pub fn range(&self) -> Range<usize> {
+        self.pos..self.pos + self.bytes.len()
+    }
+
+    /// Shifts the constant for an insertion of `size` bytes at `idx`. Fails if the insertion splits it.
+    pub fn on_insert(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if idx <= self.pos {
+            self.pos += size;
+        } else if idx < self.pos + self.bytes.len() {
+            return Err(InvalidEdit);
+        }
+        Ok(())
+    }
+
+    /// Shifts the constant for a removal of `size` bytes at `idx`. Fails if the removal overlaps it.
+    pub fn on_remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if idx + size <= self.pos {
+            self.pos -= size;
+        } else if idx < self.pos + self.bytes.len() {
+            return Err(InvalidEdit);
+        }
+        Ok(())
+    }
+}
+
+/// A string of the input: NUL-terminated (a C string), or a length-prefixed (Pascal) string whose length field is a
+/// relation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StringRegion {
+    pub pos: usize,
+    /// Length of the characters, without the terminator.
+    pub len: usize,
+    /// The characters are followed by a NUL byte.
+    pub terminated: bool,
+}
+
+impl StringRegion {
+    /// Bytes of the string, including its terminator.
+    pub fn range(&self) -> Range<usize> {
+        self.pos..self.pos + self.len + self.terminated as usize
+    }
+
+    /// Updates the string for an insertion of `size` bytes at `idx`: insertions within the characters (or right
+    /// before the terminator) extend it.
+    pub fn on_insert(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if idx <= self.pos {
+            self.pos += size;
+        } else if idx <= self.pos + self.len {
+            self.len += size;
+        }
+        Ok(())
+    }
+
+    /// Updates the string for a removal of `size` bytes at `idx`. Fails if the removal reaches beyond the characters
+    /// (e.g. the terminator) or removes all of them.
+    pub fn on_remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if idx + size <= self.pos {
+            self.pos -= size;
+        } else if idx >= self.range().end {
+            // After the string.
+        } else if idx >= self.pos && idx + size <= self.pos + self.len && size < self.len {
+            self.len -= size;
+        } else {
+            return Err(InvalidEdit);
+        }
+        Ok(())
+    }
+}
+
+/// A table of offset fields (e.g. section header offsets) which share the same anchor.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelationGroup {
+    pub id: usize,
+    pub size: usize,
+    pub le: bool,
+}
+
+
+/// Search heuristic involved in finding a relation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Heuristic {
+    /// The field value was observed as a comparison operand, so the field was tested first.
+    Cmplog,
+    /// Anchor right after the field (allowing for up to 7 bytes of padding).
+    LocalAnchor,
+    /// Anchor at the start of the input or at the field itself.
+    FieldAnchor,
+    /// Anchor found by scanning the inflection points of the known relations.
+    InflectionPoint,
+    /// Only a fallback filler of the fill strategy recovered the lost coverage.
+    FallbackFiller,
+    /// Entry of an offset table found next to another relation.
+    Table,
+    /// Length packed into some bits of a byte.
+    Bitfield,
+    /// Length of the rest of the input, found by appending at its end.
+    WholeFile,
+    /// Only a smaller value of the field (with a removal at the end of its region) revealed the relation.
+    Shrink,
+    /// Given in advance as a format hint (not searched, and never pruned).
+    Hint,
+    /// The region only matched with some of its bytes left out of the value (see `Relation::bias`).
+    HeaderBias,
+    /// Length written as ASCII decimal digits.
+    AsciiLength,
+    /// Insertion point found by bisecting the insertion points of the field after no anchor matched.
+    Bisection,
+    /// Offset pointing past the field, found by moving the pointed-to data (see `OffsetRelation`).
+    Offset,
+}
+
+impl Heuristic {
+    /// Classifies the anchor of a field at `pos` (of `size` bytes) by the heuristic which proposes it.
+    pub fn of_anchor(pos: usize, size: usize, anchor: usize) -> Self {
+        if (pos + size..=pos + size + 7).contains(&anchor) {
+            Heuristic::LocalAnchor
+        } else if anchor == 0 || anchor == pos {
+            Heuristic::FieldAnchor
+        } else {
+            Heuristic::InflectionPoint
+        }
+    }
+}
+
+/// Bits of a byte holding a length (e.g. the low nibble of a flags byte).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitfield {
+    /// Position of the lowest bit of the value.
+    pub shift: u8,
+    /// Number of bits of the value.
+    pub width: u8,
+}
+
+impl Bitfield {
+    pub fn mask(&self) -> u8 {
+        (((1u16 << self.width) - 1) << self.shift) as u8
+    }
+
+    pub fn max_value(&self) -> u64 {
+        (1 << self.width) - 1
+    }
+
+    /// Extracts the value from `byte`.
+    pub fn read(&self, byte: u8) -> u64 {
+        ((byte & self.mask()) >> self.shift) as u64
+    }
+
+    /// Returns `byte` with its bits replaced by `value` (truncated to the width of the field).
+    pub fn write(&self, byte: u8, value: u64) -> u8 {
+        (byte & !self.mask()) | (((value & self.max_value()) as u8) << self.shift)
+    }
+}
+
+fn default_stride() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Relation {
+    pub pos: usize,
+    pub value: u64,
+    pub size: usize,
+    pub le: bool,
+    pub anchor: usize,
+    pub insert: usize,
+
+    /// Used during validation to efficiently turn off relations that are invalid.
+    pub enabled: bool,
+
+    /// Positions of copies of this field which must always hold the same value.
+    #[serde(default)]
+    pub mirrors: Vec<usize>,
+
+    /// Id of the `RelationGroup` this relation belongs to.
+    #[serde(default)]
+    pub group: Option<usize>,
+
+    /// For 1-byte fields, the bits of the byte holding the value (the other bits are left untouched).
+    #[serde(default)]
+    pub bits: Option<Bitfield>,
+
+    /// Unit of the value in bytes (e.g. 4 for a length counted in dwords).
+    #[serde(default = "default_stride")]
+    pub stride: usize,
+
+    /// The region spans the rest of the input (e.g. a RIFF size or a PE `SizeOfImage`): its insertion point always
+    /// follows the end of the input, however its length changes.
+    #[serde(default)]
+    pub whole_file: bool,
+
+    /// Bytes of the region which the value does not count (e.g. a header of 8 bytes before a payload whose length is
+    /// stored): the region spans `value * stride + bias` bytes. Insertions and removals only change the counted part.
+    #[serde(default)]
+    pub bias: usize,
+
+    /// The value is written as ASCII decimal digits (e.g. an HTTP `Content-Length`), and `size` is the number of digits,
+    /// which grows and shrinks with the value (see `Structured::sanitize`). `le` is unused.
+    #[serde(default)]
+    pub ascii: bool,
+
+    /// Search heuristics which produced this relation (empty for imported or hand-written relations).
+    #[serde(default)]
+    pub heuristics: Vec<Heuristic>,
+
+    /// Number of times re-validating the relation confirmed (or refuted) that the field still matters.
+    #[serde(default)]
+    pub confirmed: u32,
+    #[serde(default)]
+    pub refuted: u32,
+
+    /// Used to restore the relation to its previous state.
+    pub old_pos: usize,
+    pub old_anchor: usize,
+    pub old_insert: usize,
+    pub old_value: u64,
+    #[serde(default)]
+    pub old_mirrors: Vec<usize>,
+}
+
+
+impl Relation {
+    pub fn new(pos: usize, value: u64, size: usize, le: bool, anchor: usize, insert: usize) -> Self {
+        Self {
+            pos,
+            value,
+            size,
+            le,
+            anchor,
+            insert,
+            enabled: true,
+            mirrors: Vec::new(),
+            group: None,
+            bits: None,
+            stride: 1,
+            whole_file: false,
+            bias: 0,
+            ascii: false,
+            heuristics: Vec::new(),
+            confirmed: 0,
+            refuted: 0,
+            old_pos: pos,
+            old_anchor: anchor,
+            old_insert: insert,
+            old_value: value,
+            old_mirrors: Vec::new(),
+        }
+    }
+
+    pub fn on_insert(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        // Error if insert is inside the field (or one of its mirrors).
+        for pos in self.field_positions() {
+            if idx > pos && idx < pos + self.size {
+                return Err(InvalidEdit);
+            }
+        }
+
+        // Check if we should update the value of the field.
+        if idx >= self.anchor && idx <= self.insert {
+            // The value can only count whole units.
+            if size % self.stride != 0 {
+                return Err(InvalidEdit);
+            }
+            self.value += (size / self.stride) as u64;
+
+            // Check if we've overflowed the field.
+            if self.value > self.max_value() {
+                return Err(InvalidEdit);
+            }
+        }
+
+        // Move the field.
+        if idx <= self.pos {
+            self.pos += size;
+        }
+
+        for mirror in self.mirrors.iter_mut() {
+            if idx <= *mirror {
+                *mirror += size;
+            }
+        }
+
+        // Move the anchor point.
+        // Anchor point of 0 is locked.
+        if idx < self.anchor {
+            self.anchor += size;
+        }
+
+        // Move the insert point.
+        if idx <= self.insert {
+            self.insert += size;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the relation along with the region it belongs to, for an insertion of `size` bytes at `idx` right before
+    /// the region: every position from `idx` on is shifted and the value is unchanged.
+    pub fn rebase(&mut self, idx: usize, size: usize) {
+        for pos in [&mut self.pos, &mut self.anchor, &mut self.insert].into_iter().chain(self.mirrors.iter_mut()) {
+            if *pos >= idx {
+                *pos += size;
+            }
+        }
+    }
+
+    /// Confidence that the relation is real, from 1.0 (never refuted) towards 0.0.
+    pub fn confidence(&self) -> f64 {
+        (self.confirmed + 1) as f64 / (self.confirmed + self.refuted + 1) as f64
+    }
+
+    /// Largest value representable by the field.
+    pub fn max_value(&self) -> u64 {
+        if let Some(bits) = &self.bits {
+            return bits.max_value();
+        }
+        if self.ascii {
+            return 10u64.pow(MAX_ASCII_DIGITS as u32) - 1;
+        }
+        max_field_value(self.size)
+    }
+
+    pub fn on_remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        // Error if remove overlaps the field (or one of its mirrors).
+        for pos in self.field_positions() {
+            if idx < pos + self.size && idx + size > pos {
+                return Err(InvalidEdit);
+            }
+        }
+
+        let pre_pos = if idx < self.pos {
+            (self.pos - idx).min(size)
+        } else {
+            0
+        };
+
+        let pre_anchor = if idx < self.anchor {
+            (self.anchor - idx).min(size)
+        } else {
+            0
+        };
+
+        let pre_insert = if idx < self.insert {
+            (self.insert - idx).min(size)
+        } else {
+            0
+        };
+
+        let overlap_min = idx.clamp(self.anchor, self.insert);
+        let overlap_max = (idx + size).clamp(self.anchor, self.insert);
+
+        let insert_overlap = overlap_max - overlap_min;
+
+        // Adjust the field value (which can only count whole units).
+        if insert_overlap % self.stride != 0 || ((insert_overlap / self.stride) as u64) > self.value {
+            return Err(InvalidEdit);
+        } else {
+            self.value -= (insert_overlap / self.stride) as u64;
+        }
+
+        // Adjust positions.
+        self.pos -= pre_pos;
+        self.anchor -= pre_anchor;
+        self.insert -= pre_insert;
+
+        for mirror in self.mirrors.iter_mut() {
+            if idx < *mirror {
+                *mirror -= (*mirror - idx).min(size);
+            }
+        }
+
+        Ok(())
+
+    }
+
+    pub fn apply(&self, input: &mut [u8]) {
+        if let Some(bits) = &self.bits {
+            for pos in self.field_positions() {
+                input[pos] = bits.write(input[pos], self.value);
+            }
+            return;
+        }
+
+        // ASCII fields are resized to their value before being applied, so only values which do not fit (e.g. lies)
+        // are clamped, and smaller ones are padded with zeros.
+        if self.ascii {
+            let max = 10u64.checked_pow(self.size as u32).map_or(u64::MAX, |m| m - 1);
+            let digits = format!("{:0width$}", self.value.min(max), width = self.size);
+            for pos in self.field_positions() {
+                input[pos..pos + self.size].copy_from_slice(digits.as_bytes());
+            }
+            return;
+        }
+
+        // Write the value of the field to the input
+        let byt = encode_field(self.value, self.size, self.le);
+
+        for pos in self.field_positions() {
+            input[pos..pos + self.size].copy_from_slice(&byt[..self.size]);
+        }
+    }
+
+    /// Number of digits of the value of an ASCII field.
+    pub fn digits(&self) -> usize {
+        self.value.checked_ilog10().map_or(1, |log| log as usize + 1)
+    }
+
+    /// Resizes an ASCII field to `digits` digits at its end, moving the positions after it. A region enclosing the
+    /// field grows or shrinks with it.
+    pub fn resize_digits(&mut self, digits: usize) {
+        let end = self.pos + self.size;
+        let moved = |p: usize| if p >= end { p + digits - self.size } else { p };
+        if self.anchor <= self.pos && end <= self.insert {
+            self.value = (self.value + digits as u64).saturating_sub(self.size as u64);
+        }
+        (self.anchor, self.insert) = (moved(self.anchor), moved(self.insert));
+        self.size = digits;
+    }
+
+    /// Positions of the field and all of its mirrors.
+    pub fn field_positions(&self) -> impl Iterator<Item = usize> + '_ {
+        std::iter::once(self.pos).chain(self.mirrors.iter().cloned())
+    }
+
+    /// Moves the insertion point of a whole-file relation to the end of an input of `len` bytes.
+    pub fn track_end(&mut self, len: usize) {
+        if self.anchor <= len {
+            self.insert = len;
+            self.value = ((len.saturating_sub(self.anchor + self.bias) / self.stride) as u64).min(self.max_value());
+        }
+    }
+
+    pub fn save(&mut self) {
+        self.old_pos = self.pos;
+        self.old_anchor = self.anchor;
+        self.old_insert = self.insert;
+        self.old_value = self.value;
+        self.old_mirrors.clone_from(&self.mirrors);
+    }
+
+    pub fn restore(&mut self) {
+        self.pos = self.old_pos;
+        self.anchor = self.old_anchor;
+        self.insert = self.old_insert;
+        self.value = self.old_value;
+        self.mirrors.clone_from(&self.old_mirrors);
+    }
+}
+
+/// Encodes `value` as a field of `size` bytes (truncated to its width).
+fn encode_field(value: u64, size: usize, le: bool) -> Vec<u8> {
+    match (size, le) {
+        (1, _) => (value as u8).to_le_bytes().to_vec(),
+        (2, true) => (value as u16).to_le_bytes().to_vec(),
+        (2, false) => (value as u16).to_be_bytes().to_vec(),
+        (3, true) => (value as u32).to_le_bytes()[0..3].to_vec(),
+        (3, false) => (value as u32).to_be_bytes()[1..4].to_vec(),
+        (4, true) => (value as u32).to_le_bytes().to_vec(),
+        (4, false) => (value as u32).to_be_bytes().to_vec(),
+        (8, true) => value.to_le_bytes().to_vec(),
+        (8, false) => value.to_be_bytes().to_vec(),
+        _ => panic!("Unsupported size")
+    }
+}
+
+/// Largest value of a field of `size` bytes.
+fn max_field_value(size: usize) -> u64 {
+    match size {
+        1 => 0xff,
+        2 => 0xffff,
+        3 => 0xffffff,
+        4 => 0xffffffff,
+        8 => 0xffffffffffffffff,
+        _ => panic!("Unsupported size")
+    }
+}
+
+/// A field holding the position of data elsewhere in the input, counted from `anchor` (e.g. the offset of a ZIP
+/// central directory, or of a PE data directory), which usually points far past the field.
+///
+/// Unlike the region of a `Relation`, the pointed-to data has no known end and moves as a whole: an insertion at
+/// `insert` (or anywhere between the anchor and it) comes before the data and moves it, and a removal of its first byte
+/// is invalid, since the field would then point to other data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OffsetRelation {
+    pub pos: usize,
+    pub size: usize,
+    pub le: bool,
+    pub anchor: usize,
+    /// Position of the pointed-to data.
+    pub insert: usize,
+
+    /// Search heuristics which produced this offset.
+    #[serde(default)]
+    pub heuristics: Vec<Heuristic>,
+
+    /// Used to restore the offset to its previous state.
+    #[serde(default)]
+    pub old_pos: usize,
+    #[serde(default)]
+    pub old_anchor: usize,
+    #[serde(default)]
+    pub old_insert: usize,
+}
+
+impl OffsetRelation {
+    pub fn new(pos: usize, size: usize, le: bool, anchor: usize, insert: usize) -> Self {
+        Self {
+            pos,
+            size,
+            le,
+            anchor,
+            insert,
+            heuristics: Vec::new(),
+            old_pos: 0,
+            old_anchor: 0,
+            old_insert: 0,
+        }
+    }
+
+    /// Value of the field.
+    pub fn value(&self) -> u64 {
+        self.insert.saturating_sub(self.anchor) as u64
+    }
+
+    /// Moves the offset for an insertion of `size` bytes at `idx`. Fails if the insertion splits the field or the
+    /// value no longer fits in it.
+    pub fn on_insert(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        if idx > self.pos && idx < self.pos + self.size {
+            return Err(InvalidEdit);
+        }
+
+        if idx <= self.pos {
+            self.pos += size;
+        }
+        // Anchor point of 0 is locked.
+        if idx < self.anchor {
+            self.anchor += size;
+        }
+        if idx <= self.insert {
+            self.insert += size;
+        }
+
+        if self.value() > max_field_value(self.size) {
+            return Err(InvalidEdit);
+        }
+        Ok(())
+    }
+
+    /// Moves the offset for a removal of `size` bytes at `idx`. Fails if the removal overlaps the field, the first
+    /// byte of the pointed-to data, or strictly contains the anchor.
+    pub fn on_remove(&mut self, idx: usize, size: usize) -> Result<(), InvalidEdit> {
+        let end = idx + size;
+        if idx < self.pos + self.size && end > self.pos || (idx..end).contains(&self.insert) || (idx < self.anchor && self.anchor < end) {
+            return Err(InvalidEdit);
+        }
+
+        for pos in [&mut self.pos, &mut self.anchor, &mut self.insert] {
+            if idx < *pos {
+                *pos -= (*pos - idx).min(size);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, input: &mut [u8]) {
+        input[self.pos..self.pos + self.size].copy_from_slice(&encode_field(self.value(), self.size, self.le));
+    }
+
+    pub fn save(&mut self) {
+        self.old_pos = self.pos;
+        self.old_anchor = self.anchor;
+        self.old_insert = self.insert;
+    }
+
+    pub fn restore(&mut self) {
+        self.pos = self.old_pos;
+        self.anchor = self.old_anchor;
+        self.insert = self.old_insert;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_size1() {
+        // ....FFFF|........|....
+        let base = Relation::new(4, 8, 4, true, 8, 16);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(0, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 9);
+        assert_eq!(rel.insert, 17);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(4, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 9);
+        assert_eq!(rel.insert, 17);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(5, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 8);
+        assert_eq!(rel.insert, 17);
+        assert_eq!(rel.value, 9);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 8);
+        assert_eq!(rel.insert, 17);
+        assert_eq!(rel.value, 9);
+    }
+
+    #[test]
+    fn test_insert_size2() {
+        // ....FFFF....|........|....
+        let base = Relation::new(4, 8, 4, true, 12, 20);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(0, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 13);
+        assert_eq!(rel.insert, 21);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(4, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 13);
+        assert_eq!(rel.insert, 21);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(5, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 13);
+        assert_eq!(rel.insert, 21);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 12);
+        assert_eq!(rel.insert, 21);
+        assert_eq!(rel.value, 9);
+    }
+
+    #[test]
+    fn test_insert_offset() {
+        // |....FFFF....|....
+        let base = Relation::new(4, 12, 4, true, 0, 12);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(0, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 13);
+        assert_eq!(rel.value, 13);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(4, 1).is_ok());
+        assert_eq!(rel.pos, 5);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 13);
+        assert_eq!(rel.value, 13);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(5, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 13);
+        assert_eq!(rel.value, 13);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 13);
+        assert_eq!(rel.value, 13);
+    }
+
+    #[test]
+    fn test_remove_size1() {
+        // ....FFFF|........|....
+        let base = Relation::new(4, 8, 4, true, 8, 16);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(0, 1).is_ok());
+        assert_eq!(rel.pos, 3);
+        assert_eq!(rel.anchor, 7);
+        assert_eq!(rel.insert, 15);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(4, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(7, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 8);
+        assert_eq!(rel.insert, 15);
+        assert_eq!(rel.value, 7);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 8);
+        assert_eq!(rel.insert, 15);
+        assert_eq!(rel.value, 7);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(16, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 8);
+        assert_eq!(rel.insert, 16);
+        assert_eq!(rel.value, 8);
+    }
+
+    #[test]
+    fn test_remove_size2() {
+        // ....FFFF....|........|....
+        let base = Relation::new(4, 8, 4, true, 12, 20);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(0, 1).is_ok());
+        assert_eq!(rel.pos, 3);
+        assert_eq!(rel.anchor, 11);
+        assert_eq!(rel.insert, 19);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(4, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(7, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 11);
+        assert_eq!(rel.insert, 19);
+        assert_eq!(rel.value, 8);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 12);
+        assert_eq!(rel.insert, 19);
+        assert_eq!(rel.value, 7);
+    }
+
+    #[test]
+    fn test_remove_offset() {
+        // |....FFFF....|....
+        let base = Relation::new(4, 12, 4, true, 0, 12);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(0, 1).is_ok());
+        assert_eq!(rel.pos, 3);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 11);
+        assert_eq!(rel.value, 11);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(4, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(7, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(8, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 11);
+        assert_eq!(rel.value, 11);
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(12, 1).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.anchor, 0);
+        assert_eq!(rel.insert, 12);
+        assert_eq!(rel.value, 12);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let rels = vec![
+            Relation::new(4, 8, 4, true, 8, 16),
+            Relation::new(4, 8, 4, true, 12, 20),
+            Relation::new(4, 12, 4, true, 0, 12)
+        ];
+
+        for base in rels {
+            for i in 0..20 {
+                for size in 1..5 {
+                    let mut rel = base.clone();
+                    if rel.on_insert(i, size).is_ok() {
+                        let mut rel2 = rel.clone();
+                        assert!(rel2.on_remove(i, size).is_ok());
+                        assert_eq!(rel2, base);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mirrors() {
+        // ....FFFF|........|MMMM
+        let mut base = Relation::new(4, 8, 4, true, 8, 16);
+        base.mirrors.push(16);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(12, 2).is_ok());
+        assert_eq!(rel.pos, 4);
+        assert_eq!(rel.mirrors, vec![18]);
+        assert_eq!(rel.value, 10);
+
+        let mut rel = base.clone();
+        assert!(rel.on_insert(17, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(17, 1).is_err());
+
+        let mut rel = base.clone();
+        assert!(rel.on_remove(0, 2).is_ok());
+        assert_eq!(rel.pos, 2);
+        assert_eq!(rel.mirrors, vec![14]);
+
+        let mut buf = vec![0; 20];
+        base.apply(&mut buf);
+        assert_eq!(&buf[4..8], &[8, 0, 0, 0]);
+        assert_eq!(&buf[16..20], &[8, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_group_disabling() {
+        // ....AAAABBBB|....aaaa....bbbb|....
+        let mut input = Structured::raw(vec![0; 36]);
+        input.add_relation(Relation::new(4, 8, 4, true, 12, 20));
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        input.add_group(0, vec![Relation::new(8, 16, 4, true, 12, 28)]);
+        assert_eq!(input.relations.len(), 3);
+        assert_eq!(input.groups.len(), 1);
+
+        // Inserting inside the first table entry invalidates the whole table.
+        input.insert_disabling(6, &[0x41]);
+        assert_eq!(input.relations.len(), 1);
+        assert_eq!(input.relations[0].size, 1);
+        assert!(input.groups.is_empty());
+    }
+
+    #[test]
+    fn test_remove_grouped_relation() {
+        // ....AAAABBBBCCCC|....aaaa....bbbb....cccc
+        let mut input = Structured::raw(vec![0; 40]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        input.add_relation(Relation::new(4, 4, 4, true, 16, 20));
+        input.add_group(1, vec![Relation::new(8, 4, 4, true, 16, 28), Relation::new(12, 4, 4, true, 16, 36)]);
+
+        // Removing a member keeps the rest of the table, later relations shift down.
+        let removed = input.remove_relation(2);
+        assert_eq!(removed.pos, 8);
+        assert_eq!(input.relations.iter().map(|r| (r.pos, r.group)).collect::<Vec<_>>(), vec![(0, None), (4, Some(0)), (12, Some(0))]);
+        assert_eq!(input.groups.len(), 1);
+
+        // A single member left is no table.
+        input.remove_relation(1);
+        assert_eq!(input.relations.iter().map(|r| (r.pos, r.group)).collect::<Vec<_>>(), vec![(0, None), (12, None)]);
+        assert!(input.groups.is_empty());
+    }
+
+    #[test]
+    fn test_chunks() {
+        // ....FFFF|........|....
+        let mut input = Structured::raw(vec![0; 20]);
+        input.add_relation(Relation::new(4, 8, 4, true, 8, 16));
+        assert_eq!(input.chunks(), vec![0..4, 4..8, 8..16, 16..20]);
+    }
+
+    #[test]
+    fn test_extend_after() {
+        // OO|II|....|....
+        let mut input = Structured::raw(vec![0; 12]);
+        input.add_relation(Relation::new(0, 10, 1, true, 2, 12));
+        input.add_relation(Relation::new(2, 4, 1, true, 4, 8));
+
+        input.extend_after(1, &[0x41, 0x41]).unwrap();
+        assert_eq!(input.get_raw().len(), 14);
+        assert_eq!(input.relations[1].value, 4);
+        assert_eq!(input.relations[0].value, 12);
+        assert_eq!(&input.get_raw()[0..4], &[12, 0, 4, 0]);
+        assert_eq!(&input.get_raw()[8..10], &[0x41, 0x41]);
+    }
+
+    #[test]
+    fn test_resize_region() {
+        // O|I|....|..
+        let mut input = Structured::raw(vec![7, 4, 1, 2, 3, 4, 5, 6]);
+        input.add_relation(Relation::new(0, 7, 1, true, 1, 8));
+        input.add_relation(Relation::new(1, 4, 1, true, 2, 6));
+
+        input.resize_region(1, 6, 0x41).unwrap();
+        assert_eq!(input.get_raw(), &[9, 6, 1, 2, 3, 4, 0x41, 0x41, 5, 6]);
+
+        input.resize_region(1, 1, 0x41).unwrap();
+        assert_eq!(input.get_raw(), &[4, 1, 1, 5, 6]);
+
+        // The outer region can not shrink over the inner field.
+        input.resize_region(1, 0, 0x41).unwrap();
+        let before = input.clone();
+        assert!(input.resize_region(0, 0, 0x41).is_err());
+        assert_eq!(input.get_raw(), before.get_raw());
+        assert_eq!((input.relations[0].value, input.relations[0].insert), (3, 4));
+    }
+
+    #[test]
+    fn test_splice_region() {
+        // O|I|....|..
+        let mut input = Structured::raw(vec![8, 4, 1, 2, 3, 4, 5, 6]);
+        input.add_relation(Relation::new(0, 7, 1, true, 1, 8));
+        input.add_relation(Relation::new(1, 4, 1, true, 2, 6));
+
+        // ..|L|...
+        let mut donor = Structured::raw(vec![0xaa, 0xbb, 3, 7, 8, 9]);
+        donor.add_relation(Relation::new(2, 3, 1, true, 3, 6));
+
+        // Splice the donor chunk at the end of the outer region.
+        input.splice_region(8, &donor, 0).unwrap();
+        assert_eq!(input.get_raw(), &[11, 4, 1, 2, 3, 4, 5, 6, 3, 7, 8, 9]);
+        assert_eq!(input.relations.len(), 3);
+
+        let rel = &input.relations[2];
+        assert_eq!((rel.pos, rel.anchor, rel.insert, rel.value), (8, 9, 12, 3));
+
+        // Offsets have no region to splice.
+        let mut offset = Structured::raw(vec![2, 0, 0]);
+        offset.add_relation(Relation::new(0, 2, 1, true, 0, 2));
+        let before = input.clone();
+        assert!(input.splice_region(8, &offset, 0).is_err());
+        assert_eq!(input, before);
+    }
+
+    #[test]
+    fn test_splice_from() {
+        let mut input = Structured::raw(vec![2, 1, 2, 0xff]);
+        input.add_relation(Relation::new(0, 2, 1, true, 1, 3));
+
+        // .|L|...|L|..
+        let mut donor = Structured::raw(vec![0xaa, 3, 7, 8, 9, 2, 5, 6, 0xbb]);
+        donor.add_relation(Relation::new(1, 3, 1, true, 2, 5));
+        donor.add_relation(Relation::new(5, 2, 1, true, 6, 8));
+
+        // Only the first relation fits in the range.
+        input.splice_from(3, &donor, 1..6).unwrap();
+        assert_eq!(input.get_raw(), &[7, 1, 2, 3, 7, 8, 9, 2, 0xff]);
+        assert_eq!(input.relations.len(), 2);
+        let rel = &input.relations[1];
+        assert_eq!((rel.pos, rel.anchor, rel.insert, rel.value), (3, 4, 7, 3));
+
+        // A stale relation (its field no longer holds its value) is not carried over.
+        let mut stale = donor.clone();
+        stale.raw[5] = 4;
+        let mut input = Structured::raw(vec![0xff]);
+        input.splice_from(0, &stale, 5..8).unwrap();
+        assert_eq!(input.get_raw(), &[4, 5, 6, 0xff]);
+        assert!(input.relations.is_empty());
+
+        assert!(input.splice_from(0, &donor, 5..10).is_err());
+    }
+
+    #[test]
+    fn test_splice_tail() {
+        // L|..|.
+        let mut input = Structured::raw(vec![2, 1, 2, 0xff, 0xff]);
+        input.add_relation(Relation::new(0, 2, 1, true, 1, 3));
+        input.add_relation(Relation::new(3, 1, 1, true, 4, 5));
+
+        // L|..|L|...
+        let mut donor = Structured::raw(vec![2, 1, 2, 3, 7, 8, 9]);
+        donor.add_relation(Relation::new(0, 2, 1, true, 1, 3));
+        donor.add_relation(Relation::new(3, 3, 1, true, 4, 7));
+
+        // The second relation of the input is cut, the second one of the donor is carried over.
+        input.splice_tail(3, &donor).unwrap();
+        assert_eq!(input.get_raw(), &[2, 1, 2, 3, 7, 8, 9]);
+        let rels = input.relations.iter().filter(|rel| rel.enabled).map(|rel| (rel.pos, rel.anchor, rel.insert, rel.value)).collect::<Vec<_>>();
+        assert_eq!(rels, vec![(0, 1, 3, 2), (3, 4, 7, 3)]);
+
+        assert!(input.splice_tail(8, &donor).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        // Two chunks: a 1-byte length, a tag, and a payload.
+        let mut input = Structured::raw(vec![2, 0xaa, 1, 2, 3, 0xbb, 4, 5, 6]);
+        input.add_relation(Relation::new(0, 2, 1, true, 2, 4));
+        input.add_relation(Relation::new(4, 3, 1, true, 6, 9));
+
+        let mut state = 7u64;
+        let mut below = |n: usize| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) as usize) % n
+        };
+
+        let mut distinct = HashSet::new();
+        for _ in 0..32 {
+            let out = input.generate(&mut below);
+            distinct.insert(out.raw.clone());
+
+            // Every length still matches its region, and the tags are kept.
+            for rel in out.relations.iter().filter(|rel| rel.enabled) {
+                assert_eq!(out.raw[rel.pos] as usize, rel.insert - rel.anchor);
+                assert!([0xaa, 0xbb].contains(&out.raw[rel.pos + 1]));
+            }
+        }
+        assert!(distinct.len() > 8);
+    }
+
+    #[test]
+    fn test_whole_file() {
+        // A RIFF-like header: the size counts every byte after it.
+        let mut input = Structured::raw(b"RIFF\x08\x00\x00\x00WAVEdata".to_vec());
+        let mut rel = Relation::new(4, 8, 4, true, 8, 16);
+        rel.whole_file = true;
+        input.add_relation(rel);
+
+        input.insert(16, b"more").unwrap();
+        assert_eq!(input.raw[4..8], [12, 0, 0, 0]);
+
+        // Even if the length changes behind the back of the relations.
+        input.raw.truncate(14);
+        input.sanitize();
+        assert_eq!(input.raw[4..8], [6, 0, 0, 0]);
+        assert_eq!(input.relations[0].insert, 14);
+
+        let mut buf = input.raw.clone();
+        buf.push(0);
+        input.sanitize_buffer(&mut buf);
+        assert_eq!(buf[4..8], [7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_tokens() {
+        // MAGI|LLLL|TYPE|....|LLLL|....
+        let mut raw = b"MAGI".to_vec();
+        raw.extend([4, 0, 0, 0]);
+        raw.extend(b"TYPE");
+        raw.extend([0x41; 4]);
+        raw.extend([0, 0, 0, 0]);
+        raw.extend([0x42; 4]);
+
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(4, 4, 4, true, 12, 16));
+        input.add_relation(Relation::new(16, 0, 4, true, 20, 20));
+        assert_eq!(input.tokens(8), vec![b"MAGI".to_vec(), b"TYPE".to_vec()]);
+        assert_eq!(input.tokens(2), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn test_nested_cascade() {
+        // OO..II|......|..|....
+        // The inner region extends past the inferred end of the outer region.
+        let mut input = Structured::raw(vec![0; 20]);
+        input.add_relation(Relation::new(0, 10, 2, true, 2, 12));
+        input.add_relation(Relation::new(4, 8, 2, true, 6, 14));
+        assert_eq!(input.parent_of(1), Some(0));
+        assert_eq!(input.parent_of(0), None);
+
+        // Growing the inner relation also grows the enclosing one.
+        input.insert(13, &[0x41, 0x41]).unwrap();
+        assert_eq!(input.relations[1].value, 10);
+        assert_eq!(input.relations[0].value, 12);
+        assert_eq!(input.relations[0].insert, 14);
+        assert_eq!(&input.get_raw()[0..6], &[12, 0, 0, 0, 10, 0]);
+
+        // Inserting inside both regions does not count twice.
+        input.insert(7, &[0x41]).unwrap();
+        assert_eq!(input.relations[1].value, 11);
+        assert_eq!(input.relations[0].value, 13);
+    }
+
+    #[test]
+    fn test_insert_disabling_cascade() {
+        // O|..II..........|.........CC|..........|
+        // Growing I overflows its parent O, which is disabled along with C (split by the insertion): the disabled
+        // relations are not in index order.
+        let mut input = Structured::raw(vec![0; 300]);
+        input.add_relation(Relation::new(0, 250, 1, true, 1, 251));
+        input.add_relation(Relation::new(10, 288, 2, true, 12, 300));
+        input.add_relation(Relation::new(279, 2, 2, true, 281, 281));
+        input.sanitize();
+
+        input.insert_disabling(280, &[0x41; 10]);
+        assert_eq!(input.relations.len(), 1);
+        assert_eq!((input.relations[0].pos, input.relations[0].value, input.relations[0].insert), (10, 298, 310));
+    }
+
+    #[test]
+    fn test_nested_rebase() {
+        // MMMMOOOO........|II......|..JJ....|..
+        // An absolute offset (O) points to a table whose first field (I) points into the table, relative to its
+        // start. The target of I holds another relative pointer (J).
+        let mut input = Structured::raw(vec![0; 40]);
+        input.add_relation(Relation::new(4, 16, 4, true, 0, 16));
+        input.add_relation(Relation::new(16, 8, 2, true, 16, 24));
+        input.add_relation(Relation::new(26, 6, 2, true, 24, 30));
+        input.sanitize();
+
+        // Moving the table moves the pointers it contains, without changing them.
+        input.insert(16, &[0x41; 4]).unwrap();
+        assert_eq!(input.relations[0].value, 20);
+        assert_eq!((input.relations[1].pos, input.relations[1].anchor, input.relations[1].insert), (20, 20, 28));
+        assert_eq!(input.relations[1].value, 8);
+        assert_eq!((input.relations[2].pos, input.relations[2].anchor, input.relations[2].insert), (30, 28, 34));
+        assert_eq!(input.relations[2].value, 6);
+
+        // Inserting before the target of I grows I and moves J.
+        input.insert(28, &[0x41; 4]).unwrap();
+        assert_eq!(input.relations[0].value, 20);
+        assert_eq!(input.relations[1].value, 12);
+        assert_eq!((input.relations[2].pos, input.relations[2].anchor, input.relations[2].insert), (34, 32, 38));
+        assert_eq!(input.relations[2].value, 6);
+        assert_eq!(&input.get_raw()[4..8], &[20, 0, 0, 0]);
+        assert_eq!(&input.get_raw()[20..22], &[12, 0]);
+        assert_eq!(&input.get_raw()[34..36], &[6, 0]);
+
+        // Without a relation ending at the anchor, an insertion there grows the region.
+        let mut rel = Relation::new(26, 6, 2, true, 24, 30);
+        assert!(rel.on_insert(24, 1).is_ok());
+        assert_eq!(rel.value, 7);
+    }
+
+    #[test]
+    fn test_oob_relation() {
+        let mut rel = Relation::new(0, 0x30, 1, true, 0, 1);
+        assert!(rel.on_insert(0, 0x40).is_ok());
+        assert!(rel.on_insert(1, 0xf0).is_err());
+    }
+
+    #[test]
+    fn test_render_annotated() {
+        colored::control::set_override(false);
+
+        let mut raw = b"HDR!".to_vec();
+        raw.extend([4, 0, 0, 0]);
+        raw.extend(b"DATA");
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(4, 4, 4, true, 8, 12));
+        input.add_relation(Relation::new(0, 0, 1, true, 0, 0));
+        input.set_relation_enabled(1, false);
+
+        let out = input.render_annotated();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "00000000: 48 44 52 21 04 00 00 00 44 41 54 41              HDR!....DATA");
+        assert_eq!(lines[2], "   #0 field 0x4 (4 bytes, le) = 4, anchor 0x8, insert 0xc");
+        assert_eq!(lines[3], "(1 disabled relations not shown)");
+    }
+
+    #[test]
+    fn test_boundary_variants() {
+        // L|....|TT
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+
+        let variants = input.boundary_variants(0, 0x42);
+        let raws = variants.iter().map(|v| v.get_raw().to_vec()).collect::<Vec<_>>();
+
+        // Lies keep the length, and disable the relation.
+        assert!(raws.contains(&vec![0, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]));
+        assert!(raws.contains(&vec![0xff, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]));
+        assert!(raws.contains(&vec![5, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]));
+        assert!(variants.iter().filter(|v| v.get_raw().len() == 7).all(|v| !v.relations[0].enabled));
+
+        // Consistent variants resize the region.
+        assert!(raws.contains(&vec![0, 0x54, 0x54]));
+        assert!(raws.contains(&vec![3, 0x41, 0x41, 0x41, 0x54, 0x54]));
+        assert!(raws.contains(&vec![8, 0x41, 0x41, 0x41, 0x41, 0x42, 0x42, 0x42, 0x42, 0x54, 0x54]));
+        assert!(!raws.contains(&input.get_raw().to_vec()));
+
+        let mut fixed = input.clone();
+        fixed.fixed_size = true;
+        assert!(fixed.boundary_variants(0, 0x42).iter().all(|v| v.get_raw().len() == 7));
+    }
+
+    #[test]
+    fn test_overrun() {
+        // L|....|TT
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        assert_eq!(input.overrun(0), None);
+
+        // The lies larger than the region reach into the trailer.
+        input.get_raw_mut()[0] = 6;
+        assert_eq!(input.overrun(0), Some(2));
+        input.get_raw_mut()[0] = 3;
+        assert_eq!(input.overrun(0), None);
+
+        let mut strided = input.clone();
+        strided.relations[0].stride = 2;
+        assert_eq!(strided.overrun(0), Some(2));
+    }
+
+    #[test]
+    fn test_bitfield() {
+        // F|....|TT, the length is the low nibble of the flags byte.
+        let mut input = Structured::raw(vec![0xa4, 0x41, 0x41, 0x41, 0x41, 0x54, 0x54]);
+        let mut rel = Relation::new(0, 4, 1, true, 1, 5);
+        rel.bits = Some(Bitfield { shift: 0, width: 4 });
+        input.add_relation(rel);
+        assert_eq!(input.relations[0].max_value(), 15);
+
+        input.insert(3, &[0x42; 3]).unwrap();
+        assert_eq!(input.get_raw()[0], 0xa7);
+        assert_eq!(input.relations[0].value, 7);
+
+        // The value does not fit in the nibble.
+        assert!(input.insert(3, &[0x42; 9]).is_err());
+        assert_eq!(input.get_raw()[0], 0xa7);
+
+        let bits = Bitfield { shift: 2, width: 6 };
+        assert_eq!(bits.mask(), 0xfc);
+        assert_eq!(bits.read(0xf3), 0x3c);
+        assert_eq!(bits.write(0x03, 5), 0x17);
+    }
+
+    #[test]
+    fn test_stride() {
+        // LL|........|TT, the length counts 4-byte units.
+        let mut raw = vec![2, 0];
+        raw.extend([0x41; 8]);
+        raw.extend([0x54, 0x54]);
+        let mut input = Structured::raw(raw);
+        let mut rel = Relation::new(0, 2, 2, true, 2, 10);
+        rel.stride = 4;
+        input.add_relation(rel);
+
+        input.insert(6, &[0x42; 4]).unwrap();
+        assert_eq!(&input.get_raw()[0..2], &[3, 0]);
+        assert_eq!(input.relations[0].insert, 14);
+
+        // Partial units can not be represented.
+        assert!(input.insert(6, &[0x42; 3]).is_err());
+        assert!(input.remove(2, 6).is_err());
+
+        input.remove(2, 8).unwrap();
+        assert_eq!(&input.get_raw()[0..2], &[1, 0]);
+
+        input.resize_region(0, 2, 0x43).unwrap();
+        assert_eq!(input.get_raw().len(), 12);
+        assert_eq!(input.relations[0].insert, 10);
+    }
+
+    #[test]
+    fn test_stride_cascade() {
+        // OO..II|....|....|......
+        // The outer length counts 4-byte units, and the inner region extends past the inferred end of the outer one.
+        let mut input = Structured::raw(vec![0; 20]);
+        let mut rel = Relation::new(0, 2, 2, true, 2, 10);
+        rel.stride = 4;
+        input.add_relation(rel);
+        input.add_relation(Relation::new(4, 8, 2, true, 6, 14));
+        input.sanitize();
+
+        input.insert(13, &[0x41; 4]).unwrap();
+        assert_eq!(input.relations[1].value, 12);
+        assert_eq!((input.relations[0].value, input.relations[0].insert), (3, 14));
+        assert_eq!(&input.get_raw()[0..2], &[3, 0]);
+
+        // Partial units of the outer length can not be represented.
+        input.insert_disabling(15, &[0x41; 2]);
+        assert_eq!(input.relations.len(), 1);
+        assert_eq!(input.relations[0].value, 14);
+    }
+
+    #[test]
+    fn test_header_bias() {
+        // LL|HHHH|....|TT, the length only counts the payload after the 4-byte header.
+        let mut raw = vec![4, 0];
+        raw.extend([0x48; 4]);
+        raw.extend([0x41; 4]);
+        raw.extend([0x54, 0x54]);
+        let mut input = Structured::raw(raw);
+        let mut rel = Relation::new(0, 4, 2, true, 2, 10);
+        rel.bias = 4;
+        input.add_relation(rel);
+
+        input.insert(8, &[0x42; 2]).unwrap();
+        assert_eq!(&input.get_raw()[0..2], &[6, 0]);
+        assert_eq!(input.relations[0].insert, 12);
+
+        // The header is never counted, so the region can't shrink below it.
+        assert!(input.remove(2, 8).is_err());
+        input.resize_region(0, 0, 0x43).unwrap();
+        assert_eq!(input.get_raw().len(), 8);
+        assert_eq!(input.relations[0].insert, 6);
+
+        // Nor is it when tracking the end of the input.
+        input.relations[0].whole_file = true;
+        input.raw.extend([0x41; 3]);
+        input.sanitize();
+        assert_eq!(&input.get_raw()[0..2], &[5, 0]);
+    }
+
+    #[test]
+    fn test_ascii_length() {
+        // T|L:9\n|.........|E, with a 1-byte length of the whole message at 0.
+        let mut raw = b"\x0fL:9\n".to_vec();
+        raw.extend([0x41; 9]);
+        raw.push(b'E');
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(0, 14, 1, true, 1, 15));
+        let mut rel = Relation::new(3, 9, 1, true, 5, 14);
+        rel.ascii = true;
+        input.add_relation(rel);
+
+        // The field grows a digit, which the enclosing length counts too.
+        input.insert(14, b"B").unwrap();
+        assert_eq!(&input.get_raw()[..6], b"\x10L:10\n");
+        assert_eq!((input.relations[1].size, input.relations[1].anchor, input.relations[1].insert), (2, 6, 16));
+        assert_eq!(input.get_raw()[16], b'E');
+
+        input.remove(6, 2).unwrap();
+        assert_eq!(&input.get_raw()[..5], b"\x0dL:8\n");
+        assert_eq!(input.get_raw().len(), 14);
+        assert_eq!(input.relations[0].insert, 14);
+
+        // Lies which do not fit are clamped.
+        let mut lie = input.relations[1].clone();
+        lie.value = 123;
+        lie.apply(input.get_raw_mut());
+        assert_eq!(input.get_raw()[3], b'9');
+    }
+
+    #[test]
+    fn test_constants() {
+        // MAGI|L|....|TAG
+        let mut raw = b"MAGI".to_vec();
+        raw.push(4);
+        raw.extend([0x41; 4]);
+        raw.extend(b"TAG");
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(4, 4, 1, true, 5, 9));
+        input.constants = vec![Constant { pos: 0, bytes: b"MAGI".to_vec() }, Constant { pos: 9, bytes: b"TAG".to_vec() }];
+
+        // Constants after an insertion shift along with it.
+        input.insert(7, &[0x42; 2]).unwrap();
+        assert_eq!(input.constants[1].pos, 11);
+        assert!(input.constants_intact());
+
+        input.get_raw_mut()[1] = 0;
+        assert!(!input.constants_intact());
+        input.repair_constants();
+        assert_eq!(&input.get_raw()[..4], b"MAGI");
+
+        // A removal overlapping a constant drops it.
+        input.remove_disabling(2, 1);
+        assert_eq!(input.constants, vec![Constant { pos: 10, bytes: b"TAG".to_vec() }]);
+    }
+
+    #[test]
+    fn test_strings() {
+        // L|name\0|LL|abcdef|
+        let mut raw = vec![7];
+        raw.extend(b"name\0");
+        raw.extend([0, 0, 6, 0]);
+        raw.extend(b"abcdef");
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(0, 7, 1, true, 1, 8));
+        input.add_relation(Relation::new(8, 6, 2, true, 10, 16));
+        input.detect_strings(4);
+
+        assert_eq!(input.strings, vec![
+            StringRegion { pos: 1, len: 4, terminated: true },
+            StringRegion { pos: 10, len: 6, terminated: false },
+        ]);
+
+        // Extending a string keeps its terminator and the enclosing length consistent.
+        input.extend_string(0, b"xy").unwrap();
+        assert_eq!(&input.get_raw()[..8], b"\x09namexy\0");
+        assert_eq!(input.strings[1].pos, 12);
+
+        input.shrink_string(1, 2).unwrap();
+        assert_eq!(&input.get_raw()[10..], &[4, 0, b'a', b'b', b'c', b'd']);
+        assert!(input.shrink_string(1, 4).is_err());
+
+        // Removing the terminator drops the string.
+        input.remove_disabling(7, 1);
+        assert_eq!(input.strings.len(), 1);
+    }
+
+    #[test]
+    fn test_offset_relation() {
+        // OO|L|abc|......|DATA
+        let mut raw = vec![12, 0, 3];
+        raw.extend(b"abc");
+        raw.extend([0xee; 6]);
+        raw.extend(b"DATA");
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(2, 3, 1, true, 3, 6));
+        input.offsets.push(OffsetRelation::new(0, 2, true, 0, 12));
+
+        // Growing a region before the data moves it.
+        input.insert(4, b"xy").unwrap();
+        assert_eq!(&input.get_raw()[..3], &[14, 0, 5]);
+        assert_eq!(&input.get_raw()[14..], b"DATA");
+
+        // Inserting at the data comes before it, inserting inside it does not move it.
+        input.insert(14, b"zz").unwrap();
+        assert_eq!(input.offsets[0].insert, 16);
+        input.insert(17, b"!").unwrap();
+        assert_eq!(input.offsets[0].value(), 16);
+        assert_eq!(&input.get_raw()[16..], b"D!ATA");
+        assert!(input.insertion_points().contains(&16));
+
+        // Removing the start of the data (or the field) is invalid, removing the bytes before it is not.
+        input.save_relations();
+        assert!(input.remove(15, 2).is_err());
+        input.restore_relations();
+        assert!(input.remove(1, 1).is_err());
+        input.restore_relations();
+        input.remove(8, 4).unwrap();
+        assert_eq!(&input.get_raw()[..2], &[12, 0]);
+        assert_eq!(&input.get_raw()[12..], b"D!ATA");
+
+        // Offsets which no longer fit in their field are dropped by the disabling insertions.
+        let mut small = Structured::raw(vec![2, 0, 0]);
+        small.offsets.push(OffsetRelation::new(0, 1, true, 0, 2));
+        assert!(small.clone().insert(1, &[0; 0x100]).is_err());
+        small.insert_disabling(1, &[0; 0x100]);
+        assert!(small.offsets.is_empty());
+    }
+}
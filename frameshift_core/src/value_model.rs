@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::{filler::format_profile, structured::{Relation, Structured}};
+
+/// Maximum number of distinct values remembered per field.
+const MAX_DISTINCT: usize = 64;
+
+/// Values observed for one field (by position) across the inputs of a format profile.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldValues {
+    pub size: usize,
+    pub le: bool,
+    /// Largest value of the field (see `Relation::max_value`).
+    pub max_value: u64,
+    /// Number of inputs in which the field was observed.
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    /// Distinct observed values (only the first `MAX_DISTINCT`).
+    pub values: BTreeSet<u64>,
+    /// More than `MAX_DISTINCT` distinct values were observed.
+    pub saturated: bool,
+}
+
+impl FieldValues {
+    fn new(rel: &Relation) -> Self {
+        let value = rel.value;
+        Self {
+            size: rel.size,
+            le: rel.le,
+            max_value: rel.max_value(),
+            count: 1,
+            min: value,
+            max: value,
+            values: BTreeSet::from([value]),
+            saturated: false,
+        }
+    }
+
+    fn observe(&mut self, value: u64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if !self.values.contains(&value) {
+            if self.values.len() < MAX_DISTINCT {
+                self.values.insert(value);
+            } else {
+                self.saturated = true;
+            }
+        }
+    }
+
+    /// Picks an in-distribution value from `r`: one of the observed values, or any value of the observed range once
+    /// there are too many distinct values to enumerate.
+    pub fn in_distribution(&self, r: u64) -> u64 {
+        if !self.saturated {
+            return *self.values.iter().nth((r % self.values.len() as u64) as usize).unwrap();
+        }
+        match (self.max - self.min).checked_add(1) {
+            Some(span) => self.min + r % span,
+            None => r,
+        }
+    }
+
+    /// Picks a value outside of the observed range from `r` (just past the bounds, far past them or the extremes of
+    /// the field), or None if the observed range covers every value of the field.
+    pub fn out_of_distribution(&self, r: u64) -> Option<u64> {
+        let max_value = self.max_value;
+
+        let candidates = [
+            self.max.checked_add(1),
+            self.max.checked_mul(2),
+            self.max.checked_add(0x100),
+            Some(max_value),
+            Some(max_value >> 1),
+            self.min.checked_sub(1),
+            Some(0),
+        ];
+
+        let candidates = candidates.into_iter()
+            .flatten()
+            .filter(|v| *v <= max_value && (*v < self.min || *v > self.max))
+            .collect::<BTreeSet<_>>();
+
+        candidates.iter().nth((r % candidates.len().max(1) as u64) as usize).cloned()
+    }
+}
+
+/// Distribution of field values across a corpus, per format profile and field position.
+///
+/// Fields of inputs with the same format at the same position are assumed to be the same field (e.g. the length
+/// of a header), which holds well for headers and less so for later chunks.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FieldValueModel {
+    profiles: HashMap<String, BTreeMap<usize, FieldValues>>,
+}
+
+impl FieldValueModel {
+    /// Records the values of the enabled relations of `input`.
+    pub fn observe(&mut self, input: &Structured) {
+        let fields = self.profiles.entry(format_profile(input.get_raw())).or_default();
+        for rel in input.relations.iter().filter(|rel| rel.enabled) {
+            match fields.get_mut(&rel.pos) {
+                // The digits of an ASCII field grow with its value.
+                Some(values) if values.max_value == rel.max_value() && values.le == rel.le && (values.size == rel.size || rel.ascii) => {
+                    values.observe(rel.value)
+                }
+                // A different field at the same position, the first one seen is kept.
+                Some(_) => {}
+                None => {
+                    fields.insert(rel.pos, FieldValues::new(rel));
+                }
+            }
+        }
+    }
+
+    /// Values observed for the field at `pos` of inputs of the same format as `raw`.
+    pub fn field(&self, raw: &[u8], pos: usize) -> Option<&FieldValues> {
+        self.profiles.get(&format_profile(raw))?.get(&pos)
+    }
+
+    /// All fields by profile (hex of the leading bytes), ordered by profile and position.
+    pub fn fields(&self) -> Vec<(&str, usize, &FieldValues)> {
+        let mut profiles = self.profiles.iter().collect::<Vec<_>>();
+        profiles.sort_by_key(|(profile, _)| *profile);
+
+        profiles.into_iter()
+            .flat_map(|(profile, fields)| fields.iter().map(move |(pos, values)| (profile.as_str(), *pos, values)))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(len: u8) -> Structured {
+        let mut raw = vec![b'R', b'I', b'F', b'F', len];
+        raw.extend(vec![0x41; len as usize]);
+        let mut input = Structured::raw(raw);
+        input.add_relation(Relation::new(4, len as u64, 1, true, 5, 5 + len as usize));
+        input
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut model = FieldValueModel::default();
+        model.observe(&chunk(4));
+        model.observe(&chunk(8));
+        model.observe(&chunk(4));
+
+        let values = model.field(b"RIFF", 4).unwrap();
+        assert_eq!((values.count, values.min, values.max), (3, 4, 8));
+        assert_eq!(values.values, BTreeSet::from([4, 8]));
+        assert!(model.field(b"RIFF", 5).is_none());
+        assert!(model.field(b"\x89PNG", 4).is_none());
+
+        for r in 0..16 {
+            assert!([4, 8].contains(&values.in_distribution(r)));
+            let v = values.out_of_distribution(r).unwrap();
+            assert!(!(4..=8).contains(&v) && v <= 0xff);
+        }
+    }
+
+    #[test]
+    fn test_saturated() {
+        let mut model = FieldValueModel::default();
+        for len in 0..=255 {
+            model.observe(&chunk(len));
+        }
+
+        let values = model.field(b"RIFF", 4).unwrap();
+        assert!(values.saturated);
+        assert_eq!(values.in_distribution(200), 200);
+        assert_eq!(values.out_of_distribution(0), None);
+    }
+
+    #[test]
+    fn test_ascii_field() {
+        // A 10-digit Content-Length, whose digits do not fit 8 bytes of a binary field.
+        let mut model = FieldValueModel::default();
+        for len in [100, 4_000_000_000] {
+            let mut input = Structured::raw(format!("LEN {:<10}", len).into_bytes());
+            let mut rel = Relation::new(4, len, len.to_string().len(), true, 14, 14);
+            rel.ascii = true;
+            input.add_relation(rel);
+            model.observe(&input);
+        }
+
+        let values = model.field(b"LEN ", 4).unwrap();
+        assert_eq!((values.count, values.min, values.max), (2, 100, 4_000_000_000));
+        for r in 0..16 {
+            let v = values.out_of_distribution(r).unwrap();
+            assert!(!(100..=4_000_000_000).contains(&v) && v <= 9_999_999_999);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Binary-only FrameShift with Frida: runs the harness of an uninstrumented shared library (e.g. a closed-source
+//! library on macOS or Android) under the Frida stalker, with edge coverage collected by `libafl_frida`.
+use std::{cell::{Cell, RefCell}, env, ffi::CString, os::raw::{c_char, c_int, c_void}, process, ptr};
+
+use clap::Parser;
+use frida_gum::{stalker::{NoneEventSink, Stalker}, Gum, MemoryRange, NativePointer};
+use libafl::{inputs::BytesInput, observers::StdMapObserver};
+use libafl_frida::{coverage_rt::{CoverageRuntime, MAP_SIZE}, helper::FridaInstrumentationHelper};
+use libafl_bolts::tuples::tuple_list;
+use frameshift_afl::{components::file_set::register_parts, entrypoint, split_args, Cli};
+
+/// Harness called when `--frida` only names the library.
+const DEFAULT_HARNESS: &str = "LLVMFuzzerTestOneInput";
+
+type Harness = unsafe extern "C" fn(*const u8, usize) -> i32;
+
+type Initialize = unsafe extern "C" fn(*mut c_int, *mut *mut *mut c_char) -> c_int;
+
+/// Calls the `LLVMFuzzerInitialize` of the library, if it has one, with `args` as its command line (like
+/// `libfuzzer_initialize` for the instrumented targets). The arguments are leaked, as the target may keep them.
+fn initialize(lib: &libloading::Library, args: &[String]) {
+    let Ok(init) = (unsafe { lib.get::<Initialize>(b"LLVMFuzzerInitialize") }) else {
+        return;
+    };
+
+    let args = Vec::leak(args.iter().map(|arg| CString::new(arg.as_str()).expect("Argument with a NUL byte")).collect());
+    let argv = Vec::leak(args.iter().map(|arg| arg.as_ptr() as *mut c_char).chain(std::iter::once(ptr::null_mut())).collect());
+    let mut argc = args.len() as c_int;
+    let mut argv = argv.as_mut_ptr();
+    if unsafe { init(&mut argc, &mut argv) } == -1 {
+        println!("Warning: LLVMFuzzerInitialize failed with -1");
+    }
+}
+
+fn main() {
+    let (args, target_args) = split_args(&env::args().collect::<Vec<_>>());
+    let options = Cli::parse_from(args).into_options();
+
+    let Some(target) = options.target.frida.clone() else {
+        println!("Must specify --frida <library> [function]");
+        process::exit(1);
+    };
+    let library = target[0].clone();
+    let function = target.get(1).map_or(DEFAULT_HARNESS, |f| f.as_str());
+
+    let lib = unsafe { libloading::Library::new(&library) }.expect("Could not load the target library");
+    let harness = unsafe { *lib.get::<Harness>(function.as_bytes()).expect("Harness function not found") };
+
+    // Like frameshift_afl, the arguments after `--` go to `LLVMFuzzerInitialize` (with the `@@<name>` placeholders of
+    // multi-file inputs replaced by their files).
+    initialize(&lib, &register_parts(&target_args));
+
+    // Only the target library is instrumented, everything else runs natively.
+    let gum = Gum::obtain();
+    let coverage = CoverageRuntime::new();
+    let helper = FridaInstrumentationHelper::builder()
+        .instrument_module_if(move |module| module.path().contains(&library))
+        .build(&gum, tuple_list!(coverage));
+
+    let mut stalker = Stalker::new(&gum);
+    for range in helper.ranges().gaps(&(0..usize::MAX)) {
+        stalker.exclude(&MemoryRange::new(NativePointer(range.start as *mut c_void), range.end - range.start));
+    }
+
+    let map_ptr = helper.map_mut_ptr().expect("The coverage runtime has no map");
+    let helper = RefCell::new(helper);
+    let stalker = RefCell::new(stalker);
+    let followed = Cell::new(false);
+
+    // Like the Frida executor of libafl: the stalker follows the fuzzer thread through the harness only.
+    let mut fuzz_fn = |data: &[u8]| -> i32 {
+        let mut helper = helper.borrow_mut();
+        let mut stalker = stalker.borrow_mut();
+        let input = BytesInput::new(data.to_vec());
+
+        helper.pre_exec(&input).unwrap();
+        if followed.replace(true) {
+            stalker.activate(NativePointer(ptr::null_mut()));
+        } else {
+            stalker.follow_me::<NoneEventSink>(helper.transformer(), None);
+        }
+
+        let ret = unsafe { harness(data.as_ptr(), data.len()) };
+
+        stalker.deactivate();
+        helper.post_exec(&input).unwrap();
+        ret
+    };
+
+    let obs = unsafe { StdMapObserver::from_mut_ptr("edges", map_ptr, MAP_SIZE) };
+
+    entrypoint(options, &mut fuzz_fn, obs);
+}
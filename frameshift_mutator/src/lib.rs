@@ -0,0 +1,414 @@
+//! FrameShift as an AFL++ custom mutator (`AFL_CUSTOM_MUTATOR_LIBRARY=libframeshift_mutator.so`).
+//!
+//! The structure of every new queue entry is inferred (if `FRAMESHIFT_COVERAGE_WORKER` or `FRAMESHIFT_COVERAGE_CMD`
+//! gives a way to collect the coverage of a probe) and persisted in a `.annotated` sidecar next to it. Mutations of an
+//! entry keep its length and offset fields consistent, and `afl_custom_post_process` re-applies them to the inputs of
+//! other mutators.
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap}, env, ffi::CStr, fs, hash::{Hash, Hasher}, os::raw::{c_char, c_uint, c_void}, path::Path, ptr, slice, time::Duration
+};
+
+use frameshift_core::{annotation::{annotation_path, write_annotation_file}, collector::{CoverageBackend, CoverageWorker, ExternalCoverage}, decode_annotation, search, AnnotationFormat, CoverageSnapshot, SearchOptions, Structured};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Maximum number of stacked mutations per fuzzed input.
+const MAX_STACK: usize = 4;
+
+/// Maximum number of bytes inserted by a single mutation.
+const MAX_INSERT: usize = 32;
+
+/// Maximum number of bytes removed by a single mutation.
+const MAX_REMOVE: usize = 16;
+
+/// Time the coverage worker gets per probe, unless `FRAMESHIFT_COVERAGE_TIMEOUT` (in milliseconds) is set.
+const WORKER_TIMEOUT_MS: u64 = 1000;
+
+struct FrameShiftMutator {
+    rng: StdRng,
+    /// Collects the coverage of search probes (searching is disabled without it).
+    coverage: Option<Box<dyn CoverageBackend>>,
+    options: SearchOptions,
+    /// Format of the sidecars written (both formats are read).
+    format: AnnotationFormat,
+    /// Structure of each queue entry, by content hash.
+    structures: HashMap<u64, Structured>,
+    /// Structure of the queue entry being fuzzed.
+    entry: Structured,
+    /// Structure of the last input produced by `afl_custom_fuzz`.
+    last: Structured,
+    out: Vec<u8>,
+    post: Vec<u8>,
+}
+
+fn content_hash(raw: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    raw.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A persistent worker (`FRAMESHIFT_COVERAGE_WORKER`) or a command run per probe (`FRAMESHIFT_COVERAGE_CMD`, whose
+/// report is in the `FRAMESHIFT_COVERAGE_FORMAT` format).
+fn coverage_backend() -> Option<Box<dyn CoverageBackend>> {
+    if let Ok(cmd) = env::var("FRAMESHIFT_COVERAGE_WORKER") {
+        let timeout = env::var("FRAMESHIFT_COVERAGE_TIMEOUT").ok().and_then(|ms| ms.parse().ok()).unwrap_or(WORKER_TIMEOUT_MS);
+        return Some(Box::new(CoverageWorker::new(&cmd, Duration::from_millis(timeout))));
+    }
+    let format = env::var("FRAMESHIFT_COVERAGE_FORMAT").ok().and_then(|f| f.parse().ok()).unwrap_or_default();
+    env::var("FRAMESHIFT_COVERAGE_CMD").ok().map(|cmd| Box::new(ExternalCoverage::new(&cmd, format)) as Box<dyn CoverageBackend>)
+}
+
+impl FrameShiftMutator {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            coverage: coverage_backend(),
+            options: SearchOptions::default(),
+            // Sidecars are read in either format, `FRAMESHIFT_ANNOTATION_FORMAT` (`json` or `binary`) sets the one
+            // written.
+            format: env::var("FRAMESHIFT_ANNOTATION_FORMAT").ok().and_then(|f| f.parse().ok()).unwrap_or_default(),
+            structures: HashMap::new(),
+            entry: Structured::raw(vec![]),
+            last: Structured::raw(vec![]),
+            out: vec![],
+            post: vec![],
+        }
+    }
+
+    /// Returns the known structure of a queue entry, loading its sidecar if needed.
+    fn structure_of(&mut self, path: &Path) -> Option<Structured> {
+        let raw = fs::read(path).ok()?;
+        let hash = content_hash(&raw);
+        if let Some(structure) = self.structures.get(&hash) {
+            return Some(structure.clone());
+        }
+
+        let structure = decode_annotation(&fs::read(annotation_path(path)).ok()?).ok()?;
+        if structure.get_raw() != raw {
+            return None;
+        }
+        self.structures.insert(hash, structure.clone());
+        Some(structure)
+    }
+
+    /// Infers the structure of a new queue entry and writes its sidecar.
+    ///
+    /// Entries produced by `afl_custom_fuzz` inherit the structure of the mutated input, others are searched.
+    fn add_entry(&mut self, path: &Path) {
+        if self.structure_of(path).is_some() {
+            return;
+        }
+        let Ok(raw) = fs::read(path) else {
+            return;
+        };
+
+        let structure = if self.last.get_raw() == raw && !self.last.relations.is_empty() {
+            self.last.clone()
+        } else if let Some(coverage) = self.coverage.as_mut() {
+            let mut oracle = |input: &[u8]| CoverageSnapshot::from(coverage.run(input));
+            search(&Structured::raw(raw.clone()), &mut oracle, self.options.clone()).0
+        } else {
+            return;
+        };
+
+        let _ = write_annotation_file(&annotation_path(path), &structure, self.format);
+        self.structures.insert(content_hash(&raw), structure);
+    }
+
+    /// Applies one structure-preserving mutation, returns false if it did not apply.
+    fn mutate_once(&mut self, input: &mut Structured, donor: Option<&Structured>) -> bool {
+        let len = input.get_raw().len();
+
+        match self.rng.gen_range(0..5) {
+            // Overwrite a byte (fields are restored by the sanitization).
+            0 if len > 0 => {
+                let pos = self.rng.gen_range(0..len);
+                input.write(pos, &[self.rng.gen()]);
+                true
+            }
+            // Insert random bytes at an insertion point, growing the enclosing regions.
+            1 if !input.fixed_size => {
+                let points = input.insertion_points();
+                let idx = points[self.rng.gen_range(0..points.len())];
+                let data = (0..self.rng.gen_range(1..=MAX_INSERT)).map(|_| self.rng.gen()).collect::<Vec<u8>>();
+                input.save_relations();
+                if input.insert(idx, &data).is_err() {
+                    input.restore_relations();
+                    return false;
+                }
+                true
+            }
+            // Remove bytes, shrinking the enclosing regions.
+            2 if !input.fixed_size && len > 0 => {
+                let idx = self.rng.gen_range(0..len);
+                let size = self.rng.gen_range(1..=MAX_REMOVE.min(len - idx));
+                input.save_relations();
+                if input.remove(idx, size).is_err() {
+                    input.restore_relations();
+                    return false;
+                }
+                true
+            }
+            // Splice a whole region of another entry.
+            3 if !input.fixed_size => {
+                let Some(donor) = donor else {
+                    return false;
+                };
+                let regions = (0..donor.relations.len()).filter(|i| donor.region_of(*i).is_some()).collect::<Vec<_>>();
+                if regions.is_empty() {
+                    return false;
+                }
+                let rel_idx = regions[self.rng.gen_range(0..regions.len())];
+                let points = input.insertion_points();
+                let idx = points[self.rng.gen_range(0..points.len())];
+                input.splice_region(idx, donor, rel_idx).is_ok()
+            }
+            // Append data inside a nested region, only growing the enclosing ones.
+            4 if !input.fixed_size => {
+                let nested = (0..input.relations.len())
+                    .filter(|i| input.relations[*i].enabled && input.parent_of(*i).is_some())
+                    .collect::<Vec<_>>();
+                if nested.is_empty() {
+                    return false;
+                }
+                let idx = nested[self.rng.gen_range(0..nested.len())];
+                let data = (0..self.rng.gen_range(1..=MAX_INSERT)).map(|_| self.rng.gen()).collect::<Vec<u8>>();
+                input.extend_after(idx, &data).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    fn fuzz(&mut self, raw: &[u8], add: &[u8], max_size: usize) -> &mut Vec<u8> {
+        let mut input = self.structures.get(&content_hash(raw)).cloned().unwrap_or_else(|| Structured::raw(raw.to_vec()));
+        let donor = self.structures.get(&content_hash(add)).cloned();
+
+        let stack = self.rng.gen_range(1..=MAX_STACK);
+        let mut applied = 0;
+        for _ in 0..stack * 4 {
+            if self.mutate_once(&mut input, donor.as_ref()) {
+                applied += 1;
+                if applied == stack {
+                    break;
+                }
+            }
+        }
+        input.sanitize();
+
+        self.out.clear();
+        self.out.extend_from_slice(&input.get_raw()[..input.get_raw().len().min(max_size)]);
+        self.last = input;
+        &mut self.out
+    }
+}
+
+/// # Safety
+///
+/// Called by AFL++ once, the returned pointer is passed to every other function.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_init(_afl: *mut c_void, seed: c_uint) -> *mut c_void {
+    Box::into_raw(Box::new(FrameShiftMutator::new(seed as u64))) as *mut c_void
+}
+
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `buf` and `add_buf` must be valid for their sizes.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_fuzz(
+    data: *mut c_void,
+    buf: *mut u8,
+    buf_size: usize,
+    out_buf: *mut *mut u8,
+    add_buf: *mut u8,
+    add_buf_size: usize,
+    max_size: usize,
+) -> usize {
+    let mutator = &mut *(data as *mut FrameShiftMutator);
+    let raw = slice::from_raw_parts(buf, buf_size);
+    let add = if add_buf.is_null() { &[][..] } else { slice::from_raw_parts(add_buf, add_buf_size) };
+
+    let out = mutator.fuzz(raw, add, max_size);
+    *out_buf = out.as_mut_ptr();
+    out.len()
+}
+
+/// Re-applies the relations of the fuzzed queue entry to inputs of the same length (e.g. produced by havoc).
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `buf` must be valid for `buf_size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_post_process(data: *mut c_void, buf: *mut u8, buf_size: usize, out_buf: *mut *mut u8) -> usize {
+    let mutator = &mut *(data as *mut FrameShiftMutator);
+
+    // Inputs from `afl_custom_fuzz` are already consistent.
+    if ptr::eq(buf, mutator.out.as_ptr()) || buf_size != mutator.entry.get_raw().len() || mutator.entry.relations.is_empty() {
+        *out_buf = buf;
+        return buf_size;
+    }
+
+    mutator.post.clear();
+    mutator.post.extend_from_slice(slice::from_raw_parts(buf, buf_size));
+    mutator.entry.sanitize_buffer(&mut mutator.post);
+    *out_buf = mutator.post.as_mut_ptr();
+    buf_size
+}
+
+/// Selects the structure of the queue entry about to be fuzzed.
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `filename` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_queue_get(data: *mut c_void, filename: *const c_char) -> u8 {
+    let mutator = &mut *(data as *mut FrameShiftMutator);
+    let path = CStr::from_ptr(filename).to_string_lossy().into_owned();
+    mutator.entry = mutator.structure_of(Path::new(&path)).unwrap_or_else(|| Structured::raw(vec![]));
+    1
+}
+
+/// Infers and persists the structure of a new queue entry.
+///
+/// # Safety
+///
+/// `data` must come from `afl_custom_init`, `filename_new_queue` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_queue_new_entry(data: *mut c_void, filename_new_queue: *const c_char, _filename_orig_queue: *const c_char) -> u8 {
+    let mutator = &mut *(data as *mut FrameShiftMutator);
+    let path = CStr::from_ptr(filename_new_queue).to_string_lossy().into_owned();
+    mutator.add_entry(Path::new(&path));
+    0
+}
+
+/// # Safety
+///
+/// `data` must come from `afl_custom_init` and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn afl_custom_deinit(data: *mut c_void) {
+    drop(Box::from_raw(data as *mut FrameShiftMutator));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use frameshift_core::Relation;
+
+    use super::*;
+
+    /// A toy target parsing a 1-byte length prefixed payload followed by a trailer.
+    struct Target;
+
+    impl CoverageBackend for Target {
+        fn run(&mut self, data: &[u8]) -> Vec<u8> {
+            let mut cov = vec![0; 64];
+            cov[0] = 1;
+            if data.is_empty() || 1 + data[0] as usize > data.len() {
+                return cov;
+            }
+            cov[1] = 1;
+            for b in data[1 + data[0] as usize..].iter() {
+                cov[2 + (*b as usize % 62)] = 1;
+            }
+            cov
+        }
+    }
+
+    fn sample() -> Structured {
+        let mut input = Structured::raw(vec![4, 0x41, 0x41, 0x41, 0x41, 10, 20, 30]);
+        input.add_relation(Relation::new(0, 4, 1, true, 1, 5));
+        input
+    }
+
+    fn mutator() -> FrameShiftMutator {
+        let mut mutator = FrameShiftMutator::new(0);
+        mutator.coverage = None;
+        mutator
+    }
+
+    /// A new empty directory for the queue entries of a test.
+    fn queue_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("frameshift-mutator-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_fuzz_keeps_length_fields() {
+        let mut mutator = mutator();
+        let entry = sample();
+        mutator.structures.insert(content_hash(entry.get_raw()), entry.clone());
+
+        for _ in 0..200 {
+            let out = mutator.fuzz(entry.get_raw(), &[], 1 << 10).clone();
+            assert_eq!(out, mutator.last.get_raw());
+
+            // The length field still matches its region.
+            let rel = &mutator.last.relations[0];
+            assert_eq!(out[rel.pos] as usize, rel.insert - rel.anchor);
+        }
+
+        // The outputs are cut at the maximum size.
+        assert!(mutator.fuzz(entry.get_raw(), &[], 3).len() <= 3);
+    }
+
+    #[test]
+    fn test_post_process() {
+        let mut mutator = mutator();
+        mutator.entry = sample();
+        let data = &mut mutator as *mut FrameShiftMutator as *mut c_void;
+
+        // A havoc output of the same length gets the length field of the entry back.
+        let mut buf = vec![99, 0x42, 0x42, 0x42, 0x42, 1, 2, 3];
+        let mut out = ptr::null_mut();
+        let len = unsafe { afl_custom_post_process(data, buf.as_mut_ptr(), buf.len(), &mut out) };
+        assert_eq!(unsafe { slice::from_raw_parts(out, len) }, [4, 0x42, 0x42, 0x42, 0x42, 1, 2, 3]);
+
+        // Inputs of another length are left alone.
+        let mut buf = vec![99, 1, 2];
+        let len = unsafe { afl_custom_post_process(data, buf.as_mut_ptr(), buf.len(), &mut out) };
+        assert_eq!((out, len), (buf.as_mut_ptr(), 3));
+    }
+
+    #[test]
+    fn test_new_entry_inherits_structure() {
+        let dir = queue_dir("inherit");
+        let mut mutator = mutator();
+        mutator.last = sample();
+
+        let path = dir.join("id:000001");
+        fs::write(&path, sample().get_raw()).unwrap();
+        mutator.add_entry(&path);
+
+        // A fresh mutator reads the structure back from the sidecar.
+        let structure = FrameShiftMutator::structure_of(&mut self::mutator(), &path).unwrap();
+        assert_eq!(structure.relations.len(), 1);
+        assert_eq!((structure.relations[0].anchor, structure.relations[0].insert), (1, 5));
+
+        // Without a coverage backend, other entries get no sidecar.
+        let other = dir.join("id:000002");
+        fs::write(&other, [1, 2, 3]).unwrap();
+        mutator.add_entry(&other);
+        assert!(!annotation_path(&other).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_new_entry_searched() {
+        let dir = queue_dir("search");
+        let mut mutator = mutator();
+        mutator.coverage = Some(Box::new(Target));
+
+        let path = dir.join("id:000001");
+        fs::write(&path, sample().get_raw()).unwrap();
+        mutator.add_entry(&path);
+
+        let structure = mutator.structure_of(&path).unwrap();
+        let rel = &structure.relations[0];
+        assert_eq!((rel.pos, rel.size, rel.anchor, rel.insert), (0, 1, 1, 5));
+        assert!(annotation_path(&path).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
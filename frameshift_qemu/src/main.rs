@@ -0,0 +1,101 @@
+//! Binary-only FrameShift: runs the `LLVMFuzzerTestOneInput` of an uninstrumented target under QEMU usermode.
+use std::{env, process};
+
+use clap::Parser;
+use libafl::{executors::ExitKind, observers::StdMapObserver};
+use libafl_qemu::{elf::EasyElf, ArchExtras, CallingConvention, GuestAddr, GuestReg, MmapPerms, Qemu, QemuExitError, QemuExitReason, Regs};
+use frameshift_afl::{components::file_set::register_parts, entrypoint, split_args, Cli};
+
+/// Size of the edge coverage map.
+const MAP_SIZE: usize = 1 << 16;
+
+/// Largest input which can be passed to the target.
+const MAX_INPUT_SIZE: usize = 1 << 20;
+
+/// Assigns a map index to every translated edge.
+#[allow(clippy::unnecessary_cast)]
+extern "C" fn gen_edge_id(_data: u64, src: GuestAddr, dest: GuestAddr) -> u64 {
+    let (src, dest) = (src as u64, dest as u64);
+    ((src >> 1) ^ dest.rotate_left(7)) & (MAP_SIZE as u64 - 1)
+}
+
+/// Counts an executed edge in the map at `map` (the data of the hook).
+extern "C" fn trace_edge(map: u64, id: u64) {
+    unsafe {
+        let count = &mut *(map as *mut u8).add(id as usize);
+        *count = count.wrapping_add(1);
+    }
+}
+
+/// How an execution of the harness ended: anything but reaching its return address is a crash.
+fn exit_kind(exit: Result<QemuExitReason, QemuExitError>, ret_addr: GuestAddr) -> ExitKind {
+    match exit {
+        Ok(QemuExitReason::Breakpoint(addr)) if addr == ret_addr => ExitKind::Ok,
+        _ => ExitKind::Crash,
+    }
+}
+
+fn main() {
+    let argv = env::args().collect::<Vec<_>>();
+    let (args, target_args) = split_args(&argv);
+    let options = Cli::parse_from(args).into_options();
+
+    // The arguments after `--` are appended to those of the target (with the `@@<name>` placeholders of multi-file
+    // inputs replaced by their files).
+    let target_args = match argv.iter().any(|arg| arg == "--") {
+        true => register_parts(&target_args[1..]),
+        false => vec![],
+    };
+
+    let Some(target) = options.target.qemu.clone() else {
+        println!("Must specify --qemu <target> [args...]");
+        process::exit(1);
+    };
+
+    // QEMU expects its own argv, with the target program first.
+    let qemu_args = std::iter::once(argv[0].clone()).chain(target).chain(target_args).collect::<Vec<_>>();
+    let qemu_env = env::vars().collect::<Vec<_>>();
+    let qemu = Qemu::init(&qemu_args, &qemu_env).expect("Could not initialize QEMU");
+
+    let mut elf_buffer = Vec::new();
+    let elf = EasyElf::from_file(qemu.binary_path(), &mut elf_buffer).expect("Could not parse target binary");
+    let test_one_input = elf
+        .resolve_symbol("LLVMFuzzerTestOneInput", qemu.load_addr())
+        .expect("Symbol LLVMFuzzerTestOneInput not found");
+
+    // Run until the harness and break when it returns.
+    qemu.entry_break(test_one_input);
+    let stack_ptr: GuestAddr = qemu.read_reg(Regs::Sp).unwrap();
+    let ret_addr: GuestAddr = qemu.read_return_address().unwrap();
+    qemu.set_breakpoint(ret_addr);
+
+    let input_addr = qemu
+        .map_private(0, MAX_INPUT_SIZE, MmapPerms::ReadWrite)
+        .expect("Could not map the input buffer");
+
+    // The edges map lives as long as the fuzzer; the hooks get its address as their data.
+    let edges_map: &'static mut [u8] = Box::leak(vec![0u8; MAP_SIZE].into_boxed_slice());
+    qemu.add_edge_hooks(edges_map.as_mut_ptr() as u64, Some(gen_edge_id), Some(trace_edge));
+
+    let mut fuzz_fn = |data: &[u8]| -> i32 {
+        let data = &data[..data.len().min(MAX_INPUT_SIZE)];
+        unsafe {
+            qemu.write_mem(input_addr, data);
+            qemu.write_function_argument(CallingConvention::Cdecl, 0, input_addr).unwrap();
+            qemu.write_function_argument(CallingConvention::Cdecl, 1, data.len() as GuestReg).unwrap();
+            qemu.write_reg(Regs::Pc, test_one_input).unwrap();
+            qemu.write_reg(Regs::Sp, stack_ptr).unwrap();
+            qemu.write_return_address(ret_addr).unwrap();
+        }
+
+        // The executors of the fuzzer report the abort as a crash of the input.
+        if exit_kind(unsafe { qemu.run() }, ret_addr) == ExitKind::Crash {
+            process::abort();
+        }
+        0
+    };
+
+    let obs = unsafe { StdMapObserver::from_mut_ptr("edges", edges_map.as_mut_ptr(), MAP_SIZE) };
+
+    entrypoint(options, &mut fuzz_fn, obs);
+}